@@ -0,0 +1,47 @@
+import { Construct } from "constructs";
+import * as cdk from "aws-cdk-lib";
+import * as events from "aws-cdk-lib/aws-events";
+import { LambdaFunction as LambdaFunctionTarget } from "aws-cdk-lib/aws-events-targets";
+import * as lambda from "aws-cdk-lib/aws-lambda";
+import * as sqs from "aws-cdk-lib/aws-sqs";
+import { RustFunctionCode } from "./rust-function-layer";
+
+interface PullerSchedulerProps {
+  /** The default rate in minutes of each log source, used unless it sets a `schedule` property. */
+  logSourceRates: Record<string, number>;
+  queue: sqs.IQueue;
+}
+
+/**
+ * Enqueues scheduled pulls for the log puller every minute, from per log source `schedule` (rate or cron)
+ * and `schedule_jitter_seconds` properties, in place of an EventBridge rule per rate.
+ */
+export class PullerScheduler extends Construct {
+  function: lambda.Function;
+  constructor(scope: Construct, id: string, props: PullerSchedulerProps) {
+    super(scope, id);
+
+    const func = new lambda.Function(this, "Function", {
+      description: "[Matano] Schedules pulls of the external log puller.",
+      runtime: lambda.Runtime.PROVIDED_AL2,
+      code: RustFunctionCode.assetCode({ package: "log_puller_scheduler" }),
+      handler: "main",
+      timeout: cdk.Duration.seconds(30),
+      memorySize: 256,
+      environment: {
+        RUST_LOG: "warn,log_puller_scheduler=info",
+        PULLER_LOG_SOURCE_RATES: JSON.stringify(props.logSourceRates),
+        PULLER_QUEUE_URL: props.queue.queueUrl,
+        LOG_SOURCES_CONFIG_DIR: "/opt/config/log_sources",
+      },
+    });
+    this.function = func;
+    props.queue.grantSendMessages(func);
+
+    const rule = new events.Rule(this, "EventsRule", {
+      description: "[Matano] Invokes the log puller scheduler every minute.",
+      schedule: events.Schedule.rate(cdk.Duration.minutes(1)),
+    });
+    rule.addTarget(new LambdaFunctionTarget(func));
+  }
+}
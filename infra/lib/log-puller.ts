@@ -5,36 +5,70 @@ import { SqsQueue as SqsQueueTarget } from "aws-cdk-lib/aws-events-targets";
 import * as lambda from "aws-cdk-lib/aws-lambda";
 import * as iam from "aws-cdk-lib/aws-iam";
 import * as secretsmanager from "aws-cdk-lib/aws-secretsmanager";
+import * as ddb from "aws-cdk-lib/aws-dynamodb";
+import * as sns from "aws-cdk-lib/aws-sns";
 import * as s3 from "aws-cdk-lib/aws-s3";
 import * as sqs from "aws-cdk-lib/aws-sqs";
 import { RustFunctionCode } from "./rust-function-layer";
 import { SqsEventSource } from "aws-cdk-lib/aws-lambda-event-sources";
 import { fail } from "./utils";
+import { MatanoStack } from "./MatanoStack";
+import { PullerScheduler } from "./log-puller-scheduler";
 
 interface ExternalLogPullerProps {
   logSources: string[];
+  /** Tenant ids by log source name, each tenant gets its own secret. */
+  tenants?: Record<string, string[]>;
+  /** Log sources configured with `managed.secret: false`, which get no secret. */
+  noSecretLogSources?: string[];
   ingestionBucket: s3.IBucket;
+  /** The queue ingestion is notified of the ingestion bucket's objects on. */
+  ingestionQueue?: sqs.IQueue;
+  /** Where puller checkpoints are stored, defaults to a DynamoDB table. */
+  checkpointStore?: "dynamodb" | "s3";
 }
 
 // Managed log source types that support pulling.
 export const PULLER_LOG_SOURCE_TYPES: string[] = [
   "onepassword",
   "aws_inspector",
+  "aws_guardduty",
+  "aws_securityhub",
   "msft",
   "o365",
   "google_workspace",
   "duo",
   "okta",
   "snyk",
+  "custom_api",
+  "graphql",
+  "imap",
+  "azure_blob",
+  "gcs",
+  "kafka",
+  "sql",
+  "elasticsearch",
+  "splunk",
+  "external_s3",
   "enrich_abusech_urlhaus",
   "enrich_abusech_malwarebazaar",
   "enrich_abusech_threatfox",
   "enrich_otx",
   "enrich_cisa_kev"
 ];
+/**
+ * Managed types of in-house pullers registered in the puller's `extensions` module, with their rate in minutes,
+ * e.g. `log_puller: { extension_types: { acme: 5 } }`.
+ */
+export function pullerExtensionTypes(stack: MatanoStack): Record<string, number> {
+  return stack.matanoConfig.log_puller?.extension_types ?? {};
+}
+
 /** Some puller log sources don't need secrets. */
 const NO_SECRET_LOG_SOURCES: string[] = [
   "aws_inspector",
+  "aws_guardduty",
+  "aws_securityhub",
   "enrich_abusech_urlhaus",
   "enrich_abusech_malwarebazaar",
   "enrich_abusech_threatfox",
@@ -44,12 +78,24 @@ const NO_SECRET_LOG_SOURCES: string[] = [
 const LOG_SOURCE_RATES: Record<string, cdk.Duration> = {
   onepassword: cdk.Duration.minutes(1),
   aws_inspector: cdk.Duration.minutes(10),
+  aws_guardduty: cdk.Duration.minutes(5),
+  aws_securityhub: cdk.Duration.minutes(5),
   msft: cdk.Duration.minutes(1),
   o365: cdk.Duration.minutes(1),
   google_workspace: cdk.Duration.minutes(1),
   duo: cdk.Duration.minutes(1),
   okta: cdk.Duration.minutes(1),
   snyk: cdk.Duration.hours(24),
+  custom_api: cdk.Duration.minutes(5),
+  graphql: cdk.Duration.minutes(5),
+  imap: cdk.Duration.minutes(10),
+  azure_blob: cdk.Duration.minutes(5),
+  gcs: cdk.Duration.minutes(5),
+  kafka: cdk.Duration.minutes(1),
+  sql: cdk.Duration.minutes(5),
+  elasticsearch: cdk.Duration.minutes(5),
+  splunk: cdk.Duration.minutes(5),
+  external_s3: cdk.Duration.minutes(5),
   enrich_abusech_urlhaus: cdk.Duration.minutes(5),
   enrich_abusech_malwarebazaar: cdk.Duration.hours(1),
   enrich_abusech_threatfox: cdk.Duration.hours(1),
@@ -65,15 +111,37 @@ const LOG_SOURCE_PLACEHOLDER_MAP: Record<string, string> = {
   google_workspace: "private_key",
   msft: "client_secret",
   o365: "client_secret",
+  custom_api: "api_token",
+  graphql: "api_token",
+  imap: "password",
+  azure_blob: "sas_token",
+  gcs: "private_key",
+  kafka: "password",
+  sql: "password",
+  elasticsearch: "password",
+  splunk: "api_token",
+  external_s3: "secret_access_key",
   enrich_otx: "api_key",
 };
 
 export class ExternalLogPuller extends Construct {
   function: lambda.Function;
+  /** Notified when a log source is disabled by its circuit breaker. */
+  healthTopic: sns.Topic;
+  /** Set when pulls are scheduled by the scheduler function rather than EventBridge rules. */
+  scheduler?: PullerScheduler;
+  /** Whether uploads are sent to the ingestion queue directly, so their S3 event notifications are deduplicated against them. */
+  notifiesIngestionQueue: boolean;
+  /** The secondary ingestion bucket uploads fail over to, ingested through the direct notifications. */
+  failoverBucket?: { name: string; region?: string };
   constructor(scope: Construct, id: string, props: ExternalLogPullerProps) {
     super(scope, id);
 
     const logSourceSecretMap: Record<string, string> = {};
+    const extensionTypes = pullerExtensionTypes(cdk.Stack.of(this) as MatanoStack);
+
+    // Optional /tmp size for pulls spilled to disk, e.g. `log_puller: { ephemeral_storage_mb: 4096 }`.
+    const ephemeralStorageMb = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.ephemeral_storage_mb;
 
     const func = new lambda.Function(this, "Function", {
       description: "[Matano] Pulls external logs for ingestion on a schedule.",
@@ -82,17 +150,117 @@ export class ExternalLogPuller extends Construct {
       handler: "main",
       timeout: cdk.Duration.minutes(2),
       memorySize: 3000,
+      ephemeralStorageSize: ephemeralStorageMb != null ? cdk.Size.mebibytes(ephemeralStorageMb) : undefined,
+      tracing: lambda.Tracing.ACTIVE,
       environment: {
         RUST_LOG: "warn,log_puller=info",
-        PULLER_LOG_SOURCE_TYPES: JSON.stringify(PULLER_LOG_SOURCE_TYPES),
+        PULLER_LOG_SOURCE_TYPES: JSON.stringify([...PULLER_LOG_SOURCE_TYPES, ...Object.keys(extensionTypes)]),
         INGESTION_BUCKET_NAME: props.ingestionBucket.bucketName,
         LOG_SOURCES_CONFIG_DIR: "/opt/config/log_sources",
       },
     });
     this.function = func;
 
+    // Optional egress proxy for all pulls, e.g. `log_puller: { proxy_url: http://proxy.internal:3128 }`.
+    const proxyUrl = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.proxy_url;
+    if (proxyUrl != null) {
+      func.addEnvironment("PULLER_PROXY_URL", proxyUrl);
+    }
+
+    // Optional limit on messages pulled at once per invocation, e.g. `log_puller: { max_concurrent_messages: 2 }`.
+    const maxConcurrentMessages = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.max_concurrent_messages;
+    if (maxConcurrentMessages != null) {
+      func.addEnvironment("PULLER_MAX_CONCURRENT_MESSAGES", maxConcurrentMessages.toString());
+    }
+
+    // Optional verbose logging for some log sources, e.g. `log_puller: { debug_log_sources: [okta] }`.
+    const debugLogSources: string[] | undefined = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller
+      ?.debug_log_sources;
+    if (debugLogSources != null && debugLogSources.length > 0) {
+      func.addEnvironment("PULLER_DEBUG_LOG_SOURCES", debugLogSources.join(","));
+    }
+
+    // Optional log source configs read from S3 instead of the layer, picked up without a redeploy,
+    // e.g. `log_puller: { config_s3_uri: s3://my-config-bucket/matano/log_sources, config_refresh_seconds: 60 }`.
+    const configS3Uri: string | undefined = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.config_s3_uri;
+    if (configS3Uri != null) {
+      const match = /^s3:\/\/([^/]+)\/?(.*)$/.exec(configS3Uri);
+      if (match == null) {
+        fail(`Invalid log_puller.config_s3_uri: ${configS3Uri}`);
+      }
+      const [, configBucketName, configPrefix] = match;
+      const configBucket = s3.Bucket.fromBucketName(this, "ConfigBucket", configBucketName);
+      configBucket.grantRead(func, configPrefix ? `${configPrefix.replace(/\/+$/, "")}/*` : "*");
+      func.addEnvironment("PULLER_CONFIG_S3_URI", configS3Uri);
+
+      const configRefreshSeconds = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.config_refresh_seconds;
+      if (configRefreshSeconds != null) {
+        func.addEnvironment("PULLER_CONFIG_REFRESH_SECONDS", configRefreshSeconds.toString());
+      }
+    }
+
+    // Optional secondary ingestion bucket uploads fail over to during S3 incidents,
+    // e.g. `log_puller: { failover_bucket: { name: matano-ingestion-us-west-2, region: us-west-2 } }`.
+    this.failoverBucket = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.failover_bucket;
+    if (this.failoverBucket != null) {
+      func.addEnvironment("INGESTION_FAILOVER_BUCKET_NAME", this.failoverBucket.name);
+      if (this.failoverBucket.region != null) {
+        func.addEnvironment("INGESTION_FAILOVER_BUCKET_REGION", this.failoverBucket.region);
+      }
+    }
+
+    // Optional direct notification of uploads on the ingestion queue, ahead of S3 event notifications,
+    // e.g. `log_puller: { notify_ingestion_queue: true }`.
+    this.notifiesIngestionQueue =
+      (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.notify_ingestion_queue === true &&
+      props.ingestionQueue != null;
+    if (this.notifiesIngestionQueue) {
+      func.addEnvironment("INGESTION_QUEUE_URL", props.ingestionQueue!.queueUrl);
+      props.ingestionQueue!.grantSendMessages(func);
+    }
+
+    // Optional variables for `${NAME}` in managed properties, so one config can be shared by accounts,
+    // e.g. `log_puller: { environment: { OKTA_DOMAIN: dev-123.okta.com } }`.
+    const pullerEnvironment: Record<string, string> | undefined = (cdk.Stack.of(this) as MatanoStack).matanoConfig
+      .log_puller?.environment;
+    for (const [name, value] of Object.entries(pullerEnvironment ?? {})) {
+      func.addEnvironment(name, `${value}`);
+    }
+
+    // Optional SSM Parameter Store parameters (SecureString, a JSON object of the secret's fields) to read
+    // credentials from instead of creating Secrets Manager secrets, by log source (or `<log source>/<tenant>`),
+    // e.g. `log_puller: { secret_parameters: { okta: /matano/okta, okta/acme: /matano/okta-acme } }`.
+    const secretParameters: Record<string, string> =
+      (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.secret_parameters ?? {};
+    const useSecretParameter = (key: string) => {
+      const parameterName = secretParameters[key];
+      if (parameterName == null) {
+        return false;
+      }
+      const parameterArn = cdk.Stack.of(this).formatArn({
+        service: "ssm",
+        resource: "parameter",
+        resourceName: parameterName.replace(/^\//, ""),
+      });
+      // Rotated OAuth2 refresh tokens are written back to the parameter.
+      func.addToRolePolicy(
+        new iam.PolicyStatement({
+          actions: ["ssm:GetParameter", "ssm:PutParameter"],
+          resources: [parameterArn],
+        })
+      );
+      logSourceSecretMap[key] = parameterArn;
+      return true;
+    };
+
     for (const logSourceName of props.logSources) {
-      if (NO_SECRET_LOG_SOURCES.includes(logSourceName)) {
+      if (NO_SECRET_LOG_SOURCES.includes(logSourceName) || props.noSecretLogSources?.includes(logSourceName)) {
+        continue;
+      }
+      for (const tenantId of props.tenants?.[logSourceName] ?? []) {
+        useSecretParameter(`${logSourceName}/${tenantId}`);
+      }
+      if (useSecretParameter(logSourceName)) {
         continue;
       }
 
@@ -113,35 +281,177 @@ export class ExternalLogPuller extends Construct {
         secretObjectValue: placeholder,
       });
       secret.grantRead(func);
+      // Rotated OAuth2 refresh tokens are written back to the secret.
+      secret.grantWrite(func);
       logSourceSecretMap[logSourceName] = secret.secretArn;
+
+      for (const tenantId of props.tenants?.[logSourceName] ?? []) {
+        if (secretParameters[`${logSourceName}/${tenantId}`] != null) {
+          continue;
+        }
+        const tenantSecret = new secretsmanager.Secret(this, `Secret-${logSourceName}-${tenantId}`, {
+          description: `[Matano] ${logSourceName} (tenant: ${tenantId}) - log pulling secret`,
+          secretObjectValue: placeholder,
+        });
+        tenantSecret.grantRead(func);
+        tenantSecret.grantWrite(func);
+        logSourceSecretMap[`${logSourceName}/${tenantId}`] = tenantSecret.secretArn;
+      }
     }
 
     func.addEnvironment("LOG_SOURCE_TO_SECRET_ARN_MAP", JSON.stringify(logSourceSecretMap));
 
+    // Puller checkpoints and the end of the last pulled window, per log source (and tenant).
+    // With the S3 store, these are state objects in the ingestion bucket instead.
+    const checkpointStore = props.checkpointStore ?? "dynamodb";
+    func.addEnvironment("PULLER_CHECKPOINT_STORE", checkpointStore);
+    if (checkpointStore === "dynamodb") {
+      const checkpointTable = new ddb.Table(this, "CheckpointTable", {
+        partitionKey: { name: "pk", type: ddb.AttributeType.STRING },
+        // Only set on records of recent pulls, used to ignore duplicate deliveries.
+        timeToLiveAttribute: "ttl",
+        billingMode: ddb.BillingMode.PAY_PER_REQUEST,
+      });
+      checkpointTable.grantReadWriteData(func);
+      func.addEnvironment("PULLER_CHECKPOINT_TABLE_NAME", checkpointTable.tableName);
+    }
+
+    // Circuit breaker state for log sources that keep failing auth.
+    const circuitBreakerTable = new ddb.Table(this, "CircuitBreakerTable", {
+      partitionKey: { name: "pk", type: ddb.AttributeType.STRING },
+      timeToLiveAttribute: "ttl",
+      billingMode: ddb.BillingMode.PAY_PER_REQUEST,
+    });
+    circuitBreakerTable.grantReadWriteData(func);
+    func.addEnvironment("PULLER_CIRCUIT_BREAKER_TABLE_NAME", circuitBreakerTable.tableName);
+
+    // Token buckets for `rate_limit_requests_per_minute`, shared across concurrent invocations.
+    const rateLimitTable = new ddb.Table(this, "RateLimitTable", {
+      partitionKey: { name: "pk", type: ddb.AttributeType.STRING },
+      timeToLiveAttribute: "ttl",
+      billingMode: ddb.BillingMode.PAY_PER_REQUEST,
+    });
+    rateLimitTable.grantReadWriteData(func);
+    func.addEnvironment("PULLER_RATE_LIMIT_TABLE_NAME", rateLimitTable.tableName);
+
+    // Seen event IDs for log sources with `dedup_id_field`.
+    const dedupTable = new ddb.Table(this, "DedupTable", {
+      partitionKey: { name: "pk", type: ddb.AttributeType.STRING },
+      timeToLiveAttribute: "ttl",
+      billingMode: ddb.BillingMode.PAY_PER_REQUEST,
+    });
+    dedupTable.grantReadWriteData(func);
+    func.addEnvironment("PULLER_DEDUP_TABLE_NAME", dedupTable.tableName);
+
+    this.healthTopic = new sns.Topic(this, "HealthTopic", {
+      displayName: "MatanoLogPullerHealthTopic",
+    });
+    this.healthTopic.grantPublish(func);
+    func.addEnvironment("PULLER_HEALTH_TOPIC_ARN", this.healthTopic.topicArn);
+
     props.ingestionBucket.grantReadWrite(func);
     // Used for managed log source.
     func.addToRolePolicy(
       new iam.PolicyStatement({
-        actions: ["inspector2:ListFindings"],
+        actions: [
+          "inspector2:ListFindings",
+          "guardduty:ListDetectors",
+          "guardduty:ListFindings",
+          "guardduty:GetFindings",
+          "securityhub:GetFindings",
+        ],
+        resources: ["*"],
+      })
+    );
+    // Used for external_s3 and aws_role_arn, access is still controlled by the external bucket policy/role trust policy.
+    func.addToRolePolicy(
+      new iam.PolicyStatement({
+        actions: ["sts:AssumeRole", "s3:ListBucket", "s3:GetObject"],
+        resources: ["*"],
+      })
+    );
+
+    // Used for s3_bucket overrides and the failover bucket, access is still controlled by the destination bucket policy.
+    func.addToRolePolicy(
+      new iam.PolicyStatement({
+        actions: ["s3:PutObject", "s3:PutObjectTagging", "s3:AbortMultipartUpload"],
+        resources: ["*"],
+      })
+    );
+    // Used for kinesis_stream_name and firehose_delivery_stream_name outputs.
+    func.addToRolePolicy(
+      new iam.PolicyStatement({
+        actions: ["kinesis:PutRecords", "firehose:PutRecordBatch"],
+        resources: ["*"],
+      })
+    );
+    // Used for eventbridge_bus_name outputs.
+    func.addToRolePolicy(
+      new iam.PolicyStatement({
+        actions: ["events:PutEvents"],
+        resources: ["*"],
+      })
+    );
+    // Optional per invocation pull stats, e.g. `log_puller: { stats_destination_arn: arn:aws:sns:... }`.
+    const statsDestinationArn = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.stats_destination_arn;
+    if (statsDestinationArn != null) {
+      func.addEnvironment("PULLER_STATS_DESTINATION_ARN", statsDestinationArn);
+      func.addToRolePolicy(
+        new iam.PolicyStatement({
+          actions: ["sns:Publish", "sqs:SendMessage", "events:PutEvents"],
+          resources: [statsDestinationArn],
+        })
+      );
+    }
+    // Used for s3_sse_kms_key_id and client_side_encryption_kms_key_id, based on user adding tags.
+    func.addToRolePolicy(
+      new iam.PolicyStatement({
+        actions: ["kms:GenerateDataKey", "kms:Decrypt"],
         resources: ["*"],
+        conditions: {
+          StringEquals: {
+            "aws:ResourceTag/matano:trusted": "true",
+          },
+        },
       })
     );
 
-    const dlq = new sqs.Queue(this, "DLQ", {});
+    // Optional FIFO queues, so each log source's pulls run one at a time in order (e.g. for cursor based sources)
+    // while log sources still run in parallel, e.g. `log_puller: { fifo_queue: true }`. Scheduled pulls are
+    // deduplicated by content, continuations, catch ups and dead letters are sent with unique deduplication IDs.
+    const fifo: boolean = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.fifo_queue ?? false;
+    const fifoProps: Partial<sqs.QueueProps> = fifo ? { fifo: true, contentBasedDeduplication: true } : {};
+
+    const dlq = new sqs.Queue(this, "DLQ", { ...fifoProps });
+    const maxReceiveCount = 3;
 
     const queue = new sqs.Queue(this, "Queue", {
+      ...fifoProps,
       visibilityTimeout: cdk.Duration.seconds(130),
       deadLetterQueue: {
         queue: dlq,
-        maxReceiveCount: 3,
+        maxReceiveCount,
       },
     });
+    // Messages failing their last attempt are sent to the DLQ by the puller, with the failure as attributes.
+    dlq.grantSendMessages(func);
+    func.addEnvironment("PULLER_DLQ_URL", dlq.queueUrl);
+    func.addEnvironment("PULLER_MAX_RECEIVE_COUNT", maxReceiveCount.toString());
+    // Catch up pulls of missed windows are enqueued by the puller itself.
+    queue.grantSendMessages(func);
+    func.addEnvironment("PULLER_QUEUE_URL", queue.queueUrl);
 
     // Can only add 5 targets per rule.
     let rateMap: Record<string, any[]> = {};
+    const logSourceRates: Record<string, cdk.Duration> = {
+      ...LOG_SOURCE_RATES,
+      ...Object.fromEntries(Object.entries(extensionTypes).map(([k, v]) => [k, cdk.Duration.minutes(v)])),
+    };
+    const defaultRateMinutes: Record<string, number> = {};
     for (const logSourceName of props.logSources) {
       const [_, rate] =
-        Object.entries(LOG_SOURCE_RATES).find(([k, _]) => logSourceName.startsWith(k)) ?? fail("Invalid log source.");
+        Object.entries(logSourceRates).find(([k, _]) => logSourceName.startsWith(k)) ?? fail("Invalid log source.");
+      defaultRateMinutes[logSourceName] = rate.toMinutes();
 
       if (Object.keys(rateMap).includes(rate.toSeconds().toString())) {
         rateMap[rate.toSeconds()].push(logSourceName);
@@ -151,6 +461,19 @@ export class ExternalLogPuller extends Construct {
     }
     rateMap = Object.fromEntries(Object.entries(rateMap).map(([k, v]) => [k, chunk(v, 5)]));
 
+    // Optional scheduling by a function reading per log source `schedule` properties (rate or cron, with jitter)
+    // instead of a rule per rate, e.g. `log_puller: { scheduler: lambda }`.
+    const scheduler = (cdk.Stack.of(this) as MatanoStack).matanoConfig.log_puller?.scheduler ?? "eventbridge";
+    if (scheduler === "lambda") {
+      this.scheduler = new PullerScheduler(this, "Scheduler", {
+        logSourceRates: defaultRateMinutes,
+        queue,
+      });
+      rateMap = {};
+    } else if (scheduler !== "eventbridge") {
+      fail(`Invalid log_puller.scheduler: ${scheduler}`);
+    }
+
     for (const [rate, logSourceChunks] of Object.entries(rateMap)) {
       for (const [idx, logSourceChunk] of logSourceChunks.entries()) {
         const scheduleRule = new events.Rule(this, `EventsRule-${rate}-${idx}`, {
@@ -166,6 +489,7 @@ export class ExternalLogPuller extends Construct {
                 log_source_name: logSourceName,
                 rate_minutes: cdk.Duration.seconds(parseInt(rate)).toMinutes(),
               }),
+              messageGroupId: fifo ? logSourceName : undefined,
             })
           );
         }
@@ -174,8 +498,9 @@ export class ExternalLogPuller extends Construct {
 
     func.addEventSource(
       new SqsEventSource(queue, {
-        batchSize: 10000,
-        maxBatchingWindow: cdk.Duration.seconds(20),
+        // FIFO queues allow batches of at most 10, without a batching window.
+        batchSize: fifo ? 10 : 10000,
+        maxBatchingWindow: fifo ? undefined : cdk.Duration.seconds(20),
         reportBatchItemFailures: true,
       })
     );
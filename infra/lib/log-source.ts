@@ -81,11 +81,22 @@ export interface LogSourceConfig {
     sqs_source?: {
       enabled?: boolean;
     };
+    webhook?: {
+      enabled?: boolean;
+      /** How deliveries are authenticated, e.g. `{ type: github }`. Defaults to the webhook token. */
+      verification?: { type: "token" | "github" | "slack" | "okta" | "hmac"; [key: string]: any };
+    };
   };
   transform?: string;
   managed?: {
     type?: string;
     properties: Record<string, any>;
+    /** Pull from multiple accounts/orgs, each with its own secret. Tenant properties override the shared ones. */
+    tenants?: { id: string; properties?: Record<string, any> }[];
+    /** Set to false for sources that need no credentials (e.g. public feeds), so no secret is created. */
+    secret?: boolean;
+    /** Set to false to pause pulling, keeping the config and secret. */
+    enabled?: boolean;
   };
   [key: string]: any;
 }
@@ -111,12 +122,15 @@ const MANAGED_LOG_SOURCE_PREFIX_MAP: Record<string, string> = {
   aws_s3inventory: "aws",
   aws_elb: "aws",
   aws_inspector: "aws",
+  aws_guardduty: "aws",
+  aws_securityhub: "aws",
   aws_config_history: "aws",
   aws_vpcflow: "aws",
   aws_waf: "aws",
   crowdstrike: "crowdstrike",
   cloudflare: "cloudflare",
   crowdstrike_falcon: "crowdstrike",
+  custom_api: "custom_api",
   duo: "duo",
   msft: "msft",
   teleport: "teleport",
@@ -129,7 +143,17 @@ const MANAGED_LOG_SOURCE_PREFIX_MAP: Record<string, string> = {
   snyk: "snyk",
   suricata: "suricata",
   zeek: "zeek",
+  graphql: "graphql",
+  imap: "imap",
+  azure_blob: "azure_blob",
+  gcs: "gcs",
+  kafka: "kafka",
+  sql: "sql",
+  elasticsearch: "elasticsearch",
+  splunk: "splunk",
+  external_s3: "external_s3",
   matano_alerts: "matano_alerts", // doesn't really make sense but OK
+  matano_puller_audit: "matano_puller_audit",
 };
 
 function getPrefixForManagedLogSourceType(logSourceType: string) {
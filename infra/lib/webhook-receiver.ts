@@ -0,0 +1,62 @@
+import { Construct } from "constructs";
+import * as cdk from "aws-cdk-lib";
+import * as lambda from "aws-cdk-lib/aws-lambda";
+import * as secretsmanager from "aws-cdk-lib/aws-secretsmanager";
+import * as s3 from "aws-cdk-lib/aws-s3";
+import { RustFunctionCode } from "./rust-function-layer";
+
+interface WebhookReceiverProps {
+  logSources: string[];
+  ingestionBucket: s3.IBucket;
+  /** Per log source `ingest.webhook.verification`, if set. */
+  verification?: Record<string, Record<string, any>>;
+}
+
+/** Accepts push-based webhook deliveries for log sources that can't be pulled. */
+export class WebhookReceiver extends Construct {
+  function: lambda.Function;
+  functionUrl: lambda.FunctionUrl;
+  constructor(scope: Construct, id: string, props: WebhookReceiverProps) {
+    super(scope, id);
+
+    const logSourceSecretMap: Record<string, string> = {};
+
+    const func = new lambda.Function(this, "Function", {
+      description: "[Matano] Receives webhook deliveries and writes them to the ingestion bucket.",
+      runtime: lambda.Runtime.PROVIDED_AL2,
+      code: RustFunctionCode.assetCode({ package: "webhook_receiver" }),
+      handler: "main",
+      timeout: cdk.Duration.seconds(30),
+      memorySize: 1024,
+      environment: {
+        RUST_LOG: "warn,webhook_receiver=info",
+        INGESTION_BUCKET_NAME: props.ingestionBucket.bucketName,
+      },
+    });
+    this.function = func;
+
+    for (const logSourceName of props.logSources) {
+      const secret = new secretsmanager.Secret(this, `Secret-${logSourceName}`, {
+        description: `[Matano] ${logSourceName} - webhook token`,
+        generateSecretString: {
+          secretStringTemplate: JSON.stringify({}),
+          generateStringKey: "webhook_token",
+          excludePunctuation: true,
+          passwordLength: 48,
+        },
+      });
+      secret.grantRead(func);
+      logSourceSecretMap[logSourceName] = secret.secretArn;
+    }
+
+    func.addEnvironment("WEBHOOK_LOG_SOURCE_TO_SECRET_ARN_MAP", JSON.stringify(logSourceSecretMap));
+    func.addEnvironment("WEBHOOK_LOG_SOURCE_VERIFICATION", JSON.stringify(props.verification ?? {}));
+    props.ingestionBucket.grantWrite(func);
+
+    // Deliveries are authenticated in the function with the per log source webhook token,
+    // or a signature keyed with it.
+    this.functionUrl = func.addFunctionUrl({
+      authType: lambda.FunctionUrlAuthType.NONE,
+    });
+  }
+}
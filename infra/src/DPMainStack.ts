@@ -44,7 +44,8 @@ import { IcebergMaintenance } from "../lib/iceberg-maintenance";
 import { MatanoSQSSources } from "../lib/sqs-sources";
 import { Enrichment } from "../lib/enrichment";
 import { IntegrationsStore } from "../lib/integrations-store";
-import { ExternalLogPuller, PULLER_LOG_SOURCE_TYPES } from "../lib/log-puller";
+import { ExternalLogPuller, PULLER_LOG_SOURCE_TYPES, pullerExtensionTypes } from "../lib/log-puller";
+import { WebhookReceiver } from "../lib/webhook-receiver";
 
 interface DPMainStackProps extends MatanoStackProps {
   matanoSourcesBucket: S3BucketWithNotifications;
@@ -115,6 +116,15 @@ export class DPMainStack extends MatanoStack {
       const formattedName = matanoResourceToCdkName(logSource.logSourceLevelConfig.name!);
       (logSource.node.id as any) = `MatanoLogs${formattedName}`; // TODO(shaeq): fix this
       logSources.push(logSource);
+
+      // Optional zstd dictionary for the log source's pulled objects, shipped next to its config.
+      const zstdDictionaryPath = path.join(logSourceConfigPath, "zstd.dict");
+      if (fs.existsSync(zstdDictionaryPath)) {
+        this.addConfigFile(
+          `log_sources/${logSource.logSourceConfig.name}/zstd.dict`,
+          fs.readFileSync(zstdDictionaryPath)
+        );
+      }
     }
 
     // Not a log source but just use it to represent
@@ -311,17 +321,73 @@ export class DPMainStack extends MatanoStack {
       lakeStorageBucket: props.lakeStorageBucket.bucket,
     });
 
+    const pullerTypes = [...PULLER_LOG_SOURCE_TYPES, ...Object.keys(pullerExtensionTypes(this))];
+    const pullerLogSources = logSources.filter(
+      (ls) =>
+        (ls.managedLogSourceType != null && pullerTypes.includes(ls.managedLogSourceType)) ||
+        !!pullerTypes.find((s) => ls.name.startsWith(s))
+    );
     const externalLogPuller = new ExternalLogPuller(this, "ExternalLogPuller", {
-      logSources: logSources
-        .filter(
-          (ls) =>
-            (ls.managedLogSourceType != null && PULLER_LOG_SOURCE_TYPES.includes(ls.managedLogSourceType)) ||
-            !!PULLER_LOG_SOURCE_TYPES.find((s) => ls.name.startsWith(s))
-        )
+      logSources: pullerLogSources.map((ls) => ls.name),
+      tenants: Object.fromEntries(
+        pullerLogSources
+          .filter((ls) => ls.logSourceConfig?.managed?.tenants != null)
+          .map((ls) => [ls.name, ls.logSourceConfig!.managed!.tenants!.map((t) => t.id)])
+      ),
+      noSecretLogSources: pullerLogSources
+        .filter((ls) => ls.logSourceConfig?.managed?.secret === false)
         .map((ls) => ls.name),
       ingestionBucket: props.matanoSourcesBucket.bucket,
+      ingestionQueue: props.matanoSourcesBucket.queue,
     });
     externalLogPuller.function.addLayers(configLayer);
+    if (externalLogPuller.notifiesIngestionQueue) {
+      // Including the log sources pullers write to with `destination_log_source`.
+      const notifiedLogSources = pullerLogSources.flatMap((ls) => [
+        ls.name,
+        ...(ls.logSourceConfig?.managed?.properties?.destination_log_source != null
+          ? [ls.logSourceConfig!.managed!.properties.destination_log_source as string]
+          : []),
+      ]);
+      rawDataBatcher.batcherFunction.addEnvironment(
+        "DIRECTLY_NOTIFIED_LOG_SOURCES",
+        [...new Set(notifiedLogSources)].join(",")
+      );
+      // Objects failed over to the secondary bucket are ingested from there.
+      const failoverBucket = externalLogPuller.failoverBucket;
+      if (failoverBucket != null) {
+        for (const func of [rawDataBatcher.batcherFunction, transformer.transformerLambda]) {
+          func.addEnvironment("INGESTION_FAILOVER_BUCKET_NAME", failoverBucket.name);
+          if (failoverBucket.region != null) {
+            func.addEnvironment("INGESTION_FAILOVER_BUCKET_REGION", failoverBucket.region);
+          }
+        }
+        transformer.transformerLambda.addToRolePolicy(
+          new iam.PolicyStatement({
+            actions: ["s3:GetObject"],
+            resources: [`arn:aws:s3:::${failoverBucket.name}/*`],
+          })
+        );
+      }
+    }
+    externalLogPuller.scheduler?.function.addLayers(configLayer);
+
+    const webhookLogSources = logSources.filter((ls) => ls.logSourceConfig?.ingest?.webhook?.enabled === true);
+    if (webhookLogSources.length > 0) {
+      const webhookReceiver = new WebhookReceiver(this, "WebhookReceiver", {
+        logSources: webhookLogSources.map((ls) => ls.name),
+        ingestionBucket: props.matanoSourcesBucket.bucket,
+        verification: Object.fromEntries(
+          webhookLogSources
+            .filter((ls) => ls.logSourceConfig?.ingest?.webhook?.verification != null)
+            .map((ls) => [ls.name, ls.logSourceConfig!.ingest!.webhook!.verification!])
+        ),
+      });
+      this.humanCfnOutput("WebhookReceiverUrl", {
+        value: webhookReceiver.functionUrl.url,
+        description: "The base URL for webhook deliveries. Send events to <url>/<log_source_name>.",
+      });
+    }
 
     this.humanCfnOutput("AlertingSnsTopicArn", {
       value: matanoAlerting.alertingTopic.topicArn,
@@ -340,7 +406,7 @@ export class DPMainStack extends MatanoStack {
     return configTempDir;
   }
 
-  private addConfigFile(fileSubpath: string, content: string) {
+  private addConfigFile(fileSubpath: string, content: string | Buffer) {
     const filePath = path.join(this.configTempDir, "config", fileSubpath);
     fs.mkdirSync(path.dirname(filePath), { recursive: true });
     fs.writeFileSync(filePath, content);
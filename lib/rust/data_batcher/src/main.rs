@@ -18,6 +18,9 @@ use shared::{
 };
 use walkdir::WalkDir;
 
+/// The event source of the log puller's direct notifications of its objects.
+const LOG_PULLER_EVENT_SOURCE: &str = "matano:log_puller";
+
 lazy_static! {
     static ref AWS_CONFIG: AsyncOnce<aws_config::SdkConfig> =
         AsyncOnce::new(async { aws_config::load_from_env().await });
@@ -27,6 +30,15 @@ lazy_static! {
         AsyncOnce::new(async { aws_sdk_dynamodb::Client::new(AWS_CONFIG.get().await) });
     static ref LOG_SOURCE_KEY_PREFIX_MAP: HashMap<String, (Option<String>, Option<String>, Option<String>)> =
         build_log_source_key_prefix_map();
+    /// Log sources the log puller notifies of its objects directly, so their S3 event
+    /// notifications are identified by the object key, like the direct ones.
+    static ref DIRECTLY_NOTIFIED_LOG_SOURCES: HashSet<String> =
+        std::env::var("DIRECTLY_NOTIFIED_LOG_SOURCES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
 }
 
 #[tokio::main]
@@ -95,6 +107,9 @@ fn build_log_source_key_prefix_map(
 
 fn get_log_source_from_object(bucket: &str, key: &str) -> Option<String> {
     let managed_bucket = std::env::var("MATANO_SOURCES_BUCKET").ok()?;
+    // Objects the log puller failed over to its secondary bucket, see `DIRECTLY_NOTIFIED_LOG_SOURCES`.
+    let failover_bucket = std::env::var("INGESTION_FAILOVER_BUCKET_NAME").ok();
+    let is_managed_bucket = managed_bucket == bucket || failover_bucket.as_deref() == Some(bucket);
     LOG_SOURCE_KEY_PREFIX_MAP
         .iter()
         .find(|(_, (ls_bucket, ls_key_prefix, ls_key_pattern))| {
@@ -104,7 +119,7 @@ fn get_log_source_from_object(bucket: &str, key: &str) -> Option<String> {
             }
             let bucket_matches = ls_bucket
                 .as_ref()
-                .map_or(managed_bucket == bucket, |b| b == bucket);
+                .map_or(is_managed_bucket, |b| b == bucket);
             let ls_key_prefix = ls_key_prefix
                 .as_ref()
                 .map(|s| s.to_string())
@@ -122,7 +137,7 @@ fn get_log_source_from_object(bucket: &str, key: &str) -> Option<String> {
         .map(|(ls, _)| ls.to_owned())
         .or_else(|| {
             // try managed
-            if managed_bucket == bucket {
+            if is_managed_bucket {
                 key.split(std::path::MAIN_SEPARATOR).next().and_then(|ls| {
                     LOG_SOURCE_KEY_PREFIX_MAP
                         .contains_key(ls)
@@ -144,7 +159,20 @@ trait S3EventExt {
 }
 impl S3EventExt for S3Event {
     fn sequencer(&self) -> String {
-        self.records[0].s3.object.sequencer.clone().unwrap()
+        let record = &self.records[0];
+        let object = &record.s3.object;
+        // Objects the log puller notified of directly have an event from both, only the first
+        // is ingested. Objects it didn't (e.g. replicated from its failover bucket) still are.
+        if record.event_source.as_deref() == Some("aws:s3") {
+            let bucket = record.s3.bucket.name.as_deref().unwrap_or_default();
+            let key = object.key.as_deref().unwrap_or_default();
+            let is_directly_notified = get_log_source_from_object(bucket, key)
+                .map_or(false, |ls| DIRECTLY_NOTIFIED_LOG_SOURCES.contains(&ls));
+            if is_directly_notified {
+                return format!("{}:{}", LOG_PULLER_EVENT_SOURCE, key);
+            }
+        }
+        object.sequencer.clone().unwrap()
     }
 }
 
@@ -0,0 +1,195 @@
+//! Runs a puller locally, for developing and debugging pullers without deploying. Built with
+//! the `cli` feature, e.g.
+//!
+//! ```sh
+//! cargo run --features cli -- pull \
+//!     --config ./log_sources/okta \
+//!     --start 2023-04-01T00:00:00Z --end 2023-04-01T01:00:00Z \
+//!     --secret api_token=... \
+//!     --output okta.ndjson
+//! ```
+//!
+//! The window is pulled with the log source's `log_source.yml` properties, and its normalized
+//! records are written to `--output`. Nothing is uploaded, and no state (last pulled time,
+//! dedup, circuit breaker) is read or written. A puller's checkpoint can be kept in a local
+//! file with `--checkpoint`, which is read before the pull and updated after it.
+//!
+//! With `--redirect <url>`, every request is sent to that server instead of the vendor,
+//! keeping its path and query, e.g. to replay recorded responses from a mock server.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset};
+use tracing::info;
+
+use crate::pullers::{self, PullLogs};
+use crate::{build_log_source_contexts, env_json, load_managed_log_source, S3_CLIENT};
+
+const USAGE: &str = "Usage: log_puller pull --config <log source dir> --start <RFC 3339 time> \
+--end <RFC 3339 time> [--tenant <id>] [--secrets <JSON file>] [--secret <field>=<value>]... \
+[--checkpoint <JSON file>] [--redirect <URL>] [--output <file>]";
+
+/// The options of a local pull.
+#[derive(Debug)]
+struct PullArgs {
+    /// The log source directory, with its `log_source.yml`.
+    config: PathBuf,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+    tenant_id: Option<String>,
+    secrets: HashMap<String, String>,
+    checkpoint: Option<PathBuf>,
+    redirect_url: Option<reqwest::Url>,
+    /// Defaults to `<log source>.ndjson`.
+    output: Option<PathBuf>,
+}
+
+impl PullArgs {
+    fn parse(args: &[String]) -> Result<PullArgs> {
+        let mut config = None;
+        let mut start_dt = None;
+        let mut end_dt = None;
+        let mut tenant_id = None;
+        let mut secrets = HashMap::new();
+        let mut checkpoint = None;
+        let mut redirect_url = None;
+        let mut output = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .with_context(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--config" => config = Some(PathBuf::from(value()?)),
+                "--start" => start_dt = Some(parse_time("--start", &value()?)?),
+                "--end" => end_dt = Some(parse_time("--end", &value()?)?),
+                "--tenant" => tenant_id = Some(value()?),
+                "--secrets" => {
+                    let path = value()?;
+                    let data = std::fs::read(&path)
+                        .with_context(|| format!("Failed to read secrets from {}", path))?;
+                    let fields: HashMap<String, String> = serde_json::from_slice(&data)
+                        .with_context(|| format!("{} must be a JSON object of strings", path))?;
+                    secrets.extend(fields);
+                }
+                "--secret" => {
+                    let field = value()?;
+                    let (key, value) = field
+                        .split_once('=')
+                        .with_context(|| format!("--secret must be <field>=<value>: {}", field))?;
+                    secrets.insert(key.to_string(), value.to_string());
+                }
+                "--checkpoint" => checkpoint = Some(PathBuf::from(value()?)),
+                "--redirect" => {
+                    let url = value()?;
+                    redirect_url = Some(
+                        reqwest::Url::parse(&url)
+                            .with_context(|| format!("--redirect must be a URL: {}", url))?,
+                    );
+                }
+                "--output" => output = Some(PathBuf::from(value()?)),
+                _ => return Err(anyhow!("Unknown argument: {}\n{}", arg, USAGE)),
+            }
+        }
+
+        Ok(PullArgs {
+            config: config.with_context(|| format!("Missing --config\n{}", USAGE))?,
+            start_dt: start_dt.with_context(|| format!("Missing --start\n{}", USAGE))?,
+            end_dt: end_dt.with_context(|| format!("Missing --end\n{}", USAGE))?,
+            tenant_id,
+            secrets,
+            checkpoint,
+            redirect_url,
+            output,
+        })
+    }
+}
+
+fn parse_time(arg: &str, value: &str) -> Result<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value)
+        .with_context(|| format!("{} must be an RFC 3339 time: {}", arg, value))
+}
+
+/// Runs the command in `args` (without the program name).
+pub async fn run(args: Vec<String>) -> Result<()> {
+    match args.split_first() {
+        Some((command, args)) if command == "pull" => pull(PullArgs::parse(args)?).await,
+        _ => Err(anyhow!(USAGE)),
+    }
+}
+
+async fn pull(args: PullArgs) -> Result<()> {
+    // Other files of the log source (e.g. a zstd dictionary) are looked up next to it.
+    if std::env::var("LOG_SOURCES_CONFIG_DIR").is_err() {
+        if let Some(dir) = args.config.parent() {
+            std::env::set_var("LOG_SOURCES_CONFIG_DIR", dir);
+        }
+    }
+    let puller_log_source_types: Vec<String> =
+        env_json("PULLER_LOG_SOURCE_TYPES").unwrap_or_default();
+    let managed = load_managed_log_source(&args.config, &puller_log_source_types)?
+        .with_context(|| format!("{} isn't a pulled log source", args.config.display()))?;
+
+    let s3 = S3_CLIENT.get().await;
+    let ctx = build_log_source_contexts(&managed, &HashMap::new(), s3)
+        .into_iter()
+        .find(|ctx| ctx.tenant_id == args.tenant_id)
+        .with_context(|| match args.tenant_id.as_ref() {
+            Some(tenant_id) => format!("No tenant {} in {}", tenant_id, managed.name),
+            None => format!("{} has tenants, pick one with --tenant", managed.name),
+        })?
+        .with_local_secrets(args.secrets);
+    let ctx = match args.redirect_url {
+        Some(redirect_url) => ctx.with_redirect_url(redirect_url),
+        None => ctx,
+    };
+
+    if let Some(path) = args.checkpoint.as_ref().filter(|p| p.exists()) {
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read checkpoint {}", path.display()))?;
+        *ctx.checkpoint_json.lock().await = Some(serde_json::from_slice(&data)?);
+    }
+
+    info!(
+        "Pulling log_source: {} from {} to {}",
+        ctx.log_source_name, args.start_dt, args.end_dt
+    );
+    let puller = ctx.log_source_type.clone();
+    let client = ctx.http_client().await?;
+    let chunks = puller.pull_log_chunks(client, &ctx, args.start_dt, args.end_dt);
+    let data = ctx.normalize_records(pullers::collect_chunks(chunks).await?);
+
+    let output = args
+        .output
+        .unwrap_or_else(|| PathBuf::from(format!("{}.ndjson", ctx.log_source_name)));
+    std::fs::write(&output, &data)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    let records = data
+        .split(|b| *b == b'\n')
+        .filter(|l| !l.is_empty())
+        .count();
+    info!(
+        "Wrote {} records ({} bytes) to {}",
+        records,
+        data.len(),
+        output.display()
+    );
+
+    let checkpoint_json = ctx.checkpoint_json.lock().await.clone();
+    if let Some(checkpoint_json) = checkpoint_json {
+        info!("Checkpoint after pull: {}", checkpoint_json);
+        if let Some(path) = args.checkpoint.as_ref() {
+            std::fs::write(path, serde_json::to_vec_pretty(&checkpoint_json)?)
+                .with_context(|| format!("Failed to write checkpoint {}", path.display()))?;
+        }
+    }
+    if ctx.take_continuation_request() {
+        info!("The puller stopped early, run again with --checkpoint to continue");
+    }
+    Ok(())
+}
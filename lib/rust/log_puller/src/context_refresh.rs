@@ -0,0 +1,72 @@
+//! Rebuilds the puller contexts of warm Lambdas on request, so rotated credentials and edited
+//! configs take effect without waiting for cold starts. A refresh is requested by invoking the
+//! puller directly, which updates a marker object in the ingestion bucket. Every Lambda checks
+//! the marker at most every `PULLER_CONFIG_REFRESH_SECONDS` (default 60), and when it changed,
+//! rebuilds its contexts, dropping their access tokens, and reloads secrets bypassing caches.
+//!
+//! ex:
+//! ```bash
+//! aws lambda invoke --function-name <puller> --cli-binary-format raw-in-base64-out \
+//!   --payload '{"refresh_contexts": true}' /dev/stdout
+//! ```
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use aws_sdk_s3::types::ByteStream;
+use serde_json::json;
+
+const REFRESH_MARKER_KEY: &str = "__puller_state__/_refresh_contexts.json";
+const DEFAULT_CHECK_SECONDS: u64 = 60;
+
+/// How long contexts are used before the refresh marker (and configs from S3) are checked.
+pub(crate) fn check_interval() -> Duration {
+    let seconds = std::env::var("PULLER_CONFIG_REFRESH_SECONDS")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_CHECK_SECONDS);
+    Duration::from_secs(seconds)
+}
+
+/// The ETag of the refresh marker, None if no refresh was ever requested or there's no
+/// ingestion bucket (e.g. local runs).
+pub(crate) async fn marker_version(s3: &aws_sdk_s3::Client) -> Result<Option<String>> {
+    let bucket = match std::env::var("INGESTION_BUCKET_NAME") {
+        Ok(bucket) => bucket,
+        Err(_) => return Ok(None),
+    };
+    let res = s3
+        .head_object()
+        .bucket(bucket)
+        .key(REFRESH_MARKER_KEY)
+        .send()
+        .await;
+    match res {
+        Ok(output) => Ok(output.e_tag().map(|s| s.to_string())),
+        Err(e) => {
+            let se = e.into_service_error();
+            match se.kind {
+                aws_sdk_s3::error::HeadObjectErrorKind::NotFound(_) => Ok(None),
+                _ => Err(se).context("Failed to check context refresh marker"),
+            }
+        }
+    }
+}
+
+/// Updates the refresh marker, so every Lambda refreshes its contexts on its next check.
+pub(crate) async fn request_refresh(s3: &aws_sdk_s3::Client) -> Result<()> {
+    let bucket = std::env::var("INGESTION_BUCKET_NAME")?;
+    let marker = json!({
+        "requested_at": chrono::Utc::now().to_rfc3339(),
+        "id": uuid::Uuid::new_v4().to_string(),
+    });
+    s3.put_object()
+        .bucket(bucket)
+        .key(REFRESH_MARKER_KEY)
+        .body(ByteStream::from(serde_json::to_vec(&marker)?))
+        .content_type("application/json")
+        .send()
+        .await
+        .context("Failed to update context refresh marker")?;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Notifies ingestion of uploaded objects directly, ahead of the ingestion bucket's S3 event
+//! notifications. With `INGESTION_QUEUE_URL` set, each object uploaded to the ingestion bucket,
+//! or to the failover bucket (see `upload_failover`), is sent to the ingestion queue as an S3
+//! event, once it's uploaded, so it's ingested without the notification delay. The failover
+//! bucket has no notifications of its own, so its objects are only ingested this way.
+//!
+//! The notification is identified by the object key, and the data batcher identifies the
+//! bucket's notifications for the pulled log sources the same way, so an object is ingested
+//! once, by whichever comes first. Objects whose direct notification couldn't be sent are still
+//! ingested by the ingestion bucket's notification.
+//!
+//! ex:
+//! ```yaml
+//! log_puller:
+//!   notify_ingestion_queue: true
+//! ```
+
+use anyhow::{Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use lazy_static::lazy_static;
+use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::upload_failover::FailoverBucket;
+
+/// The event source of notifications, the bucket's notifications are `aws:s3`.
+const EVENT_SOURCE: &str = "matano:log_puller";
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref SQS_CLIENT: AsyncOnce<aws_sdk_sqs::Client> =
+        AsyncOnce::new(async { aws_sdk_sqs::Client::new(AWS_CONFIG.get().await) });
+}
+
+/// Sends the notification for an object uploaded to the bucket, if it's the ingestion or
+/// failover bucket and the ingestion queue is set. Failures are only logged, the bucket's notification follows.
+pub(crate) async fn notify_uploaded(bucket: &str, key: &str, size: usize) {
+    if let Err(e) = send_notification(bucket, key, size).await {
+        warn!("{:#}", e);
+    }
+}
+
+async fn send_notification(bucket: &str, key: &str, size: usize) -> Result<()> {
+    let queue_url = match std::env::var("INGESTION_QUEUE_URL") {
+        Ok(url) if !url.trim().is_empty() => url,
+        _ => return Ok(()),
+    };
+    let region = if std::env::var("INGESTION_BUCKET_NAME").ok().as_deref() == Some(bucket) {
+        std::env::var("AWS_REGION").unwrap_or_default()
+    } else {
+        match FailoverBucket::from_env() {
+            Some(failover) if failover.bucket == bucket => failover
+                .region
+                .or_else(|| std::env::var("AWS_REGION").ok())
+                .unwrap_or_default(),
+            _ => return Ok(()),
+        }
+    };
+    let key = encode_key(key);
+    let event = json!({
+        "Records": [{
+            "eventVersion": "2.1",
+            "eventSource": EVENT_SOURCE,
+            "awsRegion": region,
+            "eventTime": chrono::Utc::now().to_rfc3339(),
+            "eventName": "ObjectCreated:Put",
+            "userIdentity": {},
+            "requestParameters": {},
+            "responseElements": {},
+            "s3": {
+                "s3SchemaVersion": "1.0",
+                "bucket": {
+                    "name": bucket,
+                    "ownerIdentity": {},
+                    "arn": format!("arn:aws:s3:::{}", bucket),
+                },
+                "object": {
+                    "key": key,
+                    "size": size,
+                    // Ingestion drops events with a sequencer it has seen, the bucket's
+                    // notification of the object has the same one.
+                    "sequencer": format!("{}:{}", EVENT_SOURCE, key),
+                },
+            },
+        }],
+    });
+    SQS_CLIENT
+        .get()
+        .await
+        .send_message()
+        .queue_url(queue_url)
+        .message_body(event.to_string())
+        .send()
+        .await
+        .with_context(|| format!("Error notifying ingestion of {}", key))?;
+    debug!("Notified ingestion of s3://{}/{}", bucket, key);
+    Ok(())
+}
+
+/// Keys in S3 events are URL encoded, except for the path separators.
+fn encode_key(key: &str) -> String {
+    key.split('/')
+        .map(|segment| url::form_urlencoded::byte_serialize(segment.as_bytes()).collect())
+        .collect::<Vec<String>>()
+        .join("/")
+}
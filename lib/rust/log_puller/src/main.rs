@@ -1,51 +1,250 @@
 use std::collections::HashMap;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::{anyhow, Context as AnyhowContext, Error, Result};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
 use async_once::AsyncOnce;
 use aws_config::SdkConfig;
 use aws_lambda_events::event::sqs::SqsEvent;
+use aws_sdk_s3::model::{
+    ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart, ServerSideEncryption,
+};
 use aws_sdk_s3::types::ByteStream;
-use chrono::{DateTime, Duration, DurationRound};
-use futures::future::join_all;
-use futures::stream::FuturesOrdered;
+use bytes::Bytes;
+use chrono::{DateTime, Duration, DurationRound, FixedOffset};
+use futures::future::{join_all, Either};
 use futures::{FutureExt, TryFutureExt};
 use futures_util::stream::StreamExt;
 use lambda_runtime::{run, service_fn, Error as LambdaError, LambdaEvent};
 use lazy_static::lazy_static;
-use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use shared::object_key::object_key;
+use shared::setup_tracing;
 use shared::sqs_util::*;
-use shared::{setup_logging, LOG_SOURCES_CONFIG};
+use shared::zstd_dictionary::ZstdDictionary;
+use tokio::sync::Semaphore;
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
 use walkdir::WalkDir;
 
+#[cfg(feature = "cli")]
+mod cli;
+mod context_refresh;
+mod ingestion_notify;
 mod pullers;
-use pullers::{LogSource, PullLogs, PullLogsContext};
+mod remote_config;
+mod shutdown;
+mod upload_failover;
+use pullers::{
+    validate_properties, LogSource, PreTransform, PullLogs, PullLogsContext, PullOutcome,
+    PullerError, RateLimitStats, RetryAction, RetryPolicy, VaultSecrets, DELAYED_RETRY_SECONDS,
+};
+use remote_config::RemoteConfig;
+use upload_failover::{FailoverBucket, SpooledObject};
+
+/// Object keys for pulled data, see `object_key`. Set per log source with `s3_key_template`.
+const DEFAULT_S3_KEY_TEMPLATE: &str = shared::object_key::DEFAULT_KEY_TEMPLATE;
+const DEFAULT_PARQUET_S3_KEY_TEMPLATE: &str = "{log_source}/ts_hour={ts_hour}/{uuid}.parquet";
+/// Larger pulls are split into multiple objects, so transformers stay within their memory.
+const DEFAULT_MAX_OBJECT_SIZE_MB: usize = 128;
+/// Compressed data is uploaded in parts of this size (S3 requires at least 5MB per part).
+const MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
+/// Data is compressed in chunks of this size, checking after each if a part is ready.
+const COMPRESS_CHUNK_SIZE: usize = 1024 * 1024;
+/// Missed windows older than this aren't caught up on, unless `max_catch_up_hours` is set.
+const DEFAULT_MAX_CATCH_UP_HOURS: i64 = 24;
+/// Gaps up to this long are pulled along with the current window, longer ones are enqueued
+/// as separate catch up pulls, unless `catch_up_threshold_minutes` is set.
+const DEFAULT_CATCH_UP_THRESHOLD_MINUTES: i64 = 60;
+/// Memory budget per pull if neither `memory_budget_mb` nor the function's memory size is set.
+const DEFAULT_MEMORY_BUDGET_MB: usize = 256;
+/// Records logged by a dry run, see `dry_run_pull`.
+const DRY_RUN_SAMPLE_SIZE: usize = 5;
+/// Log source configs of the configuration layer.
+const LAYER_LOG_SOURCES_DIR: &str = "/opt/config/log_sources";
+/// Messages of a batch processed at once, unless `PULLER_MAX_CONCURRENT_MESSAGES` is set.
+const DEFAULT_MAX_CONCURRENT_MESSAGES: usize = 4;
+/// Rate limited pulls are requeued at most this many times before they're retried (and
+/// eventually dead lettered) as other failures, see `requeue_rate_limited`.
+const MAX_RATE_LIMIT_REQUEUES: u32 = 10;
+/// SQS delays messages by at most 15 minutes.
+const MAX_REQUEUE_DELAY_SECONDS: i32 = 900;
 
 #[global_allocator]
 static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 lazy_static! {
-    static ref REQ_CLIENT: reqwest::Client = reqwest::Client::new();
-    static ref CONTEXTS: AsyncOnce<HashMap<String, PullLogsContext>> =
-        AsyncOnce::new(async { build_contexts().await });
+    static ref CONTEXTS: tokio::sync::Mutex<Option<LoadedContexts>> =
+        tokio::sync::Mutex::new(None);
+    /// Set to read log source configs from S3 instead of the layer, see `remote_config`.
+    static ref REMOTE_CONFIG: Option<RemoteConfig> = RemoteConfig::from_env().unwrap_or_else(|e| {
+        error!("{:#}", e);
+        None
+    });
     static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
         AsyncOnce::new(async { aws_config::load_from_env().await });
     static ref S3_CLIENT: AsyncOnce<aws_sdk_s3::Client> =
-        AsyncOnce::new(async { aws_sdk_s3::Client::new(AWS_CONFIG.get().await) });
+        AsyncOnce::new(async { s3_client(AWS_CONFIG.get().await, None) });
+    /// Clients for destination buckets in other regions, by region.
+    static ref REGIONAL_S3_CLIENTS: std::sync::Mutex<HashMap<String, aws_sdk_s3::Client>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+/// The contexts of the log sources being served.
+struct LoadedContexts {
+    contexts: Arc<HashMap<String, Vec<PullLogsContext>>>,
+    /// The directory the configs were read from.
+    config_dir: PathBuf,
+    /// Version of the configs synced from S3, None if they're from the layer.
+    config_version: Option<String>,
+    /// Version of the refresh marker when the contexts were built, see `context_refresh`.
+    refresh_marker: Option<String>,
+    /// When the configs were last synced from S3 (or checked to be unchanged).
+    checked_at: std::time::Instant,
+}
+
+/// The contexts to pull with, built on first use. With configs from S3, they're rebuilt when
+/// the configs changed, checked at most every refresh interval. If S3 can't be read, the
+/// current contexts are kept (or the layer's configs are used, before the first sync).
+///
+/// They're also rebuilt, reloading their secrets, when a refresh was requested, see
+/// `context_refresh`.
+async fn current_contexts() -> Arc<HashMap<String, Vec<PullLogsContext>>> {
+    load_contexts(false).await
+}
+
+/// See `current_contexts`, `check` checks for changes regardless of the refresh interval.
+async fn load_contexts(check: bool) -> Arc<HashMap<String, Vec<PullLogsContext>>> {
+    let mut loaded = CONTEXTS.lock().await;
+    if let Some(current) = loaded.as_ref().filter(|_| !check) {
+        let refresh_interval = REMOTE_CONFIG
+            .as_ref()
+            .map_or_else(context_refresh::check_interval, |remote| {
+                remote.refresh_interval
+            });
+        if current.checked_at.elapsed() < refresh_interval {
+            return current.contexts.clone();
+        }
+    }
+
+    let current_marker = loaded.as_ref().and_then(|l| l.refresh_marker.clone());
+    let refresh_marker = context_refresh::marker_version(S3_CLIENT.get().await)
+        .await
+        .unwrap_or_else(|e| {
+            error!("{:#}", e);
+            current_marker.clone()
+        });
+    let refresh_requested = loaded.is_some() && refresh_marker != current_marker;
+
+    let current_version = loaded.as_ref().and_then(|l| l.config_version.clone());
+    let synced = match REMOTE_CONFIG.as_ref() {
+        Some(remote) => remote
+            .sync(S3_CLIENT.get().await, current_version.as_deref())
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to sync log source configs from S3: {:#}", e);
+                None
+            }),
+        None => None,
+    };
+    let (config_dir, config_version) = match (synced, loaded.as_mut()) {
+        (Some(synced), _) => (synced.dir, Some(synced.version)),
+        (None, Some(current)) if refresh_requested => {
+            (current.config_dir.clone(), current.config_version.clone())
+        }
+        (None, Some(current)) => {
+            current.checked_at = std::time::Instant::now();
+            return current.contexts.clone();
+        }
+        (None, None) => (PathBuf::from(LAYER_LOG_SOURCES_DIR), None),
+    };
+
+    let contexts = Arc::new(build_contexts(&config_dir).await);
+    if refresh_requested {
+        // Secrets may have been rotated within the shared secret cache's lifetime.
+        for ctx in contexts.values().flatten() {
+            ctx.clear_secret_cache().await;
+        }
+        info!("Refreshed contexts as requested");
+    }
+    info!(
+        "Loaded {} log sources from {} (config version: {})",
+        contexts.len(),
+        config_dir.display(),
+        config_version.as_deref().unwrap_or("layer")
+    );
+    *loaded = Some(LoadedContexts {
+        contexts: contexts.clone(),
+        config_dir,
+        config_version,
+        refresh_marker,
+        checked_at: std::time::Instant::now(),
+    });
+    contexts
+}
+
+/// Requests a refresh of every Lambda's contexts, refreshing this one's right away, see
+/// `context_refresh`.
+async fn refresh_contexts() -> Result<RefreshContextsResponse> {
+    context_refresh::request_refresh(S3_CLIENT.get().await).await?;
+    let contexts = load_contexts(true).await;
+    Ok(RefreshContextsResponse {
+        log_sources: contexts.len(),
+    })
 }
 
-async fn build_contexts() -> HashMap<String, PullLogsContext> {
-    let puller_log_source_types: Vec<String> =
-        serde_json::from_str(&std::env::var("PULLER_LOG_SOURCE_TYPES").unwrap()).unwrap();
+/// Builds the puller contexts for each log source in `config_dir`, one per tenant if
+/// `managed.tenants` is set.
+/// Log sources whose config fails to load are logged and skipped.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: okta
+///   properties:
+///     # shared by all tenants
+///   tenants:
+///     - id: org1
+///       properties:
+///         base_url: https://org1.okta.com
+/// ```
+///
+/// Log sources that need no credentials (e.g. public feeds) can opt out of their secret.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: custom_api
+///   secret: false
+/// ```
+///
+/// A log source can be paused without removing its config, requests for it are then
+/// acknowledged without pulling.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: okta
+///   enabled: false
+/// ```
+async fn build_contexts(config_dir: &Path) -> HashMap<String, Vec<PullLogsContext>> {
+    let puller_log_source_types: Arc<Vec<String>> =
+        Arc::new(env_json("PULLER_LOG_SOURCE_TYPES").unwrap_or_else(|e| {
+            error!("{:#}", e);
+            vec![]
+        }));
     let log_source_to_secret_arn_map: HashMap<String, String> =
-        serde_json::from_str(&std::env::var("LOG_SOURCE_TO_SECRET_ARN_MAP").unwrap()).unwrap();
+        env_json("LOG_SOURCE_TO_SECRET_ARN_MAP").unwrap_or_else(|e| {
+            error!("{:#}", e);
+            HashMap::new()
+        });
 
     let s3 = S3_CLIENT.get().await;
 
-    let ret = WalkDir::new("/opt/config/log_sources")
+    // Configs are read and parsed on blocking threads, one per log source, so a cold start
+    // doesn't wait on them one by one.
+    let loads = WalkDir::new(config_dir)
         .min_depth(1)
         .max_depth(1)
         .into_iter()
@@ -54,98 +253,409 @@ async fn build_contexts() -> HashMap<String, PullLogsContext> {
             let p = e.path().to_owned();
             p.is_dir().then_some(p)
         })
-        .flat_map(|log_source_dir_path| {
-            let ls_config_path = log_source_dir_path.join("log_source.yml");
-            let ls_config_path = ls_config_path.as_path().to_str().unwrap();
-
-            let file = std::fs::File::open(ls_config_path).unwrap();
-            let config: serde_yaml::Value = serde_yaml::from_reader(file).unwrap();
-
-            let ls_name = config
-                .get("name")
-                .and_then(|v| v.as_str())
-                .map(|s| s.to_string());
-            let managed_type = config
-                .get("managed")
-                .and_then(|v| v.get("type"))
-                .and_then(|v| v.as_str())
-                .map(|s| s.to_string().to_lowercase());
-
-            // Either regular managed log source or managed enrichment table.
-            let managed_type = managed_type.or_else(|| {
-                ls_name
-                    .as_ref()
-                    .and_then(|lsn| {
-                        puller_log_source_types
-                            .iter()
-                            .find(|s| lsn.starts_with(s.as_str()))
-                    })
-                    .map(|s| s.trim_start_matches("enrich_").to_string())
-            });
+        .map(|log_source_dir_path| {
+            let puller_log_source_types = puller_log_source_types.clone();
+            tokio::task::spawn_blocking(move || {
+                let res = load_managed_log_source(&log_source_dir_path, &puller_log_source_types);
+                (log_source_dir_path, res)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut ret = HashMap::new();
+    // One bad config shouldn't stop the other log sources from being pulled, they're skipped
+    // and reported together.
+    let mut skipped = vec![];
+    for res in join_all(loads).await {
+        let (log_source_dir_path, res) = match res {
+            Ok(v) => v,
+            Err(e) => {
+                skipped.push(json!({ "path": null, "error": e.to_string() }));
+                continue;
+            }
+        };
+        match res {
+            // Requests for disabled log sources are acknowledged without pulling.
+            Ok(Some(managed)) if !managed.enabled => {
+                info!("Log source: {} is disabled", managed.name);
+                ret.insert(managed.name, vec![]);
+            }
+            Ok(Some(managed)) => {
+                let ctxs = build_log_source_contexts(&managed, &log_source_to_secret_arn_map, s3);
+                ret.insert(managed.name, ctxs);
+            }
+            Ok(None) => (),
+            Err(e) => skipped.push(json!({
+                "path": log_source_dir_path.display().to_string(),
+                "error": format!("{:#}", e),
+            })),
+        }
+    }
+    if !skipped.is_empty() {
+        let summary = skipped
+            .iter()
+            .map(|s| {
+                let path = s["path"].as_str().unwrap_or("?");
+                format!("{}: {}", path, s["error"].as_str().unwrap_or_default())
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        let count = skipped.len();
+        error!(
+            skipped_log_sources = %serde_json::Value::Array(skipped),
+            "Skipped {} invalid log source configs, serving {} log sources: {}",
+            count,
+            ret.len(),
+            summary
+        );
+    }
+    ret
+}
 
-            let managed_properties = config
-                .get("managed")
-                .and_then(|v| v.get("properties"))
-                .and_then(|v| v.as_mapping())
-                .map(|v| v.to_owned())
-                .unwrap_or(serde_yaml::Mapping::new());
+fn env_json<T: serde::de::DeserializeOwned>(name: &str) -> Result<T> {
+    let value = std::env::var(name).with_context(|| format!("Missing {}", name))?;
+    serde_json::from_str(&value).with_context(|| format!("Invalid {}", name))
+}
 
-            let log_source = managed_type
-                .as_ref()
-                .and_then(|lsn| LogSource::from_str(lsn));
+/// The parts of a `log_source.yml` the puller reads.
+#[derive(Deserialize, Debug)]
+struct LogSourceFile {
+    name: String,
+    #[serde(default)]
+    managed: Option<ManagedConfig>,
+}
 
-            debug!(
-                "Processed: log source name: {:?}, type: {:?}, is_log_source: {:?}",
-                ls_name,
-                managed_type,
-                log_source.is_some()
-            );
+#[derive(Deserialize, Debug, Default)]
+struct ManagedConfig {
+    #[serde(rename = "type")]
+    managed_type: Option<String>,
+    #[serde(default)]
+    properties: Properties,
+    #[serde(default)]
+    tenants: Vec<TenantConfig>,
+    /// False for log sources without credentials, e.g. public feeds.
+    secret: Option<bool>,
+    /// False to pause pulling, e.g. a noisy log source, without removing its config.
+    enabled: Option<bool>,
+}
 
-            if managed_type == Some("okta".to_string())
-                && !managed_properties.contains_key("base_url")
-            {
-                return None;
+#[derive(Deserialize, Debug)]
+struct TenantConfig {
+    id: String,
+    #[serde(default)]
+    properties: Properties,
+}
+
+/// Properties by name, empty ones are ignored.
+type Properties = HashMap<String, Option<PropertyValue>>;
+
+/// A property, which may be written as a string, number or boolean.
+#[derive(Debug)]
+struct PropertyValue(String);
+
+impl<'de> Deserialize<'de> for PropertyValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PropertyVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for PropertyVisitor {
+            type Value = PropertyValue;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a string, number or boolean")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<PropertyValue, E> {
+                Ok(PropertyValue(v.to_string()))
+            }
+
+            fn visit_bool<E>(self, v: bool) -> Result<PropertyValue, E> {
+                Ok(PropertyValue(v.to_string()))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<PropertyValue, E> {
+                Ok(PropertyValue(v.to_string()))
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<PropertyValue, E> {
+                Ok(PropertyValue(v.to_string()))
+            }
+
+            fn visit_f64<E>(self, v: f64) -> Result<PropertyValue, E> {
+                Ok(PropertyValue(v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(PropertyVisitor)
+    }
+}
+
+/// The non empty properties, with environment variables substituted (see `substitute_env`).
+/// `path` names the properties in errors, e.g. `managed.properties`.
+fn property_strings(properties: Properties, path: &str) -> Result<HashMap<String, String>> {
+    properties
+        .into_iter()
+        .filter_map(|(k, v)| Some((k, v?.0)))
+        .map(|(k, v)| {
+            let v = substitute_env(&v).with_context(|| format!("{}.{}", path, k))?;
+            Ok((k, v))
+        })
+        .collect()
+}
+
+/// Substitutes `${NAME}` in a property with the environment variable `NAME`, so one config
+/// can be shared by accounts (e.g. dev and prod). `${NAME:-default}` falls back to `default`
+/// if the variable is unset or empty, and `$${` is a literal `${`.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: okta
+///   properties:
+///     base_url: https://${OKTA_DOMAIN}
+///     batch_size: ${OKTA_BATCH_SIZE:-1000}
+/// ```
+fn substitute_env(value: &str) -> Result<String> {
+    let mut ret = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        ret.push_str(&rest[..start]);
+        let after = &rest[start..];
+        if let Some(escaped) = after.strip_prefix("$${") {
+            ret.push_str("${");
+            rest = escaped;
+        } else if let Some(template) = after.strip_prefix("${") {
+            let end = template
+                .find('}')
+                .with_context(|| format!("Unclosed ${{ in: {}", value))?;
+            let (name, default) = match template[..end].split_once(":-") {
+                Some((name, default)) => (name.trim(), Some(default)),
+                None => (template[..end].trim(), None),
+            };
+            let var = std::env::var(name).ok().filter(|v| !v.is_empty());
+            match (var, default) {
+                (Some(var), _) => ret.push_str(&var),
+                (None, Some(default)) => ret.push_str(default),
+                (None, None) => return Err(anyhow!("Missing environment variable {}", name)),
             }
+            rest = &template[end + 1..];
+        } else {
+            ret.push('$');
+            rest = &after[1..];
+        }
+    }
+    ret.push_str(rest);
+    Ok(ret)
+}
+
+/// The config of a managed log source, see `load_managed_log_source`.
+struct ManagedLogSource {
+    name: String,
+    log_source: LogSource,
+    properties: HashMap<String, String>,
+    /// Tenant IDs with their properties, which override the shared ones.
+    tenants: Vec<(String, HashMap<String, String>)>,
+    has_secret: bool,
+    enabled: bool,
+    tables_config: HashMap<String, config::Config>,
+}
+
+/// Reads the `log_source.yml` and tables of a log source, None if it isn't pulled. Invalid
+/// configs fail with the file and field at fault, e.g. "Invalid .../okta/log_source.yml:
+/// managed.tenants[0]: missing field `id` at line 5 column 7".
+fn load_managed_log_source(
+    log_source_dir_path: &std::path::Path,
+    puller_log_source_types: &[String],
+) -> Result<Option<ManagedLogSource>> {
+    let ls_config_path = log_source_dir_path.join("log_source.yml");
+    let file = std::fs::File::open(&ls_config_path)
+        .with_context(|| format!("Failed to open {}", ls_config_path.display()))?;
+    let config: LogSourceFile = serde_yaml::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("Invalid {}", ls_config_path.display()))?;
+
+    let ls_name = config.name;
+    let managed = config.managed.unwrap_or_default();
+
+    // Either regular managed log source or managed enrichment table.
+    let managed_type = managed.managed_type.map(|t| t.to_lowercase()).or_else(|| {
+        puller_log_source_types
+            .iter()
+            .find(|s| ls_name.starts_with(s.as_str()))
+            .map(|s| s.trim_start_matches("enrich_").to_string())
+    });
+
+    let log_source = managed_type
+        .as_ref()
+        .and_then(|lsn| LogSource::from_str(lsn));
+
+    debug!(
+        "Processed: log source name: {:?}, type: {:?}, is_log_source: {:?}",
+        ls_name,
+        managed_type,
+        log_source.is_some()
+    );
+
+    let mut properties = property_strings(managed.properties, "managed.properties")
+        .with_context(|| format!("Invalid {}", ls_config_path.display()))?;
 
-            Some((ls_name?, log_source?, managed_type?, managed_properties))
+    let (log_source, managed_type) = match (log_source, managed_type) {
+        (Some(log_source), Some(managed_type)) => (log_source, managed_type),
+        _ => return Ok(None),
+    };
+    properties.insert("log_source_type".to_string(), managed_type.clone());
+
+    let has_secret = managed.secret != Some(false);
+    let managed_enabled = managed.enabled != Some(false);
+    let tenants = managed
+        .tenants
+        .into_iter()
+        .enumerate()
+        .map(|(i, tenant)| {
+            let path = format!("managed.tenants[{}].properties", i);
+            let properties = property_strings(tenant.properties, &path)
+                .with_context(|| format!("Invalid {}", ls_config_path.display()))?;
+            Ok((tenant.id, properties))
         })
-        .map(|(ls_name, log_source, managed_type, managed_properties)| {
-            let mut props = managed_properties
-                .into_iter()
-                .filter_map(|(k, v)| Some((k.as_str()?.to_string(), v.as_str()?.to_string())))
-                .collect::<HashMap<_, _>>();
-            props.insert("log_source_type".to_string(), managed_type);
-
-            let tables_config = LOG_SOURCES_CONFIG.with(|c| {
-                let log_sources_config = c.borrow();
-
-                (*log_sources_config)
-                    .get(&ls_name)
-                    .unwrap()
-                    .tables
-                    .to_owned()
-            });
+        .collect::<Result<Vec<_>>>()?;
+
+    // Checked as the contexts will see them, i.e. per tenant with its overrides.
+    let specs = log_source.properties();
+    let mut context_properties = vec![];
+    if tenants.is_empty() {
+        context_properties.push((ls_name.clone(), properties.clone()));
+    }
+    for (tenant_id, tenant_properties) in tenants.iter() {
+        let mut merged = properties.clone();
+        merged.extend(tenant_properties.clone());
+        context_properties.push((format!("{}/{}", ls_name, tenant_id), merged));
+    }
+    for (label, context_properties) in context_properties.iter() {
+        validate_properties(&managed_type, label, specs, context_properties)?;
+        PreTransform::from_config(context_properties)
+            .with_context(|| format!("{} source '{}'", managed_type, label))?;
+        VaultSecrets::from_config(context_properties)
+            .with_context(|| format!("{} source '{}'", managed_type, label))?;
+    }
+    // Requests of pullers with their own clients aren't counted, see `PullLogs::counts_requests`.
+    let has_request_budget = std::iter::once(&properties)
+        .chain(tenants.iter().map(|(_, properties)| properties))
+        .any(|properties| properties.contains_key("daily_request_budget"));
+    if has_request_budget && !log_source.counts_requests() {
+        return Err(anyhow!(
+            "{} source '{}' doesn't support daily_request_budget, its requests can't be counted",
+            managed_type,
+            ls_name
+        ));
+    }
+
+    let (_, ls_configuration) = shared::load_log_source_configuration(log_source_dir_path)?;
+
+    Ok(Some(ManagedLogSource {
+        name: ls_name,
+        log_source,
+        properties,
+        tenants,
+        has_secret,
+        enabled: managed_enabled,
+        tables_config: ls_configuration.tables,
+    }))
+}
+
+/// The contexts of a managed log source, one per tenant if it has tenants.
+fn build_log_source_contexts(
+    managed: &ManagedLogSource,
+    log_source_to_secret_arn_map: &HashMap<String, String>,
+    s3: &aws_sdk_s3::Client,
+) -> Vec<PullLogsContext> {
+    let ls_name = &managed.name;
+    let props = &managed.properties;
+    let get_secret_arn = |key: &str| match managed.has_secret {
+        true => log_source_to_secret_arn_map.get(key),
+        false => None,
+    };
+    let secret_arn = get_secret_arn(ls_name);
 
-            let secret_arn = log_source_to_secret_arn_map.get(&ls_name);
+    // Shared by all tenants, so the limit applies to the log source as a whole.
+    let pull_limit = match props
+        .get("max_concurrent_pulls")
+        .map(|s| s.trim().parse::<usize>())
+    {
+        Some(Ok(n)) if n > 0 => Some(Arc::new(Semaphore::new(n))),
+        Some(_) => {
+            error!("Invalid max_concurrent_pulls for log source: {}", ls_name);
+            None
+        }
+        None => None,
+    };
+
+    if managed.tenants.is_empty() {
+        let ctx = PullLogsContext::new(
+            ls_name.to_owned(),
+            None,
+            secret_arn.cloned(),
+            managed.log_source.clone(),
+            props.clone(),
+            managed.tables_config.clone(),
+            s3.clone(),
+        )
+        .with_pull_limit(pull_limit);
+        return vec![ctx];
+    }
+
+    managed
+        .tenants
+        .iter()
+        .map(|(tenant_id, properties)| {
+            // Tenant properties override the shared ones.
+            let mut tenant_props = props.clone();
+            tenant_props.extend(properties.clone());
+            let tenant_secret_arn =
+                get_secret_arn(&format!("{}/{}", ls_name, tenant_id)).or(secret_arn);
 
-            let ctx = PullLogsContext::new(
+            PullLogsContext::new(
                 ls_name.to_owned(),
-                secret_arn.cloned(),
-                log_source,
-                props,
-                tables_config,
+                Some(tenant_id.clone()),
+                tenant_secret_arn.cloned(),
+                managed.log_source.clone(),
+                tenant_props,
+                managed.tables_config.clone(),
                 s3.clone(),
-            );
-
-            (ls_name.to_string(), ctx)
+            )
+            .with_pull_limit(pull_limit.clone())
         })
-        .collect::<HashMap<_, _>>();
-    ret
+        .collect()
 }
 
 #[tokio::main]
 async fn main() -> Result<(), LambdaError> {
-    setup_logging();
+    setup_tracing();
+
+    #[cfg(feature = "extensions")]
+    pullers::extensions::register();
+    // Experimental, see `pullers::wasm`.
+    #[cfg(feature = "wasm")]
+    pullers::register_puller("wasm", pullers::WasmPuller);
+
+    // Pulls locally instead when built with the `cli` feature and given a command, see `cli`.
+    #[cfg(feature = "cli")]
+    if std::env::args().len() > 1 {
+        cli::run(std::env::args().skip(1).collect()).await?;
+        return Ok(());
+    }
+
+    if shutdown::is_enabled() {
+        let res = match shutdown::on_sigterm(flush_on_shutdown) {
+            Ok(()) => shutdown::register_extension().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            error!("Graceful shutdown is disabled: {:#}", e);
+        }
+    }
+
+    // Contexts are built while the runtime waits for the first event, rather than by it.
+    tokio::spawn(async {
+        current_contexts().await;
+    });
 
     let func = service_fn(handler);
     run(func).await?;
@@ -153,17 +663,211 @@ async fn main() -> Result<(), LambdaError> {
     Ok(())
 }
 
+/// Stops pulls in progress and uploads what's pending, when the execution environment shuts
+/// down, see `shutdown`.
+async fn flush_on_shutdown() {
+    pullers::set_invocation_deadline(chrono::Utc::now().timestamp_millis() as u64);
+    futures::join!(pullers::flush_pull_attempts(), upload_spooled_objects());
+}
+
+/// A scheduled pull of the `rate_minutes` before `time`.
+///
+/// A backfill of history, e.g. for a newly onboarded log source, can be requested by sending
+/// a message to the puller queue. The range is split into `backfill_window_minutes` windows
+/// (default 60) that are enqueued as separate pulls, so rate limits still apply to each.
+///
+/// ex:
+/// ```json
+/// {"log_source_name": "okta", "backfill": true, "start_time": "2023-01-01T00:00:00Z", "time": "2023-04-01T00:00:00Z"}
+/// ```
+///
+/// A new log source's config can be checked in production with a dry run, which pulls but
+/// doesn't upload anything (see `dry_run_pull`).
+///
+/// ex:
+/// ```json
+/// {"log_source_name": "okta", "dry_run": true, "start_time": "2023-04-01T00:00:00Z", "time": "2023-04-01T01:00:00Z"}
+/// ```
 #[derive(Serialize, Deserialize, Debug)]
 struct PullerRequest {
     log_source_name: String,
     time: String,
+    #[serde(default)]
     rate_minutes: u32,
+    /// Set for catch up pulls of missed windows, which pull exactly `[start_time, time)`.
+    #[serde(default)]
+    start_time: Option<String>,
+    /// Set for catch up pulls of a single tenant.
+    #[serde(default)]
+    tenant_id: Option<String>,
+    /// Splits `[start_time, time)` into pulls instead of pulling it.
+    #[serde(default)]
+    backfill: bool,
+    /// Pulls and logs what was pulled, without uploading it.
+    #[serde(default)]
+    dry_run: bool,
+    /// Times the pull was sent back to the queue after being rate limited.
+    #[serde(default)]
+    rate_limit_requeues: u32,
+}
+
+/// How a puller message was received, to delay or dead letter its retries.
+#[derive(Debug)]
+struct Delivery {
+    receipt_handle: Option<String>,
+    body: String,
+    /// `ApproximateReceiveCount`, 1 on the first attempt.
+    receive_count: u32,
+}
+
+/// The SQS batch response, with what was pulled per log source so orchestration (e.g. a test
+/// invoking the puller directly) can verify throughput. Lambda ignores the extra field.
+#[derive(Serialize, Debug)]
+struct PullerResponse {
+    #[serde(flatten)]
+    batch: Option<SQSBatchResponse>,
+    stats: pullers::PullStats,
+}
+
+/// A pull requested by invoking the puller directly rather than through the queue, e.g. during
+/// an incident or from a script, which returns what was pulled. The window is
+/// `[start_time, end_time)`, or the `minutes` before `end_time` (default now).
+///
+/// ex:
+/// ```bash
+/// aws lambda invoke --function-name <puller> --cli-binary-format raw-in-base64-out \
+///   --payload '{"log_source_name": "okta", "minutes": 15}' /dev/stdout
+/// ```
+#[derive(Deserialize, Debug)]
+struct OnDemandPull {
+    log_source_name: String,
+    #[serde(default)]
+    tenant_id: Option<String>,
+    #[serde(default)]
+    start_time: Option<String>,
+    #[serde(default)]
+    end_time: Option<String>,
+    #[serde(default)]
+    minutes: Option<i64>,
+    /// Pulls without uploading, see `dry_run_pull`.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// The result of an `OnDemandPull`, with an attempt per tenant.
+#[derive(Serialize, Debug)]
+struct OnDemandPullResponse {
+    log_source_name: String,
+    window_start: String,
+    window_end: String,
+    pulls: Vec<pullers::PullAttempt>,
+    stats: pullers::PullStats,
+}
+
+#[derive(Serialize, Debug)]
+struct RefreshContextsResponse {
+    log_sources: usize,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum HandlerResponse {
+    Sqs(PullerResponse),
+    OnDemand(OnDemandPullResponse),
+    Refresh(RefreshContextsResponse),
+}
+
+/// Handles the queue's events, or direct invocations with an `OnDemandPull` or a context
+/// refresh request (see `context_refresh`).
+async fn handler(event: LambdaEvent<serde_json::Value>) -> Result<HandlerResponse> {
+    let (payload, context) = event.into_parts();
+    if payload.get("Records").is_some() {
+        let event = LambdaEvent::new(serde_json::from_value(payload)?, context);
+        return handle_sqs_event(event).await.map(HandlerResponse::Sqs);
+    }
+    if payload.get("refresh_contexts") == Some(&serde_json::Value::Bool(true)) {
+        return refresh_contexts().await.map(HandlerResponse::Refresh);
+    }
+    let request: OnDemandPull =
+        serde_json::from_value(payload).context("Invalid on demand pull request")?;
+    pullers::set_invocation_deadline(context.deadline);
+    on_demand_pull(request).await.map(HandlerResponse::OnDemand)
+}
+
+#[instrument(name = "on_demand_pull", skip_all, fields(log_source = %request.log_source_name))]
+async fn on_demand_pull(request: OnDemandPull) -> Result<OnDemandPullResponse> {
+    let contexts = current_contexts().await;
+    let end_dt = match request.end_time.as_ref() {
+        Some(end_time) => DateTime::parse_from_rfc3339(end_time)?,
+        None => chrono::Utc::now().into(),
+    };
+    let start_dt = match (request.start_time.as_ref(), request.minutes) {
+        (Some(start_time), None) => DateTime::parse_from_rfc3339(start_time)?,
+        (None, Some(minutes)) if minutes > 0 => end_dt - Duration::minutes(minutes),
+        _ => return Err(anyhow!("Either start_time or minutes must be set")),
+    };
+    if start_dt >= end_dt {
+        return Err(anyhow!("start_time must be before end_time"));
+    }
+
+    let ctxs = contexts
+        .get(&request.log_source_name)
+        .context("Invalid log source.")?
+        .iter()
+        .filter(|ctx| request.tenant_id.is_none() || ctx.tenant_id == request.tenant_id)
+        .collect::<Vec<_>>();
+    if ctxs.is_empty() {
+        return Err(anyhow!(
+            "Log source: {} is disabled or has no such tenant",
+            request.log_source_name
+        ));
+    }
+    info!(
+        "On demand pull of log_source: {} from {} to {}",
+        request.log_source_name, start_dt, end_dt
+    );
+
+    // Pulled as a catch up pull, i.e. exactly the window, without moving the last pulled time.
+    let futs = ctxs
+        .iter()
+        .map(|ctx| pull_and_upload(ctx, start_dt, end_dt, true, request.dry_run));
+    for (res, ctx) in join_all(futs).await.into_iter().zip(ctxs.iter()) {
+        if let Err(e) = res {
+            error!(
+                "On demand pull of log_source: {} (tenant: {:?}) failed: {:#}",
+                ctx.log_source_name, ctx.tenant_id, e
+            );
+        }
+    }
+
+    let pulls = pullers::flush_pull_attempts().await;
+    let stats = pullers::PullStats::from_attempts(&pulls);
+    stats.publish().await;
+    Ok(OnDemandPullResponse {
+        log_source_name: request.log_source_name,
+        window_start: start_dt.to_rfc3339(),
+        window_end: end_dt.to_rfc3339(),
+        pulls,
+        stats,
+    })
 }
 
-async fn handler(event: LambdaEvent<SqsEvent>) -> Result<Option<SQSBatchResponse>> {
+/// Invocations are traced with the X-Ray trace id, so the spans of a pull can be matched to
+/// its trace. The runtime also sets `_X_AMZN_TRACE_ID`, which the AWS SDK sends on its
+/// requests, so S3, DynamoDB and SQS calls (and pulls they enqueue) join the same trace.
+#[instrument(
+    name = "invocation",
+    skip_all,
+    fields(
+        request_id = %event.context.request_id,
+        xray_trace_id = event.context.xray_trace_id.as_deref(),
+    )
+)]
+async fn handle_sqs_event(event: LambdaEvent<SqsEvent>) -> Result<PullerResponse> {
     info!("Starting....");
-    let client = REQ_CLIENT.clone();
-    let contexts = CONTEXTS.get().await;
+    let contexts = current_contexts().await;
+    pullers::set_invocation_deadline(event.context.deadline);
+    upload_spooled_objects().await;
 
     let mut errors = vec![];
 
@@ -171,8 +875,20 @@ async fn handler(event: LambdaEvent<SqsEvent>) -> Result<Option<SQSBatchResponse
         .payload
         .records
         .into_iter()
-        .flat_map(|msg| Some((msg.message_id?, msg.body?)))
-        .filter_map(|(id, body)| {
+        .flat_map(|msg| {
+            let receive_count = msg
+                .attributes
+                .get("ApproximateReceiveCount")
+                .and_then(|c| c.parse().ok())
+                .unwrap_or(1);
+            Some((
+                msg.message_id?,
+                msg.receipt_handle,
+                msg.body?,
+                receive_count,
+            ))
+        })
+        .filter_map(|(id, receipt_handle, body, receive_count)| {
             let maybe_req = serde_json::from_str::<PullerRequest>(&body)
                 .map_err(|e| {
                     let sqs_err = SQSLambdaError::new(
@@ -182,7 +898,12 @@ async fn handler(event: LambdaEvent<SqsEvent>) -> Result<Option<SQSBatchResponse
                     errors.push(sqs_err)
                 })
                 .ok();
-            Some((id, maybe_req?))
+            let delivery = Delivery {
+                receipt_handle,
+                body,
+                receive_count,
+            };
+            Some((id, delivery, maybe_req?))
         })
         .collect::<Vec<_>>();
 
@@ -190,122 +911,2259 @@ async fn handler(event: LambdaEvent<SqsEvent>) -> Result<Option<SQSBatchResponse
 
     debug!("Using contexts: {:?}", contexts.keys().collect::<Vec<_>>());
 
-    let futs = records
+    let records = records
         .into_iter()
-        .filter(|(_, record)| {
-            let ctx = contexts.get(&record.log_source_name);
-            if ctx.is_none() {
-                debug!("Skipping invalid log source: {}", &record.log_source_name);
+        .filter(|(_, _, record)| {
+            let ctxs = contexts.get(&record.log_source_name);
+            match ctxs {
+                Some(ctxs) if ctxs.is_empty() => {
+                    info!("Skipping disabled log source: {}", &record.log_source_name);
+                }
+                Some(_) => (),
+                None => debug!("Skipping invalid log source: {}", &record.log_source_name),
             }
-            ctx.is_some()
+            ctxs.map_or(false, |ctxs| !ctxs.is_empty())
         })
-        .map(|(msg_id, record)| {
-            process_record(msg_id.clone(), record, client.clone(), contexts)
-                .map_err(|e| SQSLambdaError::new(format!("{:#}", e), vec![msg_id]))
+        .collect::<Vec<_>>();
+
+    let is_fifo = pullers::is_fifo_queue();
+    let records = match is_fifo {
+        // Not coalesced, so each message is pulled in the order it was sent.
+        true => records
+            .into_iter()
+            .map(|(id, delivery, record)| (vec![(id, delivery)], record))
+            .collect(),
+        false => coalesce_records(records),
+    };
+    let pulls = records
+        .into_iter()
+        .map(|(messages, record)| {
+            let msg_ids = messages
+                .iter()
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<_>>();
+            let log_source_name = record.log_source_name.clone();
+            // Correlates the logs of each message, e.g. in Logs Insights by `message_id`.
+            let span = info_span!(
+                "message",
+                message_id = %msg_ids.join(","),
+                log_source = %record.log_source_name,
+            );
+            let _entered = span.enter();
+            let fut = process_record(messages, record, &contexts)
+                .map(|fut| fut.instrument(span.clone()))
+                .map_err(|e| SQSLambdaError::new(format!("{:#}", e), msg_ids.clone()));
+            (log_source_name, msg_ids, fut)
         })
-        .filter_map(|r| r.map_err(|e| errors.push(e)).ok())
         .collect::<Vec<_>>();
 
-    join_all(futs)
+    let futs = match is_fifo {
+        true => in_message_group_order(pulls),
+        false => pulls
+            .into_iter()
+            .filter_map(|(_, _, fut)| fut.map_err(|e| errors.push(e)).ok())
+            .map(|fut| fut.boxed_local())
+            .collect(),
+    };
+
+    futures::stream::iter(futs)
+        .buffer_unordered(max_concurrent_messages())
+        .collect::<Vec<_>>()
         .await
         .into_iter()
         .filter_map(|r| r.map_err(|e| errors.push(e)).ok())
         .for_each(drop);
 
-    sqs_errors_to_response(errors)
+    let stats = pullers::PullStats::from_attempts(&pullers::flush_pull_attempts().await);
+    stats.publish().await;
+    pullers::emit_latency_metrics();
+
+    Ok(PullerResponse {
+        batch: sqs_errors_to_response(errors)?,
+        stats,
+    })
 }
 
-fn process_record(
-    msg_id: String,
-    record: PullerRequest,
-    client: reqwest::Client,
-    contexts: &'static HashMap<String, PullLogsContext>,
-) -> Result<impl futures::Future<Output = Result<(), SQSLambdaError>>> {
+/// Pulls of a FIFO queue's messages, one after another per log source (its message group) and
+/// in the order they were sent, e.g. so a cursor is always advanced by the pull after the one
+/// that saved it. After a failure, the rest of the group is returned to the queue unpulled,
+/// as SQS requires for the group to stay in order. Log sources are still pulled concurrently.
+fn in_message_group_order<'a, F>(
+    pulls: Vec<(String, Vec<String>, Result<F, SQSLambdaError>)>,
+) -> Vec<futures::future::LocalBoxFuture<'a, Result<(), SQSLambdaError>>>
+where
+    F: futures::Future<Output = Result<(), SQSLambdaError>> + 'a,
+{
+    let mut groups: Vec<(String, Vec<_>)> = vec![];
+    for (log_source_name, msg_ids, fut) in pulls {
+        match groups.iter_mut().find(|(name, _)| *name == log_source_name) {
+            Some((_, group)) => group.push((msg_ids, fut)),
+            None => groups.push((log_source_name, vec![(msg_ids, fut)])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(log_source_name, group)| {
+            async move {
+                let mut pulls = group.into_iter();
+                let mut failure = None;
+                for (_, fut) in pulls.by_ref() {
+                    let res = match fut {
+                        Ok(fut) => fut.await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = res {
+                        failure = Some(e);
+                        break;
+                    }
+                }
+                let mut e = match failure {
+                    Some(e) => e,
+                    None => return Ok(()),
+                };
+                let skipped = pulls.flat_map(|(msg_ids, _)| msg_ids).collect::<Vec<_>>();
+                if !skipped.is_empty() {
+                    warn!(
+                        "Returning {} later messages of log_source: {} to the queue after a failure",
+                        skipped.len(),
+                        log_source_name
+                    );
+                    e.ids.extend(skipped);
+                }
+                Err(e)
+            }
+            .boxed_local()
+        })
+        .collect()
+}
+
+/// How many messages of a batch are processed at once, so a full batch doesn't pull and upload
+/// everything simultaneously and run out of memory or sockets.
+fn max_concurrent_messages() -> usize {
+    std::env::var("PULLER_MAX_CONCURRENT_MESSAGES")
+        .ok()
+        .and_then(|n| n.trim().parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_MESSAGES)
+}
+
+/// The window a request pulls.
+fn request_window(
+    record: &PullerRequest,
+) -> Result<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
     let event_dt = DateTime::parse_from_rfc3339(&record.time)?;
+    match record.start_time.as_ref() {
+        Some(start_time) => Ok((DateTime::parse_from_rfc3339(start_time)?, event_dt)),
+        None => {
+            let end_dt = event_dt.duration_trunc(Duration::minutes(1))?;
+            Ok((
+                end_dt - Duration::minutes(record.rate_minutes as i64),
+                end_dt,
+            ))
+        }
+    }
+}
+
+/// Merges pulls of the same log source and tenant whose windows overlap or touch (e.g. after
+/// a redrive) into one pull of their combined window, so the vendor isn't asked for the same
+/// logs more than once. Scheduled and catch up pulls are merged separately, as only the former
+/// adjust their window to the last pulled time. Backfills, dry runs and continuation pulls
+/// aren't merged.
+fn coalesce_records(
+    records: Vec<(String, Delivery, PullerRequest)>,
+) -> Vec<(Vec<(String, Delivery)>, PullerRequest)> {
+    type Group = (
+        Vec<(String, Delivery)>,
+        PullerRequest,
+        DateTime<FixedOffset>,
+        DateTime<FixedOffset>,
+    );
+    let mut ret = vec![];
+    let mut groups: HashMap<(String, Option<String>, bool), Vec<Group>> = HashMap::new();
+    for (msg_id, delivery, record) in records {
+        let window = match request_window(&record) {
+            Ok((start_dt, end_dt)) if start_dt < end_dt => Some((start_dt, end_dt)),
+            _ => None,
+        };
+        match window {
+            Some((start_dt, end_dt)) if !record.backfill && !record.dry_run => {
+                let key = (
+                    record.log_source_name.clone(),
+                    record.tenant_id.clone(),
+                    record.start_time.is_some(),
+                );
+                let group = (vec![(msg_id, delivery)], record, start_dt, end_dt);
+                groups.entry(key).or_default().push(group);
+            }
+            _ => ret.push((vec![(msg_id, delivery)], record)),
+        }
+    }
+
+    for (_, mut pulls) in groups {
+        pulls.sort_by_key(|(_, _, start_dt, _)| *start_dt);
+        let mut merged: Vec<Group> = vec![];
+        for pull in pulls {
+            match merged.last_mut() {
+                Some(last) if pull.2 <= last.3 => {
+                    last.0.extend(pull.0);
+                    if pull.3 > last.3 {
+                        last.1.time = pull.1.time;
+                        last.3 = pull.3;
+                    }
+                }
+                _ => merged.push(pull),
+            }
+        }
+        for (messages, mut record, start_dt, end_dt) in merged {
+            if messages.len() > 1 {
+                info!(
+                    "Coalesced {} messages for log_source: {} into one pull from {} to {}",
+                    messages.len(),
+                    record.log_source_name,
+                    start_dt,
+                    end_dt
+                );
+                match record.start_time.as_mut() {
+                    Some(start_time) => *start_time = start_dt.to_rfc3339(),
+                    None => record.rate_minutes = (end_dt - start_dt).num_minutes() as u32,
+                }
+            }
+            ret.push((messages, record));
+        }
+    }
+    ret
+}
 
-    let end_dt = event_dt.duration_trunc(Duration::minutes(1))?;
-    let start_dt = end_dt - Duration::minutes(record.rate_minutes as i64);
+/// Processes a request, for one or more messages (see `coalesce_records`). Failed messages
+/// are each retried, delayed or dead lettered as their receive count calls for.
+fn process_record<'a>(
+    messages: Vec<(String, Delivery)>,
+    record: PullerRequest,
+    contexts: &'a HashMap<String, Vec<PullLogsContext>>,
+) -> Result<impl futures::Future<Output = Result<(), SQSLambdaError>> + 'a> {
+    let is_catch_up = record.start_time.is_some();
+    let dry_run = record.dry_run;
+    let (start_dt, end_dt) = request_window(&record)?;
 
     info!(
         "Processing log_source: {}, from {} to {}",
         &record.log_source_name, &start_dt, &end_dt
     );
 
-    let ctx = contexts
+    let ctxs = contexts
         .get(&record.log_source_name)
         .context("Invalid log source.")?;
 
-    let puller = ctx.log_source_type.clone();
+    let ctxs = ctxs
+        .iter()
+        .filter(|ctx| record.tenant_id.is_none() || ctx.tenant_id == record.tenant_id)
+        .collect::<Vec<_>>();
+
     let log_source_name = record.log_source_name.clone();
-    let client = client.clone();
+
+    if record.backfill {
+        if !is_catch_up {
+            return Err(anyhow!("Backfill requires start_time"));
+        }
+        if dry_run {
+            return Err(anyhow!("Backfills can't be dry runs"));
+        }
+        let fut = async move {
+            for ctx in ctxs {
+                enqueue_backfill(ctx, start_dt, end_dt).await?;
+            }
+            anyhow::Ok(())
+        }
+        .map_err(move |e| {
+            let e = e.context(format!(
+                "Backfill error for log_source: {}",
+                log_source_name
+            ));
+            let msg_ids = messages.into_iter().map(|(id, _)| id).collect();
+            SQSLambdaError::new(format!("{:#}", e), msg_ids)
+        });
+        return Ok(Either::Left(fut));
+    }
 
     let fut = async move {
-        ctx.load_checkpoint().await?;
-        let data = puller.pull_logs(client, ctx, start_dt, end_dt).await?;
-        let did_upload = upload_data(data, &record.log_source_name).await?;
-        if did_upload {
-            let checkpoint_json = ctx.checkpoint_json.lock().await.clone();
-            let is_initial_run = checkpoint_json.is_none();
-            if is_initial_run {
-                ctx.upload_checkpoint(&json!({
-                    "initial_run": "complete"
-                }))
-                .await?;
-                info!(
-                    "Marked initial run complete for log_source: {}",
-                    ctx.log_source_name
-                );
-            } else {
-                let checkpoint_json = checkpoint_json.unwrap();
-                if checkpoint_json
-                    != json!({
-                        "initial_run": "complete"
-                    })
-                {
-                    ctx.upload_checkpoint(&checkpoint_json).await?;
-                    info!(
-                        "Uploaded new checkpoint for log_source: {}, checkpoint state: {:?}",
-                        ctx.log_source_name, checkpoint_json
-                    );
+        // Pull all tenants, a failing tenant shouldn't block the others.
+        let futs = ctxs
+            .iter()
+            .map(|ctx| pull_and_upload(ctx, start_dt, end_dt, is_catch_up, dry_run))
+            .collect::<Vec<_>>();
+        let failures = join_all(futs)
+            .await
+            .into_iter()
+            .zip(ctxs.iter())
+            .filter_map(|(res, ctx)| {
+                let e = res.err()?;
+                let class = PullerError::of(&e).unwrap_or(PullerError::Other);
+                let msg = match ctx.tenant_id.as_ref() {
+                    Some(tenant_id) => format!("tenant {}: {:#}", tenant_id, e),
+                    None => format!("{:#}", e),
+                };
+                Some((ctx, class, msg))
+            })
+            .collect::<Vec<_>>();
+        let errors = failures
+            .iter()
+            .map(|(_, _, msg)| msg.as_str())
+            .collect::<Vec<_>>()
+            .join("; ");
+        // The message is retried as the most conservative action of its failed pulls needs.
+        let (ctx, class) = match failures
+            .iter()
+            .max_by_key(|(_, class, _)| class.retry_action())
+        {
+            Some((ctx, class, _)) => (ctx, *class),
+            None => return Ok(()),
+        };
+        if failures
+            .iter()
+            .all(|(_, class, _)| *class == PullerError::RateLimited)
+        {
+            let tenant_ids = failures
+                .iter()
+                .map(|(ctx, _, _)| ctx.tenant_id.clone())
+                .collect::<Vec<_>>();
+            let retry_after = failures
+                .iter()
+                .filter_map(|(ctx, _, _)| ctx.rate_limit_retry_after())
+                .max();
+            match requeue_rate_limited(&record, &tenant_ids, retry_after).await {
+                Ok(true) => return Ok(()),
+                Ok(false) => (),
+                Err(e) => error!("{:#}", e),
+            }
+        }
+        if class.retry_action() == RetryAction::Quarantine {
+            error!(
+                "Not retrying log_source: {}, responses were quarantined: {}",
+                record.log_source_name, errors
+            );
+            return Ok(());
+        }
+        let mut failed_ids = vec![];
+        for (msg_id, delivery) in messages {
+            if pullers::is_final_attempt(delivery.receive_count) {
+                let failure = pullers::PullFailure {
+                    log_source: record.log_source_name.clone(),
+                    tenant_ids: failures
+                        .iter()
+                        .filter_map(|(ctx, _, _)| ctx.tenant_id.clone())
+                        .collect(),
+                    error_class: class,
+                    http_status: ctx.last_error_status().map(|s| s.as_u16()),
+                    window_start: start_dt,
+                    window_end: end_dt,
+                    attempts: delivery.receive_count,
+                    error_message: errors.clone(),
+                };
+                match pullers::send_to_dlq(&delivery.body, &failure).await {
+                    Ok(()) => {
+                        error!(
+                            "Sent log_source: {} to the DLQ after {} attempts: {}",
+                            record.log_source_name, delivery.receive_count, errors
+                        );
+                        continue;
+                    }
+                    Err(e) => error!("{:#}", e),
+                }
+            } else if class.retry_action() == RetryAction::Delay {
+                if let Some(handle) = delivery.receipt_handle.as_ref() {
+                    if let Err(e) = pullers::delay_retry(handle, DELAYED_RETRY_SECONDS).await {
+                        warn!(
+                            "Failed to delay retry of {}: {:#}",
+                            record.log_source_name, e
+                        );
+                    }
                 }
             }
+            failed_ids.push(msg_id);
         }
-        anyhow::Ok(())
-    }
-    .map_err(move |e| {
-        let e = e.context(format!("Error for log_source: {}", log_source_name));
-        SQSLambdaError::new(format!("{:#}", e), vec![msg_id])
-    });
-    Ok(fut)
+        if failed_ids.is_empty() {
+            return Ok(());
+        }
+        let e = anyhow!(errors).context(format!("Error for log_source: {}", log_source_name));
+        Err(SQSLambdaError::new(format!("{:#}", e), failed_ids))
+    };
+    Ok(Either::Right(fut))
 }
 
-async fn upload_data(data: Vec<u8>, log_source: &str) -> Result<bool> {
-    if data.is_empty() {
-        info!("No new data for log_source: {}", log_source);
+/// Sends copies of a rate limited request back to the queue, one per failed tenant, delayed
+/// until the vendor's rate limit resets (from `Retry-After`) or `DELAYED_RETRY_SECONDS`. Its
+/// messages are then reported as succeeded, so predictable throttling doesn't use up their
+/// receives and dead letter them. Returns false if the request can't be requeued, e.g. after
+/// `MAX_RATE_LIMIT_REQUEUES` or on a FIFO queue, and should be retried as usual.
+async fn requeue_rate_limited(
+    record: &PullerRequest,
+    tenant_ids: &[Option<String>],
+    retry_after: Option<std::time::Duration>,
+) -> Result<bool> {
+    if record.dry_run
+        || record.rate_limit_requeues >= MAX_RATE_LIMIT_REQUEUES
+        || pullers::is_fifo_queue()
+    {
         return Ok(false);
     }
-    info!("Uploading data for {}", log_source);
-    let bucket = std::env::var("INGESTION_BUCKET_NAME")?;
-    let key = format!(
-        "{}/{}.json.zst",
-        log_source,
-        uuid::Uuid::new_v4().to_string()
+    let delay_seconds = retry_after
+        .map_or(DELAYED_RETRY_SECONDS, |d| d.as_secs_f64().ceil() as i32)
+        .clamp(1, MAX_REQUEUE_DELAY_SECONDS);
+    for tenant_id in tenant_ids {
+        let request = PullerRequest {
+            tenant_id: tenant_id.clone().or_else(|| record.tenant_id.clone()),
+            rate_limit_requeues: record.rate_limit_requeues + 1,
+            log_source_name: record.log_source_name.clone(),
+            time: record.time.clone(),
+            start_time: record.start_time.clone(),
+            ..*record
+        };
+        pullers::requeue(&serde_json::to_string(&request)?, delay_seconds).await?;
+    }
+    warn!(
+        "Rate limited log_source: {}, requeued with a delay of {}s (requeue {} of {})",
+        record.log_source_name,
+        delay_seconds,
+        record.rate_limit_requeues + 1,
+        MAX_RATE_LIMIT_REQUEUES
     );
-    let s3 = S3_CLIENT.get().await;
-    info!("Writing to s3://{}/{}", bucket, key);
-
-    let mut zencoder = zstd::Encoder::new(vec![], 0)?;
-    zencoder.write_all(data.as_slice())?;
-    let final_data = zencoder.finish()?;
-
-    s3.put_object()
-        .bucket(&bucket)
-        .key(&key)
-        .body(ByteStream::from(final_data))
-        .content_encoding("application/zstd".to_string())
-        .send()
+    Ok(true)
+}
+
+#[instrument(
+    name = "pull",
+    skip_all,
+    fields(
+        log_source = %ctx.log_source_name,
+        tenant_id = ctx.tenant_id.as_deref(),
+        start = %start_dt,
+        end = %end_dt,
+    )
+)]
+async fn pull_and_upload(
+    ctx: &PullLogsContext,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+    is_catch_up: bool,
+    dry_run: bool,
+) -> Result<()> {
+    if dry_run {
+        return dry_run_pull(ctx, start_dt, end_dt).await;
+    }
+    let started_at = std::time::Instant::now();
+    let res = pull_and_upload_claimed(ctx, start_dt, end_dt, is_catch_up)
         .await
-        .map_err(|e| anyhow!(e).context(format!("Error putting {} to S3", key)))?;
+        .map_err(|e| {
+            let class = ctx.classify_error(&e);
+            class.emit_metric(&ctx.log_source_name);
+            match PullerError::of(&e) {
+                None if class != PullerError::Other => e.context(class),
+                _ => e,
+            }
+        });
+    let duration = started_at.elapsed();
+    if !matches!(res, Ok(PullOutcome::Skipped(_))) {
+        pullers::record_pull_duration(ctx, duration);
+    }
+    pullers::record_pull_attempt(ctx, (start_dt, end_dt), is_catch_up, &res, duration);
+    res.map(|_| ())
+}
 
-    Ok(true)
+/// Pulls `[start_dt, end_dt)` and logs how many records were pulled with a sample of them,
+/// without uploading them or updating any state (checkpoint, last pulled time, dedup, circuit
+/// breaker or audit), so a new log source's config can be validated without polluting the lake.
+async fn dry_run_pull(
+    ctx: &PullLogsContext,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<()> {
+    let puller = ctx.log_source_type.clone();
+
+    // The puller updates the loaded checkpoint in memory only, it's reloaded on the next pull.
+    ctx.load_checkpoint().await?;
+    let client = ctx.http_client().await?;
+    let data = pullers::collect_chunks(puller.pull_log_chunks(client, ctx, start_dt, end_dt)).await;
+    ctx.take_continuation_request();
+    ctx.take_rate_limit_stats();
+
+    let data = ctx.normalize_records(data?);
+    let records = data
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    let sample = records
+        .iter()
+        .take(DRY_RUN_SAMPLE_SIZE)
+        .map(|r| String::from_utf8_lossy(r))
+        .collect::<Vec<_>>();
+    info!(
+        "Dry run for log_source: {} from {} to {} pulled {} records ({} bytes), sample:\n{}",
+        ctx.log_source_name,
+        start_dt,
+        end_dt,
+        records.len(),
+        data.len(),
+        sample.join("\n")
+    );
+    Ok(())
+}
+
+async fn pull_and_upload_claimed(
+    ctx: &PullLogsContext,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+    is_catch_up: bool,
+) -> Result<PullOutcome> {
+    // Limits concurrent pulls of the same log source when a batch has many records for it.
+    let _permit = match ctx.pull_limit() {
+        Some(semaphore) => Some(semaphore.acquire().await?),
+        None => None,
+    };
+
+    if let Some(open_until) = ctx.circuit_open_until().await? {
+        info!(
+            "Skipping log_source: {}, circuit breaker is open until {}",
+            ctx.log_source_name, open_until
+        );
+        return Ok(PullOutcome::Skipped("circuit_open"));
+    }
+    if let Some(resets_at) = ctx.request_budget_exhausted_until().await? {
+        warn!(
+            "Skipping log_source: {}, daily request budget is used up until {}",
+            ctx.log_source_name, resets_at
+        );
+        return Ok(PullOutcome::Skipped("request_budget_exhausted"));
+    }
+
+    // SQS may deliver the same request twice, only the first delivery pulls. Continuation
+    // pulls have an empty window and are never duplicates.
+    let is_claimed = start_dt < end_dt;
+    if is_claimed && !ctx.claim_pull(start_dt, end_dt).await? {
+        info!(
+            "Skipping duplicate pull for log_source: {} from {} to {}",
+            ctx.log_source_name, start_dt, end_dt
+        );
+        return Ok(PullOutcome::Skipped("duplicate"));
+    }
+
+    let res = pull_and_upload_once(ctx, start_dt, end_dt, is_catch_up).await;
+    if let Err(e) = ctx.record_requests().await {
+        error!(
+            "Failed to record requests for log_source: {}: {:#}",
+            ctx.log_source_name, e
+        );
+    }
+    if let Err(e) = ctx.record_pull_result(&res).await {
+        error!(
+            "Failed to update circuit breaker for log_source: {}: {:#}",
+            ctx.log_source_name, e
+        );
+    }
+    if is_claimed {
+        if let Err(e) = ctx.finish_pull(start_dt, end_dt, res.is_ok()).await {
+            error!(
+                "Failed to record pull for log_source: {}: {:#}",
+                ctx.log_source_name, e
+            );
+        }
+    }
+    res
+}
+
+async fn pull_and_upload_once(
+    ctx: &PullLogsContext,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+    is_catch_up: bool,
+) -> Result<PullOutcome> {
+    let puller = ctx.log_source_type.clone();
+
+    ctx.load_checkpoint().await?;
+    // Catch up pulls are for an exact window that's already behind the last pulled time.
+    let (start_dt, end_dt) = if is_catch_up {
+        (start_dt, end_dt)
+    } else {
+        let (start_dt, end_dt) = scheduled_window(ctx, start_dt, end_dt)?;
+        match pull_window(ctx, start_dt, end_dt).await? {
+            // Late arriving events are picked up by the overlap, repeats are dropped by dedup.
+            Some((start_dt, end_dt)) => (start_dt - lookback_overlap(ctx)?, end_dt),
+            None => return Ok(PullOutcome::Skipped("already_pulled")),
+        }
+    };
+    if !is_catch_up && enqueue_shards(ctx, start_dt, end_dt).await? {
+        // The shards own the window now, a failed shard is retried by the queue and then dead
+        // lettered rather than pulled again by a later schedule (see `enqueue_shards`).
+        ctx.set_last_pulled_at(end_dt).await?;
+        return Ok(PullOutcome::Skipped("sharded"));
+    }
+    let client = ctx.http_client().await?;
+    let chunks = puller.pull_log_chunks(client, ctx, start_dt, end_dt);
+    let mut upload = DataUpload::new(ctx, start_dt, end_dt).await?;
+    let res = upload_chunks(ctx, chunks, &mut upload, start_dt, end_dt).await;
+    let continuation_requested = ctx.take_continuation_request();
+
+    let stats = ctx.take_rate_limit_stats();
+    if stats != RateLimitStats::default() {
+        info!(
+            "Rate limited for log_source: {}, throttled {} times, waited {}ms",
+            ctx.log_source_name, stats.throttled_count, stats.waited_ms
+        );
+    }
+
+    let (event_ids, snapshot_hash) = match res {
+        Ok(res) => res,
+        Err(e) => {
+            upload.abort().await;
+            return Err(e);
+        }
+    };
+    let outcome = PullOutcome::Pulled {
+        events: upload.records,
+        bytes: upload.bytes,
+    };
+    let did_upload = upload.finish().await?;
+    if did_upload {
+        ctx.record_event_ids(&event_ids).await?;
+        if let Some(hash) = snapshot_hash.as_ref() {
+            ctx.set_snapshot_hash(hash).await?;
+        }
+    }
+    // A pull stopped at the deadline saves where it stopped even if nothing was uploaded yet
+    // (e.g. a slow first page, or only repeats), so its continuation resumes from there.
+    if did_upload || continuation_requested {
+        let checkpoint_json = ctx.checkpoint_json.lock().await.clone();
+        let is_initial_run = checkpoint_json.is_none();
+        if is_initial_run {
+            if did_upload {
+                ctx.upload_checkpoint(&json!({
+                    "initial_run": "complete"
+                }))
+                .await?;
+                info!(
+                    "Marked initial run complete for log_source: {}",
+                    ctx.log_source_name
+                );
+            }
+        } else {
+            let checkpoint_json = checkpoint_json.unwrap();
+            if checkpoint_json
+                != json!({
+                    "initial_run": "complete"
+                })
+            {
+                ctx.upload_checkpoint(&checkpoint_json).await?;
+                info!(
+                    "Uploaded new checkpoint for log_source: {}, checkpoint state: {:?}",
+                    ctx.log_source_name, checkpoint_json
+                );
+            }
+        }
+    }
+    // The rest is pulled by another invocation right away, rather than on the next run.
+    if continuation_requested {
+        pullers::enqueue_continuation(&ctx.log_source_name, ctx.tenant_id.as_deref(), end_dt)
+            .await?;
+        info!(
+            "Enqueued continuation pull for log_source: {}",
+            ctx.log_source_name
+        );
+    }
+    ctx.set_last_pulled_at(end_dt).await?;
+    Ok(outcome)
+}
+
+/// Adjusts the window to start where the last successful pull ended, if tracked, so failed runs
+/// don't leave gaps and redelivered messages don't pull the same logs twice. Returns None if
+/// the window was already pulled.
+///
+/// Gaps longer than `catch_up_threshold_minutes` (e.g. after an outage or dead lettered
+/// messages) are enqueued as separate catch up pulls rather than pulled in one invocation, so
+/// no data is skipped however long the puller was broken (up to `max_catch_up_hours`).
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     catch_up_threshold_minutes: 30
+/// ```
+async fn pull_window(
+    ctx: &PullLogsContext,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)>> {
+    let last_pulled_at = match ctx.last_pulled_at().await? {
+        Some(dt) => dt,
+        None => return Ok(Some((start_dt, end_dt))),
+    };
+    if last_pulled_at >= end_dt {
+        info!(
+            "Skipping log_source: {}, already pulled up to {}",
+            ctx.log_source_name, last_pulled_at
+        );
+        return Ok(None);
+    }
+    if let Some(window_minutes) = minutes_property(ctx, "pull_window_minutes")? {
+        if last_pulled_at > end_dt - Duration::minutes(window_minutes) {
+            debug!(
+                "Skipping log_source: {}, pulled up to {}, less than pull_window_minutes ago",
+                ctx.log_source_name, last_pulled_at
+            );
+            return Ok(None);
+        }
+    }
+    let catch_up_threshold = minutes_property(ctx, "catch_up_threshold_minutes")?
+        .unwrap_or(DEFAULT_CATCH_UP_THRESHOLD_MINUTES);
+    if last_pulled_at >= start_dt - Duration::minutes(catch_up_threshold) {
+        if last_pulled_at != start_dt {
+            info!(
+                "Adjusted window for log_source: {} to start at {}",
+                ctx.log_source_name, last_pulled_at
+            );
+        }
+        return Ok(Some((last_pulled_at, end_dt)));
+    }
+
+    // Older gaps are skipped rather than re-pulling an unbounded backlog.
+    let max_catch_up = match ctx.config().get("max_catch_up_hours") {
+        Some(h) => h
+            .trim()
+            .parse::<i64>()
+            .context("max_catch_up_hours must be an integer")?,
+        None => DEFAULT_MAX_CATCH_UP_HOURS,
+    };
+    let gap_start = last_pulled_at.max(start_dt - Duration::hours(max_catch_up));
+    if gap_start > last_pulled_at {
+        warn!(
+            "Not catching up on log_source: {} from {} to {}, older than max_catch_up_hours",
+            ctx.log_source_name, last_pulled_at, gap_start
+        );
+    }
+    if gap_start < start_dt {
+        let count = pullers::enqueue_catch_up(
+            &ctx.log_source_name,
+            ctx.tenant_id.as_deref(),
+            gap_start,
+            start_dt,
+            pullers::CATCH_UP_WINDOW_MINUTES,
+        )
+        .await?;
+        warn!(
+            "Found gap for log_source: {} from {} to {}, enqueued {} catch up pulls",
+            ctx.log_source_name, gap_start, start_dt, count
+        );
+    }
+    // The gap is now covered by the catch up pulls, so a retry of this window doesn't
+    // enqueue them again.
+    ctx.set_last_pulled_at(start_dt).await?;
+    Ok(Some((start_dt, end_dt)))
+}
+
+/// Maximum uncompressed size of each uploaded object, from `max_object_size_mb`.
+fn max_object_size(ctx: &PullLogsContext) -> Result<usize> {
+    let mb = match ctx.config().get("max_object_size_mb") {
+        Some(mb) => mb
+            .trim()
+            .parse::<usize>()
+            .context("max_object_size_mb must be an integer")?,
+        None => DEFAULT_MAX_OBJECT_SIZE_MB,
+    };
+    Ok(mb.max(1) * 1024 * 1024)
+}
+
+/// Maximum size of the object data a pull holds in memory, from `memory_budget_mb`. Objects
+/// held whole (Parquet and client side encrypted ones) are uploaded early once they reach it,
+/// so a big backfill can't use up the Lambda's memory. Defaults to half the function's memory
+/// split between the messages processed at once.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     memory_budget_mb: 512
+/// ```
+fn memory_budget(ctx: &PullLogsContext) -> Result<usize> {
+    let mb = match ctx.config().get("memory_budget_mb") {
+        Some(mb) => mb
+            .trim()
+            .parse::<usize>()
+            .context("memory_budget_mb must be an integer")?,
+        None => std::env::var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE")
+            .ok()
+            .and_then(|mb| mb.trim().parse::<usize>().ok())
+            .map(|mb| mb / 2 / max_concurrent_messages())
+            .unwrap_or(DEFAULT_MEMORY_BUDGET_MB),
+    };
+    Ok(mb.max(1) * 1024 * 1024)
+}
+
+/// Whether to skip uploading a pull identical to the last uploaded one, for sources that
+/// return a full snapshot (e.g. an asset inventory) on each pull.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     skip_unchanged_snapshots: true
+/// ```
+fn skip_unchanged_snapshots(ctx: &PullLogsContext) -> Result<bool> {
+    match ctx.config().get("skip_unchanged_snapshots") {
+        Some(v) => v
+            .trim()
+            .parse::<bool>()
+            .context("skip_unchanged_snapshots must be true or false"),
+        None => Ok(false),
+    }
+}
+
+/// Hex SHA256 of NDJSON records, normalized so the order of records and of object keys
+/// doesn't matter. Hashed from a digest of each record, so a snapshot can be hashed as it's
+/// pulled, without holding its records.
+struct SnapshotHash {
+    digests: Vec<[u8; 32]>,
+}
+
+impl SnapshotHash {
+    fn new() -> SnapshotHash {
+        SnapshotHash { digests: vec![] }
+    }
+
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        for line in data.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+            let normalized = match serde_json::from_slice(line) {
+                Ok(v) => serde_json::to_vec(&sort_keys(v))?,
+                Err(_) => line.to_vec(),
+            };
+            let digest = ring::digest::digest(&ring::digest::SHA256, &normalized);
+            self.digests.push(digest.as_ref().try_into()?);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> String {
+        self.digests.sort_unstable();
+        let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+        for digest in self.digests {
+            ctx.update(&digest);
+        }
+        hex::encode(ctx.finish())
+    }
+}
+
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(obj) => {
+            let mut entries = obj
+                .into_iter()
+                .map(|(k, v)| (k, sort_keys(v)))
+                .collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            serde_json::Value::Object(entries.into_iter().collect())
+        }
+        serde_json::Value::Array(arr) => {
+            serde_json::Value::Array(arr.into_iter().map(sort_keys).collect())
+        }
+        v => v,
+    }
+}
+
+/// The window of a scheduled pull, adjusted with the log source's properties:
+///
+/// - `pull_lag_minutes`: pulls up to this long ago, for vendors whose events take a while to
+///   be queryable. Defaults to the puller's lag (e.g. 30 for O365), 0 turns it off.
+/// - `pull_window_minutes`: pulls windows of this size, skipping scheduled pulls until one has
+///   passed since the last pulled time, to pull less often than the schedule.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: okta
+///   properties:
+///     pull_lag_minutes: 5
+///     pull_window_minutes: 15
+/// ```
+///
+/// Windows never end after the current time, in case the scheduled time is ahead of this
+/// Lambda's clock, as events of the rest of the window would be missed.
+fn scheduled_window(
+    ctx: &PullLogsContext,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+    let lag = match ctx.config().get("pull_lag_minutes") {
+        Some(_) => Duration::minutes(minutes_property(ctx, "pull_lag_minutes")?.unwrap_or(0)),
+        None => ctx.log_source_type.default_lag(),
+    };
+    let now = chrono::Utc::now().with_timezone(&end_dt.timezone());
+    if end_dt > now {
+        warn!(
+            "Scheduled window of log_source: {} ends at {}, after the current time {}",
+            ctx.log_source_name, end_dt, now
+        );
+    }
+    let end_dt = end_dt.min(now) - lag;
+    let start_dt = match minutes_property(ctx, "pull_window_minutes")? {
+        Some(window_minutes) => end_dt - Duration::minutes(window_minutes),
+        None => start_dt - lag,
+    };
+    Ok((start_dt, end_dt))
+}
+
+/// A positive number of minutes set with `prop`, None if it's unset or 0.
+fn minutes_property(ctx: &PullLogsContext, prop: &str) -> Result<Option<i64>> {
+    let minutes = match ctx.config().get(prop) {
+        Some(m) => m
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .filter(|m| *m >= 0)
+            .with_context(|| format!("{} must be a non negative integer", prop))?,
+        None => return Ok(None),
+    };
+    Ok(Some(minutes).filter(|m| *m > 0))
+}
+
+/// How much earlier than the window each pull starts, from `lookback_overlap_minutes`.
+fn lookback_overlap(ctx: &PullLogsContext) -> Result<Duration> {
+    let minutes = match ctx.config().get("lookback_overlap_minutes") {
+        Some(m) => m
+            .trim()
+            .parse::<i64>()
+            .context("lookback_overlap_minutes must be an integer")?,
+        None => 0,
+    };
+    if minutes > 0 && ctx.config().get("dedup_id_field").is_none() {
+        warn!(
+            "lookback_overlap_minutes is set without dedup_id_field for log_source: {}, events may be duplicated",
+            ctx.log_source_name
+        );
+    }
+    Ok(Duration::minutes(minutes.max(0)))
+}
+
+/// Enqueues pulls of `[start_dt, end_dt)` in `backfill_window_minutes` windows.
+async fn enqueue_backfill(
+    ctx: &PullLogsContext,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<()> {
+    let window_minutes = match ctx.config().get("backfill_window_minutes") {
+        Some(m) => m
+            .trim()
+            .parse::<i64>()
+            .context("backfill_window_minutes must be an integer")?,
+        None => pullers::CATCH_UP_WINDOW_MINUTES,
+    };
+    let count = pullers::enqueue_catch_up(
+        &ctx.log_source_name,
+        ctx.tenant_id.as_deref(),
+        start_dt,
+        end_dt,
+        window_minutes,
+    )
+    .await?;
+    info!(
+        "Enqueued {} backfill pulls for log_source: {} from {} to {}",
+        count, ctx.log_source_name, start_dt, end_dt
+    );
+    Ok(())
+}
+
+/// Splits a scheduled window into `pull_shards` sub-intervals pulled by separate invocations,
+/// for log sources with too much data for one, returning whether it was split. Shards are
+/// enqueued as catch up pulls of their exact window, so they suit pullers that pull by time
+/// rather than by a cursor in the checkpoint.
+///
+/// The last pulled time advances past the window once its shards are enqueued, not once they
+/// succeed, so the next scheduled pull doesn't wait on them. A shard that fails all its
+/// attempts is sent to the puller DLQ with its window (`matano.window_start` and
+/// `matano.window_end`), and the gap is only filled by redriving it manually.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     pull_shards: 4
+/// ```
+async fn enqueue_shards(
+    ctx: &PullLogsContext,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<bool> {
+    let shards = match ctx.config().get("pull_shards") {
+        Some(n) => n
+            .trim()
+            .parse::<i64>()
+            .context("pull_shards must be an integer")?,
+        None => return Ok(false),
+    };
+    let window_minutes = (end_dt - start_dt).num_minutes();
+    // Shards are at least a minute long.
+    if shards <= 1 || window_minutes < 2 {
+        return Ok(false);
+    }
+    let shard_minutes = (window_minutes + shards - 1) / shards;
+    let count = pullers::enqueue_catch_up(
+        &ctx.log_source_name,
+        ctx.tenant_id.as_deref(),
+        start_dt,
+        end_dt,
+        shard_minutes,
+    )
+    .await?;
+    info!(
+        "Enqueued {} shards of {} minutes for log_source: {} from {} to {}",
+        count, shard_minutes, ctx.log_source_name, start_dt, end_dt
+    );
+    Ok(true)
+}
+
+/// Adds `_tenant_id` to each JSON object line, so the transform can tell tenants apart.
+fn tag_tenant_id(data: Vec<u8>, tenant_id: &str) -> Result<Vec<u8>> {
+    pullers::add_json_field(data, "_tenant_id", &tenant_id.into())
+}
+
+/// Whether to add a `matano` object to each record with where and when it was pulled, so
+/// transforms and detections can reason about ingestion lag and provenance, e.g.
+/// `{"pull_time": "...", "log_source": "...", "tenant_id": "...", "puller_version": "...",
+/// "pull_start": "...", "pull_end": "..."}`.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     ingestion_metadata: true
+/// ```
+fn ingestion_metadata(ctx: &PullLogsContext) -> Result<bool> {
+    match ctx.config().get("ingestion_metadata") {
+        Some(v) => v
+            .trim()
+            .parse::<bool>()
+            .context("ingestion_metadata must be true or false"),
+        None => Ok(false),
+    }
+}
+
+fn add_ingestion_metadata(
+    data: Vec<u8>,
+    ctx: &PullLogsContext,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<Vec<u8>> {
+    if data.is_empty() {
+        return Ok(data);
+    }
+    let rfc3339_utc =
+        |dt: DateTime<chrono::Utc>| dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    let metadata = json!({
+        "pull_time": rfc3339_utc(chrono::Utc::now()),
+        "log_source": ctx.log_source_name,
+        "tenant_id": ctx.tenant_id,
+        "puller_version": env!("CARGO_PKG_VERSION"),
+        "pull_start": rfc3339_utc(start_dt.with_timezone(&chrono::Utc)),
+        "pull_end": rfc3339_utc(end_dt.with_timezone(&chrono::Utc)),
+    });
+    pullers::add_json_field(data, "matano", &metadata)
+}
+
+/// Processes pulled chunks (normalizing, transforming, redacting, validating, tagging and
+/// deduplicating their records) and writes them to the upload as they come in. Returns the new
+/// event IDs, to record once uploaded, and the snapshot hash if `skip_unchanged_snapshots` is
+/// set.
+///
+/// Snapshots are hashed whole, so they're collected and only written once complete. They're
+/// buffered in ephemeral storage past `spill_threshold_mb`, see `SpillBuffer`.
+async fn upload_chunks(
+    ctx: &PullLogsContext,
+    mut chunks: pullers::RecordChunks<'_>,
+    upload: &mut DataUpload<'_>,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<(Vec<String>, Option<String>)> {
+    let add_metadata = ingestion_metadata(ctx)?;
+    let mut snapshot = match skip_unchanged_snapshots(ctx)? {
+        true => {
+            let threshold = pullers::spill_threshold(ctx.config())?;
+            let buffer = pullers::SpillBuffer::new(&ctx.log_source_name, threshold);
+            Some((SnapshotHash::new(), buffer))
+        }
+        false => None,
+    };
+    let mut event_ids = vec![];
+    while let Some(chunk) = chunks.next().await {
+        let data = ctx.normalize_records(chunk?);
+        let data = ctx.pre_transform(data).await?;
+        // Before validation, so quarantined records are redacted too.
+        let data = ctx.redact(data).await?;
+        // Before anything is added to the records, which the schema may not allow.
+        let data = ctx.validate_records(data).await?;
+        // After validation, as the schema may not allow the table field.
+        let data = ctx.route_tables(data)?;
+        let data = match ctx.tenant_id.as_ref() {
+            Some(tenant_id) => tag_tenant_id(data, tenant_id)?,
+            None => data,
+        };
+        let (data, ids) = ctx.dedup(data).await?;
+        event_ids.extend(ids);
+        if data.is_empty() {
+            continue;
+        }
+        match snapshot.as_mut() {
+            Some((hash, buffer)) => {
+                hash.update(&data)?;
+                buffer.write(&data).await?;
+            }
+            None if add_metadata => {
+                upload
+                    .write(add_ingestion_metadata(data, ctx, start_dt, end_dt)?)
+                    .await?
+            }
+            None => upload.write(data).await?,
+        }
+    }
+
+    let (hash, buffer) = match snapshot {
+        Some((hash, buffer)) if !buffer.is_empty() => (hash.finish(), buffer),
+        _ => return Ok((event_ids, None)),
+    };
+    if ctx.snapshot_hash().await?.as_ref() == Some(&hash) {
+        info!(
+            "Skipping unchanged snapshot for log_source: {}",
+            ctx.log_source_name
+        );
+        return Ok((event_ids, Some(hash)));
+    }
+    let mut chunks = buffer.into_chunks().await?;
+    while let Some(data) = chunks.next().await? {
+        // Added last, as the pull time would otherwise defeat the snapshot hash.
+        let data = match add_metadata {
+            true => add_ingestion_metadata(data, ctx, start_dt, end_dt)?,
+            false => data,
+        };
+        upload.write(data).await?;
+    }
+    Ok((event_ids, Some(hash)))
+}
+
+/// Uploads pulled data as it's written, as objects of at most `max_object_size` (uncompressed)
+/// each, and sends it to Kinesis or EventBridge if configured (see `KinesisOutput` and
+/// `EventBridgeOutput`). Only the object being written is held in memory, compressed, see
+/// `ObjectStream`.
+struct DataUpload<'a> {
+    ctx: &'a PullLogsContext,
+    end_dt: DateTime<FixedOffset>,
+    max_object_size: usize,
+    memory_budget: usize,
+    key_template: String,
+    encryption: UploadEncryption,
+    parquet: Option<pullers::ParquetOutput>,
+    dictionary: Option<Arc<ZstdDictionary>>,
+    kinesis_output: Option<pullers::KinesisOutput>,
+    eventbridge_output: Option<pullers::EventBridgeOutput>,
+    /// None if the data is only sent to Kinesis.
+    destination: Option<UploadDestination>,
+    labels: ObjectLabels,
+    manifest: PullManifest,
+    object: Option<PendingObject>,
+    object_records: usize,
+    object_bytes: usize,
+    /// Written in total.
+    records: usize,
+    bytes: usize,
+}
+
+/// The object data is being written to.
+enum PendingObject {
+    /// Parquet objects are written whole, see `upload_object`.
+    Whole {
+        key: String,
+        data: Vec<u8>,
+    },
+    Streamed(ObjectStream),
+}
+
+impl PendingObject {
+    /// Object data held in memory until it's uploaded.
+    fn buffered_bytes(&self) -> usize {
+        match self {
+            PendingObject::Whole { data, .. } => data.len(),
+            PendingObject::Streamed(object) => object.zencoder.get_ref().len(),
+        }
+    }
+}
+
+impl<'a> DataUpload<'a> {
+    async fn new(
+        ctx: &'a PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<DataUpload<'a>> {
+        let parquet = pullers::ParquetOutput::from_config(ctx.config())?;
+        let key_template = ctx
+            .config()
+            .get("s3_key_template")
+            .map(|t| t.as_str())
+            .unwrap_or(match parquet {
+                Some(_) => DEFAULT_PARQUET_S3_KEY_TEMPLATE,
+                None => DEFAULT_S3_KEY_TEMPLATE,
+            })
+            .to_string();
+        let kinesis_output = pullers::KinesisOutput::from_config(ctx.config())?;
+        let destination = match kinesis_output.as_ref() {
+            Some(output) if !output.output_to_s3() => None,
+            _ => Some(UploadDestination::from_config(ctx.config()).await?),
+        };
+        // Parquet objects are compressed by the Parquet writer.
+        let dictionary = match parquet {
+            Some(_) => None,
+            None => ctx.zstd_dictionary().cloned(),
+        };
+        let labels = ObjectLabels::new(ctx, start_dt, end_dt).dictionary(dictionary.as_deref());
+        Ok(DataUpload {
+            ctx,
+            end_dt,
+            max_object_size: max_object_size(ctx)?,
+            memory_budget: memory_budget(ctx)?,
+            key_template,
+            encryption: UploadEncryption::from_config(ctx.config()),
+            parquet,
+            dictionary,
+            kinesis_output,
+            eventbridge_output: pullers::EventBridgeOutput::from_config(ctx.config()),
+            destination,
+            labels,
+            manifest: PullManifest::new(ctx, start_dt, end_dt),
+            object: None,
+            object_records: 0,
+            object_bytes: 0,
+            records: 0,
+            bytes: 0,
+        })
+    }
+
+    /// Writes NDJSON records, starting a new object at a line boundary when the current one
+    /// would exceed `max_object_size`. A single line longer than that is kept whole.
+    async fn write(&mut self, data: Vec<u8>) -> Result<()> {
+        let log_source = self.ctx.log_source_name.as_str();
+        self.records += count_records(&data);
+        self.bytes += data.len();
+        if let Some(output) = self.kinesis_output.as_ref() {
+            output.send(log_source, &data).await?;
+        }
+        if let Some(output) = self.eventbridge_output.as_ref() {
+            output.send(log_source, &data).await?;
+        }
+        if self.destination.is_none() {
+            return Ok(());
+        }
+
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            // Lines in an object are separated by a newline.
+            let separator = (self.object_bytes > 0) as usize;
+            let remaining = self
+                .max_object_size
+                .saturating_sub(self.object_bytes + separator);
+            let split_at = if rest.len() <= remaining {
+                rest.len()
+            } else {
+                match rest[..=remaining].iter().rposition(|b| *b == b'\n') {
+                    Some(i) => i,
+                    None if self.object_bytes == 0 => {
+                        rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len())
+                    }
+                    None => {
+                        self.finish_object().await?;
+                        continue;
+                    }
+                }
+            };
+            let (lines, tail) = rest.split_at(split_at);
+            if !lines.is_empty() {
+                self.write_to_object(lines).await?;
+            }
+            self.flush_over_budget().await?;
+            rest = tail.strip_prefix(b"\n").unwrap_or(tail);
+            if !rest.is_empty() {
+                self.finish_object().await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_to_object(&mut self, lines: &[u8]) -> Result<()> {
+        let destination = self.destination.as_ref().unwrap();
+        if self.object.is_none() {
+            let key = destination.key(object_key(
+                &self.key_template,
+                self.ctx.destination_log_source(),
+                self.ctx.tenant_id.as_deref(),
+                self.end_dt,
+            ));
+            self.object = Some(match self.parquet.is_some() {
+                true => PendingObject::Whole { key, data: vec![] },
+                false => {
+                    info!("Writing to s3://{}/{}", destination.target().1, key);
+                    PendingObject::Streamed(ObjectStream::new(key, self.dictionary.as_deref())?)
+                }
+            });
+        }
+        let separator: &[u8] = match self.object_bytes {
+            0 => b"",
+            _ => b"\n",
+        };
+        match self.object.as_mut().unwrap() {
+            PendingObject::Whole { data, .. } => {
+                data.extend_from_slice(separator);
+                data.extend_from_slice(lines);
+            }
+            PendingObject::Streamed(object) => {
+                let (labels, encryption) = (&self.labels, &self.encryption);
+                let retry_policy = &self.ctx.retry_policy;
+                for data in [separator, lines] {
+                    let res = object
+                        .write(data, destination, labels, encryption, retry_policy)
+                        .await;
+                    if res.is_err() {
+                        destination.upload_failed();
+                    }
+                    res.context(PullerError::S3)?;
+                }
+            }
+        }
+        self.object_records += count_records(lines);
+        self.object_bytes += separator.len() + lines.len();
+        Ok(())
+    }
+
+    /// Completes the current object early if the data it holds in memory reached the budget,
+    /// see `memory_budget`.
+    async fn flush_over_budget(&mut self) -> Result<()> {
+        let buffered = match self.object.as_ref() {
+            Some(object) => object.buffered_bytes(),
+            None => return Ok(()),
+        };
+        if buffered < self.memory_budget {
+            return Ok(());
+        }
+        info!(
+            "Flushing object for log_source: {} at memory budget, {} bytes buffered",
+            self.ctx.log_source_name, buffered
+        );
+        self.finish_object().await
+    }
+
+    /// Completes the current object, if any, and adds it to the manifest.
+    async fn finish_object(&mut self) -> Result<()> {
+        let object = match self.object.take() {
+            Some(object) => object,
+            None => return Ok(()),
+        };
+        let destination = self.destination.as_ref().unwrap();
+        let (labels, encryption) = (&self.labels, &self.encryption);
+        let retry_policy = &self.ctx.retry_policy;
+        let (key, res) = match object {
+            PendingObject::Whole { key, data } => {
+                let res = upload_object(
+                    &data,
+                    destination,
+                    &key,
+                    labels,
+                    encryption,
+                    self.parquet.as_ref(),
+                    retry_policy,
+                )
+                .await;
+                (key, res)
+            }
+            PendingObject::Streamed(object) => {
+                let key = object.key.clone();
+                let res = object
+                    .finish(destination, labels, encryption, retry_policy)
+                    .await;
+                (key, res)
+            }
+        };
+        if res.is_err() {
+            destination.upload_failed();
+        }
+        let compressed_bytes = res.context(PullerError::S3)?;
+        self.manifest.add_object(
+            destination.target().1,
+            &key,
+            self.object_records,
+            self.object_bytes,
+            compressed_bytes,
+        );
+        self.object_records = 0;
+        self.object_bytes = 0;
+        Ok(())
+    }
+
+    /// Completes the upload, returns whether any data was written.
+    async fn finish(mut self) -> Result<bool> {
+        let log_source = self.ctx.log_source_name.as_str();
+        if self.bytes == 0 {
+            info!("No new data for log_source: {}", log_source);
+            return Ok(false);
+        }
+        self.finish_object().await?;
+        // The data is already uploaded, so a missing manifest shouldn't fail (and repeat) the pull.
+        if let Some(destination) = self.destination.as_ref() {
+            if let Err(e) = self.manifest.upload(destination).await {
+                error!("Failed to write pull manifest for {}: {:#}", log_source, e);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Aborts the object being written after a failed pull, so its parts aren't kept.
+    async fn abort(&mut self) {
+        if let Some(PendingObject::Streamed(object)) = self.object.take() {
+            object.abort().await;
+        }
+    }
+}
+
+fn count_records(data: &[u8]) -> usize {
+    data.split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .count()
+}
+
+/// A record of what a pull uploaded, written to `_manifests/` in the destination bucket (under
+/// `s3_key_prefix`, if set) after the data, so operators can audit exactly what was ingested
+/// and when. Not under a log source prefix, so manifests aren't ingested themselves.
+#[derive(Debug, Serialize)]
+struct PullManifest {
+    log_source: String,
+    tenant_id: Option<String>,
+    window_start: String,
+    window_end: String,
+    uploaded_at: String,
+    record_count: usize,
+    bytes: usize,
+    compressed_bytes: usize,
+    objects: Vec<ManifestObject>,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestObject {
+    bucket: String,
+    key: String,
+    record_count: usize,
+    bytes: usize,
+    compressed_bytes: usize,
+}
+
+impl PullManifest {
+    fn new(
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> PullManifest {
+        PullManifest {
+            log_source: ctx.log_source_name.clone(),
+            tenant_id: ctx.tenant_id.clone(),
+            window_start: start_dt.to_rfc3339(),
+            window_end: end_dt.to_rfc3339(),
+            uploaded_at: String::new(),
+            record_count: 0,
+            bytes: 0,
+            compressed_bytes: 0,
+            objects: vec![],
+        }
+    }
+
+    fn add_object(
+        &mut self,
+        bucket: &str,
+        key: &str,
+        record_count: usize,
+        bytes: usize,
+        compressed_bytes: usize,
+    ) {
+        self.record_count += record_count;
+        self.bytes += bytes;
+        self.compressed_bytes += compressed_bytes;
+        self.objects.push(ManifestObject {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            record_count,
+            bytes,
+            compressed_bytes,
+        });
+    }
+
+    async fn upload(&mut self, destination: &UploadDestination) -> Result<()> {
+        let now = chrono::Utc::now();
+        self.uploaded_at = now.to_rfc3339();
+        let key = destination.key(format!(
+            "_manifests/{}/ts_hour={}/{}.json",
+            self.log_source,
+            now.format("%Y-%m-%d-%H"),
+            uuid::Uuid::new_v4()
+        ));
+        let (s3, bucket) = destination.target();
+        s3.put_object()
+            .bucket(bucket)
+            .key(&key)
+            .body(ByteStream::from(serde_json::to_vec(&self)?))
+            .content_type("application/json")
+            .send()
+            .await
+            .with_context(|| format!("Error putting {} to S3", key))?;
+        debug!("Wrote pull manifest to {}", key);
+        Ok(())
+    }
+}
+
+/// Where pulled data is uploaded. Defaults to the ingestion bucket, a log source can write to
+/// another bucket instead, e.g. in another account or region for data residency. Ingesting
+/// from that bucket is then up to its owner. The bucket policy must allow the puller's role
+/// to put objects.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     s3_bucket: eu-security-logs
+///     # optional, if the bucket isn't in the puller's region
+///     s3_bucket_region: eu-central-1
+///     # optional, prepended to each object key
+///     s3_key_prefix: matano/raw
+/// ```
+///
+/// Uploads to the ingestion bucket fail over to a secondary bucket if one is set, see
+/// `upload_failover`.
+struct UploadDestination {
+    s3: aws_sdk_s3::Client,
+    bucket: String,
+    /// None if the bucket is in the function's region.
+    region: Option<String>,
+    key_prefix: Option<String>,
+    failover: Option<(aws_sdk_s3::Client, FailoverBucket)>,
+}
+
+impl UploadDestination {
+    async fn from_config(config: &HashMap<String, String>) -> Result<UploadDestination> {
+        let get = |prop: &str| {
+            config
+                .get(prop)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let (bucket, failover) = match get("s3_bucket") {
+            Some(bucket) => (bucket, None),
+            None => (
+                std::env::var("INGESTION_BUCKET_NAME")?,
+                FailoverBucket::from_env(),
+            ),
+        };
+        let region = get("s3_bucket_region");
+        let s3 = bucket_s3_client(region.clone()).await;
+        let failover = match failover {
+            Some(failover) => Some((bucket_s3_client(failover.region.clone()).await, failover)),
+            None => None,
+        };
+        let key_prefix = get("s3_key_prefix").map(|p| p.trim_matches('/').to_string());
+        Ok(UploadDestination {
+            s3,
+            bucket,
+            region,
+            key_prefix,
+            failover,
+        })
+    }
+
+    /// The client and bucket to upload to, the failover bucket while uploads to the bucket
+    /// are failing.
+    fn target(&self) -> (&aws_sdk_s3::Client, &str) {
+        match self.failover.as_ref() {
+            Some((s3, failover)) if upload_failover::is_failing(&self.bucket) => {
+                (s3, &failover.bucket)
+            }
+            _ => (&self.s3, &self.bucket),
+        }
+    }
+
+    /// Fails over the next uploads (e.g. of the retried pull) after a failed upload, if
+    /// there's a failover bucket. Failed single request uploads fail over right away, see
+    /// `put_object`.
+    fn upload_failed(&self) {
+        if self.failover.is_some() {
+            upload_failover::mark_failed(self.target().1);
+        }
+    }
+
+    fn key(&self, key: String) -> String {
+        match self.key_prefix.as_ref() {
+            Some(prefix) if !prefix.is_empty() => format!("{}/{}", prefix, key),
+            _ => key,
+        }
+    }
+}
+
+async fn bucket_s3_client(region: Option<String>) -> aws_sdk_s3::Client {
+    match region {
+        Some(region) => regional_s3_client(region).await,
+        None => S3_CLIENT.get().await.clone(),
+    }
+}
+
+async fn regional_s3_client(region: String) -> aws_sdk_s3::Client {
+    let cached = REGIONAL_S3_CLIENTS.lock().unwrap().get(&region).cloned();
+    if let Some(client) = cached {
+        return client;
+    }
+    let client = s3_client(AWS_CONFIG.get().await, Some(region.clone()));
+    REGIONAL_S3_CLIENTS
+        .lock()
+        .unwrap()
+        .insert(region, client.clone());
+    client
+}
+
+/// An S3 client, in another region than the function's if set. Uses the endpoint from
+/// `AWS_ENDPOINT_URL_S3` or `AWS_ENDPOINT_URL` if set, with path style addressing, e.g. for
+/// LocalStack.
+pub(crate) fn s3_client(sdk_config: &SdkConfig, region: Option<String>) -> aws_sdk_s3::Client {
+    let mut builder = aws_sdk_s3::config::Builder::from(sdk_config);
+    if let Some(region) = region {
+        builder = builder.region(aws_sdk_s3::Region::new(region));
+    }
+    if let Some(endpoint_url) = shared::aws_endpoint_url("S3") {
+        builder = builder.endpoint_url(endpoint_url).force_path_style(true);
+    }
+    aws_sdk_s3::Client::from_conf(builder.build())
+}
+
+/// Where an uploaded object came from, as object tags (e.g. `matano:log_source`) for
+/// lifecycle rules, and as metadata (e.g. `x-amz-meta-matano-log-source`) for forensics.
+struct ObjectLabels {
+    labels: Vec<(&'static str, String)>,
+    /// Only in the metadata, see `shared::zstd_dictionary`.
+    dictionary_id: Option<String>,
+}
+
+impl ObjectLabels {
+    fn new(
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> ObjectLabels {
+        let rfc3339_utc = |dt: DateTime<FixedOffset>| {
+            dt.with_timezone(&chrono::Utc)
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        };
+        let mut labels = vec![
+            ("log_source", ctx.log_source_name.clone()),
+            ("pull_start", rfc3339_utc(start_dt)),
+            ("pull_end", rfc3339_utc(end_dt)),
+            ("puller_version", env!("CARGO_PKG_VERSION").to_string()),
+        ];
+        if let Some(tenant_id) = ctx.tenant_id.as_ref() {
+            labels.push(("tenant_id", tenant_id.clone()));
+        }
+        ObjectLabels {
+            labels,
+            dictionary_id: None,
+        }
+    }
+
+    /// Names the dictionary objects are compressed with.
+    fn dictionary(mut self, dictionary: Option<&ZstdDictionary>) -> ObjectLabels {
+        self.dictionary_id = dictionary.map(|d| d.id.clone());
+        self
+    }
+
+    /// URL encoded, as S3 expects the tagging header.
+    fn tagging(&self) -> String {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        for (name, value) in &self.labels {
+            serializer.append_pair(&format!("matano:{}", name), value);
+        }
+        serializer.finish()
+    }
+
+    fn metadata(&self) -> HashMap<String, String> {
+        let mut metadata = self
+            .labels
+            .iter()
+            .map(|(name, value)| (format!("matano-{}", name.replace('_', "-")), value.clone()))
+            .collect::<HashMap<_, _>>();
+        if let Some(id) = self.dictionary_id.as_ref() {
+            metadata.insert(
+                shared::zstd_dictionary::ZSTD_DICTIONARY_METADATA.to_string(),
+                id.clone(),
+            );
+        }
+        metadata
+    }
+}
+
+/// Encryption of uploaded objects, in addition to the bucket's default encryption.
+///
+/// `s3_sse_kms_key_id` encrypts objects server side (SSE-KMS) with the given key.
+/// `client_side_encryption_kms_key_id` encrypts objects before upload with a data key from the
+/// given key (see `shared::envelope`), for sensitive sources whose raw data shouldn't be
+/// readable with access to the bucket alone. The key must be tagged `matano:trusted` for the
+/// puller and transformer to use it.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     s3_sse_kms_key_id: arn:aws:kms:us-east-1:123456789012:key/1234abcd-12ab-34cd-56ef-1234567890ab
+///     client_side_encryption_kms_key_id: alias/matano-sensitive-logs
+/// ```
+#[derive(Debug)]
+struct UploadEncryption {
+    sse_kms_key_id: Option<String>,
+    envelope_kms_key_id: Option<String>,
+}
+
+impl UploadEncryption {
+    fn from_config(config: &HashMap<String, String>) -> UploadEncryption {
+        let get = |prop: &str| {
+            config
+                .get(prop)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        UploadEncryption {
+            sse_kms_key_id: get("s3_sse_kms_key_id"),
+            envelope_kms_key_id: get("client_side_encryption_kms_key_id"),
+        }
+    }
+
+    fn server_side_encryption(&self) -> Option<ServerSideEncryption> {
+        self.sse_kms_key_id
+            .as_ref()
+            .map(|_| ServerSideEncryption::AwsKms)
+    }
+}
+
+#[instrument(name = "s3_upload", skip_all, fields(bucket = %destination.bucket, key = %key))]
+async fn upload_object(
+    data: &[u8],
+    destination: &UploadDestination,
+    key: &str,
+    labels: &ObjectLabels,
+    encryption: &UploadEncryption,
+    parquet: Option<&pullers::ParquetOutput>,
+    retry_policy: &RetryPolicy,
+) -> Result<usize> {
+    info!("Writing to s3://{}/{}", destination.target().1, key);
+
+    // Parquet is written whole, the footer describes the entire file.
+    if let Some(parquet) = parquet {
+        let body = parquet
+            .serialize(data)
+            .with_context(|| format!("Error writing Parquet for {}", key))?;
+        let mut metadata = labels.metadata();
+        let body = encrypt_object(body, key, encryption, &mut metadata).await?;
+        let size = body.len();
+        let object = ObjectBody {
+            data: body.into(),
+            content_encoding: None,
+            metadata,
+            tagging: labels.tagging(),
+        };
+        put_object(destination, key, object, encryption, retry_policy).await?;
+        return Ok(size);
+    }
+
+    let mut object = ObjectStream::new(key.to_string(), None)?;
+    if let Err(e) = object
+        .write(data, destination, labels, encryption, retry_policy)
+        .await
+    {
+        object.abort().await;
+        return Err(e);
+    }
+    object
+        .finish(destination, labels, encryption, retry_policy)
+        .await
+}
+
+/// Encrypts an object body if `client_side_encryption_kms_key_id` is set, adding the envelope
+/// to the object's metadata.
+async fn encrypt_object(
+    body: Vec<u8>,
+    key: &str,
+    encryption: &UploadEncryption,
+    metadata: &mut HashMap<String, String>,
+) -> Result<Vec<u8>> {
+    match encryption.envelope_kms_key_id.as_ref() {
+        Some(kms_key_id) => {
+            let (encrypted, envelope_metadata) = shared::envelope::encrypt(kms_key_id, body)
+                .await
+                .with_context(|| format!("Error encrypting {}", key))?;
+            metadata.extend(envelope_metadata);
+            Ok(encrypted)
+        }
+        None => Ok(body),
+    }
+}
+
+/// An object compressed as data is written to it, so the uncompressed data doesn't have to be
+/// held until the upload. Large objects are uploaded in parts as they're compressed, instead of
+/// holding the whole compressed payload in memory, small ones in a single request when
+/// finished.
+///
+/// Client side encrypted objects are uploaded whole, the nonce and tag cover the entire
+/// payload, but only their compressed data is held.
+struct ObjectStream {
+    key: String,
+    zencoder: zstd::Encoder<'static, Vec<u8>>,
+    check: CompressionCheck,
+    multipart: Option<MultipartUpload>,
+    /// Entered for each chunk, so its busy time is the total compression time.
+    compress_span: tracing::Span,
+    bytes: usize,
+    compressed_bytes: usize,
+}
+
+impl ObjectStream {
+    fn new(key: String, dictionary: Option<&ZstdDictionary>) -> Result<ObjectStream> {
+        let zencoder = match dictionary {
+            Some(dictionary) => zstd::Encoder::with_dictionary(vec![], 0, &dictionary.data)?,
+            None => zstd::Encoder::new(vec![], 0)?,
+        };
+        Ok(ObjectStream {
+            key,
+            zencoder,
+            check: CompressionCheck::new(dictionary)?,
+            multipart: None,
+            compress_span: info_span!("compress"),
+            bytes: 0,
+            compressed_bytes: 0,
+        })
+    }
+
+    async fn write(
+        &mut self,
+        data: &[u8],
+        destination: &UploadDestination,
+        labels: &ObjectLabels,
+        encryption: &UploadEncryption,
+        retry_policy: &RetryPolicy,
+    ) -> Result<()> {
+        for chunk in data.chunks(COMPRESS_CHUNK_SIZE) {
+            let zencoder = &mut self.zencoder;
+            self.compress_span.in_scope(|| zencoder.write_all(chunk))?;
+            self.bytes += chunk.len();
+            let is_whole = encryption.envelope_kms_key_id.is_some();
+            if !is_whole && self.zencoder.get_ref().len() >= MULTIPART_PART_SIZE {
+                let part = std::mem::take(self.zencoder.get_mut());
+                self.check.update(&part)?;
+                self.compressed_bytes += part.len();
+                if self.multipart.is_none() {
+                    let upload =
+                        MultipartUpload::create(destination, &self.key, labels, encryption).await?;
+                    self.multipart = Some(upload);
+                }
+                let upload = self.multipart.as_mut().unwrap();
+                if let Err(e) = upload.upload_part(part, retry_policy).await {
+                    upload.abort().await;
+                    self.multipart = None;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Uploads the rest of the object, returns its compressed size.
+    async fn finish(
+        self,
+        destination: &UploadDestination,
+        labels: &ObjectLabels,
+        encryption: &UploadEncryption,
+        retry_policy: &RetryPolicy,
+    ) -> Result<usize> {
+        let ObjectStream {
+            key,
+            zencoder,
+            mut check,
+            multipart,
+            compress_span,
+            bytes,
+            mut compressed_bytes,
+        } = self;
+        let final_data = compress_span.in_scope(|| zencoder.finish())?;
+        check.update(&final_data)?;
+        compressed_bytes += final_data.len();
+        let verified = check
+            .verify(bytes)
+            .with_context(|| format!("Compressed data for {} is corrupt", key));
+
+        if let Some(mut upload) = multipart {
+            if let Err(e) = verified {
+                upload.abort().await;
+                return Err(e);
+            }
+            let res = match upload.upload_part(final_data, retry_policy).await {
+                Ok(()) => upload.complete().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                upload.abort().await;
+                return Err(e);
+            }
+            ingestion_notify::notify_uploaded(&upload.bucket, &key, compressed_bytes).await;
+            return Ok(compressed_bytes);
+        }
+
+        verified?;
+        let mut metadata = labels.metadata();
+        let body = encrypt_object(final_data, &key, encryption, &mut metadata).await?;
+        let size = body.len();
+        let object = ObjectBody {
+            data: body.into(),
+            content_encoding: Some("application/zstd".to_string()),
+            metadata,
+            tagging: labels.tagging(),
+        };
+        put_object(destination, &key, object, encryption, retry_policy).await?;
+        Ok(size)
+    }
+
+    /// Aborts the multipart upload, if one was started.
+    async fn abort(self) {
+        if let Some(upload) = self.multipart {
+            upload.abort().await;
+        }
+    }
+}
+
+/// Decompresses encoder output as it's produced, to check it decodes to as many bytes as
+/// were compressed before the upload completes.
+struct CompressionCheck {
+    decoder: zstd::stream::write::Decoder<'static, ByteCounter>,
+}
+
+impl CompressionCheck {
+    fn new(dictionary: Option<&ZstdDictionary>) -> Result<CompressionCheck> {
+        let decoder = match dictionary {
+            Some(dictionary) => {
+                zstd::stream::write::Decoder::with_dictionary(ByteCounter(0), &dictionary.data)?
+            }
+            None => zstd::stream::write::Decoder::new(ByteCounter(0))?,
+        };
+        Ok(CompressionCheck { decoder })
+    }
+
+    fn update(&mut self, compressed: &[u8]) -> Result<()> {
+        self.decoder
+            .write_all(compressed)
+            .context("Invalid compressed data")
+    }
+
+    fn verify(mut self, expected_len: usize) -> Result<()> {
+        self.decoder.flush()?;
+        let len = self.decoder.get_ref().0;
+        if len != expected_len {
+            return Err(anyhow!(
+                "Compressed data decodes to {} bytes, expected {}",
+                len,
+                expected_len
+            ));
+        }
+        Ok(())
+    }
+}
+
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Base64 encoded SHA256 of an upload, S3 rejects the upload if the body doesn't match.
+fn sha256_checksum(data: &[u8]) -> String {
+    base64::encode(ring::digest::digest(&ring::digest::SHA256, data))
+}
+
+/// The body of an object uploaded in one request.
+struct ObjectBody {
+    /// Cloned for each attempt without copying the data.
+    data: Bytes,
+    content_encoding: Option<String>,
+    metadata: HashMap<String, String>,
+    tagging: String,
+}
+
+/// Puts an object in the destination. If that fails and there's a failover bucket, it's put
+/// there instead, or else spooled to ephemeral storage, see `upload_failover`.
+#[instrument(name = "s3_put_object", skip_all)]
+async fn put_object(
+    destination: &UploadDestination,
+    key: &str,
+    object: ObjectBody,
+    encryption: &UploadEncryption,
+    retry_policy: &RetryPolicy,
+) -> Result<()> {
+    let (s3, bucket) = destination.target();
+    let err = match put_object_to(s3, bucket, key, &object, encryption, retry_policy).await {
+        Ok(()) => {
+            ingestion_notify::notify_uploaded(bucket, key, object.data.len()).await;
+            return Ok(());
+        }
+        Err(e) => e,
+    };
+    let (failover_s3, failover) = match destination.failover.as_ref() {
+        Some(failover) => failover,
+        None => return Err(err),
+    };
+    upload_failover::mark_failed(bucket);
+    if bucket != failover.bucket {
+        let res = put_object_to(
+            failover_s3,
+            &failover.bucket,
+            key,
+            &object,
+            encryption,
+            retry_policy,
+        )
+        .await;
+        match res {
+            Ok(()) => {
+                ingestion_notify::notify_uploaded(&failover.bucket, key, object.data.len()).await;
+                return Ok(());
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                upload_failover::mark_failed(&failover.bucket);
+            }
+        }
+    }
+
+    let spooled = SpooledObject {
+        bucket: destination.bucket.clone(),
+        region: destination.region.clone(),
+        key: key.to_string(),
+        content_encoding: object.content_encoding.clone(),
+        metadata: object.metadata.clone(),
+        tagging: object.tagging.clone(),
+        sse_kms_key_id: encryption.sse_kms_key_id.clone(),
+    };
+    match upload_failover::spool(&spooled, &object.data).await {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            error!("{:#}", e);
+            Err(err)
+        }
+    }
+}
+
+async fn put_object_to(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    object: &ObjectBody,
+    encryption: &UploadEncryption,
+    retry_policy: &RetryPolicy,
+) -> Result<()> {
+    let checksum = &sha256_checksum(&object.data);
+    retry_policy
+        .retry("S3 upload", || async move {
+            s3.put_object()
+                .bucket(bucket)
+                .key(key)
+                .body(ByteStream::from(object.data.clone()))
+                .set_content_encoding(object.content_encoding.clone())
+                .set_metadata(Some(object.metadata.clone()))
+                .tagging(&object.tagging)
+                .checksum_sha256(checksum)
+                .set_server_side_encryption(encryption.server_side_encryption())
+                .set_ssekms_key_id(encryption.sse_kms_key_id.clone())
+                .send()
+                .await
+                .map_err(|e| anyhow!(e).context(format!("Error putting {} to S3", key)))
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Uploads objects spooled while S3 was failing, see `upload_failover`. Stops at the first
+/// failure, the rest are tried again by the next invocation.
+async fn upload_spooled_objects() {
+    let spooled = match upload_failover::spooled().await {
+        Ok(spooled) => spooled,
+        Err(e) => {
+            error!("Failed to read upload spool: {:#}", e);
+            return;
+        }
+    };
+    let failover = FailoverBucket::from_env();
+    for (path, object) in spooled {
+        if let Err(e) = upload_spooled_object(&path, &object, failover.as_ref()).await {
+            error!("Failed to upload spooled {}: {:#}", object.key, e);
+            break;
+        }
+    }
+}
+
+async fn upload_spooled_object(
+    path: &Path,
+    spooled: &SpooledObject,
+    failover: Option<&FailoverBucket>,
+) -> Result<()> {
+    let (bucket, region) = match failover {
+        Some(failover) if upload_failover::is_failing(&spooled.bucket) => {
+            (&failover.bucket, failover.region.clone())
+        }
+        _ => (&spooled.bucket, spooled.region.clone()),
+    };
+    let object = ObjectBody {
+        data: upload_failover::read_body(path).await?.into(),
+        content_encoding: spooled.content_encoding.clone(),
+        metadata: spooled.metadata.clone(),
+        tagging: spooled.tagging.clone(),
+    };
+    // Client side encryption was applied before the object was spooled.
+    let encryption = UploadEncryption {
+        sse_kms_key_id: spooled.sse_kms_key_id.clone(),
+        envelope_kms_key_id: None,
+    };
+    let s3 = bucket_s3_client(region).await;
+    let retry_policy = RetryPolicy::default();
+    let res = put_object_to(
+        &s3,
+        bucket,
+        &spooled.key,
+        &object,
+        &encryption,
+        &retry_policy,
+    )
+    .await;
+    if let Err(e) = res {
+        upload_failover::mark_failed(bucket);
+        return Err(e);
+    }
+    info!("Uploaded spooled object to s3://{}/{}", bucket, spooled.key);
+    ingestion_notify::notify_uploaded(bucket, &spooled.key, object.data.len()).await;
+    upload_failover::remove(path).await
+}
+
+/// An S3 multipart upload of compressed pulled data.
+struct MultipartUpload {
+    s3: aws_sdk_s3::Client,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    parts: Vec<CompletedPart>,
+}
+
+impl MultipartUpload {
+    async fn create(
+        destination: &UploadDestination,
+        key: &str,
+        labels: &ObjectLabels,
+        encryption: &UploadEncryption,
+    ) -> Result<MultipartUpload> {
+        let (s3, bucket) = destination.target();
+        let res = s3
+            .create_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .content_encoding("application/zstd".to_string())
+            .set_metadata(Some(labels.metadata()))
+            .tagging(labels.tagging())
+            .set_server_side_encryption(encryption.server_side_encryption())
+            .set_ssekms_key_id(encryption.sse_kms_key_id.clone())
+            .checksum_algorithm(ChecksumAlgorithm::Sha256)
+            .send()
+            .await
+            .with_context(|| format!("Error creating multipart upload for {}", key))?;
+        let upload_id = res
+            .upload_id()
+            .context("Missing multipart upload id")?
+            .to_string();
+        debug!("Started multipart upload for {}", key);
+        Ok(MultipartUpload {
+            s3: s3.clone(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            upload_id,
+            parts: vec![],
+        })
+    }
+
+    #[instrument(name = "s3_upload_part", skip_all, fields(part_number = self.parts.len() + 1))]
+    async fn upload_part(&mut self, part: Vec<u8>, retry_policy: &RetryPolicy) -> Result<()> {
+        let part_number = self.parts.len() as i32 + 1;
+        let checksum = sha256_checksum(&part);
+        // Cloned for each attempt without copying the data.
+        let part = Bytes::from(part);
+        let (this, part, checksum_ref) = (&*self, &part, &checksum);
+        let res = retry_policy
+            .retry("S3 part upload", || async move {
+                this.s3
+                    .upload_part()
+                    .bucket(&this.bucket)
+                    .key(&this.key)
+                    .upload_id(&this.upload_id)
+                    .part_number(part_number)
+                    .body(ByteStream::from(part.clone()))
+                    .checksum_sha256(checksum_ref)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        anyhow!(e).context(format!(
+                            "Error uploading part {} of {} to S3",
+                            part_number, this.key
+                        ))
+                    })
+            })
+            .await?;
+        self.parts.push(
+            CompletedPart::builder()
+                .set_e_tag(res.e_tag().map(|s| s.to_string()))
+                .checksum_sha256(checksum)
+                .part_number(part_number)
+                .build(),
+        );
+        Ok(())
+    }
+
+    async fn complete(&mut self) -> Result<()> {
+        let parts = CompletedMultipartUpload::builder()
+            .set_parts(Some(std::mem::take(&mut self.parts)))
+            .build();
+        self.s3
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .multipart_upload(parts)
+            .send()
+            .await
+            .with_context(|| format!("Error completing multipart upload for {}", self.key))?;
+        Ok(())
+    }
+
+    /// Aborts the upload so the parts aren't kept (and billed), errors are only logged.
+    async fn abort(&self) {
+        let res = self
+            .s3
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .send()
+            .await;
+        if let Err(e) = res {
+            error!("Failed to abort multipart upload for {}: {}", self.key, e);
+        }
+    }
 }
@@ -1,12 +1,12 @@
 use anyhow::{anyhow, Context as AnyhowContext, Error, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, FixedOffset};
-use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     io::{Read, Write},
 };
+use tracing::{debug, error, info};
 
 use super::{PullLogs, PullLogsContext};
 
@@ -42,7 +42,11 @@ impl PullLogs for AbuseChUrlhausPuller {
         end_dt: DateTime<FixedOffset>,
     ) -> Result<Vec<u8>> {
         info!("Pulling URLhaus...");
-        let resp = client.get(URLHAUS_URL).send().await?.bytes().await?;
+        let resp = ctx
+            .send(&client, client.get(URLHAUS_URL))
+            .await?
+            .bytes()
+            .await?;
         let mut zipfile = zip::ZipArchive::new(std::io::Cursor::new(resp))?;
         let csvfile = zipfile.by_name("csv.txt")?;
 
@@ -74,13 +78,11 @@ impl PullLogs for AbuseChMalwareBazaarPuller {
         end_dt: DateTime<FixedOffset>,
     ) -> Result<Vec<u8>> {
         info!("Pulling malware bazaar");
-        let resp = client
+        let req = client
             .post(MALWAREBAZAAR_URL)
-            .form(&[("query", "get_recent"), ("selector", "time")])
-            .send()
-            .await?
-            .json::<serde_json::Value>()
-            .await?;
+            .form(&[("query", "get_recent"), ("selector", "time")]);
+        let resp = ctx.send(&client, req).await?;
+        let resp: serde_json::Value = ctx.response_json(resp).await?;
 
         let query_status = resp
             .get("query_status")
@@ -136,13 +138,10 @@ impl PullLogs for AbuseChThreatfoxPuller {
             "{{\"query\":\"get_iocs\",\"days\":\"{}\"}}",
             days_to_retrieve
         );
-        let resp = client
-            .post(THREATFOX_URL)
-            .body(body)
-            .send()
-            .await?
-            .json::<serde_json::Value>()
+        let resp = ctx
+            .send(&client, client.post(THREATFOX_URL).body(body))
             .await?;
+        let resp: serde_json::Value = ctx.response_json(resp).await?;
 
         let data = resp
             .get("data")
@@ -16,8 +16,9 @@ use aws_smithy_types::DateTime as SmithyDateTime;
 use aws_smithy_types_convert::date_time::DateTimeExt;
 use chrono::{DateTime, FixedOffset};
 use lazy_static::lazy_static;
-use log::{debug, error, info};
+use tracing::{debug, error, info};
 
+use super::aws_role::AwsRole;
 use super::{PullLogs, PullLogsContext};
 use async_once::AsyncOnce;
 use shared::JsonValueExt;
@@ -43,6 +44,10 @@ fn make_inspector_raw_client() -> aws_smithy_client::Client {
 
 #[async_trait]
 impl PullLogs for AmazonInspectorPuller {
+    fn counts_requests(&self) -> bool {
+        false
+    }
+
     async fn pull_logs(
         self,
         client: reqwest::Client,
@@ -54,7 +59,20 @@ impl PullLogs for AmazonInspectorPuller {
 
         let raw_client = &INSPECTOR_RAW_CLIENT;
         let sdk_conf = AWS_CONFIG.get().await;
-        let client_conf = aws_sdk_inspector2::config::Config::new(sdk_conf);
+        // Findings of another account are listed with its role, see `AwsRole`.
+        let client_conf = match AwsRole::from_config(ctx.config()) {
+            Some(role) => {
+                let region = match ctx.config().get("aws_region") {
+                    Some(region) => aws_sdk_s3::Region::new(region.trim().to_string()),
+                    None => sdk_conf.region().cloned().context("Missing aws_region")?,
+                };
+                aws_sdk_inspector2::config::Builder::from(sdk_conf)
+                    .credentials_provider(role.credentials_provider(region.clone()))
+                    .region(region)
+                    .build()
+            }
+            None => aws_sdk_inspector2::config::Config::new(sdk_conf),
+        };
 
         let checkpoint_json = ctx.checkpoint_json.lock().await;
         let is_initial_run = checkpoint_json.is_none();
@@ -0,0 +1,158 @@
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_s3::types::ByteStream;
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use shared::LOG_SOURCES_CONFIG;
+use tracing::{error, info};
+
+use super::{PullLogsContext, PullerError};
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref S3_CLIENT: AsyncOnce<aws_sdk_s3::Client> =
+        AsyncOnce::new(async { crate::s3_client(AWS_CONFIG.get().await, None) });
+    static ref AUDIT_LOG_SOURCE: Option<String> = audit_log_source();
+    /// Pull attempts of the current invocation, see `flush_pull_attempts`.
+    static ref PULL_ATTEMPTS: Mutex<Vec<PullAttempt>> = Mutex::new(vec![]);
+}
+
+/// The managed log source type that pull attempts are written to.
+const AUDIT_LOG_SOURCE_TYPE: &str = "matano_puller_audit";
+
+/// How a pull attempt that didn't fail ended.
+#[derive(Debug, Clone, Copy)]
+pub enum PullOutcome {
+    /// Uploaded `events` events, `bytes` uncompressed.
+    Pulled { events: usize, bytes: usize },
+    /// Not pulled, e.g. `circuit_open` or `duplicate`.
+    Skipped(&'static str),
+}
+
+/// A pull attempt, as written to the audit log source.
+#[derive(Debug, Clone, Serialize)]
+pub struct PullAttempt {
+    pub timestamp: String,
+    pub log_source: String,
+    pub tenant_id: Option<String>,
+    pub window_start: String,
+    pub window_end: String,
+    pub catch_up: bool,
+    /// `success`, `failure` or `skipped`.
+    pub outcome: &'static str,
+    pub skip_reason: Option<&'static str>,
+    pub event_count: usize,
+    pub uploaded_bytes: usize,
+    pub duration_ms: u64,
+    pub error_class: Option<&'static str>,
+    pub error_message: Option<String>,
+    pub puller_version: &'static str,
+}
+
+/// The audit log source, if one is configured, e.g.
+///
+/// ```yaml
+/// name: matano_puller_audit
+/// managed:
+///   type: matano_puller_audit
+/// ```
+fn audit_log_source() -> Option<String> {
+    LOG_SOURCES_CONFIG.with(|c| {
+        c.borrow()
+            .iter()
+            .find(|(_, config)| {
+                config
+                    .base
+                    .get_string("managed.type")
+                    .map_or(false, |t| t.to_lowercase() == AUDIT_LOG_SOURCE_TYPE)
+            })
+            .map(|(name, _)| name.clone())
+    })
+}
+
+/// Records a pull attempt (source, window, outcome, counts and error class), to be written to
+/// the `matano_puller_audit` log source if one is configured, so the lake can answer when
+/// ingestion last succeeded.
+pub fn record_pull_attempt(
+    ctx: &PullLogsContext,
+    window: (DateTime<FixedOffset>, DateTime<FixedOffset>),
+    is_catch_up: bool,
+    res: &Result<PullOutcome>,
+    duration: std::time::Duration,
+) {
+    let rfc3339_utc = |dt: DateTime<Utc>| dt.to_rfc3339_opts(SecondsFormat::Millis, true);
+    let (outcome, skip_reason, events, bytes) = match res {
+        Ok(PullOutcome::Pulled { events, bytes }) => ("success", None, *events, *bytes),
+        Ok(PullOutcome::Skipped(reason)) => ("skipped", Some(*reason), 0, 0),
+        Err(_) => ("failure", None, 0, 0),
+    };
+    let attempt = PullAttempt {
+        timestamp: rfc3339_utc(Utc::now()),
+        log_source: ctx.log_source_name.clone(),
+        tenant_id: ctx.tenant_id.clone(),
+        window_start: rfc3339_utc(window.0.with_timezone(&Utc)),
+        window_end: rfc3339_utc(window.1.with_timezone(&Utc)),
+        catch_up: is_catch_up,
+        outcome,
+        skip_reason,
+        event_count: events,
+        uploaded_bytes: bytes,
+        duration_ms: duration.as_millis() as u64,
+        error_class: res
+            .as_ref()
+            .err()
+            .map(|e| PullerError::of(e).unwrap_or(PullerError::Other).as_str()),
+        error_message: res.as_ref().err().map(|e| format!("{:#}", e)),
+        puller_version: env!("CARGO_PKG_VERSION"),
+    };
+    PULL_ATTEMPTS.lock().unwrap().push(attempt);
+}
+
+/// Returns the pull attempts recorded in this invocation, after writing them to the audit log
+/// source as one object if it's configured. Errors are only logged, auditing shouldn't fail
+/// pulls.
+pub async fn flush_pull_attempts() -> Vec<PullAttempt> {
+    let attempts = std::mem::take(&mut *PULL_ATTEMPTS.lock().unwrap());
+    if let Some(log_source) = AUDIT_LOG_SOURCE.as_ref() {
+        if !attempts.is_empty() {
+            match write_audit_events(log_source, &attempts).await {
+                Ok(key) => info!("Wrote {} pull audit events to {}", attempts.len(), key),
+                Err(e) => error!("Failed to write pull audit events: {:#}", e),
+            }
+        }
+    }
+    attempts
+}
+
+async fn write_audit_events(log_source: &str, events: &[PullAttempt]) -> Result<String> {
+    let bucket = std::env::var("INGESTION_BUCKET_NAME")?;
+    let key = format!(
+        "{}/ts_hour={}/{}.json.zst",
+        log_source,
+        Utc::now().format("%Y-%m-%d-%H"),
+        uuid::Uuid::new_v4()
+    );
+    let data = events
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<serde_json::Result<Vec<_>>>()?
+        .join("\n");
+    let compressed = zstd::encode_all(data.as_bytes(), 0)?;
+    S3_CLIENT
+        .get()
+        .await
+        .put_object()
+        .bucket(bucket)
+        .key(&key)
+        .body(ByteStream::from(compressed))
+        .content_encoding("application/zstd")
+        .send()
+        .await
+        .with_context(|| format!("Error putting {} to S3", key))?;
+    Ok(key)
+}
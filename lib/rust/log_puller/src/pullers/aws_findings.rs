@@ -0,0 +1,342 @@
+use std::io::Write;
+
+use anyhow::{Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_sdk_guardduty::input::{GetFindingsInput, ListDetectorsInput, ListFindingsInput};
+use aws_sdk_guardduty::model::{Condition, FindingCriteria};
+use aws_sdk_s3::Region;
+use aws_sdk_securityhub::input::GetFindingsInput as SecurityHubGetFindingsInput;
+use aws_sdk_securityhub::model::{AwsSecurityFindingFilters, DateFilter};
+use aws_smithy_client::erase::{DynConnector, DynMiddleware};
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use futures::future::join_all;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use tracing::{debug, info};
+
+use super::aws_role::AwsRole;
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
+use async_once::AsyncOnce;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<aws_config::SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref GUARDDUTY_RAW_CLIENT: aws_smithy_client::Client = make_raw_client(
+        DynMiddleware::new(aws_sdk_guardduty::middleware::DefaultMiddleware::new())
+    );
+    static ref SECURITYHUB_RAW_CLIENT: aws_smithy_client::Client = make_raw_client(
+        DynMiddleware::new(aws_sdk_securityhub::middleware::DefaultMiddleware::new())
+    );
+}
+
+/// GuardDuty returns at most 50 findings per request.
+const GUARDDUTY_PAGE_SIZE: i32 = 50;
+const SECURITYHUB_PAGE_SIZE: i32 = 100;
+
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::optional("regions", PropertyType::String, "us-east-1,eu-west-1"),
+    PropertySpec::optional(
+        "role_arns",
+        PropertyType::String,
+        "arn:aws:iam::123456789012:role/matano-findings",
+    ),
+];
+
+fn make_raw_client(middleware: DynMiddleware<DynConnector>) -> aws_smithy_client::Client {
+    aws_smithy_client::Client::builder()
+        .dyn_https_connector(
+            aws_smithy_client::http_connector::ConnectorSettings::builder().build(),
+        )
+        .middleware::<DynMiddleware<DynConnector>>(middleware)
+        .build()
+}
+
+/// Findings updated during the window, from GuardDuty detectors in each of the configured
+/// `regions` (default the puller's) and accounts. Accounts are read through `role_arns` (comma
+/// separated, each assumed with `aws_external_id` if set), or else `aws_role_arn` or the
+/// puller's own role, for environments not using the native EventBridge integration.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: aws_guardduty
+///   properties:
+///     regions: us-east-1,eu-west-1
+///     role_arns: arn:aws:iam::111111111111:role/matano-findings,arn:aws:iam::222222222222:role/matano-findings
+/// ```
+#[derive(Clone)]
+pub struct GuardDutyPuller;
+
+/// Security Hub findings, in the AWS Security Finding Format (ASFF), updated during the window,
+/// from each of the configured `regions` and accounts as for `GuardDutyPuller`. With cross
+/// region aggregation, only the aggregation region should be configured, or findings are
+/// pulled more than once.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: aws_securityhub
+///   properties:
+///     regions: us-east-1
+/// ```
+#[derive(Clone)]
+pub struct SecurityHubPuller;
+
+#[async_trait]
+impl PullLogs for GuardDutyPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    fn counts_requests(&self) -> bool {
+        false
+    }
+
+    async fn pull_logs(
+        self,
+        _client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!("Pulling GuardDuty findings....");
+        let targets = targets(ctx).await?;
+        let futs = targets
+            .iter()
+            .map(|target| pull_guardduty(target, start_dt, end_dt));
+        let findings = join_all(futs)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?
+            .concat();
+        info!("Loaded {} GuardDuty findings", findings.len());
+        to_ndjson(findings)
+    }
+}
+
+#[async_trait]
+impl PullLogs for SecurityHubPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    fn counts_requests(&self) -> bool {
+        false
+    }
+
+    async fn pull_logs(
+        self,
+        _client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!("Pulling Security Hub findings....");
+        let targets = targets(ctx).await?;
+        let futs = targets
+            .iter()
+            .map(|target| pull_securityhub(target, start_dt, end_dt));
+        let findings = join_all(futs)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?
+            .concat();
+        info!("Loaded {} Security Hub findings", findings.len());
+        to_ndjson(findings)
+    }
+}
+
+/// An account and region pulled from, with the account's role if it's not the puller's own.
+struct Target {
+    region: Region,
+    credentials: Option<SharedCredentialsProvider>,
+}
+
+async fn targets(ctx: &PullLogsContext) -> Result<Vec<Target>> {
+    let sdk_conf = AWS_CONFIG.get().await;
+    let list = |prop: &str| {
+        ctx.config()
+            .get(prop)
+            .map(|s| {
+                s.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut regions = list("regions")
+        .into_iter()
+        .map(Region::new)
+        .collect::<Vec<_>>();
+    if regions.is_empty() {
+        regions.push(sdk_conf.region().cloned().context("Missing regions")?);
+    }
+    let external_id = ctx.config().get("aws_external_id").cloned();
+    let mut roles = list("role_arns")
+        .into_iter()
+        .map(|role_arn| Some(AwsRole::new(role_arn, external_id.clone())))
+        .collect::<Vec<_>>();
+    if roles.is_empty() {
+        roles.push(AwsRole::from_config(ctx.config()));
+    }
+
+    let mut ret = vec![];
+    for role in roles.iter() {
+        for region in regions.iter() {
+            ret.push(Target {
+                region: region.clone(),
+                credentials: role
+                    .as_ref()
+                    .map(|role| role.credentials_provider(region.clone())),
+            });
+        }
+    }
+    Ok(ret)
+}
+
+async fn pull_guardduty(
+    target: &Target,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<Vec<Value>> {
+    let mut builder = aws_sdk_guardduty::config::Builder::from(AWS_CONFIG.get().await)
+        .region(target.region.clone());
+    if let Some(credentials) = target.credentials.clone() {
+        builder = builder.credentials_provider(credentials);
+    }
+    let conf = builder.build();
+    let raw_client = &GUARDDUTY_RAW_CLIENT;
+
+    let op = ListDetectorsInput::builder()
+        .build()?
+        .make_operation(&conf)
+        .await?;
+    let detectors = json_body(raw_client.call_raw(op).await?.raw)?;
+    let detector_ids = string_array(&detectors["detectorIds"]);
+
+    let criteria = FindingCriteria::builder()
+        .criterion(
+            "updatedAt",
+            Condition::builder()
+                .greater_than_or_equal(start_dt.timestamp_millis())
+                .less_than(end_dt.timestamp_millis())
+                .build(),
+        )
+        .build();
+    let mut findings = vec![];
+    for detector_id in detector_ids {
+        let mut next_token: Option<String> = None;
+        loop {
+            let op = ListFindingsInput::builder()
+                .detector_id(&detector_id)
+                .finding_criteria(criteria.clone())
+                .max_results(GUARDDUTY_PAGE_SIZE)
+                .set_next_token(next_token.clone())
+                .build()?
+                .make_operation(&conf)
+                .await?;
+            let page = json_body(raw_client.call_raw(op).await?.raw)?;
+            let finding_ids = string_array(&page["findingIds"]);
+
+            if !finding_ids.is_empty() {
+                let op = GetFindingsInput::builder()
+                    .detector_id(&detector_id)
+                    .set_finding_ids(Some(finding_ids))
+                    .build()?
+                    .make_operation(&conf)
+                    .await?;
+                let mut res = json_body(raw_client.call_raw(op).await?.raw)?;
+                if let Value::Array(page_findings) = res["findings"].take() {
+                    findings.extend(page_findings);
+                }
+            }
+
+            next_token = page["nextToken"]
+                .as_str()
+                .filter(|t| !t.is_empty())
+                .map(|t| t.to_string());
+            debug!("Loaded page for GuardDuty detector {}", detector_id);
+            if next_token.is_none() {
+                break;
+            }
+        }
+    }
+    Ok(findings)
+}
+
+async fn pull_securityhub(
+    target: &Target,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<Vec<Value>> {
+    let mut builder = aws_sdk_securityhub::config::Builder::from(AWS_CONFIG.get().await)
+        .region(target.region.clone());
+    if let Some(credentials) = target.credentials.clone() {
+        builder = builder.credentials_provider(credentials);
+    }
+    let conf = builder.build();
+    let raw_client = &SECURITYHUB_RAW_CLIENT;
+
+    let filters = AwsSecurityFindingFilters::builder()
+        .updated_at(
+            DateFilter::builder()
+                .start(start_dt.to_rfc3339_opts(SecondsFormat::Millis, true))
+                .end(end_dt.to_rfc3339_opts(SecondsFormat::Millis, true))
+                .build(),
+        )
+        .build();
+    let mut findings = vec![];
+    let mut next_token: Option<String> = None;
+    loop {
+        let op = SecurityHubGetFindingsInput::builder()
+            .filters(filters.clone())
+            .max_results(SECURITYHUB_PAGE_SIZE)
+            .set_next_token(next_token.clone())
+            .build()?
+            .make_operation(&conf)
+            .await?;
+        let mut page = json_body(raw_client.call_raw(op).await?.raw)?;
+        if let Value::Array(page_findings) = page["Findings"].take() {
+            findings.extend(page_findings);
+        }
+        next_token = page["NextToken"]
+            .as_str()
+            .filter(|t| !t.is_empty())
+            .map(|t| t.to_string());
+        debug!("Loaded page for Security Hub");
+        if next_token.is_none() {
+            break;
+        }
+    }
+    Ok(findings)
+}
+
+/// The JSON body of a raw response, as the SDK models can't be serialized.
+fn json_body(raw: aws_smithy_http::operation::Response) -> Result<Value> {
+    let (raw_resp, _) = raw.into_parts();
+    let body = raw_resp.body().bytes().context("Missing response body")?;
+    Ok(serde_json::from_slice(body)?)
+}
+
+fn string_array(value: &Value) -> Vec<String> {
+    value
+        .as_array()
+        .map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn to_ndjson(findings: Vec<Value>) -> Result<Vec<u8>> {
+    let mut ret = vec![];
+    for finding in findings {
+        serde_json::to_writer(&mut ret, &finding)?;
+        ret.write_all(b"\n")?;
+    }
+    Ok(ret)
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use aws_config::environment::credentials::EnvironmentVariableCredentialsProvider;
+use aws_config::sts::AssumeRoleProvider;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_sdk_s3::Region;
+
+/// A role in another AWS account that pullers calling AWS APIs (e.g. `amazon_inspector`,
+/// `external_s3` or SigV4 signed APIs like OpenSearch) assume, instead of using the puller's
+/// own role or long lived keys. Set with the `aws_role_arn` property, and `aws_external_id` if
+/// the role's trust policy requires one.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     aws_role_arn: arn:aws:iam::123456789012:role/matano-puller-access
+///     aws_external_id: 7f3c1a
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct AwsRole {
+    role_arn: String,
+    external_id: Option<String>,
+}
+
+impl AwsRole {
+    pub fn new(role_arn: impl Into<String>, external_id: Option<String>) -> AwsRole {
+        AwsRole {
+            role_arn: role_arn.into(),
+            external_id,
+        }
+    }
+
+    pub fn from_config(config: &HashMap<String, String>) -> Option<AwsRole> {
+        let get = |k: &str| {
+            config
+                .get(k)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        Some(AwsRole::new(get("aws_role_arn")?, get("aws_external_id")))
+    }
+
+    /// Credentials of the role, assumed with the puller's own credentials.
+    pub fn credentials_provider(&self, region: Region) -> SharedCredentialsProvider {
+        let mut builder = AssumeRoleProvider::builder(&self.role_arn)
+            .region(region)
+            .session_name("matano");
+        if let Some(external_id) = self.external_id.as_ref() {
+            builder = builder.external_id(external_id);
+        }
+        SharedCredentialsProvider::new(
+            builder.build(Arc::new(EnvironmentVariableCredentialsProvider::new()) as Arc<_>),
+        )
+    }
+}
@@ -0,0 +1,318 @@
+use std::io::Read;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use futures::future::join_all;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info};
+
+use super::oauth2::{ClientCredentials, TokenSource};
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
+use shared::{convert_json_array_str_to_ndjson, JsonValueExt};
+
+/// Mirrors new blobs from an Azure Storage container, authenticating with either a SAS token
+/// or a service principal.
+#[derive(Clone)]
+pub struct AzureBlobPuller;
+
+const AZURE_STORAGE_API_VERSION: &str = "2021-08-06";
+/// Limit the number of blobs downloaded per invocation, the rest are picked up on the next run.
+const MAX_BLOBS_PER_RUN: usize = 500;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct EnumerationResults {
+    blobs: Blobs,
+    next_marker: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Blobs {
+    #[serde(rename = "Blob", default)]
+    blob: Vec<Blob>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Blob {
+    name: String,
+    properties: BlobProperties,
+}
+
+#[derive(Deserialize, Debug)]
+struct BlobProperties {
+    #[serde(rename = "Last-Modified")]
+    last_modified: String,
+    #[serde(rename = "Content-Encoding", default)]
+    content_encoding: Option<String>,
+}
+
+enum AzureAuth {
+    Sas(String),
+    Bearer(String),
+}
+
+impl AzureAuth {
+    fn apply(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let req = req.header("x-ms-version", AZURE_STORAGE_API_VERSION);
+        match self {
+            AzureAuth::Sas(sas) => {
+                let params = url::form_urlencoded::parse(sas.trim_start_matches('?').as_bytes())
+                    .into_owned()
+                    .collect::<Vec<_>>();
+                req.query(&params)
+            }
+            AzureAuth::Bearer(token) => req.bearer_auth(token),
+        }
+    }
+}
+
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required("account_name", PropertyType::String, "mystorageaccount"),
+    PropertySpec::required("container", PropertyType::String, "logs"),
+];
+
+#[async_trait]
+impl PullLogs for AzureBlobPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    async fn pull_logs(
+        self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        _start_dt: DateTime<FixedOffset>,
+        _end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!("Pulling Azure Blob Storage for {}....", ctx.log_source_name);
+
+        let config = ctx.config();
+
+        let account_name = config.get("account_name").context("Missing account_name")?;
+        let container = config.get("container").context("Missing container")?;
+        let prefix = config.get("prefix").cloned().unwrap_or_default();
+
+        let auth = match config.get("tenant_id") {
+            Some(tenant_id) => {
+                let client_id = config.get("client_id").context("Missing client_id")?;
+                let client_secret = ctx
+                    .get_secret_field("client_secret")
+                    .await?
+                    .context("Missing client secret")?;
+                if client_secret == "<placeholder>" {
+                    info!("Skipping azure_blob because secret is still <placeholder>");
+                    return Ok(vec![]);
+                }
+                let access_token = ClientCredentials::new(
+                    format!(
+                        "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+                        tenant_id
+                    ),
+                    client_id,
+                    client_secret,
+                )
+                .scope("https://storage.azure.com/.default")
+                .access_token(&client, ctx)
+                .await?;
+                AzureAuth::Bearer(access_token)
+            }
+            None => {
+                let sas_token = ctx
+                    .get_secret_field("sas_token")
+                    .await?
+                    .context("Missing SAS token")?;
+                if sas_token == "<placeholder>" {
+                    info!("Skipping azure_blob because secret is still <placeholder>");
+                    return Ok(vec![]);
+                }
+                AzureAuth::Sas(sas_token)
+            }
+        };
+
+        let container_url = format!(
+            "https://{}.blob.core.windows.net/{}",
+            account_name, container
+        );
+
+        let mut checkpoint_json = ctx.checkpoint_json.lock().await;
+        let checkpoint_last_modified = checkpoint_json
+            .as_ref()
+            .and_then(|v| v["last_modified"].as_str())
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok());
+        // Blobs sharing the checkpoint timestamp that were already pulled.
+        let checkpoint_names = checkpoint_json
+            .as_ref()
+            .and_then(|v| v["names_at_last_modified"].as_array().cloned())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|v| v.into_str())
+            .collect::<Vec<_>>();
+
+        let mut new_blobs = vec![];
+        let mut marker: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("restype", "container".to_string()),
+                ("comp", "list".to_string()),
+                ("prefix", prefix.clone()),
+            ];
+            if let Some(m) = marker.as_ref() {
+                query.push(("marker", m.clone()));
+            }
+            let req = auth.apply(client.get(&container_url).query(&query));
+            let res = ctx.send(&client, req).await?;
+            let status = res.status();
+            let body = res.text().await?;
+            if !status.is_success() {
+                return Err(anyhow!(
+                    "Error listing blobs in {}, status: {}, response: {}",
+                    &container_url,
+                    status,
+                    body
+                ));
+            }
+
+            let results: EnumerationResults = quick_xml::de::from_str(&body)?;
+            for blob in results.blobs.blob {
+                let last_modified = DateTime::parse_from_rfc2822(&blob.properties.last_modified)
+                    .with_context(|| format!("Invalid Last-Modified for {}", &blob.name))?;
+                let is_new = match checkpoint_last_modified {
+                    Some(cp) => {
+                        last_modified > cp
+                            || (last_modified == cp && !checkpoint_names.contains(&blob.name))
+                    }
+                    None => true,
+                };
+                if is_new {
+                    new_blobs.push((last_modified, blob));
+                }
+            }
+
+            marker = results.next_marker.filter(|m| !m.is_empty());
+            if marker.is_none() {
+                break;
+            }
+        }
+
+        // Oldest first, so the checkpoint only moves past what was actually pulled.
+        new_blobs.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.name.cmp(&b.1.name)));
+        new_blobs.truncate(MAX_BLOBS_PER_RUN);
+
+        info!(
+            "Found {} new blobs for {}",
+            new_blobs.len(),
+            ctx.log_source_name
+        );
+        if new_blobs.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let futs = new_blobs
+            .iter()
+            .map(|(_, blob)| download_blob(&client, ctx, &auth, &container_url, blob))
+            .collect::<Vec<_>>();
+        let chunks = join_all(futs)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut ret: Vec<u8> = vec![];
+        for chunk in chunks.into_iter().filter(|c| !c.is_empty()) {
+            if !ret.is_empty() {
+                ret.push(b'\n');
+            }
+            ret.extend(chunk);
+        }
+
+        let (max_last_modified, _) = new_blobs.last().unwrap();
+        let mut names_at_last_modified = new_blobs
+            .iter()
+            .filter(|(lm, _)| lm == max_last_modified)
+            .map(|(_, b)| b.name.clone())
+            .collect::<Vec<_>>();
+        if checkpoint_last_modified.as_ref() == Some(max_last_modified) {
+            names_at_last_modified.extend(checkpoint_names);
+        }
+
+        // update checkpoint
+        *checkpoint_json = Some(json!({
+            "last_modified": max_last_modified.to_rfc3339(),
+            "names_at_last_modified": names_at_last_modified,
+        }));
+
+        Ok(ret)
+    }
+}
+
+async fn download_blob(
+    client: &reqwest::Client,
+    ctx: &PullLogsContext,
+    auth: &AzureAuth,
+    container_url: &str,
+    blob: &Blob,
+) -> Result<Vec<u8>> {
+    debug!("Downloading blob: {}", &blob.name);
+    let url = format!("{}/{}", container_url, encode_blob_name(&blob.name));
+    let res = ctx.send(client, auth.apply(client.get(&url))).await?;
+    let status = res.status();
+    if !status.is_success() {
+        let body = res.text().await.unwrap_or_default();
+        return Err(anyhow!(
+            "Error downloading blob {}, status: {}, response: {}",
+            &blob.name,
+            status,
+            body
+        ));
+    }
+    let bytes = res.bytes().await?;
+
+    let content_encoding = blob.properties.content_encoding.as_deref();
+    decode_object_payload(&blob.name, content_encoding, &bytes)
+}
+
+/// Percent-encodes each path segment of a blob name, keeping the `/` between them.
+fn encode_blob_name(name: &str) -> String {
+    name.split('/')
+        .map(|segment| {
+            // form encoding uses `+` for spaces, which isn't valid in a path segment.
+            url::form_urlencoded::byte_serialize(segment.as_bytes())
+                .collect::<String>()
+                .replace('+', "%20")
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Decompresses gzip objects and converts JSON array objects to NDJSON. Everything else is passed through as is.
+pub(crate) fn decode_object_payload(
+    name: &str,
+    content_encoding: Option<&str>,
+    bytes: &[u8],
+) -> Result<Vec<u8>> {
+    let is_gzip = name.ends_with(".gz") || content_encoding == Some("gzip");
+    let data = if is_gzip {
+        let mut decoded = vec![];
+        flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut decoded)?;
+        decoded
+    } else {
+        bytes.to_vec()
+    };
+
+    let is_json_array = data
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .map_or(false, |b| *b == b'[');
+    if is_json_array {
+        let s = String::from_utf8(data)?;
+        Ok(convert_json_array_str_to_ndjson(&s)?.into_bytes())
+    } else {
+        let mut data = data;
+        while data.last().map_or(false, |b| b.is_ascii_whitespace()) {
+            data.pop();
+        }
+        Ok(data)
+    }
+}
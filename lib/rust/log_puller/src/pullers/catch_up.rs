@@ -0,0 +1,169 @@
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_sqs::model::SendMessageBatchRequestEntry;
+use chrono::{DateTime, Duration, FixedOffset};
+use lazy_static::lazy_static;
+use serde_json::json;
+use tracing::warn;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref SQS_CLIENT: AsyncOnce<aws_sdk_sqs::Client> =
+        AsyncOnce::new(async { aws_sdk_sqs::Client::new(AWS_CONFIG.get().await) });
+}
+
+/// Missed intervals are re-pulled in windows of this size.
+pub const CATCH_UP_WINDOW_MINUTES: i64 = 60;
+/// SQS batch size limit.
+const MAX_BATCH_SIZE: usize = 10;
+
+/// Whether the puller queue is a FIFO queue, whose messages are pulled in order per log source,
+/// see `in_message_group_order`.
+pub fn is_fifo_queue() -> bool {
+    std::env::var("PULLER_QUEUE_URL").map_or(false, |url| is_fifo(&url))
+}
+
+fn is_fifo(queue_url: &str) -> bool {
+    queue_url.ends_with(".fifo")
+}
+
+/// The message group of a log source's messages, which FIFO queues deliver in order. None for
+/// standard queues.
+pub(crate) fn message_group_id(queue_url: &str, log_source_name: &str) -> Option<String> {
+    is_fifo(queue_url).then(|| log_source_name.to_string())
+}
+
+/// A unique deduplication ID for each message to a FIFO queue, None for standard queues. The
+/// queues deduplicate by content otherwise, which drops e.g. a second continuation of the same
+/// window sent within the deduplication interval.
+pub(crate) fn message_deduplication_id(queue_url: &str) -> Option<String> {
+    is_fifo(queue_url).then(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Enqueues pulls of `[start_dt, end_dt)` to the puller queue (`PULLER_QUEUE_URL`), split into
+/// windows of `window_minutes`, for windows that were missed e.g. during an outage or after
+/// messages were dead lettered, or for backfills. Returns the number of pulls enqueued.
+pub async fn enqueue_catch_up(
+    log_source_name: &str,
+    tenant_id: Option<&str>,
+    start_dt: DateTime<FixedOffset>,
+    end_dt: DateTime<FixedOffset>,
+    window_minutes: i64,
+) -> Result<usize> {
+    let queue_url = std::env::var("PULLER_QUEUE_URL").context("Missing PULLER_QUEUE_URL")?;
+
+    let mut windows = vec![];
+    let mut window_start = start_dt;
+    while window_start < end_dt {
+        let window_end = (window_start + Duration::minutes(window_minutes.max(1))).min(end_dt);
+        windows.push((window_start, window_end));
+        window_start = window_end;
+    }
+
+    let sqs = SQS_CLIENT.get().await;
+    for chunk in windows.chunks(MAX_BATCH_SIZE) {
+        let entries = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, (start, end))| {
+                let body = json!({
+                    "log_source_name": log_source_name,
+                    "tenant_id": tenant_id,
+                    "time": end.to_rfc3339(),
+                    "start_time": start.to_rfc3339(),
+                    "rate_minutes": (*end - *start).num_minutes(),
+                });
+                SendMessageBatchRequestEntry::builder()
+                    .id(i.to_string())
+                    .message_body(body.to_string())
+                    .set_message_group_id(message_group_id(&queue_url, log_source_name))
+                    .set_message_deduplication_id(message_deduplication_id(&queue_url))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let res = sqs
+            .send_message_batch()
+            .queue_url(&queue_url)
+            .set_entries(Some(entries))
+            .send()
+            .await
+            .context("Failed to enqueue catch up pulls")?;
+        if let Some(failed) = res.failed().filter(|f| !f.is_empty()) {
+            for f in failed {
+                warn!("Failed to enqueue catch up pull: {:?}", f.message());
+            }
+            return Err(anyhow!(
+                "Failed to enqueue {} catch up pulls for {}",
+                failed.len(),
+                log_source_name
+            ));
+        }
+    }
+    Ok(windows.len())
+}
+
+/// Enqueues a pull that resumes a pull stopped early by the invocation deadline, from the
+/// continuation saved in the checkpoint. Its window is empty, so only the rest is pulled.
+pub async fn enqueue_continuation(
+    log_source_name: &str,
+    tenant_id: Option<&str>,
+    end_dt: DateTime<FixedOffset>,
+) -> Result<()> {
+    let queue_url = std::env::var("PULLER_QUEUE_URL").context("Missing PULLER_QUEUE_URL")?;
+    let body = json!({
+        "log_source_name": log_source_name,
+        "tenant_id": tenant_id,
+        "time": end_dt.to_rfc3339(),
+        "start_time": end_dt.to_rfc3339(),
+        "rate_minutes": 0,
+    });
+    SQS_CLIENT
+        .get()
+        .await
+        .send_message()
+        .set_message_group_id(message_group_id(&queue_url, log_source_name))
+        .set_message_deduplication_id(message_deduplication_id(&queue_url))
+        .queue_url(queue_url)
+        .message_body(body.to_string())
+        .send()
+        .await
+        .context("Failed to enqueue continuation pull")?;
+    Ok(())
+}
+
+/// Sends a copy of a puller message back to the queue, delivered after `delay_seconds` (at
+/// most 15 minutes), e.g. to retry a rate limited pull without using up the original's
+/// receives. Not supported by FIFO queues, which can't delay single messages.
+pub async fn requeue(body: &str, delay_seconds: i32) -> Result<()> {
+    let queue_url = std::env::var("PULLER_QUEUE_URL").context("Missing PULLER_QUEUE_URL")?;
+    SQS_CLIENT
+        .get()
+        .await
+        .send_message()
+        .queue_url(queue_url)
+        .message_body(body)
+        .delay_seconds(delay_seconds)
+        .send()
+        .await
+        .context("Failed to requeue pull")?;
+    Ok(())
+}
+
+/// Makes a received puller message visible again only after `delay_seconds`, so its retry
+/// waits longer than the queue's visibility timeout.
+pub async fn delay_retry(receipt_handle: &str, delay_seconds: i32) -> Result<()> {
+    let queue_url = std::env::var("PULLER_QUEUE_URL").context("Missing PULLER_QUEUE_URL")?;
+    SQS_CLIENT
+        .get()
+        .await
+        .change_message_visibility()
+        .queue_url(queue_url)
+        .receipt_handle(receipt_handle)
+        .visibility_timeout(delay_seconds)
+        .send()
+        .await
+        .context("Failed to delay retry")?;
+    Ok(())
+}
@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::model::AttributeValue;
+use aws_sdk_s3::types::{ByteStream, SdkError};
+use chrono::{DateTime, FixedOffset, Utc};
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde_json::{json, Value};
+use tracing::{debug, error};
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref DDB_CLIENT: AsyncOnce<aws_sdk_dynamodb::Client> =
+        AsyncOnce::new(async { aws_sdk_dynamodb::Client::new(AWS_CONFIG.get().await) });
+    /// S3 state objects last read or written by this Lambda, by key. Warm invocations only
+    /// download a state object again if its ETag changed.
+    static ref S3_STATE_CACHE: Mutex<HashMap<String, S3State>> = Mutex::new(HashMap::new());
+}
+
+/// Checkpoints written before checkpoint stores were added, still read when there's no state.
+const LEGACY_S3_CHECKPOINT_PREFIX: &str = "__puller_last_run_checkpoint__";
+const S3_STATE_PREFIX: &str = "__puller_state__";
+/// Attempts to update an S3 state object when other invocations keep updating it concurrently.
+const MAX_CONFLICT_RETRIES: usize = 10;
+/// A claimed pull can be claimed again after this long, in case the invocation died.
+const PULL_LEASE_SECS: i64 = 5 * 60;
+/// Completed pulls are remembered this long, to ignore duplicate deliveries.
+const PULL_RECORD_SECS: i64 = 24 * 60 * 60;
+/// Daily request counts are kept a day longer than the day they're for.
+const REQUEST_COUNT_SECS: i64 = 2 * 24 * 60 * 60;
+
+/// Where checkpoints are stored.
+#[derive(Debug, Clone)]
+enum Store {
+    /// The `PULLER_CHECKPOINT_TABLE_NAME` DynamoDB table.
+    DynamoDb(String),
+    /// A state object per log source in the ingestion bucket, updated with conditional puts.
+    S3,
+}
+
+/// Persists puller state (timestamps, cursors, stream positions) across invocations. The end of
+/// the last successfully pulled window is also tracked, so the next pull starts where the last
+/// one ended instead of relying on the window of the scheduling message.
+///
+/// `PULLER_CHECKPOINT_STORE` selects the store, `dynamodb` or `s3`. Defaults to DynamoDB if
+/// `PULLER_CHECKPOINT_TABLE_NAME` is set, otherwise S3.
+#[derive(Debug, Clone)]
+pub struct Checkpointer {
+    s3: aws_sdk_s3::Client,
+    store: Store,
+}
+
+/// An S3 state object and its ETag, None if it doesn't exist yet.
+#[derive(Clone)]
+struct S3State {
+    state: Value,
+    etag: Option<String>,
+}
+
+impl Checkpointer {
+    pub fn new(s3: aws_sdk_s3::Client) -> Checkpointer {
+        let table_name = std::env::var("PULLER_CHECKPOINT_TABLE_NAME").ok();
+        let store = match std::env::var("PULLER_CHECKPOINT_STORE").as_deref() {
+            Ok("s3") => Store::S3,
+            Ok("dynamodb") | Err(_) => match table_name {
+                Some(table_name) => Store::DynamoDb(table_name),
+                None => Store::S3,
+            },
+            Ok(other) => {
+                error!("Invalid PULLER_CHECKPOINT_STORE: {}, using default", other);
+                match table_name {
+                    Some(table_name) => Store::DynamoDb(table_name),
+                    None => Store::S3,
+                }
+            }
+        };
+        Checkpointer { s3, store }
+    }
+
+    /// Loads the checkpoint stored under `name`, None if there's none yet.
+    pub async fn load(&self, name: &str) -> Result<Option<Value>> {
+        let checkpoint = match &self.store {
+            Store::DynamoDb(table_name) => {
+                let item = DDB_CLIENT
+                    .get()
+                    .await
+                    .get_item()
+                    .table_name(table_name)
+                    .key("pk", AttributeValue::S(name.to_string()))
+                    .consistent_read(true)
+                    .send()
+                    .await
+                    .context("Failed to load checkpoint")?
+                    .item;
+                item.as_ref()
+                    .and_then(|item| item.get("checkpoint"))
+                    .and_then(|v| v.as_s().ok())
+                    .map(|s| serde_json::from_str(s))
+                    .transpose()
+                    .context("failed to parse checkpoint as json")?
+            }
+            Store::S3 => self
+                .load_s3_state(name)
+                .await?
+                .state
+                .get("checkpoint")
+                .cloned(),
+        };
+        match checkpoint {
+            Some(checkpoint) => Ok(Some(checkpoint)),
+            None => {
+                debug!(
+                    "No checkpoint in store for {}, checking legacy location",
+                    name
+                );
+                self.load_legacy(name).await
+            }
+        }
+    }
+
+    pub async fn save(&self, name: &str, checkpoint: &Value) -> Result<()> {
+        match &self.store {
+            Store::DynamoDb(table_name) => {
+                DDB_CLIENT
+                    .get()
+                    .await
+                    .update_item()
+                    .table_name(table_name)
+                    .key("pk", AttributeValue::S(name.to_string()))
+                    .update_expression("SET checkpoint = :checkpoint, updated_at = :updated_at")
+                    .expression_attribute_values(
+                        ":checkpoint",
+                        AttributeValue::S(serde_json::to_string(checkpoint)?),
+                    )
+                    .expression_attribute_values(
+                        ":updated_at",
+                        AttributeValue::N(Utc::now().timestamp().to_string()),
+                    )
+                    .send()
+                    .await
+                    .context("Failed to save checkpoint")?;
+                Ok(())
+            }
+            Store::S3 => {
+                self.update_s3_state(name, |state| {
+                    state["checkpoint"] = checkpoint.clone();
+                    true
+                })
+                .await
+            }
+        }
+    }
+
+    /// End of the last successfully pulled window, None if unknown.
+    pub async fn last_pulled_at(&self, name: &str) -> Result<Option<DateTime<FixedOffset>>> {
+        let last_pulled_at = match &self.store {
+            Store::DynamoDb(table_name) => {
+                let item = DDB_CLIENT
+                    .get()
+                    .await
+                    .get_item()
+                    .table_name(table_name)
+                    .key("pk", AttributeValue::S(name.to_string()))
+                    .consistent_read(true)
+                    .send()
+                    .await
+                    .context("Failed to load last pulled time")?
+                    .item;
+                item.as_ref()
+                    .and_then(|item| item.get("last_pulled_at"))
+                    .and_then(|v| v.as_s().ok())
+                    .cloned()
+            }
+            Store::S3 => self
+                .load_s3_state(name)
+                .await?
+                .state
+                .get("last_pulled_at")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+        };
+        let last_pulled_at = last_pulled_at
+            .map(|s| DateTime::parse_from_rfc3339(&s))
+            .transpose()
+            .context("Invalid last_pulled_at in checkpoint store")?;
+        Ok(last_pulled_at)
+    }
+
+    /// Records a successfully pulled window end. Never moves backwards, so a late retry of an
+    /// old window doesn't cause the next pull to repeat newer ones.
+    pub async fn set_last_pulled_at(&self, name: &str, dt: DateTime<FixedOffset>) -> Result<()> {
+        // RFC 3339 in UTC sorts the same as the times, so strings can be compared.
+        let dt = dt
+            .with_timezone(&Utc)
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        match &self.store {
+            Store::DynamoDb(table_name) => {
+                let res = DDB_CLIENT
+                    .get()
+                    .await
+                    .update_item()
+                    .table_name(table_name)
+                    .key("pk", AttributeValue::S(name.to_string()))
+                    .update_expression("SET last_pulled_at = :dt")
+                    .condition_expression(
+                        "attribute_not_exists(last_pulled_at) OR last_pulled_at < :dt",
+                    )
+                    .expression_attribute_values(":dt", AttributeValue::S(dt))
+                    .send()
+                    .await;
+                match res {
+                    Ok(_) => Ok(()),
+                    Err(e) => {
+                        let se = e.into_service_error();
+                        if se.is_conditional_check_failed_exception() {
+                            Ok(())
+                        } else {
+                            Err(se).context("Failed to save last pulled time")
+                        }
+                    }
+                }
+            }
+            Store::S3 => {
+                self.update_s3_state(name, |state| {
+                    let is_newer = state
+                        .get("last_pulled_at")
+                        .and_then(|v| v.as_str())
+                        .map_or(true, |prev| prev < dt.as_str());
+                    if is_newer {
+                        state["last_pulled_at"] = json!(dt);
+                    }
+                    is_newer
+                })
+                .await
+            }
+        }
+    }
+
+    /// Hash of the last uploaded snapshot under `name`, see `set_snapshot_hash`.
+    pub async fn snapshot_hash(&self, name: &str) -> Result<Option<String>> {
+        let hash = match &self.store {
+            Store::DynamoDb(table_name) => DDB_CLIENT
+                .get()
+                .await
+                .get_item()
+                .table_name(table_name)
+                .key("pk", AttributeValue::S(name.to_string()))
+                .consistent_read(true)
+                .send()
+                .await
+                .context("Failed to load snapshot hash")?
+                .item
+                .as_ref()
+                .and_then(|item| item.get("snapshot_hash"))
+                .and_then(|v| v.as_s().ok())
+                .cloned(),
+            Store::S3 => self
+                .load_s3_state(name)
+                .await?
+                .state
+                .get("snapshot_hash")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+        };
+        Ok(hash)
+    }
+
+    /// Saves the hash of an uploaded snapshot, for sources that return their full state on
+    /// each pull, so an unchanged snapshot isn't uploaded again.
+    pub async fn set_snapshot_hash(&self, name: &str, hash: &str) -> Result<()> {
+        match &self.store {
+            Store::DynamoDb(table_name) => {
+                DDB_CLIENT
+                    .get()
+                    .await
+                    .update_item()
+                    .table_name(table_name)
+                    .key("pk", AttributeValue::S(name.to_string()))
+                    .update_expression("SET snapshot_hash = :hash")
+                    .expression_attribute_values(":hash", AttributeValue::S(hash.to_string()))
+                    .send()
+                    .await
+                    .context("Failed to save snapshot hash")?;
+                Ok(())
+            }
+            Store::S3 => {
+                self.update_s3_state(name, |state| {
+                    let changed = state.get("snapshot_hash").and_then(|v| v.as_str()) != Some(hash);
+                    if changed {
+                        state["snapshot_hash"] = json!(hash);
+                    }
+                    changed
+                })
+                .await
+            }
+        }
+    }
+
+    /// Claims the pull of `window` (e.g. `{start}_{end}`) under `name`, so a duplicate delivery
+    /// of the same request is a no-op. Returns false if it's already pulled or being pulled.
+    pub async fn claim_pull(&self, name: &str, window: &str) -> Result<bool> {
+        let now = Utc::now().timestamp();
+        match &self.store {
+            Store::DynamoDb(table_name) => {
+                let res = DDB_CLIENT
+                    .get()
+                    .await
+                    .put_item()
+                    .table_name(table_name)
+                    .item("pk", AttributeValue::S(pull_key(name, window)))
+                    .item("status", AttributeValue::S("in_progress".to_string()))
+                    .item(
+                        "lease_until",
+                        AttributeValue::N((now + PULL_LEASE_SECS).to_string()),
+                    )
+                    .item(
+                        "ttl",
+                        AttributeValue::N((now + PULL_RECORD_SECS).to_string()),
+                    )
+                    .condition_expression(
+                        "attribute_not_exists(pk) OR (#status = :in_progress AND lease_until < :now)",
+                    )
+                    .expression_attribute_names("#status", "status")
+                    .expression_attribute_values(
+                        ":in_progress",
+                        AttributeValue::S("in_progress".to_string()),
+                    )
+                    .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+                    .send()
+                    .await;
+                match res {
+                    Ok(_) => Ok(true),
+                    Err(e) => {
+                        let se = e.into_service_error();
+                        if se.is_conditional_check_failed_exception() {
+                            Ok(false)
+                        } else {
+                            Err(se).context("Failed to claim pull")
+                        }
+                    }
+                }
+            }
+            Store::S3 => {
+                let claimed = AtomicBool::new(false);
+                self.update_s3_state(name, |state| {
+                    prune_pulls(state, now);
+                    if state["pulls"].get(window).is_some() {
+                        claimed.store(false, Ordering::SeqCst);
+                        return false;
+                    }
+                    state["pulls"][window] = json!({
+                        "status": "in_progress",
+                        "until": now + PULL_LEASE_SECS,
+                    });
+                    claimed.store(true, Ordering::SeqCst);
+                    true
+                })
+                .await?;
+                Ok(claimed.load(Ordering::SeqCst))
+            }
+        }
+    }
+
+    /// Marks a claimed pull as done, or releases it if it failed so a retry can claim it.
+    pub async fn finish_pull(&self, name: &str, window: &str, succeeded: bool) -> Result<()> {
+        let now = Utc::now().timestamp();
+        match &self.store {
+            Store::DynamoDb(table_name) => {
+                let ddb = DDB_CLIENT.get().await;
+                let key = AttributeValue::S(pull_key(name, window));
+                if succeeded {
+                    ddb.update_item()
+                        .table_name(table_name)
+                        .key("pk", key)
+                        .update_expression("SET #status = :completed")
+                        .expression_attribute_names("#status", "status")
+                        .expression_attribute_values(
+                            ":completed",
+                            AttributeValue::S("completed".to_string()),
+                        )
+                        .send()
+                        .await
+                        .context("Failed to complete pull")?;
+                } else {
+                    ddb.delete_item()
+                        .table_name(table_name)
+                        .key("pk", key)
+                        .send()
+                        .await
+                        .context("Failed to release pull")?;
+                }
+                Ok(())
+            }
+            Store::S3 => {
+                self.update_s3_state(name, |state| {
+                    prune_pulls(state, now);
+                    if succeeded {
+                        state["pulls"][window] = json!({
+                            "status": "completed",
+                            "until": now + PULL_RECORD_SECS,
+                        });
+                    } else if let Some(pulls) = state["pulls"].as_object_mut() {
+                        pulls.remove(window);
+                    }
+                    true
+                })
+                .await
+            }
+        }
+    }
+
+    /// Number of requests made under `name` on `day` (e.g. `2023-04-01`), see `add_requests`.
+    pub async fn requests_on(&self, name: &str, day: &str) -> Result<u64> {
+        let count = match &self.store {
+            Store::DynamoDb(table_name) => DDB_CLIENT
+                .get()
+                .await
+                .get_item()
+                .table_name(table_name)
+                .key("pk", AttributeValue::S(requests_key(name, day)))
+                .consistent_read(true)
+                .send()
+                .await
+                .context("Failed to load request count")?
+                .item
+                .as_ref()
+                .and_then(|item| item.get("requests"))
+                .and_then(|v| v.as_n().ok())
+                .and_then(|n| n.parse::<u64>().ok()),
+            Store::S3 => {
+                let state = self.load_s3_state(name).await?.state;
+                match state["requests"]["day"].as_str() {
+                    Some(d) if d == day => state["requests"]["count"].as_u64(),
+                    _ => None,
+                }
+            }
+        };
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Adds to the number of requests made under `name` on `day`.
+    pub async fn add_requests(&self, name: &str, day: &str, count: u64) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+        match &self.store {
+            Store::DynamoDb(table_name) => {
+                let ttl = Utc::now().timestamp() + REQUEST_COUNT_SECS;
+                DDB_CLIENT
+                    .get()
+                    .await
+                    .update_item()
+                    .table_name(table_name)
+                    .key("pk", AttributeValue::S(requests_key(name, day)))
+                    .update_expression("ADD requests :count SET #ttl = :ttl")
+                    .expression_attribute_names("#ttl", "ttl")
+                    .expression_attribute_values(":count", AttributeValue::N(count.to_string()))
+                    .expression_attribute_values(":ttl", AttributeValue::N(ttl.to_string()))
+                    .send()
+                    .await
+                    .context("Failed to save request count")?;
+                Ok(())
+            }
+            Store::S3 => {
+                self.update_s3_state(name, |state| {
+                    let prev = match state["requests"]["day"].as_str() {
+                        Some(d) if d == day => state["requests"]["count"].as_u64().unwrap_or(0),
+                        _ => 0,
+                    };
+                    state["requests"] = json!({"day": day, "count": prev + count});
+                    true
+                })
+                .await
+            }
+        }
+    }
+
+    fn s3_state_key(name: &str) -> String {
+        format!("{}/{}.json", S3_STATE_PREFIX, name)
+    }
+
+    /// Loads a state object, reusing the cached one if it's unchanged (`If-None-Match`).
+    async fn load_s3_state(&self, name: &str) -> Result<S3State> {
+        let bucket = std::env::var("INGESTION_BUCKET_NAME").context("need bucket!")?;
+        let key = Self::s3_state_key(name);
+        let cached = S3_STATE_CACHE.lock().unwrap().get(&key).cloned();
+        let res = self
+            .s3
+            .get_object()
+            .bucket(&bucket)
+            .key(&key)
+            .set_if_none_match(cached.as_ref().and_then(|c| c.etag.clone()))
+            .send()
+            .await;
+        match res {
+            Ok(output) => {
+                let etag = output.e_tag().map(|s| s.to_string());
+                let state = serde_json::from_slice(&output.body.collect().await?.into_bytes())
+                    .context("failed to parse puller state file as json")?;
+                let loaded = S3State { state, etag };
+                S3_STATE_CACHE.lock().unwrap().insert(key, loaded.clone());
+                Ok(loaded)
+            }
+            Err(SdkError::ServiceError(e))
+                if e.raw().http().status() == http::StatusCode::NOT_MODIFIED
+                    && cached.is_some() =>
+            {
+                debug!("Puller state for {} is unchanged, using cached", name);
+                Ok(cached.unwrap())
+            }
+            Err(e) => {
+                let se = e.into_service_error();
+                match se.kind {
+                    aws_sdk_s3::error::GetObjectErrorKind::NoSuchKey(_) => Ok(S3State {
+                        state: json!({}),
+                        etag: None,
+                    }),
+                    _ => Err(se.into()),
+                }
+            }
+        }
+    }
+
+    /// Applies `f` to the state object, writing it back only if it wasn't changed in the
+    /// meantime (`If-Match`, or `If-None-Match` for a new object). `f` returns false to skip
+    /// the write.
+    async fn update_s3_state<F>(&self, name: &str, f: F) -> Result<()>
+    where
+        F: Fn(&mut Value) -> bool,
+    {
+        let bucket = std::env::var("INGESTION_BUCKET_NAME").context("need bucket!")?;
+        for _ in 0..MAX_CONFLICT_RETRIES {
+            let S3State { mut state, etag } = self.load_s3_state(name).await?;
+            if !f(&mut state) {
+                return Ok(());
+            }
+
+            let mut op = self
+                .s3
+                .put_object()
+                .bucket(&bucket)
+                .key(Self::s3_state_key(name))
+                .body(ByteStream::from(serde_json::to_vec(&state)?))
+                .customize()
+                .await?;
+            let (header, value) = match etag.as_ref() {
+                Some(etag) => ("If-Match", etag.as_str()),
+                None => ("If-None-Match", "*"),
+            };
+            op.request_mut()
+                .headers_mut()
+                .insert(header, http::HeaderValue::from_str(value)?);
+
+            match op.send().await {
+                Ok(output) => {
+                    let etag = output.e_tag().map(|s| s.to_string());
+                    S3_STATE_CACHE
+                        .lock()
+                        .unwrap()
+                        .insert(Self::s3_state_key(name), S3State { state, etag });
+                    return Ok(());
+                }
+                Err(SdkError::ServiceError(e))
+                    if e.raw().http().status() == http::StatusCode::PRECONDITION_FAILED
+                        || e.raw().http().status() == http::StatusCode::CONFLICT =>
+                {
+                    debug!("Puller state for {} changed concurrently, retrying", name);
+                    let jitter = rand::thread_rng().gen_range(5..50);
+                    tokio::time::sleep(std::time::Duration::from_millis(jitter)).await;
+                }
+                Err(e) => return Err(e).context("Failed to save puller state"),
+            }
+        }
+        Err(anyhow!(
+            "Failed to update puller state for {}, too much contention",
+            name
+        ))
+    }
+
+    async fn load_legacy(&self, name: &str) -> Result<Option<Value>> {
+        let bucket = std::env::var("INGESTION_BUCKET_NAME").context("need bucket!")?;
+        let s3_key = format!("{}/{}.json", LEGACY_S3_CHECKPOINT_PREFIX, name);
+
+        let res = self
+            .s3
+            .get_object()
+            .bucket(&bucket)
+            .key(&s3_key)
+            .send()
+            .await;
+        match res {
+            Ok(output) => Ok(Some(
+                serde_json::from_slice(&output.body.collect().await?.into_bytes())
+                    .context("failed to parse last checkpoint file as json")?,
+            )),
+            Err(e) => {
+                let se = e.into_service_error();
+                match se.kind {
+                    aws_sdk_s3::error::GetObjectErrorKind::NoSuchKey(_) => Ok(None),
+                    _ => Err(se.into()),
+                }
+            }
+        }
+    }
+}
+
+fn pull_key(name: &str, window: &str) -> String {
+    format!("{}#pull#{}", name, window)
+}
+
+fn requests_key(name: &str, day: &str) -> String {
+    format!("{}#requests#{}", name, day)
+}
+
+/// Removes expired pull claims and records from a state object.
+fn prune_pulls(state: &mut Value, now: i64) {
+    if let Some(pulls) = state.get_mut("pulls").and_then(|p| p.as_object_mut()) {
+        pulls.retain(|_, p| p.get("until").and_then(|u| u.as_i64()).unwrap_or(0) > now);
+    }
+}
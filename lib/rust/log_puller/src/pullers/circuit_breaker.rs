@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::model::AttributeValue;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use lazy_static::lazy_static;
+use serde_json::json;
+use tracing::{error, info, warn};
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref DDB_CLIENT: AsyncOnce<aws_sdk_dynamodb::Client> =
+        AsyncOnce::new(async { aws_sdk_dynamodb::Client::new(AWS_CONFIG.get().await) });
+    static ref SNS_CLIENT: AsyncOnce<aws_sdk_sns::Client> =
+        AsyncOnce::new(async { aws_sdk_sns::Client::new(AWS_CONFIG.get().await) });
+}
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_COOLDOWN_MINUTES: i64 = 60;
+/// Failure counts are forgotten after this long without a new failure.
+const FAILURE_TTL_DAYS: i64 = 7;
+
+/// Stops pulling a log source that keeps failing auth, so a misconfigured source doesn't burn
+/// Lambda retries and API quota. After `circuit_breaker_threshold` consecutive auth failures the
+/// breaker opens and pulls are skipped for `circuit_breaker_cooldown_minutes`, then retried.
+///
+/// State is kept in the `PULLER_CIRCUIT_BREAKER_TABLE_NAME` DynamoDB table, keyed by log source
+/// (and tenant). An alert is published to `PULLER_HEALTH_TOPIC_ARN` when the breaker opens.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    /// The table isn't set up in some deployments (e.g. local runs), then this is a no-op.
+    table_name: Option<String>,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<CircuitBreaker> {
+        let get = |k: &str| config.get(k).map(|s| s.trim());
+        let threshold = get("circuit_breaker_threshold")
+            .map(|s| s.parse::<u32>())
+            .transpose()
+            .context("circuit_breaker_threshold must be an integer")?
+            .unwrap_or(DEFAULT_FAILURE_THRESHOLD)
+            .max(1);
+        let cooldown_minutes = get("circuit_breaker_cooldown_minutes")
+            .map(|s| s.parse::<i64>())
+            .transpose()
+            .context("circuit_breaker_cooldown_minutes must be an integer")?
+            .unwrap_or(DEFAULT_COOLDOWN_MINUTES);
+
+        Ok(CircuitBreaker {
+            table_name: std::env::var("PULLER_CIRCUIT_BREAKER_TABLE_NAME").ok(),
+            threshold,
+            cooldown: Duration::minutes(cooldown_minutes),
+        })
+    }
+
+    /// Returns when the breaker closes again if it's currently open for `key`.
+    pub async fn open_until(&self, key: &str) -> Result<Option<DateTime<Utc>>> {
+        let table_name = match self.table_name.as_ref() {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let ddb = DDB_CLIENT.get().await;
+        let res = ddb
+            .get_item()
+            .table_name(table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await
+            .context("Failed to load circuit breaker state")?;
+
+        let open_until = res
+            .item()
+            .and_then(|item| item.get("open_until"))
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse::<i64>().ok())
+            .map(|ts| Utc.timestamp(ts, 0))
+            .filter(|open_until| *open_until > Utc::now());
+        Ok(open_until)
+    }
+
+    /// Resets the failure count after a successful pull.
+    pub async fn record_success(&self, key: &str) -> Result<()> {
+        let table_name = match self.table_name.as_ref() {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        let ddb = DDB_CLIENT.get().await;
+        let res = ddb
+            .delete_item()
+            .table_name(table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .condition_expression("attribute_exists(pk)")
+            .send()
+            .await;
+        match res {
+            Ok(_) => {
+                info!("Reset circuit breaker for {}", key);
+                Ok(())
+            }
+            Err(e) => {
+                let se = e.into_service_error();
+                if se.is_conditional_check_failed_exception() {
+                    Ok(())
+                } else {
+                    Err(se).context("Failed to reset circuit breaker")
+                }
+            }
+        }
+    }
+
+    /// Counts an auth failure, opening the breaker (and alerting) once the threshold is reached.
+    pub async fn record_auth_failure(&self, key: &str, err: &anyhow::Error) -> Result<()> {
+        let table_name = match self.table_name.as_ref() {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        let ddb = DDB_CLIENT.get().await;
+        let ttl = (Utc::now() + Duration::days(FAILURE_TTL_DAYS)).timestamp();
+        let res = ddb
+            .update_item()
+            .table_name(table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .update_expression("ADD failures :one SET #ttl = :ttl")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":ttl", AttributeValue::N(ttl.to_string()))
+            .return_values(aws_sdk_dynamodb::model::ReturnValue::UpdatedNew)
+            .send()
+            .await
+            .context("Failed to record circuit breaker failure")?;
+
+        let failures = res
+            .attributes()
+            .and_then(|attrs| attrs.get("failures"))
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse::<u32>().ok())
+            .unwrap_or(0);
+        warn!(
+            "Auth failure {} of {} for {}",
+            failures, self.threshold, key
+        );
+        if failures < self.threshold {
+            return Ok(());
+        }
+
+        // Reset the count so the source gets `threshold` more attempts after the cooldown.
+        let open_until = Utc::now() + self.cooldown;
+        ddb.update_item()
+            .table_name(table_name)
+            .key("pk", AttributeValue::S(key.to_string()))
+            .update_expression("SET open_until = :open_until, failures = :zero")
+            .expression_attribute_values(
+                ":open_until",
+                AttributeValue::N(open_until.timestamp().to_string()),
+            )
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .send()
+            .await
+            .context("Failed to open circuit breaker")?;
+        error!(
+            "Opened circuit breaker for {} until {} after {} auth failures",
+            key, open_until, failures
+        );
+
+        if let Err(e) = self.alert(key, failures, open_until, err).await {
+            error!("Failed to send circuit breaker alert for {}: {:#}", key, e);
+        }
+        Ok(())
+    }
+
+    async fn alert(
+        &self,
+        key: &str,
+        failures: u32,
+        open_until: DateTime<Utc>,
+        err: &anyhow::Error,
+    ) -> Result<()> {
+        let topic_arn = match std::env::var("PULLER_HEALTH_TOPIC_ARN") {
+            Ok(t) => t,
+            Err(_) => return Ok(()),
+        };
+        let message = json!({
+            "type": "puller_circuit_breaker_open",
+            "log_source": key,
+            "failures": failures,
+            "open_until": open_until.to_rfc3339(),
+            "error": format!("{:#}", err),
+        });
+        SNS_CLIENT
+            .get()
+            .await
+            .publish()
+            .topic_arn(topic_arn)
+            .subject(format!("[Matano] Log puller disabled for {}", key))
+            .message(message.to_string())
+            .send()
+            .await?;
+        Ok(())
+    }
+}
@@ -1,20 +1,20 @@
 use anyhow::{anyhow, Context as AnyhowContext, Error, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, FixedOffset};
-use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     io::{Read, Write},
 };
+use tracing::{debug, error, info};
 
 use super::{PullLogs, PullLogsContext};
 
 #[derive(Clone)]
 pub struct CisaKevPuller;
 
-
-const CISA_KEV_URL: &str = "https://www.cisa.gov/sites/default/files/csv/known_exploited_vulnerabilities.csv";
+const CISA_KEV_URL: &str =
+    "https://www.cisa.gov/sites/default/files/csv/known_exploited_vulnerabilities.csv";
 const CISA_KEV_HEADERS: [&str; 9] = [
     "cveID",
     "vendorProject",
@@ -37,7 +37,11 @@ impl PullLogs for CisaKevPuller {
         end_dt: DateTime<FixedOffset>,
     ) -> Result<Vec<u8>> {
         info!("Pulling CISA KEV...");
-        let resp = client.get(CISA_KEV_URL).send().await?.text().await?;
+        let resp = ctx
+            .send(&client, client.get(CISA_KEV_URL))
+            .await?
+            .text()
+            .await?;
 
         let mut json_bytes = vec![];
 
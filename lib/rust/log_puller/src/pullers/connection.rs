@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+/// Connections idle longer are closed. Below the usual 60s load balancer idle timeout, so
+/// pooled connections aren't reused just after the server side closed them.
+const DEFAULT_POOL_IDLE_TIMEOUT_SECS: f64 = 50.0;
+const DEFAULT_TCP_KEEPALIVE_SECS: f64 = 30.0;
+
+/// Which HTTP version a log source's client speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTP/2 if the server offers it during the TLS handshake, otherwise HTTP/1.1.
+    Auto,
+    Http1,
+    /// HTTP/2 without negotiating it first, e.g. for plaintext (h2c) endpoints.
+    Http2,
+}
+
+/// Connection pooling for a log source's HTTP client. Each log source gets its own client and
+/// pool, so chatty APIs reuse their connections across pages instead of doing a TLS handshake
+/// per request.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     # idle connections kept per host, unlimited by default
+///     http_pool_max_idle_per_host: 4
+///     http_pool_idle_timeout_seconds: 50
+///     http_tcp_keepalive_seconds: 30
+///     # auto, "1.1" or "2"
+///     http_version: auto
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct HttpConnectionOptions {
+    pub max_idle_per_host: Option<usize>,
+    pub pool_idle_timeout: Duration,
+    pub tcp_keepalive: Duration,
+    pub version: HttpVersion,
+}
+
+impl Default for HttpConnectionOptions {
+    fn default() -> Self {
+        HttpConnectionOptions {
+            max_idle_per_host: None,
+            pool_idle_timeout: Duration::from_secs_f64(DEFAULT_POOL_IDLE_TIMEOUT_SECS),
+            tcp_keepalive: Duration::from_secs_f64(DEFAULT_TCP_KEEPALIVE_SECS),
+            version: HttpVersion::Auto,
+        }
+    }
+}
+
+impl HttpConnectionOptions {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<HttpConnectionOptions> {
+        let max_idle_per_host = match config.get("http_pool_max_idle_per_host") {
+            Some(v) => Some(
+                v.trim()
+                    .parse::<usize>()
+                    .context("http_pool_max_idle_per_host must be a non negative integer")?,
+            ),
+            None => None,
+        };
+        let version = match config.get("http_version").map(|v| v.trim()) {
+            None | Some("auto") => HttpVersion::Auto,
+            Some("1.1") => HttpVersion::Http1,
+            Some("2") => HttpVersion::Http2,
+            Some(v) => return Err(anyhow!("Unsupported http_version: {}", v)),
+        };
+        Ok(HttpConnectionOptions {
+            max_idle_per_host,
+            pool_idle_timeout: parse_seconds(
+                config,
+                "http_pool_idle_timeout_seconds",
+                DEFAULT_POOL_IDLE_TIMEOUT_SECS,
+            )?,
+            tcp_keepalive: parse_seconds(
+                config,
+                "http_tcp_keepalive_seconds",
+                DEFAULT_TCP_KEEPALIVE_SECS,
+            )?,
+            version,
+        })
+    }
+
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        builder = builder
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive);
+        if let Some(max_idle) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        match self.version {
+            HttpVersion::Auto => builder.http2_adaptive_window(true),
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge().http2_adaptive_window(true),
+        }
+    }
+}
+
+fn parse_seconds(config: &HashMap<String, String>, key: &str, default: f64) -> Result<Duration> {
+    let secs = match config.get(key) {
+        Some(v) => v
+            .trim()
+            .parse::<f64>()
+            .with_context(|| format!("{} must be a number", key))?,
+        None => default,
+    };
+    if !secs.is_finite() || secs <= 0.0 {
+        return Err(anyhow!("{} must be positive", key));
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
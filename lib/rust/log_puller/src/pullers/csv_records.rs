@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use serde_json::{Map, Number, Value};
+
+/// Formats of timestamps without a time zone, read as UTC, e.g. `2023-01-31 12:00:00` (LastPass)
+/// or `20230131120000.123` (Salesforce event log files).
+const NAIVE_TIMESTAMP_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y%m%d%H%M%S%.f",
+    "%m/%d/%Y %H:%M:%S",
+];
+
+/// The JSON type a CSV column is converted to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvType {
+    String,
+    Integer,
+    Float,
+    /// `true`/`false`, `1`/`0` or `yes`/`no`.
+    Boolean,
+    /// Converted to an RFC 3339 UTC timestamp. Read as RFC 3339, epoch seconds or millis, or one
+    /// of the common formats without a time zone (as UTC), unless a chrono format is given.
+    Timestamp(Option<String>),
+    /// A JSON document within a column, e.g. a list of IPs.
+    Json,
+}
+
+impl CsvType {
+    fn parse(s: &str) -> Result<CsvType> {
+        let s = s.trim();
+        if let Some(format) = s
+            .strip_prefix("timestamp(")
+            .and_then(|f| f.strip_suffix(')'))
+        {
+            return Ok(CsvType::Timestamp(Some(format.to_string())));
+        }
+        Ok(match s.to_lowercase().as_str() {
+            "" | "string" => CsvType::String,
+            "int" | "integer" => CsvType::Integer,
+            "float" | "double" => CsvType::Float,
+            "bool" | "boolean" => CsvType::Boolean,
+            "timestamp" => CsvType::Timestamp(None),
+            "json" => CsvType::Json,
+            t => return Err(anyhow!("Invalid csv_columns type: {}", t)),
+        })
+    }
+
+    fn convert(&self, s: &str) -> Result<Value> {
+        Ok(match self {
+            CsvType::String => Value::String(s.to_string()),
+            CsvType::Integer => Value::from(s.parse::<i64>()?),
+            CsvType::Float => Number::from_f64(s.parse::<f64>()?)
+                .map(Value::Number)
+                .context("Float isn't finite")?,
+            CsvType::Boolean => match s.to_lowercase().as_str() {
+                "true" | "1" | "yes" => Value::Bool(true),
+                "false" | "0" | "no" => Value::Bool(false),
+                b => return Err(anyhow!("Invalid boolean: {}", b)),
+            },
+            CsvType::Timestamp(format) => Value::String(
+                parse_timestamp(s, format.as_deref())?.to_rfc3339_opts(SecondsFormat::Millis, true),
+            ),
+            CsvType::Json => serde_json::from_str(s)?,
+        })
+    }
+}
+
+/// A CSV column converted to a typed JSON field.
+#[derive(Debug, Clone)]
+pub struct CsvColumn {
+    header: String,
+    field: String,
+    kind: CsvType,
+}
+
+impl CsvColumn {
+    pub fn new(header: impl Into<String>, field: impl Into<String>, kind: CsvType) -> CsvColumn {
+        CsvColumn {
+            header: header.into(),
+            field: field.into(),
+            kind,
+        }
+    }
+}
+
+/// Converts CSV/TSV exports (e.g. Salesforce event log files or LastPass reports) to NDJSON
+/// records, renaming and typing the mapped columns so records match the log source's schema.
+/// Unmapped columns are kept as strings under their header, and empty cells are left out, as
+/// CSV can't tell them from nulls.
+///
+/// Pullers declare the mapping with `CsvMapping::new`, and `custom_api` sources with the
+/// `csv_columns` property, as comma separated `header:field:type` entries (`field` defaults to
+/// the header, `type` to string). `csv_headers` names the columns of exports without a header
+/// row, and `csv_delimiter` is `,` (default), `tab` or any single character.
+/// CSV/TSV files within pulled archives or attachments are converted without a mapping.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     csv_columns: "TIMESTAMP:timestamp:timestamp(%Y%m%d%H%M%S%.3f), USER_ID:user_id, RUN_TIME:run_time_ms:int, IS_API:is_api:bool"
+///     csv_delimiter: tab
+/// ```
+#[derive(Debug, Clone)]
+pub struct CsvMapping {
+    columns: Vec<CsvColumn>,
+    headers: Option<Vec<String>>,
+    delimiter: u8,
+}
+
+impl CsvMapping {
+    pub fn new(columns: Vec<CsvColumn>) -> CsvMapping {
+        CsvMapping {
+            columns,
+            headers: None,
+            delimiter: b',',
+        }
+    }
+
+    /// For exports without a header row.
+    pub fn with_headers(mut self, headers: Vec<String>) -> CsvMapping {
+        self.headers = Some(headers);
+        self
+    }
+
+    pub fn with_delimiter(mut self, delimiter: u8) -> CsvMapping {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<CsvMapping>> {
+        let get = |k: &str| {
+            config
+                .get(k)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let spec = match get("csv_columns") {
+            Some(spec) => spec,
+            None => return Ok(None),
+        };
+        let columns = spec
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|column| {
+                // The type may be a chrono format with colons, so it's the rest of the entry.
+                let mut parts = column.splitn(3, ':').map(|s| s.trim());
+                let header = parts.next().unwrap_or_default();
+                let field = parts.next().filter(|s| !s.is_empty()).unwrap_or(header);
+                let kind = CsvType::parse(parts.next().unwrap_or_default())
+                    .with_context(|| format!("Invalid csv_columns entry: {}", column))?;
+                Ok(CsvColumn::new(header, field, kind))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut mapping = CsvMapping::new(columns);
+        if let Some(headers) = get("csv_headers") {
+            mapping =
+                mapping.with_headers(headers.split(',').map(|s| s.trim().to_string()).collect());
+        }
+        if let Some(delimiter) = get("csv_delimiter") {
+            let delimiter = match delimiter.as_str() {
+                "tab" | "\\t" => b'\t',
+                d if d.len() == 1 => d.as_bytes()[0],
+                d => return Err(anyhow!("Invalid csv_delimiter: {}", d)),
+            };
+            mapping = mapping.with_delimiter(delimiter);
+        }
+        Ok(Some(mapping))
+    }
+
+    /// Converts each row of the CSV data to an NDJSON record.
+    pub fn to_ndjson(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.headers.is_none())
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(data);
+        let headers = match self.headers.as_ref() {
+            Some(headers) => csv::StringRecord::from(headers.clone()),
+            None => reader.headers()?.clone(),
+        };
+        let columns = headers
+            .iter()
+            .map(|h| self.columns.iter().find(|c| c.header == h))
+            .collect::<Vec<_>>();
+
+        for (i, row) in reader.records().enumerate() {
+            let row = row?;
+            let mut record = Map::new();
+            for ((header, column), cell) in headers.iter().zip(columns.iter()).zip(row.iter()) {
+                if cell.is_empty() {
+                    continue;
+                }
+                match column {
+                    Some(column) => {
+                        let value = column.kind.convert(cell).with_context(|| {
+                            format!("Invalid {} in CSV row {}: {}", header, i + 1, cell)
+                        })?;
+                        record.insert(column.field.clone(), value);
+                    }
+                    None => {
+                        record.insert(header.to_string(), Value::String(cell.to_string()));
+                    }
+                }
+            }
+            serde_json::to_writer(&mut *out, &record)?;
+            out.push(b'\n');
+        }
+        Ok(())
+    }
+}
+
+fn parse_timestamp(s: &str, format: Option<&str>) -> Result<DateTime<Utc>> {
+    if let Some(format) = format {
+        if let Ok(dt) = DateTime::parse_from_str(s, format) {
+            return Ok(dt.with_timezone(&Utc));
+        }
+        return Ok(Utc.from_utc_datetime(&NaiveDateTime::parse_from_str(s, format)?));
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+    // Epoch seconds have 10 digits and millis 13, longer numbers are compact timestamps.
+    if s.len() <= 13 && s.bytes().all(|b| b.is_ascii_digit()) {
+        let n = s.parse::<i64>()?;
+        let dt = match s.len() {
+            len if len > 10 => Utc.timestamp_millis_opt(n).single(),
+            _ => Utc.timestamp_opt(n, 0).single(),
+        };
+        return dt.with_context(|| format!("Invalid epoch timestamp: {}", s));
+    }
+    NAIVE_TIMESTAMP_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .map(|dt| Utc.from_utc_datetime(&dt))
+        .with_context(|| format!("Invalid timestamp: {}", s))
+}
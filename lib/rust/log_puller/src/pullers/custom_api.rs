@@ -0,0 +1,705 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use reqwest::header;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+use super::csv_records::CsvMapping;
+use super::oauth2::{RefreshTokenGrant, TokenSource};
+use super::pagination::{
+    fetch_pages_concurrently, lookup_paging_value, CursorToken, FetchedPage, LinkHeader, NextLink,
+    NoPagination, OffsetLimit, Page, PageNumber, PageRequest, Pages, Paginator, DEFAULT_MAX_PAGES,
+};
+use super::payload;
+use super::signing::RequestSigner;
+use super::sigv4::AwsSigV4Signer;
+use super::xml_records::{self, XmlMapping};
+use super::{response_source, PropertySpec, PropertyType, PullLogs, PullLogsContext};
+
+/// Generic puller for REST APIs, driven entirely by the `managed.properties` of the log source.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: custom_api
+///   properties:
+///     base_url: https://api.example.com/v1/events
+///     auth_type: bearer
+///     start_time_param: since
+///     end_time_param: until
+///     time_format: rfc3339
+///     pagination: cursor
+///     cursor_param: cursor
+///     cursor_path: meta.next_cursor
+///     records_path: data
+/// ```
+///
+/// For page or offset pagination, `parallelism: 4` fetches pages concurrently. Setting
+/// `total_count_path` (with `page_size`) avoids requesting pages past the end.
+///
+/// For APIs returning CSV instead of JSON, `csv_columns` converts each row to a typed record,
+/// see `CsvMapping`. XML responses are converted to JSON (see `XmlMapping`), with paths like
+/// `records_path` then relative to the root element.
+#[derive(Clone)]
+pub struct CustomApiPuller;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum AuthType {
+    None,
+    /// `Authorization: Bearer <api_token>`
+    Bearer,
+    /// `Authorization: Basic <username:password>`
+    Basic,
+    /// `<auth_header_name>: <auth_header_prefix><api_token>`
+    Header,
+    /// `?<auth_query_param>=<api_token>`
+    Query,
+    /// `Authorization: Bearer <access_token>`, refreshed from `<oauth2_token_url>` using the
+    /// `refresh_token` secret. Rotated refresh tokens are written back to the secret.
+    OAuth2RefreshToken,
+    /// AWS SigV4 signed requests, see `AwsSigV4Signer`.
+    AwsSigV4,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TimeFormat {
+    Rfc3339,
+    Unix,
+    UnixMillis,
+    /// Any chrono strftime format string.
+    Custom(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Pagination {
+    None,
+    /// Incrementing page number, stops on an empty page.
+    Page,
+    /// Incrementing record offset, stops on a short page.
+    Offset,
+    /// Opaque cursor read from the response body and passed back as a query param.
+    Cursor,
+    /// Full next page URL read from the response body.
+    NextUrl,
+    /// RFC 8288 `Link: <...>; rel="next"` response header.
+    LinkHeader,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CustomApiConfig {
+    pub base_url: String,
+    pub method: reqwest::Method,
+    pub auth: ApiAuth,
+    pub start_time_param: Option<String>,
+    pub end_time_param: Option<String>,
+    pub time_format: TimeFormat,
+    pub pagination: Pagination,
+    pub page_param: String,
+    pub page_start: i64,
+    pub offset_param: String,
+    pub page_size_param: Option<String>,
+    pub page_size: Option<usize>,
+    pub cursor_param: String,
+    pub cursor_path: String,
+    pub next_url_path: String,
+    pub records_path: Option<String>,
+    pub extra_query: Vec<(String, String)>,
+    pub max_pages: usize,
+    /// Pages fetched concurrently, for page and offset pagination.
+    pub parallelism: usize,
+    /// Path of the total record count in the first page, so no pages past the end are requested.
+    pub total_count_path: Option<String>,
+    /// For APIs returning CSV instead of JSON, see `CsvMapping`.
+    pub csv: Option<CsvMapping>,
+    /// How XML responses are converted to JSON, before `records_path` and pagination apply.
+    pub xml: XmlMapping,
+}
+
+impl CustomApiConfig {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<CustomApiConfig> {
+        let get = |k: &str| config.get(k).map(|s| s.trim().to_string());
+        let get_or = |k: &str, default: &str| get(k).unwrap_or_else(|| default.to_string());
+
+        let base_url = get("base_url").context("Missing base_url")?;
+        let method = match get_or("method", "GET").to_uppercase().as_str() {
+            "GET" => reqwest::Method::GET,
+            "POST" => reqwest::Method::POST,
+            m => return Err(anyhow!("Unsupported method: {}", m)),
+        };
+
+        let pagination = match get_or("pagination", "none").to_lowercase().as_str() {
+            "none" => Pagination::None,
+            "page" => Pagination::Page,
+            "offset" => Pagination::Offset,
+            "cursor" => Pagination::Cursor,
+            "next_url" => Pagination::NextUrl,
+            "link_header" => Pagination::LinkHeader,
+            p => return Err(anyhow!("Unsupported pagination: {}", p)),
+        };
+
+        let page_size = get("page_size")
+            .map(|s| s.parse::<usize>())
+            .transpose()
+            .context("page_size must be an integer")?;
+        if pagination == Pagination::Offset && page_size.is_none() {
+            return Err(anyhow!("page_size is required for offset pagination"));
+        }
+
+        // Static query params, ex: `query_params: "type=audit&include=actor"`
+        let extra_query = get("query_params")
+            .map(|qs| {
+                url::form_urlencoded::parse(qs.trim_start_matches('?').as_bytes())
+                    .into_owned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let records_path = get("records_path").filter(|s| !s.is_empty());
+
+        Ok(CustomApiConfig {
+            base_url,
+            method,
+            auth: ApiAuth::from_config(config)?,
+            start_time_param: get("start_time_param"),
+            end_time_param: get("end_time_param"),
+            time_format: TimeFormat::from_config(config),
+            pagination,
+            page_param: get_or("page_param", "page"),
+            page_start: get_or("page_start", "1")
+                .parse()
+                .context("page_start must be an integer")?,
+            offset_param: get_or("offset_param", "offset"),
+            page_size_param: get("page_size_param"),
+            page_size,
+            cursor_param: get_or("cursor_param", "cursor"),
+            cursor_path: get_or("cursor_path", "next_cursor"),
+            next_url_path: get_or("next_url_path", "next"),
+            records_path,
+            extra_query,
+            max_pages: get("max_pages")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(DEFAULT_MAX_PAGES),
+            parallelism: get("parallelism")
+                .map(|s| s.parse::<usize>())
+                .transpose()
+                .context("parallelism must be an integer")?
+                .unwrap_or(1),
+            total_count_path: get("total_count_path"),
+            csv: CsvMapping::from_config(config)?,
+            xml: XmlMapping::from_config(config),
+        })
+    }
+
+    /// Query params sent with every page of a window.
+    fn base_query(
+        &self,
+        auth_query: &[(String, String)],
+        start_dt: &DateTime<FixedOffset>,
+        end_dt: &DateTime<FixedOffset>,
+    ) -> Vec<(String, String)> {
+        let mut base_query = self.extra_query.clone();
+        base_query.extend_from_slice(auth_query);
+
+        if let Some(param) = self.start_time_param.as_ref() {
+            base_query.push((param.clone(), self.time_format.format(start_dt)));
+        }
+        if let Some(param) = self.end_time_param.as_ref() {
+            base_query.push((param.clone(), self.time_format.format(end_dt)));
+        }
+        if let (Some(param), Some(size)) = (&self.page_size_param, self.page_size) {
+            base_query.push((param.clone(), size.to_string()));
+        }
+        base_query
+    }
+
+    fn paginator(&self) -> Box<dyn Paginator> {
+        match self.pagination {
+            Pagination::None => Box::new(NoPagination),
+            Pagination::Page => Box::new(PageNumber::new(&self.page_param, self.page_start)),
+            Pagination::Offset => Box::new(OffsetLimit::new(
+                &self.offset_param,
+                self.page_size.unwrap_or(1),
+            )),
+            Pagination::Cursor => Box::new(CursorToken::new(&self.cursor_param, &self.cursor_path)),
+            Pagination::NextUrl => Box::new(NextLink::new(&self.next_url_path)),
+            Pagination::LinkHeader => Box::new(LinkHeader),
+        }
+    }
+}
+
+impl TimeFormat {
+    pub fn from_config(config: &HashMap<String, String>) -> TimeFormat {
+        match config.get("time_format").map(|s| s.trim()) {
+            None | Some("rfc3339") => TimeFormat::Rfc3339,
+            Some("unix") => TimeFormat::Unix,
+            Some("unix_ms") => TimeFormat::UnixMillis,
+            Some(f) => TimeFormat::Custom(f.to_string()),
+        }
+    }
+
+    pub fn format(&self, dt: &DateTime<FixedOffset>) -> String {
+        match self {
+            TimeFormat::Rfc3339 => dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            TimeFormat::Unix => dt.timestamp().to_string(),
+            TimeFormat::UnixMillis => dt.timestamp_millis().to_string(),
+            TimeFormat::Custom(f) => dt.format(f).to_string(),
+        }
+    }
+}
+
+/// Auth settings shared by the declarative pullers (`auth_type`, `auth_header_name`, ...).
+#[derive(Debug, Clone)]
+pub(crate) struct ApiAuth {
+    pub auth_type: AuthType,
+    pub header_name: String,
+    pub header_prefix: String,
+    pub query_param: String,
+    pub username: Option<String>,
+    pub oauth2_token_url: Option<String>,
+    pub oauth2_client_id: Option<String>,
+    pub signer: Option<Arc<dyn RequestSigner>>,
+}
+
+impl ApiAuth {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<ApiAuth> {
+        let get = |k: &str| config.get(k).map(|s| s.trim().to_string());
+
+        let auth_type = match get("auth_type")
+            .unwrap_or_else(|| "none".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "none" => AuthType::None,
+            "bearer" => AuthType::Bearer,
+            "basic" => AuthType::Basic,
+            "header" => AuthType::Header,
+            "query" => AuthType::Query,
+            "oauth2_refresh_token" => AuthType::OAuth2RefreshToken,
+            "aws_sigv4" => AuthType::AwsSigV4,
+            a => return Err(anyhow!("Unsupported auth_type: {}", a)),
+        };
+
+        let signer: Option<Arc<dyn RequestSigner>> = match auth_type {
+            AuthType::AwsSigV4 => Some(Arc::new(AwsSigV4Signer::from_config(config)?)),
+            _ => None,
+        };
+
+        Ok(ApiAuth {
+            auth_type,
+            header_name: get("auth_header_name").unwrap_or_else(|| "Authorization".to_string()),
+            // not trimmed, prefixes like "Token " are common
+            header_prefix: config
+                .get("auth_header_prefix")
+                .cloned()
+                .unwrap_or_default(),
+            query_param: get("auth_query_param").unwrap_or_else(|| "api_key".to_string()),
+            username: get("username"),
+            oauth2_token_url: get("oauth2_token_url"),
+            oauth2_client_id: get("oauth2_client_id"),
+            signer,
+        })
+    }
+
+    /// Loads the secret and returns the headers and query params to send on each request, and
+    /// sets the context's request signer if needed. Returns None if the secret is still a placeholder.
+    pub async fn resolve(
+        &self,
+        client: &reqwest::Client,
+        ctx: &PullLogsContext,
+    ) -> Result<Option<(header::HeaderMap, Vec<(String, String)>)>> {
+        let secret = match self.auth_type {
+            AuthType::None | AuthType::AwsSigV4 => None,
+            AuthType::Basic => Some(
+                ctx.get_secret_field("password")
+                    .await?
+                    .context("Missing password")?,
+            ),
+            AuthType::OAuth2RefreshToken => Some(
+                ctx.get_secret_field("refresh_token")
+                    .await?
+                    .context("Missing refresh_token")?,
+            ),
+            _ => Some(
+                ctx.get_secret_field("api_token")
+                    .await?
+                    .context("Missing api_token")?,
+            ),
+        };
+
+        // skip early if secret is equal <placeholder>
+        if secret.as_deref() == Some("<placeholder>") {
+            info!(
+                "Skipping {} because secret is still <placeholder>",
+                ctx.log_source_name
+            );
+            return Ok(None);
+        }
+
+        ctx.set_signer(self.signer.clone()).await;
+
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::USER_AGENT,
+            "rust-reqwest/matano".parse().expect("invalid user-agent"),
+        );
+        let mut query = vec![];
+
+        match (&self.auth_type, secret.as_ref()) {
+            (AuthType::Bearer, Some(token)) => {
+                headers.insert(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", token)
+                        .parse()
+                        .map_err(|err| anyhow!("Failed to parse auth token: {}", err))?,
+                );
+            }
+            (AuthType::Basic, Some(password)) => {
+                let username = self
+                    .username
+                    .as_ref()
+                    .context("Missing username for basic auth")?;
+                let creds = base64::encode(format!("{}:{}", username, password));
+                headers.insert(
+                    header::AUTHORIZATION,
+                    format!("Basic {}", creds)
+                        .parse()
+                        .map_err(|err| anyhow!("Failed to parse basic auth: {}", err))?,
+                );
+            }
+            (AuthType::Header, Some(token)) => {
+                let name = header::HeaderName::from_bytes(self.header_name.as_bytes())
+                    .context("Invalid auth_header_name")?;
+                headers.insert(
+                    name,
+                    format!("{}{}", self.header_prefix, token)
+                        .parse()
+                        .map_err(|err| anyhow!("Failed to parse auth token: {}", err))?,
+                );
+            }
+            (AuthType::Query, Some(token)) => {
+                query.push((self.query_param.clone(), token.clone()));
+            }
+            (AuthType::OAuth2RefreshToken, Some(_)) => {
+                let grant = RefreshTokenGrant::new(
+                    self.oauth2_token_url
+                        .as_ref()
+                        .context("Missing oauth2_token_url")?,
+                    self.oauth2_client_id
+                        .as_ref()
+                        .context("Missing oauth2_client_id")?,
+                    ctx.get_secret_field("client_secret").await?,
+                );
+                let access_token = grant.access_token(client, ctx).await?;
+                headers.insert(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", access_token)
+                        .parse()
+                        .map_err(|err| anyhow!("Failed to parse access token: {}", err))?,
+                );
+            }
+            _ => {}
+        }
+
+        Ok(Some((headers, query)))
+    }
+}
+
+/// Looks up a dotted path (ex: `data.items` or `results.0.events`) in a JSON value.
+pub(crate) fn lookup_json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.is_empty() || path == "." {
+        return Some(value);
+    }
+    let pointer = format!("/{}", path.trim_start_matches('.').replace('.', "/"));
+    value.pointer(&pointer)
+}
+
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required(
+        "base_url",
+        PropertyType::Url,
+        "https://api.example.com/v1/events",
+    ),
+    PropertySpec::optional("page_size", PropertyType::Integer, "100"),
+    PropertySpec::optional("max_pages", PropertyType::Integer, "100"),
+    PropertySpec::optional("parallelism", PropertyType::Integer, "4"),
+    PropertySpec::optional(
+        "csv_columns",
+        PropertyType::String,
+        "TIMESTAMP:timestamp:timestamp, RUN_TIME:run_time_ms:int",
+    ),
+];
+
+#[async_trait]
+impl PullLogs for CustomApiPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    async fn pull_logs(
+        self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!("Pulling custom API logs for {}....", ctx.log_source_name);
+
+        let api_config = CustomApiConfig::from_config(ctx.config())?;
+
+        let (headers, auth_query) = match api_config.auth.resolve(&client, ctx).await? {
+            Some(auth) => auth,
+            None => return Ok(vec![]),
+        };
+
+        // Finish a window cut short by the invocation deadline first, then pull from its end.
+        let mut checkpoint_json = ctx.checkpoint_json.lock().await;
+        let continuation = checkpoint_json
+            .as_ref()
+            .and_then(|c| c.get("continuation"))
+            .and_then(|c| serde_json::from_value::<Continuation>(c.clone()).ok());
+        let mut windows = vec![];
+        if let Some(continuation) = continuation.as_ref() {
+            info!(
+                "Resuming window {} to {} for {}",
+                continuation.start, continuation.end, ctx.log_source_name
+            );
+            windows.push((
+                continuation.start,
+                continuation.end,
+                Some(continuation.next_page.clone()),
+            ));
+        }
+        let start_dt = continuation.as_ref().map_or(start_dt, |c| c.end);
+        if start_dt < end_dt {
+            windows.push((start_dt, end_dt, None));
+        }
+
+        let mut ret: Vec<u8> = vec![];
+        let mut new_continuation = None;
+        for (start_dt, end_dt, resume_from) in windows {
+            let base_query = api_config.base_query(&auth_query, &start_dt, &end_dt);
+            let request = RequestParts {
+                client: &client,
+                ctx,
+                api_config: &api_config,
+                base_query: &base_query,
+                headers: &headers,
+            };
+            let pending = pull_window(&request, &mut ret, resume_from).await?;
+            if let Some(next_page) = pending {
+                info!(
+                    "Stopping early for {} to upload before the deadline, will resume in a continuation pull",
+                    ctx.log_source_name
+                );
+                ctx.request_continuation();
+                new_continuation = Some(Continuation {
+                    start: start_dt,
+                    end: end_dt,
+                    next_page,
+                });
+                break;
+            }
+        }
+
+        // Clear a finished continuation, the checkpoint is only written if it changed.
+        if new_continuation.is_some() || continuation.is_some() {
+            *checkpoint_json = Some(json!({ "continuation": new_continuation }));
+        }
+
+        // Remove last newline
+        if ret.last() == Some(&b'\n') {
+            ret.pop();
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Where a pull stopped early because of the invocation deadline, saved in the checkpoint.
+#[derive(Debug, Serialize, Deserialize)]
+struct Continuation {
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    next_page: PageRequest,
+}
+
+/// Pulls the pages of a single window into `ret`. Returns the next page if it stopped early
+/// because of the invocation deadline.
+async fn pull_window(
+    request: &RequestParts<'_>,
+    ret: &mut Vec<u8>,
+    resume_from: Option<PageRequest>,
+) -> Result<Option<PageRequest>> {
+    let api_config = request.api_config;
+    let ctx = request.ctx;
+    let paginator = api_config.paginator();
+
+    if api_config.parallelism > 1 && paginator.nth(0).is_some() && resume_from.is_none() {
+        let total_pages = |body: &Value| {
+            let path = api_config.total_count_path.as_ref()?;
+            let total = lookup_paging_value(body, path)?.parse::<usize>().ok()?;
+            let page_size = api_config.page_size?.max(1);
+            Some((total + page_size - 1) / page_size)
+        };
+        let pages = fetch_pages_concurrently(
+            paginator.as_ref(),
+            api_config.parallelism,
+            api_config.max_pages,
+            api_config.page_size,
+            total_pages,
+            move |page_req| request.fetch_page(page_req),
+        )
+        .await
+        .with_context(|| format!("Error paging {}", ctx.log_source_name))?;
+        debug!(
+            "Loaded {} pages concurrently for {}",
+            pages.len(),
+            ctx.log_source_name
+        );
+
+        for page in pages {
+            for record in page.records {
+                ret.extend(serde_json::to_vec(&record)?);
+                ret.push(b'\n');
+            }
+        }
+        return Ok(None);
+    }
+
+    let mut pages = match resume_from {
+        Some(next_page) => Pages::resume(paginator, next_page, api_config.max_pages),
+        None => Pages::new(paginator, api_config.max_pages),
+    };
+
+    while let Some(page_req) = pages
+        .next_request()
+        .with_context(|| format!("Error paging {}", ctx.log_source_name))?
+    {
+        if pages.page_num() > 0 && ctx.pull_deadline_reached() {
+            return Ok(Some(page_req));
+        }
+
+        let (response_headers, page) = request.fetch_page_with_headers(page_req).await?;
+        let num_records = page.records.len();
+
+        for record in page.records.iter() {
+            ret.extend(serde_json::to_vec(record)?);
+            ret.push(b'\n');
+        }
+
+        pages.advance(&Page {
+            headers: &response_headers,
+            body: &page.body,
+            num_records,
+        });
+        debug!(
+            "Loaded page {} with {} records for {}",
+            pages.page_num(),
+            num_records,
+            ctx.log_source_name
+        );
+    }
+
+    Ok(None)
+}
+
+/// Everything needed to request a page, shared by the sequential and concurrent paths.
+struct RequestParts<'a> {
+    client: &'a reqwest::Client,
+    ctx: &'a PullLogsContext,
+    api_config: &'a CustomApiConfig,
+    base_query: &'a [(String, String)],
+    headers: &'a header::HeaderMap,
+}
+
+impl<'a> RequestParts<'a> {
+    async fn fetch_page(&self, page_req: PageRequest) -> Result<FetchedPage> {
+        Ok(self.fetch_page_with_headers(page_req).await?.1)
+    }
+
+    async fn fetch_page_with_headers(
+        &self,
+        page_req: PageRequest,
+    ) -> Result<(header::HeaderMap, FetchedPage)> {
+        let api_config = self.api_config;
+        let req = match page_req {
+            PageRequest::Params(paging_params) => self
+                .client
+                .request(api_config.method.clone(), &api_config.base_url)
+                .query(self.base_query)
+                .query(&paging_params),
+            PageRequest::Url(url) => self.client.request(api_config.method.clone(), url),
+        };
+        let response = self
+            .ctx
+            .send(self.client, req.headers(self.headers.clone()))
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let msg = response.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "Error calling {}, status: {}, response: {}",
+                &api_config.base_url,
+                status,
+                msg
+            ));
+        }
+
+        let response_headers = response.headers().clone();
+        let source = response_source(&response);
+        let data = self.ctx.read_body(response).await?;
+        // Some APIs return the records as a (zipped) file instead of a JSON body.
+        if payload::is_archive(&data) {
+            let mut ndjson = vec![];
+            if let Err(e) = payload::payload_to_ndjson("", &data, &mut ndjson) {
+                return Err(self.ctx.quarantine(&source, &data, e).await);
+            }
+            let records = serde_json::Deserializer::from_slice(&ndjson)
+                .into_iter::<Value>()
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let body = Value::Null;
+            return Ok((response_headers, FetchedPage { body, records }));
+        }
+        if let Some(csv) = api_config.csv.as_ref() {
+            let mut ndjson = vec![];
+            if let Err(e) = csv.to_ndjson(&data, &mut ndjson) {
+                return Err(self.ctx.quarantine(&source, &data, e).await);
+            }
+            let records = serde_json::Deserializer::from_slice(&ndjson)
+                .into_iter::<Value>()
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let body = Value::Null;
+            return Ok((response_headers, FetchedPage { body, records }));
+        }
+        let content_type = response_headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok());
+        let body: Value = if xml_records::is_xml(content_type, &data) {
+            match api_config.xml.to_json(&data) {
+                Ok(body) => body,
+                Err(e) => return Err(self.ctx.quarantine(&source, &data, e).await),
+            }
+        } else {
+            self.ctx.parse_json(&source, &data).await?
+        };
+
+        let records = match api_config.records_path.as_ref() {
+            Some(path) => lookup_json_path(&body, path)
+                .with_context(|| format!("Missing records at path: {}", path))?,
+            None => &body,
+        };
+        let records = match records {
+            Value::Array(arr) => arr.to_owned(),
+            Value::Null => vec![],
+            v => vec![v.to_owned()],
+        };
+
+        Ok((response_headers, FetchedPage { body, records }))
+    }
+}
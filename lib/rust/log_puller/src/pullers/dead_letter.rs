@@ -0,0 +1,122 @@
+use anyhow::{Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_sqs::model::MessageAttributeValue;
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use lazy_static::lazy_static;
+
+use super::catch_up::{message_deduplication_id, message_group_id};
+use super::PullerError;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref SQS_CLIENT: AsyncOnce<aws_sdk_sqs::Client> =
+        AsyncOnce::new(async { aws_sdk_sqs::Client::new(AWS_CONFIG.get().await) });
+}
+
+/// Error messages are truncated to this many characters in the message attributes.
+const MAX_ERROR_ATTRIBUTE_LEN: usize = 4096;
+
+/// Why a puller message failed its last attempt, sent along with it to the DLQ as `matano.*`
+/// message attributes, so redrive tooling can triage it without pulling the window again.
+#[derive(Debug, Clone)]
+pub struct PullFailure {
+    pub log_source: String,
+    /// Tenants whose pulls failed, if the log source has multiple.
+    pub tenant_ids: Vec<String>,
+    pub error_class: PullerError,
+    /// Status of the last error response from the vendor, if any.
+    pub http_status: Option<u16>,
+    pub window_start: DateTime<FixedOffset>,
+    pub window_end: DateTime<FixedOffset>,
+    pub attempts: u32,
+    pub error_message: String,
+}
+
+/// Whether a message received `receive_count` times won't be received again, because the
+/// queue moves it to the DLQ after `PULLER_MAX_RECEIVE_COUNT` receives. Always false if the DLQ
+/// isn't configured, failed messages are then dead lettered by the queue as is.
+pub fn is_final_attempt(receive_count: u32) -> bool {
+    if std::env::var("PULLER_DLQ_URL").is_err() {
+        return false;
+    }
+    std::env::var("PULLER_MAX_RECEIVE_COUNT")
+        .ok()
+        .and_then(|c| c.trim().parse::<u32>().ok())
+        .map_or(false, |max_receive_count| {
+            receive_count >= max_receive_count
+        })
+}
+
+/// Sends a failed message body to the DLQ (`PULLER_DLQ_URL`) with its failure context. The
+/// original message should then be deleted, so the queue doesn't dead letter it too.
+pub async fn send_to_dlq(body: &str, failure: &PullFailure) -> Result<()> {
+    let queue_url = std::env::var("PULLER_DLQ_URL").context("Missing PULLER_DLQ_URL")?;
+    let rfc3339_utc = |dt: DateTime<FixedOffset>| {
+        dt.with_timezone(&Utc)
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    };
+    let error_message = failure
+        .error_message
+        .chars()
+        .take(MAX_ERROR_ATTRIBUTE_LEN)
+        .collect::<String>();
+
+    let mut attributes = vec![
+        ("matano.log_source", string_attribute(&failure.log_source)),
+        (
+            "matano.error_class",
+            string_attribute(failure.error_class.as_str()),
+        ),
+        (
+            "matano.window_start",
+            string_attribute(&rfc3339_utc(failure.window_start)),
+        ),
+        (
+            "matano.window_end",
+            string_attribute(&rfc3339_utc(failure.window_end)),
+        ),
+        ("matano.attempts", number_attribute(failure.attempts)),
+        ("matano.error", string_attribute(&error_message)),
+    ];
+    if let Some(status) = failure.http_status {
+        attributes.push(("matano.http_status", number_attribute(status)));
+    }
+    if !failure.tenant_ids.is_empty() {
+        attributes.push((
+            "matano.tenant_ids",
+            string_attribute(&failure.tenant_ids.join(",")),
+        ));
+    }
+
+    let mut req = SQS_CLIENT
+        .get()
+        .await
+        .send_message()
+        .set_message_group_id(message_group_id(&queue_url, &failure.log_source))
+        .set_message_deduplication_id(message_deduplication_id(&queue_url))
+        .queue_url(queue_url)
+        .message_body(body);
+    for (name, value) in attributes {
+        req = req.message_attributes(name, value);
+    }
+    req.send()
+        .await
+        .context("Failed to send message to the DLQ")?;
+    Ok(())
+}
+
+fn string_attribute(value: &str) -> MessageAttributeValue {
+    MessageAttributeValue::builder()
+        .data_type("String")
+        .string_value(value)
+        .build()
+}
+
+fn number_attribute(value: impl ToString) -> MessageAttributeValue {
+    MessageAttributeValue::builder()
+        .data_type("Number")
+        .string_value(value.to_string())
+        .build()
+}
@@ -0,0 +1,31 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+
+/// Time kept in reserve for uploading results before the Lambda times out.
+pub(crate) const DEADLINE_MARGIN: Duration = Duration::from_secs(15);
+/// Pullers stop paging this long before the deadline, leaving time to compress and upload.
+pub(crate) const PULL_DEADLINE_MARGIN: Duration = Duration::from_secs(30);
+
+/// Deadline of the current invocation in epoch millis, 0 if unknown.
+static INVOCATION_DEADLINE_MS: AtomicI64 = AtomicI64::new(0);
+
+/// Sets the deadline of the current Lambda invocation, from the `LambdaEvent` context.
+pub fn set_invocation_deadline(deadline_ms: u64) {
+    INVOCATION_DEADLINE_MS.store(deadline_ms as i64, Ordering::SeqCst);
+}
+
+/// Time left in the current invocation after keeping `margin` in reserve, None if unknown.
+pub(crate) fn remaining_time(margin: Duration) -> Option<Duration> {
+    let deadline_ms = INVOCATION_DEADLINE_MS.load(Ordering::SeqCst);
+    if deadline_ms == 0 {
+        return None;
+    }
+    let remaining_ms = deadline_ms - Utc::now().timestamp_millis();
+    Some(
+        Duration::from_millis(remaining_ms.max(0) as u64)
+            .checked_sub(margin)
+            .unwrap_or_default(),
+    )
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_s3::types::ByteStream;
+use lazy_static::lazy_static;
+use rand::Rng;
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use tracing::info;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref S3_CLIENT: AsyncOnce<aws_sdk_s3::Client> =
+        AsyncOnce::new(async { crate::s3_client(AWS_CONFIG.get().await, None) });
+    /// JSON fields and form/query parameters that hold credentials, e.g. in OAuth token responses.
+    static ref SENSITIVE_FIELD_RE: Regex = Regex::new(
+        r#"(?i)("(?:[a-z_]*token|[a-z_]*secret|password|api_?key|signature)"\s*:\s*)"[^"]*"|\b((?:[a-z_]*token|[a-z_]*secret|password|api_?key|signature)=)[^&\s]*"#
+    )
+    .unwrap();
+}
+
+/// Not under a log source prefix, so sampled payloads aren't ingested.
+const DEBUG_PREFIX: &str = "__puller_debug__";
+/// Logged bodies are truncated to this many bytes.
+const MAX_LOGGED_BODY_LEN: usize = 4096;
+/// Secrets shorter than this aren't redacted by value, they'd match too much.
+const MIN_REDACTED_SECRET_LEN: usize = 4;
+const REDACTED: &str = "[REDACTED]";
+
+/// Troubleshooting a single log source, e.g. when its records fail to parse: verbose logging of
+/// its requests and responses, with secrets redacted, and sampling its raw response bodies to
+/// the ingestion bucket under `__puller_debug__/{log_source}/{date}/`.
+///
+/// Verbose logging can also be enabled without changing the log source by listing it in the
+/// `PULLER_DEBUG_LOG_SOURCES` environment variable, comma separated or `*` for all.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     debug_logging: true
+///     # fraction of response bodies to sample, e.g. 1 in 100
+///     debug_payload_sample_rate: 0.01
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DebugOptions {
+    pub verbose: bool,
+    pub payload_sample_rate: f64,
+}
+
+impl DebugOptions {
+    pub fn from_config(
+        log_source_name: &str,
+        config: &HashMap<String, String>,
+    ) -> Result<DebugOptions> {
+        let verbose = match config.get("debug_logging") {
+            Some(v) => v
+                .trim()
+                .parse::<bool>()
+                .context("debug_logging must be true or false")?,
+            None => false,
+        } || enabled_by_env(log_source_name);
+        let payload_sample_rate = match config.get("debug_payload_sample_rate") {
+            Some(v) => v
+                .trim()
+                .parse::<f64>()
+                .context("debug_payload_sample_rate must be a number")?,
+            None => 0.0,
+        };
+        if !(0.0..=1.0).contains(&payload_sample_rate) {
+            return Err(anyhow!("debug_payload_sample_rate must be between 0 and 1"));
+        }
+        Ok(DebugOptions {
+            verbose,
+            payload_sample_rate,
+        })
+    }
+
+    /// Whether to sample the next payload.
+    pub fn sample_payload(&self) -> bool {
+        self.payload_sample_rate > 0.0 && rand::thread_rng().gen::<f64>() < self.payload_sample_rate
+    }
+}
+
+fn enabled_by_env(log_source_name: &str) -> bool {
+    std::env::var("PULLER_DEBUG_LOG_SOURCES").map_or(false, |sources| {
+        sources
+            .split(',')
+            .map(|s| s.trim())
+            .any(|s| s == "*" || s == log_source_name)
+    })
+}
+
+/// Writes a sampled response body to the ingestion bucket, with where it came from as object
+/// metadata.
+pub(crate) async fn write_payload_sample(
+    log_source: &str,
+    tenant_id: Option<&str>,
+    source: &str,
+    payload: &[u8],
+) -> Result<String> {
+    let bucket = std::env::var("INGESTION_BUCKET_NAME")?;
+    let key = format!(
+        "{}/{}/{}/{}",
+        DEBUG_PREFIX,
+        log_source,
+        chrono::Utc::now().format("%Y-%m-%d"),
+        uuid::Uuid::new_v4()
+    );
+    S3_CLIENT
+        .get()
+        .await
+        .put_object()
+        .bucket(bucket)
+        .key(&key)
+        .body(ByteStream::from(payload.to_vec()))
+        .metadata("matano-log-source", log_source)
+        .metadata("matano-tenant-id", tenant_id.unwrap_or("default"))
+        .metadata("matano-source", source)
+        .send()
+        .await
+        .with_context(|| format!("Failed to sample payload for {}", log_source))?;
+    info!(
+        "Sampled payload from {} for {} to {}",
+        source, log_source, key
+    );
+    Ok(key)
+}
+
+/// Headers as `name: value` pairs, with the values of credential headers redacted.
+pub(crate) fn redact_headers(headers: &HeaderMap, secrets: &[String]) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_sensitive_header(name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                redact_text(&String::from_utf8_lossy(value.as_bytes()), secrets)
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn is_sensitive_header(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name == "cookie"
+        || name == "set-cookie"
+        || ["auth", "token", "key", "secret", "signature", "password"]
+            .iter()
+            .any(|s| name.contains(s))
+}
+
+/// A (request or response) body to log, truncated and with credentials redacted.
+pub(crate) fn redact_body(body: &[u8], secrets: &[String]) -> String {
+    let truncated = &body[..body.len().min(MAX_LOGGED_BODY_LEN)];
+    let mut text = redact_text(&String::from_utf8_lossy(truncated), secrets);
+    if body.len() > MAX_LOGGED_BODY_LEN {
+        text.push_str(&format!("... ({} bytes)", body.len()));
+    }
+    text
+}
+
+/// Redacts the log source's secret values and credential fields, e.g. `"access_token": "..."`
+/// or `client_secret=...`.
+pub(crate) fn redact_text(text: &str, secrets: &[String]) -> String {
+    let mut text = SENSITIVE_FIELD_RE
+        .replace_all(text, |caps: &regex::Captures| {
+            let prefix = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+            match caps.get(1) {
+                Some(_) => format!("{}\"{}\"", prefix, REDACTED),
+                None => format!("{}{}", prefix, REDACTED),
+            }
+        })
+        .into_owned();
+    for secret in secrets
+        .iter()
+        .filter(|s| s.len() >= MIN_REDACTED_SECRET_LEN)
+    {
+        text = text.replace(secret.as_str(), REDACTED);
+    }
+    text
+}
@@ -0,0 +1,276 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::model::{AttributeValue, KeysAndAttributes, PutRequest, WriteRequest};
+use chrono::{Duration, Utc};
+use lazy_static::lazy_static;
+use serde_json::value::RawValue;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use super::payload::lookup_raw_json_path;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref DDB_CLIENT: AsyncOnce<aws_sdk_dynamodb::Client> =
+        AsyncOnce::new(async { aws_sdk_dynamodb::Client::new(AWS_CONFIG.get().await) });
+}
+
+const DEFAULT_TTL_HOURS: i64 = 48;
+/// IDs are also kept in memory for at least this long, for warm invocations.
+const MIN_RECENT_TTL_MINUTES: i64 = 10;
+/// DynamoDB limits for BatchGetItem and BatchWriteItem.
+const GET_BATCH_SIZE: usize = 100;
+const WRITE_BATCH_SIZE: usize = 25;
+/// Attempts for a batch with unprocessed items, e.g. when throttled.
+const MAX_BATCH_ATTEMPTS: u32 = 5;
+
+/// Drops events that were already pulled, by an ID field, so overlapping windows and SQS
+/// redrives don't produce duplicate rows. Seen IDs are kept in the `PULLER_DEDUP_TABLE_NAME`
+/// DynamoDB table for `dedup_ttl_hours`, if set up.
+///
+/// IDs are also kept in memory for twice `lookback_overlap_minutes`, which covers the events
+/// pulled again by the overlap of consecutive windows while the Lambda stays warm.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     dedup_id_field: uuid
+///     # optional, defaults to 48
+///     dedup_ttl_hours: 72
+///     # optional, pull each window starting this much earlier for late arriving events
+///     lookback_overlap_minutes: 5
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct Deduplicator {
+    table_name: Option<String>,
+    id_field: String,
+    ttl: Duration,
+    /// Recently seen IDs and when they expire, in epoch seconds.
+    recent: Arc<Mutex<HashMap<String, i64>>>,
+    recent_ttl: Duration,
+}
+
+impl Deduplicator {
+    /// Returns None if no ID field is configured.
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<Deduplicator>> {
+        let id_field = match config.get("dedup_id_field") {
+            Some(f) if !f.trim().is_empty() => f.trim().to_string(),
+            _ => return Ok(None),
+        };
+        let ttl_hours = config
+            .get("dedup_ttl_hours")
+            .map(|s| s.trim().parse::<i64>())
+            .transpose()
+            .context("dedup_ttl_hours must be an integer")?
+            .unwrap_or(DEFAULT_TTL_HOURS);
+        let overlap_minutes = config
+            .get("lookback_overlap_minutes")
+            .map(|s| s.trim().parse::<i64>())
+            .transpose()
+            .context("lookback_overlap_minutes must be an integer")?
+            .unwrap_or(0);
+        Ok(Some(Deduplicator {
+            table_name: std::env::var("PULLER_DEDUP_TABLE_NAME").ok(),
+            id_field,
+            ttl: Duration::hours(ttl_hours),
+            recent: Arc::new(Mutex::new(HashMap::new())),
+            recent_ttl: Duration::minutes((overlap_minutes * 2).max(MIN_RECENT_TTL_MINUTES)),
+        }))
+    }
+
+    /// Drops NDJSON lines whose ID was already seen under `key` or repeats within `data`.
+    /// Returns the remaining data and the new IDs, to pass to `record` once uploaded.
+    pub async fn filter(&self, key: &str, data: Vec<u8>) -> Result<(Vec<u8>, Vec<String>)> {
+        let lines = data
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| (line, self.event_id(line)))
+            .collect::<Vec<_>>();
+
+        let ids = lines
+            .iter()
+            .filter_map(|(_, id)| id.clone())
+            .collect::<HashSet<_>>();
+        if ids.is_empty() {
+            return Ok((data, vec![]));
+        }
+        let mut seen = match self.table_name.as_ref() {
+            Some(table_name) => self.seen_ids(table_name, key, &ids).await?,
+            None => HashSet::new(),
+        };
+        {
+            let now = Utc::now().timestamp();
+            let mut recent = self.recent.lock().await;
+            recent.retain(|_, expiry| *expiry > now);
+            seen.extend(ids.iter().filter(|id| recent.contains_key(*id)).cloned());
+        }
+
+        let mut ret = Vec::with_capacity(data.len());
+        let mut new_ids = vec![];
+        let mut dropped = 0;
+        for (line, id) in lines {
+            if let Some(id) = id {
+                // Also drops repeats within this pull.
+                if !seen.insert(id.clone()) {
+                    dropped += 1;
+                    continue;
+                }
+                new_ids.push(id);
+            }
+            if !ret.is_empty() {
+                ret.push(b'\n');
+            }
+            ret.extend_from_slice(line);
+        }
+        if dropped > 0 {
+            info!("Dropped {} duplicate events for {}", dropped, key);
+        }
+        Ok((ret, new_ids))
+    }
+
+    /// Records IDs as seen, after their events were uploaded.
+    pub async fn record(&self, key: &str, ids: &[String]) -> Result<()> {
+        let recent_expiry = (Utc::now() + self.recent_ttl).timestamp();
+        self.recent
+            .lock()
+            .await
+            .extend(ids.iter().map(|id| (id.clone(), recent_expiry)));
+
+        let table_name = match self.table_name.as_ref() {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        let ddb = DDB_CLIENT.get().await;
+        let ttl = (Utc::now() + self.ttl).timestamp().to_string();
+        for chunk in ids.chunks(WRITE_BATCH_SIZE) {
+            let mut requests = chunk
+                .iter()
+                .map(|id| {
+                    let put = PutRequest::builder()
+                        .item("pk", AttributeValue::S(dedup_key(key, id)))
+                        .item("ttl", AttributeValue::N(ttl.clone()))
+                        .build();
+                    WriteRequest::builder().put_request(put).build()
+                })
+                .collect::<Vec<_>>();
+
+            let mut attempt = 0;
+            while !requests.is_empty() {
+                if attempt >= MAX_BATCH_ATTEMPTS {
+                    return Err(anyhow!("Failed to record seen event IDs for {}", key));
+                }
+                backoff(attempt).await;
+                let res = ddb
+                    .batch_write_item()
+                    .request_items(table_name, requests)
+                    .send()
+                    .await
+                    .context("Failed to record seen event IDs")?;
+                requests = res
+                    .unprocessed_items()
+                    .and_then(|items| items.get(table_name))
+                    .cloned()
+                    .unwrap_or_default();
+                attempt += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn event_id(&self, line: &[u8]) -> Option<String> {
+        let value = serde_json::from_slice::<&RawValue>(line).ok()?;
+        let id = lookup_raw_json_path(value, &self.id_field)?.get();
+        match id.as_bytes().first()? {
+            b'"' => serde_json::from_str::<String>(id).ok(),
+            _ => serde_json::from_str::<serde_json::Number>(id)
+                .ok()
+                .map(|n| n.to_string()),
+        }
+    }
+
+    async fn seen_ids(
+        &self,
+        table_name: &str,
+        key: &str,
+        ids: &HashSet<String>,
+    ) -> Result<HashSet<String>> {
+        let ddb = DDB_CLIENT.get().await;
+        let ids = ids.iter().collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+        for chunk in ids.chunks(GET_BATCH_SIZE) {
+            let mut keys = chunk
+                .iter()
+                .map(|id| {
+                    HashMap::from([("pk".to_string(), AttributeValue::S(dedup_key(key, id)))])
+                })
+                .collect::<Vec<_>>();
+
+            let mut attempt = 0;
+            while !keys.is_empty() {
+                if attempt >= MAX_BATCH_ATTEMPTS {
+                    return Err(anyhow!("Failed to load seen event IDs for {}", key));
+                }
+                backoff(attempt).await;
+                let request = KeysAndAttributes::builder()
+                    .set_keys(Some(keys))
+                    .consistent_read(true)
+                    .build();
+                let res = ddb
+                    .batch_get_item()
+                    .request_items(table_name, request)
+                    .send()
+                    .await
+                    .context("Failed to load seen event IDs")?;
+
+                let prefix = format!("{}#", key);
+                let now = Utc::now().timestamp();
+                let items = res
+                    .responses()
+                    .and_then(|r| r.get(table_name))
+                    .map(|items| items.as_slice())
+                    .unwrap_or_default();
+                for item in items {
+                    // Expired items may not have been deleted yet.
+                    let expired = item
+                        .get("ttl")
+                        .and_then(|v| v.as_n().ok())
+                        .and_then(|n| n.parse::<i64>().ok())
+                        .map_or(false, |ttl| ttl < now);
+                    let id = item
+                        .get("pk")
+                        .and_then(|v| v.as_s().ok())
+                        .and_then(|pk| pk.strip_prefix(&prefix));
+                    if let (Some(id), false) = (id, expired) {
+                        seen.insert(id.to_string());
+                    }
+                }
+                keys = res
+                    .unprocessed_keys()
+                    .and_then(|k| k.get(table_name))
+                    .and_then(|k| k.keys())
+                    .map(|k| k.to_vec())
+                    .unwrap_or_default();
+                attempt += 1;
+            }
+        }
+        Ok(seen)
+    }
+}
+
+fn dedup_key(key: &str, id: &str) -> String {
+    format!("{}#{}", key, id)
+}
+
+/// Waits before retrying unprocessed batch items, no wait on the first attempt.
+async fn backoff(attempt: u32) {
+    if attempt > 0 {
+        let wait = std::time::Duration::from_millis(100 * 2u64.pow(attempt));
+        tokio::time::sleep(wait).await;
+    }
+}
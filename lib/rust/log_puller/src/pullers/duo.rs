@@ -9,23 +9,40 @@ use futures::{future::join_all, FutureExt, Stream};
 use futures_util::pin_mut;
 use futures_util::stream::StreamExt;
 use lazy_static::lazy_static;
-use log::{debug, error, info};
 use regex::Regex;
 use std::collections::HashMap;
 use std::time::Duration;
+use tracing::{debug, error, info};
 
-use reqwest::{Method, RequestBuilder, StatusCode};
-use ring::hmac;
+use reqwest::StatusCode;
 use serde::{de::DeserializeOwned, Deserialize, Deserializer};
 
-use super::{PullLogs, PullLogsContext};
+use super::signing::DuoSigner;
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
 use shared::JsonValueExt;
 
 #[derive(Clone)]
 pub struct DuoPuller;
 
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required(
+        "api_hostname",
+        PropertyType::String,
+        "api-xxxxxxxx.duosecurity.com",
+    ),
+    PropertySpec::required(
+        "integration_key",
+        PropertyType::String,
+        "DIXXXXXXXXXXXXXXXXXX",
+    ),
+];
+
 #[async_trait]
 impl PullLogs for DuoPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
     async fn pull_logs(
         self,
         client: reqwest::Client,
@@ -77,7 +94,9 @@ impl PullLogs for DuoPuller {
         let mintime = start_dt.timestamp_millis();
         let maxtime = end_dt.timestamp_millis();
 
-        let duo = DuoClient::new(api_hostname, integration_key, secret_key, client.clone())?;
+        ctx.set_signer(Some(Arc::new(DuoSigner::new(integration_key, secret_key))))
+            .await;
+        let duo = DuoClient::new(api_hostname, client.clone(), ctx)?;
         let newline_u8 = "\n".to_string().into_bytes();
 
         // Authentication logs (v2)
@@ -246,28 +265,24 @@ impl PullLogs for DuoPuller {
 
 // DuoClient implementation
 
-/// Encapsulates Duo server connection.
-#[derive(Debug)]
-pub struct DuoClient {
+/// Encapsulates Duo server connection. Requests are signed by the context's `DuoSigner`.
+pub struct DuoClient<'a> {
     api_hostname: String,
-    integration_key: String,
-    secret_key: String,
     client: reqwest::Client,
+    ctx: &'a PullLogsContext,
 }
 
-impl DuoClient {
+impl<'a> DuoClient<'a> {
     /// Creates a new DuoClient.
-    pub fn new<S: Into<String>, T: Into<String>, U: Into<String>>(
+    pub fn new<S: Into<String>>(
         api_hostname: S,
-        integration_key: T,
-        secret_key: U,
         client: reqwest::Client,
-    ) -> Result<DuoClient> {
+        ctx: &'a PullLogsContext,
+    ) -> Result<DuoClient<'a>> {
         Ok(DuoClient {
             api_hostname: api_hostname.into(),
-            integration_key: integration_key.into(),
-            secret_key: secret_key.into(),
-            client: client,
+            client,
+            ctx,
         })
     }
 
@@ -281,9 +296,8 @@ impl DuoClient {
         let uri = format!("https://{}{}?{}", self.api_hostname, path, params_str);
 
         let req = self.client.get(&uri);
-        let req = self.sign_req(req, Method::GET, path, &params);
 
-        let res = req.send().await;
+        let res = self.ctx.send(&self.client, req).await;
         match res {
             Ok(response) if response.status() == expected => {
                 let text = response.text().await?;
@@ -294,31 +308,6 @@ impl DuoClient {
             Err(err) => Err(anyhow!("failed send req: {}", err)),
         }
     }
-
-    fn sign_req(
-        &self,
-        req: RequestBuilder,
-        method: Method,
-        path: &str,
-        params: &HashMap<String, String>,
-    ) -> RequestBuilder {
-        let now = Local::now().to_rfc2822();
-        let method = method.as_str();
-        let api_hostname = self.api_hostname.to_lowercase();
-        let params = encode_params(params);
-        let canon = [
-            now.as_str(),
-            method,
-            api_hostname.as_str(),
-            path,
-            params.as_str(),
-        ];
-
-        let basic_auth = basic_auth_for_canon(&self.integration_key, &self.secret_key, &canon);
-
-        req.header("Date", &now)
-            .header("Authorization", &basic_auth)
-    }
 }
 
 fn encode_params(params: &HashMap<String, String>) -> String {
@@ -332,22 +321,8 @@ fn encode_params(params: &HashMap<String, String>) -> String {
     encoder.finish()
 }
 
-fn basic_auth_for_canon(integration_key: &str, secret_key: &str, canon: &[&str]) -> String {
-    let canon = canon.join("\n");
-
-    let s_key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret_key.as_bytes());
-    let mut s_ctx = hmac::Context::with_key(&s_key);
-    s_ctx.update(canon.as_bytes());
-    let sig = s_ctx.sign();
-    let auth = format!("{}:{}", integration_key, hex::encode(sig.as_ref()));
-
-    let basic_auth = format!("Basic {}", base64::encode(&auth));
-
-    basic_auth
-}
-
 async fn get_duo_logs_stream<'a>(
-    duo: &'a DuoClient,
+    duo: &'a DuoClient<'a>,
     integration_name: &'a str,
     integration_version: usize,
     integration_path_version: usize,
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use reqwest::header;
+use serde_json::{json, Value};
+use tracing::{debug, error, info};
+
+use super::custom_api::ApiAuth;
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
+use shared::JsonValueExt;
+
+/// Runs a time bounded search against an Elasticsearch or OpenSearch index, paging through hits with a
+/// point in time (PIT) and `search_after`. Useful for migrating data out of existing SIEM indices.
+#[derive(Clone)]
+pub struct ElasticsearchPuller;
+
+const DEFAULT_PAGE_SIZE: usize = 5000;
+const PIT_KEEP_ALIVE: &str = "2m";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Engine {
+    Elasticsearch,
+    OpenSearch,
+}
+
+struct SearchConfig {
+    base_url: String,
+    index: String,
+    engine: Engine,
+    time_field: String,
+    tiebreaker_field: String,
+    query: Option<Value>,
+    page_size: usize,
+    auth: ApiAuth,
+}
+
+impl SearchConfig {
+    fn from_config(config: &HashMap<String, String>) -> Result<SearchConfig> {
+        let engine = match config.get("engine").map(|s| s.to_lowercase()).as_deref() {
+            None | Some("elasticsearch") => Engine::Elasticsearch,
+            Some("opensearch") => Engine::OpenSearch,
+            Some(e) => return Err(anyhow!("Unsupported engine: {}", e)),
+        };
+        // OpenSearch doesn't support the `_shard_doc` tiebreaker.
+        let default_tiebreaker = match engine {
+            Engine::Elasticsearch => "_shard_doc",
+            Engine::OpenSearch => "_id",
+        };
+        Ok(SearchConfig {
+            base_url: config
+                .get("base_url")
+                .context("Missing base_url")?
+                .trim_end_matches('/')
+                .to_string(),
+            index: config.get("index").context("Missing index")?.to_string(),
+            engine,
+            time_field: config
+                .get("time_field")
+                .cloned()
+                .unwrap_or_else(|| "@timestamp".to_string()),
+            tiebreaker_field: config
+                .get("tiebreaker_field")
+                .cloned()
+                .unwrap_or_else(|| default_tiebreaker.to_string()),
+            // Additional filter as a JSON query DSL, ANDed with the time range.
+            query: config
+                .get("query")
+                .map(|q| serde_json::from_str(q))
+                .transpose()
+                .context("query must be a JSON query DSL object")?,
+            page_size: config
+                .get("page_size")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(DEFAULT_PAGE_SIZE),
+            auth: ApiAuth::from_config(config)?,
+        })
+    }
+}
+
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required("base_url", PropertyType::Url, "https://es.example.com:9200"),
+    PropertySpec::required("index", PropertyType::String, "logs-*"),
+    PropertySpec::optional("page_size", PropertyType::Integer, "1000"),
+    PropertySpec::optional(
+        "query",
+        PropertyType::Json,
+        "{\"term\": {\"event.module\": \"auth\"}}",
+    ),
+];
+
+#[async_trait]
+impl PullLogs for ElasticsearchPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    async fn pull_logs(
+        self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!(
+            "Pulling Elasticsearch index for {}....",
+            ctx.log_source_name
+        );
+
+        let search_config = SearchConfig::from_config(ctx.config())?;
+        let (mut headers, auth_query) = match search_config.auth.resolve(&client, ctx).await? {
+            Some(auth) => auth,
+            None => return Ok(vec![]),
+        };
+        headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json"),
+        );
+
+        let mut pit_id = open_pit(&client, ctx, &search_config, &headers, &auth_query).await?;
+
+        let mut filters = vec![json!({
+            "range": {
+                &search_config.time_field: {
+                    "gte": start_dt.to_rfc3339(),
+                    "lt": end_dt.to_rfc3339(),
+                    "format": "strict_date_optional_time",
+                }
+            }
+        })];
+        if let Some(q) = search_config.query.as_ref() {
+            filters.push(q.clone());
+        }
+
+        let search_url = format!("{}/_search", search_config.base_url);
+        let mut ret: Vec<u8> = vec![];
+        let mut search_after: Option<Value> = None;
+
+        let result: Result<()> = async {
+            loop {
+                let mut body = json!({
+                    "size": search_config.page_size,
+                    "query": { "bool": { "filter": filters } },
+                    "pit": { "id": pit_id, "keep_alive": PIT_KEEP_ALIVE },
+                    "sort": [
+                        { &search_config.time_field: "asc" },
+                        { &search_config.tiebreaker_field: "asc" },
+                    ],
+                    "track_total_hits": false,
+                });
+                if let Some(sa) = search_after.as_ref() {
+                    body["search_after"] = sa.clone();
+                }
+
+                let req = client
+                    .post(&search_url)
+                    .headers(headers.clone())
+                    .query(&auth_query)
+                    .json(&body);
+                let res = ctx.send(&client, req).await?;
+                if !res.status().is_success() {
+                    let status = res.status();
+                    let msg = res.text().await.unwrap_or_default();
+                    return Err(anyhow!(
+                        "Search failed, status: {}, response: {}",
+                        status,
+                        msg
+                    ));
+                }
+                let mut res_body: Value = ctx.response_json(res).await?;
+
+                // The PIT id can change between requests, always use the latest.
+                if let Some(id) = res_body.get_mut("pit_id").and_then(|v| v.take().into_str()) {
+                    pit_id = id;
+                }
+
+                let hits = res_body["hits"]["hits"]
+                    .take()
+                    .into_array()
+                    .unwrap_or_default();
+                let num_hits = hits.len();
+
+                for mut hit in hits {
+                    search_after = Some(hit["sort"].take());
+                    let mut source = hit["_source"].take();
+                    if let Some(obj) = source.as_object_mut() {
+                        obj.insert("_index".to_string(), hit["_index"].take());
+                        obj.insert("_id".to_string(), hit["_id"].take());
+                    }
+                    ret.extend(serde_json::to_vec(&source)?);
+                    ret.push(b'\n');
+                }
+                debug!("Loaded {} hits for {}", num_hits, ctx.log_source_name);
+
+                if num_hits < search_config.page_size {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        // Always release the PIT, even on failure.
+        if let Err(e) =
+            close_pit(&client, ctx, &search_config, &headers, &auth_query, &pit_id).await
+        {
+            error!("Failed to close PIT for {}: {:#}", ctx.log_source_name, e);
+        }
+        result?;
+
+        // Remove last newline
+        if ret.last() == Some(&b'\n') {
+            ret.pop();
+        }
+
+        Ok(ret)
+    }
+}
+
+async fn open_pit(
+    client: &reqwest::Client,
+    ctx: &PullLogsContext,
+    search_config: &SearchConfig,
+    headers: &header::HeaderMap,
+    auth_query: &[(String, String)],
+) -> Result<String> {
+    let (url, id_key) = match search_config.engine {
+        Engine::Elasticsearch => (
+            format!("{}/{}/_pit", search_config.base_url, search_config.index),
+            "id",
+        ),
+        Engine::OpenSearch => (
+            format!(
+                "{}/{}/_search/point_in_time",
+                search_config.base_url, search_config.index
+            ),
+            "pit_id",
+        ),
+    };
+    let req = client
+        .post(&url)
+        .headers(headers.clone())
+        .query(auth_query)
+        .query(&[("keep_alive", PIT_KEEP_ALIVE)]);
+    let res = ctx.send(client, req).await?;
+    if !res.status().is_success() {
+        let status = res.status();
+        let msg = res.text().await.unwrap_or_default();
+        return Err(anyhow!(
+            "Failed to open PIT on {}, status: {}, response: {}",
+            &search_config.index,
+            status,
+            msg
+        ));
+    }
+    let mut body: Value = res.json().await?;
+    body.get_mut(id_key)
+        .and_then(|v| v.take().into_str())
+        .context("Missing PIT id")
+}
+
+async fn close_pit(
+    client: &reqwest::Client,
+    ctx: &PullLogsContext,
+    search_config: &SearchConfig,
+    headers: &header::HeaderMap,
+    auth_query: &[(String, String)],
+    pit_id: &str,
+) -> Result<()> {
+    let (url, body) = match search_config.engine {
+        Engine::Elasticsearch => (
+            format!("{}/_pit", search_config.base_url),
+            json!({ "id": pit_id }),
+        ),
+        Engine::OpenSearch => (
+            format!("{}/_search/point_in_time", search_config.base_url),
+            json!({ "pit_id": [pit_id] }),
+        ),
+    };
+    let req = client
+        .delete(&url)
+        .headers(headers.clone())
+        .query(auth_query)
+        .json(&body);
+    ctx.send(client, req).await?.error_for_status()?;
+    Ok(())
+}
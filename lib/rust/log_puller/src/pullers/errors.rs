@@ -0,0 +1,128 @@
+use reqwest::StatusCode;
+use serde_json::json;
+
+/// Retries of rate limited or failing vendors wait this long, instead of the queue's
+/// visibility timeout.
+pub const DELAYED_RETRY_SECONDS: i32 = 300;
+/// CloudWatch namespace of the puller's metrics.
+pub(crate) const METRICS_NAMESPACE: &str = "Matano/LogPuller";
+
+/// Why a pull failed, which decides how it's retried (see `RetryAction`) and is reported in
+/// metrics and the audit log.
+///
+/// Attached to errors as context, e.g. `.context(PullerError::S3)`, where the cause is known.
+/// Otherwise it's worked out from the error and the last response, see
+/// `PullLogsContext::classify_error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PullerError {
+    /// The vendor rejected the credentials (401/403).
+    Auth,
+    /// The vendor kept rate limiting requests (429).
+    RateLimited,
+    /// The vendor failed (5xx) or didn't respond in time.
+    VendorServer,
+    /// A vendor response couldn't be parsed, it was quarantined (see `quarantine_payload`).
+    Parse,
+    /// Writing pulled data to S3 failed.
+    S3,
+    Other,
+}
+
+/// How the SQS message of a failed pull is handled, from least to most conservative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RetryAction {
+    /// Deleted, retrying won't parse the response and the next pull picks up the window.
+    Quarantine,
+    /// Returned to the queue after `DELAYED_RETRY_SECONDS`, giving the vendor time to recover.
+    Delay,
+    /// Returned to the queue after its visibility timeout, dead lettered after max receives.
+    Fail,
+}
+
+impl PullerError {
+    /// The class attached to an error with `.context(..)`, if any.
+    pub fn of(e: &anyhow::Error) -> Option<PullerError> {
+        e.downcast_ref::<PullerError>().copied()
+    }
+
+    /// Classifies a vendor response status, if it's an error.
+    pub fn from_status(status: StatusCode) -> Option<PullerError> {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Some(PullerError::Auth),
+            StatusCode::TOO_MANY_REQUESTS => Some(PullerError::RateLimited),
+            s if s.is_server_error() => Some(PullerError::VendorServer),
+            _ => None,
+        }
+    }
+
+    /// Classifies an error without an attached class from its causes.
+    pub(crate) fn infer(e: &anyhow::Error) -> Option<PullerError> {
+        for cause in e.chain() {
+            if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+                if err.is_timeout() {
+                    return Some(PullerError::VendorServer);
+                }
+                if let Some(class) = err.status().and_then(PullerError::from_status) {
+                    return Some(class);
+                }
+            }
+            if cause.is::<tokio::time::error::Elapsed>() {
+                return Some(PullerError::VendorServer);
+            }
+        }
+        None
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PullerError::Auth => "auth",
+            PullerError::RateLimited => "rate_limited",
+            PullerError::VendorServer => "vendor_server",
+            PullerError::Parse => "parse",
+            PullerError::S3 => "s3",
+            PullerError::Other => "other",
+        }
+    }
+
+    pub fn retry_action(&self) -> RetryAction {
+        match self {
+            PullerError::Parse => RetryAction::Quarantine,
+            PullerError::RateLimited | PullerError::VendorServer => RetryAction::Delay,
+            PullerError::Auth | PullerError::S3 | PullerError::Other => RetryAction::Fail,
+        }
+    }
+
+    /// Emits a `PullErrors` metric for the log source and class, in CloudWatch embedded
+    /// metric format, so alarms can be set on e.g. auth failures of a single source.
+    pub fn emit_metric(&self, log_source: &str) {
+        let metric = json!({
+            "_aws": {
+                "Timestamp": chrono::Utc::now().timestamp_millis(),
+                "CloudWatchMetrics": [{
+                    "Namespace": METRICS_NAMESPACE,
+                    "Dimensions": [["log_source", "error_class"], ["error_class"]],
+                    "Metrics": [{"Name": "PullErrors", "Unit": "Count"}],
+                }],
+            },
+            "log_source": log_source,
+            "error_class": self.as_str(),
+            "PullErrors": 1,
+        });
+        // Embedded metrics must be their own log line, not wrapped by the log formatter.
+        println!("{}", metric);
+    }
+}
+
+impl std::fmt::Display for PullerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            PullerError::Auth => "Authentication failed",
+            PullerError::RateLimited => "Rate limited",
+            PullerError::VendorServer => "Vendor server error",
+            PullerError::Parse => "Unparseable response (quarantined)",
+            PullerError::S3 => "Failed writing to S3",
+            PullerError::Other => "Pull failed",
+        };
+        write!(f, "{}", msg)
+    }
+}
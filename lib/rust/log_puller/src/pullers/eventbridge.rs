@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_eventbridge::model::PutEventsRequestEntry;
+use lazy_static::lazy_static;
+use tracing::{error, info, warn};
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref EVENTBRIDGE_CLIENT: AsyncOnce<aws_sdk_eventbridge::Client> =
+        AsyncOnce::new(async { aws_sdk_eventbridge::Client::new(AWS_CONFIG.get().await) });
+}
+
+const DEFAULT_DETAIL_TYPE: &str = "Matano Pulled Event";
+/// PutEvents limits.
+const MAX_BATCH_ENTRIES: usize = 10;
+const MAX_BATCH_SIZE: usize = 256 * 1024;
+/// Attempts for a batch with failed entries, e.g. when throttled.
+const MAX_BATCH_ATTEMPTS: u32 = 5;
+
+/// Emits each pulled event to an EventBridge bus, in addition to the S3 upload, so response
+/// automation can react to alert feeds immediately. Events have the source
+/// `matano.{log_source}` and the record as their detail. Meant for low volume sources,
+/// PutEvents is billed per event.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     eventbridge_bus_name: security-alerts
+///     # optional, defaults to "Matano Pulled Event"
+///     eventbridge_detail_type: Recorded Future Alert
+/// ```
+#[derive(Debug, Clone)]
+pub struct EventBridgeOutput {
+    bus_name: String,
+    detail_type: String,
+}
+
+impl EventBridgeOutput {
+    /// Returns None if no bus is configured.
+    pub fn from_config(config: &HashMap<String, String>) -> Option<EventBridgeOutput> {
+        let get = |prop: &str| {
+            config
+                .get(prop)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        Some(EventBridgeOutput {
+            bus_name: get("eventbridge_bus_name")?,
+            detail_type: get("eventbridge_detail_type")
+                .unwrap_or_else(|| DEFAULT_DETAIL_TYPE.to_string()),
+        })
+    }
+
+    /// Emits each NDJSON line as an event. Lines that aren't JSON objects are skipped, as
+    /// EventBridge requires the detail to be one.
+    pub async fn send(&self, log_source: &str, data: &[u8]) -> Result<()> {
+        let source = format!("matano.{}", log_source);
+        let mut batch = vec![];
+        let mut batch_size = 0;
+        let (mut sent, mut skipped) = (0, 0);
+        for line in data.split(|b| *b == b'\n') {
+            let detail = match serde_json::from_slice::<serde_json::Value>(line) {
+                Ok(v @ serde_json::Value::Object(_)) => v.to_string(),
+                _ if line.is_empty() => continue,
+                _ => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let size = source.len() + self.detail_type.len() + detail.len();
+            if size > MAX_BATCH_SIZE {
+                skipped += 1;
+                continue;
+            }
+            if batch.len() >= MAX_BATCH_ENTRIES || batch_size + size > MAX_BATCH_SIZE {
+                sent += batch.len();
+                self.send_batch(std::mem::take(&mut batch)).await?;
+                batch_size = 0;
+            }
+            batch.push(
+                PutEventsRequestEntry::builder()
+                    .event_bus_name(&self.bus_name)
+                    .source(&source)
+                    .detail_type(&self.detail_type)
+                    .detail(detail)
+                    .build(),
+            );
+            batch_size += size;
+        }
+        if !batch.is_empty() {
+            sent += batch.len();
+            self.send_batch(batch).await?;
+        }
+        if skipped > 0 {
+            error!(
+                "Skipped {} events for {} that aren't JSON objects or are too large for EventBridge",
+                skipped, log_source
+            );
+        }
+        info!(
+            "Sent {} events for {} to EventBridge bus {}",
+            sent, log_source, self.bus_name
+        );
+        Ok(())
+    }
+
+    /// Sends a batch, retrying entries that failed until all succeed.
+    async fn send_batch(&self, mut entries: Vec<PutEventsRequestEntry>) -> Result<()> {
+        let client = EVENTBRIDGE_CLIENT.get().await;
+        let mut attempt = 0;
+        while !entries.is_empty() {
+            if attempt >= MAX_BATCH_ATTEMPTS {
+                return Err(anyhow!(
+                    "Failed to send {} events to EventBridge bus {}",
+                    entries.len(),
+                    self.bus_name
+                ));
+            }
+            if attempt > 0 {
+                let wait = std::time::Duration::from_millis(100 * 2u64.pow(attempt));
+                tokio::time::sleep(wait).await;
+            }
+            let res = client
+                .put_events()
+                .set_entries(Some(entries.clone()))
+                .send()
+                .await
+                .with_context(|| format!("Error sending events to {}", self.bus_name))?;
+            // Results are in the same order as the entries.
+            let failed = res
+                .entries()
+                .unwrap_or_default()
+                .iter()
+                .zip(entries)
+                .filter(|(r, _)| r.error_code().is_some())
+                .map(|(_, e)| e)
+                .collect::<Vec<_>>();
+            if !failed.is_empty() {
+                warn!(
+                    "{} events to {} failed, retrying",
+                    failed.len(),
+                    self.bus_name
+                );
+            }
+            entries = failed;
+            attempt += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+//! In-house pullers, registered at startup when built with the `extensions` feature. Add a
+//! puller's module here and register it by its managed type, see `registry`.
+//!
+//! ex:
+//! ```ignore
+//! mod acme;
+//!
+//! pub fn register() {
+//!     crate::register_pullers! {
+//!         "acme" => acme::AcmePuller,
+//!     }
+//! }
+//! ```
+
+/// Registers the in-house pullers, called at startup.
+pub fn register() {}
@@ -0,0 +1,412 @@
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_stream::try_stream;
+use async_trait::async_trait;
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Credentials, Region};
+use chrono::{DateTime, FixedOffset};
+use futures::future::join_all;
+use futures::StreamExt;
+use serde_json::json;
+use tracing::{debug, info};
+
+use super::aws_role::AwsRole;
+use super::azure_blob::decode_object_payload;
+use super::{collect_chunks, PropertySpec, PropertyType, PullLogs, PullLogsContext, RecordChunks};
+
+/// Copies new objects under a prefix of an S3 bucket owned by a third party, for vendors
+/// (e.g. Cisco Umbrella, CrowdStrike FDR) that deliver logs to their own bucket.
+///
+/// Access is either through static keys (`access_key_id` + secret `secret_access_key`), an assumed
+/// `role_arn` (or `aws_role_arn`, see `AwsRole`), or the puller's own role if the bucket policy grants it access. In the last case,
+/// `server_side_copy: "true"` copies objects straight into the ingestion bucket without downloading them.
+///
+/// With `compressed_passthrough: "true"`, gzip and zstd objects are uploaded to the ingestion
+/// bucket as downloaded, instead of being decompressed and compressed again, for objects that
+/// already hold NDJSON records. Objects are still transcoded when the log source needs their
+/// records, e.g. for deduplication or Parquet output, see `transcode_reason`.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: external_s3
+///   properties:
+///     bucket: vendor-fdr-bucket
+///     role_arn: arn:aws:iam::123456789012:role/fdr-access
+///     compressed_passthrough: true
+/// ```
+#[derive(Clone)]
+pub struct ExternalS3Puller;
+
+/// Limit the number of objects pulled per invocation, the rest are picked up on the next run.
+const MAX_OBJECTS_PER_RUN: usize = 500;
+/// Objects downloaded at once, ahead of the one being uploaded.
+const MAX_CONCURRENT_DOWNLOADS: usize = 8;
+/// Records are sent to these outputs as well, so objects can't be passed through.
+const STREAMING_OUTPUT_PROPERTIES: &[&str] = &[
+    "kinesis_stream_name",
+    "firehose_delivery_stream_name",
+    "eventbridge_bus_name",
+];
+/// Passed through objects are only uploaded where `copy_object` copies them.
+const UPLOAD_DESTINATION_PROPERTIES: &[&str] = &[
+    "s3_bucket",
+    "s3_bucket_region",
+    "s3_key_prefix",
+    "s3_key_template",
+];
+
+const PROPERTIES: &[PropertySpec] = &[PropertySpec::required(
+    "bucket",
+    PropertyType::String,
+    "vendor-logs",
+)];
+
+#[async_trait]
+impl PullLogs for ExternalS3Puller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    fn counts_requests(&self) -> bool {
+        false
+    }
+
+    async fn pull_logs(
+        self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        collect_chunks(self.pull_log_chunks(client, ctx, start_dt, end_dt)).await
+    }
+
+    /// Each downloaded object is a chunk, so only a few objects are held in memory at once.
+    fn pull_log_chunks<'a>(
+        self,
+        _client: reqwest::Client,
+        ctx: &'a PullLogsContext,
+        _start_dt: DateTime<FixedOffset>,
+        _end_dt: DateTime<FixedOffset>,
+    ) -> RecordChunks<'a> {
+        Box::pin(try_stream! {
+            info!("Pulling external S3 bucket for {}....", ctx.log_source_name);
+
+            let config = ctx.config();
+            let bucket = config.get("bucket").context("Missing bucket")?;
+            let prefix = config.get("prefix").cloned().unwrap_or_default();
+            let server_side_copy = config
+                .get("server_side_copy")
+                .map_or(false, |v| v.trim() == "true");
+            let passthrough = config
+                .get("compressed_passthrough")
+                .map_or(false, |v| v.trim() == "true");
+            let passthrough = match transcode_reason(ctx) {
+                Some(reason) if passthrough => {
+                    info!(
+                        "Transcoding objects for {}, {} needs their records",
+                        ctx.log_source_name, reason
+                    );
+                    false
+                }
+                _ => passthrough,
+            };
+
+            let s3 = match build_s3_client(ctx).await? {
+                Some(s3) => s3,
+                None => return,
+            };
+            if server_side_copy
+                && (config.contains_key("access_key_id") || config.contains_key("role_arn"))
+            {
+                Err::<(), _>(anyhow!(
+                    "server_side_copy can't be used with access_key_id or role_arn, the puller's own role must have access to the bucket"
+                ))?;
+            }
+
+            let checkpoint_key = ctx
+                .checkpoint_json
+                .lock()
+                .await
+                .as_ref()
+                .and_then(|v| v["last_key"].as_str())
+                .map(|s| s.to_string());
+            let new_objects = list_new_objects(&s3, bucket, &prefix, checkpoint_key).await?;
+            info!(
+                "Found {} new objects for {}",
+                new_objects.len(),
+                ctx.log_source_name
+            );
+            let last_key = match new_objects.last() {
+                Some(k) => k.clone(),
+                None => return,
+            };
+            let new_checkpoint = json!({ "last_key": last_key });
+
+            if server_side_copy {
+                let futs = new_objects
+                    .iter()
+                    .map(|key| copy_object(&ctx.s3, bucket, key, &ctx.log_source_name))
+                    .collect::<Vec<_>>();
+                join_all(futs)
+                    .await
+                    .into_iter()
+                    .collect::<Result<Vec<_>>>()?;
+                // Nothing is uploaded by the caller for copies, so persist the checkpoint here.
+                ctx.upload_checkpoint(&new_checkpoint).await?;
+                return;
+            }
+
+            let mut downloads = futures::stream::iter(new_objects.iter())
+                .map(|key| pull_object(&s3, bucket, key, ctx, passthrough))
+                .buffered(MAX_CONCURRENT_DOWNLOADS);
+            let mut yielded = false;
+            while let Some(chunk) = downloads.next().await {
+                match chunk? {
+                    Some(chunk) if !chunk.is_empty() => {
+                        yielded = true;
+                        yield chunk;
+                    }
+                    _ => (),
+                }
+            }
+
+            // The caller only saves the checkpoint if it uploaded something.
+            match yielded {
+                true => *ctx.checkpoint_json.lock().await = Some(new_checkpoint),
+                false => ctx.upload_checkpoint(&new_checkpoint).await?,
+            }
+        })
+    }
+}
+
+/// Lists up to `MAX_OBJECTS_PER_RUN` keys after the checkpointed key. Keys are listed in
+/// lexicographic order, which matches delivery order for the usual date partitioned layouts.
+async fn list_new_objects(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: &str,
+    checkpoint_key: Option<String>,
+) -> Result<Vec<String>> {
+    let mut new_objects = vec![];
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let mut req = s3
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation_token.clone());
+        if continuation_token.is_none() {
+            req = req.set_start_after(checkpoint_key.clone());
+        }
+        let res = req
+            .send()
+            .await
+            .with_context(|| format!("Error listing objects in s3://{}", bucket))?;
+
+        new_objects.extend(
+            res.contents()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|o| o.key())
+                // Skip folder markers.
+                .filter(|k| !k.ends_with('/'))
+                .map(|k| k.to_string()),
+        );
+
+        continuation_token = res.next_continuation_token().map(|s| s.to_string());
+        if continuation_token.is_none() || new_objects.len() >= MAX_OBJECTS_PER_RUN {
+            break;
+        }
+    }
+    new_objects.truncate(MAX_OBJECTS_PER_RUN);
+    Ok(new_objects)
+}
+
+/// Builds an S3 client for the external bucket. Returns None if the secret is still a placeholder.
+async fn build_s3_client(ctx: &PullLogsContext) -> Result<Option<aws_sdk_s3::Client>> {
+    let config = ctx.config();
+    let region = Region::new(
+        config
+            .get("region")
+            .cloned()
+            .or_else(|| std::env::var("AWS_REGION").ok())
+            .unwrap_or_else(|| "us-east-1".to_string()),
+    );
+
+    let role = match config.get("role_arn") {
+        Some(role_arn) => Some(AwsRole::new(role_arn, config.get("external_id").cloned())),
+        None => AwsRole::from_config(config),
+    };
+    let s3_config = match (config.get("access_key_id"), role) {
+        (Some(access_key_id), _) => {
+            let secret_access_key = ctx
+                .get_secret_field("secret_access_key")
+                .await?
+                .context("Missing secret_access_key")?;
+            // skip early if secret_access_key is equal <placeholder>
+            if secret_access_key == "<placeholder>" {
+                info!("Skipping external_s3 because secret is still <placeholder>");
+                return Ok(None);
+            }
+            let session_token = ctx.get_secret_field("session_token").await?;
+            let creds = Credentials::new(
+                access_key_id,
+                secret_access_key,
+                session_token,
+                None,
+                "matano-external-s3",
+            );
+            aws_sdk_s3::Config::builder()
+                .credentials_provider(creds)
+                .region(region)
+                .build()
+        }
+        (None, Some(role)) => aws_sdk_s3::Config::builder()
+            .credentials_provider(role.credentials_provider(region.clone()))
+            .region(region)
+            .build(),
+        (None, None) => {
+            let sdk_config = aws_config::from_env().region(region).load().await;
+            aws_sdk_s3::Config::new(&sdk_config)
+        }
+    };
+
+    Ok(Some(aws_sdk_s3::Client::from_conf(s3_config)))
+}
+
+/// Why the log source needs the records of downloaded objects, which rules out uploading
+/// them as is. None if it doesn't.
+fn transcode_reason(ctx: &PullLogsContext) -> Option<&'static str> {
+    let config = ctx.config();
+    let is_set = |prop: &str| config.get(prop).map_or(false, |v| !v.trim().is_empty());
+    let is_true = |prop: &str| config.get(prop).map_or(false, |v| v.trim() == "true");
+    if ctx.tenant_id.is_some() {
+        Some("tenant tagging")
+    } else if is_set("records_envelope_path") {
+        Some("records_envelope_path")
+    } else if is_set("dedup_id_field") {
+        Some("dedup_id_field")
+    } else if is_true("ingestion_metadata") {
+        Some("ingestion_metadata")
+    } else if is_true("skip_unchanged_snapshots") {
+        Some("skip_unchanged_snapshots")
+    } else if config
+        .get("output_format")
+        .map_or(false, |f| f.trim().eq_ignore_ascii_case("parquet"))
+    {
+        Some("Parquet output_format")
+    } else if is_set("client_side_encryption_kms_key_id") {
+        Some("client_side_encryption_kms_key_id")
+    } else if ctx.zstd_dictionary().is_some() {
+        Some("the zstd dictionary")
+    } else if STREAMING_OUTPUT_PROPERTIES.iter().any(|p| is_set(p)) {
+        Some("streaming output")
+    } else if UPLOAD_DESTINATION_PROPERTIES.iter().any(|p| is_set(p)) {
+        Some("the custom upload destination")
+    } else {
+        None
+    }
+}
+
+/// Downloads an object and returns its records, or uploads it as is and returns None if
+/// `passthrough` is set and it's compressed.
+async fn pull_object(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    ctx: &PullLogsContext,
+    passthrough: bool,
+) -> Result<Option<Vec<u8>>> {
+    debug!("Downloading object: s3://{}/{}", bucket, key);
+    let res = s3
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .with_context(|| format!("Error downloading s3://{}/{}", bucket, key))?;
+    let content_encoding = res.content_encoding().map(|s| s.to_string());
+    let bytes = res.body.collect().await?.into_bytes();
+
+    let compression = match passthrough {
+        true => compression_of(key, content_encoding.as_deref()),
+        false => None,
+    };
+    match compression {
+        Some(encoding) => {
+            put_object(&ctx.s3, key, &ctx.log_source_name, encoding, bytes).await?;
+            Ok(None)
+        }
+        None => Ok(Some(decode_object_payload(
+            key,
+            content_encoding.as_deref(),
+            &bytes,
+        )?)),
+    }
+}
+
+/// The content encoding of a gzip or zstd compressed object, from its key or metadata.
+fn compression_of(key: &str, content_encoding: Option<&str>) -> Option<&'static str> {
+    let extension = key.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+    match (extension.as_deref(), content_encoding) {
+        (Some("gz" | "gzip"), _) | (_, Some("gzip")) => Some("gzip"),
+        (Some("zst" | "zstd"), _) | (_, Some("zstd" | "application/zstd")) => Some("zstd"),
+        _ => None,
+    }
+}
+
+/// Uploads a compressed object as downloaded, where `copy_object` would have copied it.
+async fn put_object(
+    s3: &aws_sdk_s3::Client,
+    key: &str,
+    log_source_name: &str,
+    content_encoding: &str,
+    data: bytes::Bytes,
+) -> Result<()> {
+    let ingestion_bucket = std::env::var("INGESTION_BUCKET_NAME").context("need bucket!")?;
+    let dest_key = format!("{}/{}", log_source_name, key);
+    debug!(
+        "Uploading {} object as is: s3://{}/{}",
+        content_encoding, &ingestion_bucket, &dest_key
+    );
+    s3.put_object()
+        .bucket(&ingestion_bucket)
+        .key(&dest_key)
+        .content_encoding(content_encoding)
+        .body(ByteStream::from(data))
+        .send()
+        .await
+        .with_context(|| format!("Error uploading s3://{}/{}", &ingestion_bucket, &dest_key))?;
+    Ok(())
+}
+
+/// Copies with the puller's own client, which is in the ingestion bucket's region.
+async fn copy_object(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    log_source_name: &str,
+) -> Result<()> {
+    let ingestion_bucket = std::env::var("INGESTION_BUCKET_NAME").context("need bucket!")?;
+    let dest_key = format!("{}/{}", log_source_name, key);
+    debug!(
+        "Copying object: s3://{}/{} to s3://{}/{}",
+        bucket, key, &ingestion_bucket, &dest_key
+    );
+
+    // form encoding uses `+` for spaces, and the key's `/` separators must be kept as is.
+    let encoded_key = url::form_urlencoded::byte_serialize(key.as_bytes())
+        .collect::<String>()
+        .replace('+', "%20")
+        .replace("%2F", "/");
+    s3.copy_object()
+        .copy_source(format!("{}/{}", bucket, encoded_key))
+        .bucket(&ingestion_bucket)
+        .key(&dest_key)
+        .send()
+        .await
+        .with_context(|| format!("Error copying s3://{}/{}", bucket, key))?;
+
+    Ok(())
+}
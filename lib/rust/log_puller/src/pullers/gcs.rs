@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use futures::future::join_all;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info};
+
+use super::azure_blob::decode_object_payload;
+use super::google_auth::ServiceAccountJwt;
+use super::oauth2::TokenSource;
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
+use shared::JsonValueExt;
+
+/// Copies new objects under a prefix of a Google Cloud Storage bucket, for products
+/// (e.g. Cisco Umbrella, GCP exports) that can only write to GCS.
+#[derive(Clone)]
+pub struct GcsPuller;
+
+const GCS_API_URL: &str = "https://storage.googleapis.com/storage/v1";
+const GCS_READ_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_only";
+/// Limit the number of objects downloaded per invocation, the rest are picked up on the next run.
+const MAX_OBJECTS_PER_RUN: usize = 500;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListObjectsResponse {
+    #[serde(default)]
+    items: Vec<GcsObject>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GcsObject {
+    name: String,
+    generation: String,
+    updated: String,
+    content_encoding: Option<String>,
+}
+
+const PROPERTIES: &[PropertySpec] = &[PropertySpec::required(
+    "bucket",
+    PropertyType::String,
+    "vendor-logs",
+)];
+
+#[async_trait]
+impl PullLogs for GcsPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    async fn pull_logs(
+        self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        _start_dt: DateTime<FixedOffset>,
+        _end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!(
+            "Pulling Google Cloud Storage for {}....",
+            ctx.log_source_name
+        );
+
+        let config = ctx.config();
+
+        let bucket = config.get("bucket").context("Missing bucket")?;
+        let prefix = config.get("prefix").cloned().unwrap_or_default();
+        let access_token = match ServiceAccountJwt::from_ctx(ctx, &[GCS_READ_SCOPE]).await? {
+            Some(sa) => sa.access_token(&client, ctx).await?,
+            None => return Ok(vec![]),
+        };
+
+        let mut checkpoint_json = ctx.checkpoint_json.lock().await;
+        let checkpoint_updated = checkpoint_json
+            .as_ref()
+            .and_then(|v| v["updated"].as_str())
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok());
+        // Object generations sharing the checkpoint timestamp that were already pulled.
+        let checkpoint_generations = checkpoint_json
+            .as_ref()
+            .and_then(|v| v["generations_at_updated"].as_array().cloned())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|v| v.into_str())
+            .collect::<Vec<_>>();
+
+        let list_url = format!("{}/b/{}/o", GCS_API_URL, bucket);
+        let mut new_objects = vec![];
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("prefix", prefix.as_str()),
+                (
+                    "fields",
+                    "items(name,generation,updated,contentEncoding),nextPageToken",
+                ),
+            ];
+            if let Some(token) = page_token.as_ref() {
+                query.push(("pageToken", token.as_str()));
+            }
+            let req = client
+                .get(&list_url)
+                .bearer_auth(&access_token)
+                .query(&query);
+            let res = ctx.send(&client, req).await?;
+            if !res.status().is_success() {
+                let body = res.text().await?;
+                return Err(anyhow!(
+                    "Error listing objects in gs://{}, response: {}",
+                    bucket,
+                    body
+                ));
+            }
+
+            let page: ListObjectsResponse = res.json().await?;
+            for object in page.items {
+                let updated = DateTime::parse_from_rfc3339(&object.updated)
+                    .with_context(|| format!("Invalid updated time for {}", &object.name))?;
+                let is_new = match checkpoint_updated {
+                    Some(cp) => {
+                        updated > cp
+                            || (updated == cp
+                                && !checkpoint_generations.contains(&object.generation))
+                    }
+                    None => true,
+                };
+                if is_new {
+                    new_objects.push((updated, object));
+                }
+            }
+
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        // Oldest first, so the checkpoint only moves past what was actually pulled.
+        new_objects.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.name.cmp(&b.1.name)));
+        new_objects.truncate(MAX_OBJECTS_PER_RUN);
+
+        info!(
+            "Found {} new objects for {}",
+            new_objects.len(),
+            ctx.log_source_name
+        );
+        if new_objects.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let futs = new_objects
+            .iter()
+            .map(|(_, object)| download_object(&client, ctx, &access_token, bucket, object))
+            .collect::<Vec<_>>();
+        let chunks = join_all(futs)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut ret: Vec<u8> = vec![];
+        for chunk in chunks.into_iter().filter(|c| !c.is_empty()) {
+            if !ret.is_empty() {
+                ret.push(b'\n');
+            }
+            ret.extend(chunk);
+        }
+
+        let (max_updated, _) = new_objects.last().unwrap();
+        let mut generations_at_updated = new_objects
+            .iter()
+            .filter(|(u, _)| u == max_updated)
+            .map(|(_, o)| o.generation.clone())
+            .collect::<Vec<_>>();
+        if checkpoint_updated.as_ref() == Some(max_updated) {
+            generations_at_updated.extend(checkpoint_generations);
+        }
+
+        // update checkpoint
+        *checkpoint_json = Some(json!({
+            "updated": max_updated.to_rfc3339(),
+            "generations_at_updated": generations_at_updated,
+        }));
+
+        Ok(ret)
+    }
+}
+
+async fn download_object(
+    client: &reqwest::Client,
+    ctx: &PullLogsContext,
+    access_token: &str,
+    bucket: &str,
+    object: &GcsObject,
+) -> Result<Vec<u8>> {
+    debug!("Downloading object: gs://{}/{}", bucket, &object.name);
+    // form encoding uses `+` for spaces, which isn't valid in a path segment.
+    let object_name = url::form_urlencoded::byte_serialize(object.name.as_bytes())
+        .collect::<String>()
+        .replace('+', "%20");
+    let url = format!("{}/b/{}/o/{}", GCS_API_URL, bucket, object_name);
+    let req = client
+        .get(&url)
+        .bearer_auth(access_token)
+        .query(&[("alt", "media"), ("generation", object.generation.as_str())])
+        // Otherwise GCS transparently decompresses gzip-encoded objects.
+        .header(reqwest::header::ACCEPT_ENCODING, "gzip");
+    let res = ctx.send(client, req).await?;
+    if !res.status().is_success() {
+        let body = res.text().await.unwrap_or_default();
+        return Err(anyhow!(
+            "Error downloading gs://{}/{}, response: {}",
+            bucket,
+            &object.name,
+            body
+        ));
+    }
+    let bytes = res.bytes().await?;
+
+    decode_object_payload(&object.name, object.content_encoding.as_deref(), &bytes)
+}
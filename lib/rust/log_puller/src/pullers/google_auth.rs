@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::oauth2::{cached_token, parse_expires_in, TokenSource};
+use super::PullLogsContext;
+
+const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+/// Google service account auth, exchanging a signed JWT assertion for an access token.
+///
+/// The service account's `client_email` comes from the log source properties and its
+/// `private_key` (and optional `private_key_id`) from the log source secret. Set `subject`
+/// to impersonate a user through domain-wide delegation, e.g. a Workspace admin.
+#[derive(Debug, Clone)]
+pub(crate) struct ServiceAccountJwt {
+    pub client_email: String,
+    pub private_key: String,
+    pub private_key_id: Option<String>,
+    pub scopes: Vec<String>,
+    pub subject: Option<String>,
+}
+
+impl ServiceAccountJwt {
+    /// Loads the service account key for the log source. Returns None if the secret is still a placeholder.
+    pub async fn from_ctx(
+        ctx: &PullLogsContext,
+        scopes: &[&str],
+    ) -> Result<Option<ServiceAccountJwt>> {
+        let client_email = ctx
+            .config()
+            .get("client_email")
+            .context("Missing client_email")?;
+        let private_key = ctx
+            .get_secret_field("private_key")
+            .await?
+            .context("Missing private key")?;
+
+        // skip early if private_key is equal <placeholder>
+        if private_key == "<placeholder>" {
+            info!(
+                "Skipping {} because secret is still <placeholder>",
+                ctx.log_source_name
+            );
+            return Ok(None);
+        }
+
+        Ok(Some(ServiceAccountJwt {
+            client_email: client_email.to_string(),
+            private_key,
+            private_key_id: ctx.get_secret_field("private_key_id").await?,
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            subject: None,
+        }))
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> ServiceAccountJwt {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    fn cache_key(&self) -> String {
+        format!(
+            "google_access_token:{}:{}:{}",
+            self.client_email,
+            self.subject.as_deref().unwrap_or_default(),
+            self.scopes.join(" ")
+        )
+    }
+
+    /// Builds the signed JWT assertion, valid for an hour.
+    fn assertion(&self) -> Result<String> {
+        let now = chrono::Utc::now().timestamp();
+
+        let mut claims = json!({
+          "iss": self.client_email,
+          "scope": self.scopes.join(" "),
+          "aud": GOOGLE_TOKEN_URL,
+          "iat": now,
+          "exp": now + 3600,
+        });
+        if let Some(subject) = self.subject.as_ref() {
+            claims["sub"] = subject.as_str().into();
+        }
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = self.private_key_id.clone();
+        // Keys copied from the JSON key file often keep the escaped newlines.
+        let key = EncodingKey::from_rsa_pem(self.private_key.replace("\\n", "\n").as_bytes())
+            .context("Invalid service account private key")?;
+        Ok(encode(&header, &claims, &key)?)
+    }
+
+    async fn request_token(
+        &self,
+        client: &reqwest::Client,
+        ctx: &PullLogsContext,
+    ) -> Result<(String, Option<chrono::Duration>)> {
+        info!("Getting access token for {}", &self.client_email);
+        let request = client.post(GOOGLE_TOKEN_URL).form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", &self.assertion()?),
+        ]);
+        let res = ctx.send(client, request).await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let msg = res.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "Failed to get Google access token, status: {}, response: {}",
+                status,
+                msg
+            ));
+        }
+        let auth_body = res.json::<Value>().await?;
+
+        let access_token = auth_body
+            .get("access_token")
+            .and_then(|v| v.as_str())
+            .context("Missing access token")?
+            .to_string();
+        Ok((access_token, parse_expires_in(&auth_body)))
+    }
+}
+
+#[async_trait]
+impl TokenSource for ServiceAccountJwt {
+    async fn access_token(
+        &self,
+        client: &reqwest::Client,
+        ctx: &PullLogsContext,
+    ) -> Result<String> {
+        cached_token(ctx, &self.cache_key(), || self.request_token(client, ctx)).await
+    }
+
+    async fn invalidate(&self, ctx: &PullLogsContext) {
+        ctx.cache().lock().await.remove(&self.cache_key());
+    }
+}
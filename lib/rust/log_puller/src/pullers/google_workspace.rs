@@ -6,16 +6,22 @@ use async_stream::{stream, try_stream};
 use async_trait::async_trait;
 use chrono::{DateTime, FixedOffset};
 use futures::{future::join_all, FutureExt};
-use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 use lazy_static::lazy_static;
-use log::{debug, error, info};
+use tracing::{debug, error, info};
 
-use super::{PullLogs, PullLogsContext};
+use super::google_auth::ServiceAccountJwt;
+use super::oauth2::TokenSource;
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
 use shared::JsonValueExt;
 
 #[derive(Clone)]
 pub struct GoogleWorkspacePuller;
 
+const WORKSPACE_SCOPES: &[&str] = &[
+    "https://www.googleapis.com/auth/admin.reports.audit.readonly",
+    "https://www.googleapis.com/auth/apps.alerts",
+];
+
 struct GoogResourceProps {
     resource: String,
     /// Google workspace events can be lagged from minutes to hours to days. See https://support.google.com/a/answer/7061566
@@ -48,8 +54,18 @@ lazy_static! {
     static ref TABLE_RESOURCE_MAP: HashMap<String, GoogResourceProps> = table_resource_map();
 }
 
+const PROPERTIES: &[PropertySpec] = &[PropertySpec::required(
+    "admin_email",
+    PropertyType::String,
+    "admin@example.com",
+)];
+
 #[async_trait]
 impl PullLogs for GoogleWorkspacePuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
     async fn pull_logs(
         self,
         client: reqwest::Client,
@@ -60,39 +76,14 @@ impl PullLogs for GoogleWorkspacePuller {
         info!("Pulling Google Workspace logs....");
 
         let config = ctx.config();
-        let cache = ctx.cache();
 
         let checkpoint_json = ctx.checkpoint_json.lock().await;
         let is_initial_run = checkpoint_json.is_none();
 
-        let client_email = config.get("client_email").context("Missing client_email")?;
         let admin_email = config.get("admin_email").context("Missing admin_email")?;
-        let private_key = ctx
-            .get_secret_field("private_key")
-            .await
-            .map_err(|e| {
-                error!("Error getting private key: {}", e);
-                e
-            })?
-            .context("Missing private key")?;
-
-        // skip early if private_key is equal <placeholder>
-        if private_key == "<placeholder>" {
-            return Ok(vec![]);
-        }
-
-        let access_token = {
-            let mut cache = cache.lock().await;
-            match cache.get("access_token") {
-                Some(token) => token.to_owned(),
-                None => {
-                    let token =
-                        get_access_token(client.clone(), client_email, admin_email, &private_key)
-                            .await?;
-                    cache.set("access_token", token.clone(), None);
-                    token
-                }
-            }
+        let access_token = match ServiceAccountJwt::from_ctx(ctx, WORKSPACE_SCOPES).await? {
+            Some(sa) => sa.subject(admin_email).access_token(&client, ctx).await?,
+            None => return Ok(vec![]),
         };
 
         let start_dt = if is_initial_run {
@@ -121,6 +112,7 @@ impl PullLogs for GoogleWorkspacePuller {
 
                 list_resource(
                     client.clone(),
+                    ctx,
                     &resource.resource,
                     table,
                     &access_token,
@@ -140,6 +132,7 @@ impl PullLogs for GoogleWorkspacePuller {
                 .to_string();
             let alerts_fut = list_alerts(
                 client.clone(),
+                ctx,
                 "alert",
                 &access_token,
                 alert_start_time,
@@ -161,44 +154,9 @@ impl PullLogs for GoogleWorkspacePuller {
     }
 }
 
-async fn get_access_token(
-    client: reqwest::Client,
-    client_email: &str,
-    admin_email: &str,
-    private_key: &str,
-) -> Result<String> {
-    let now = chrono::Utc::now().timestamp();
-
-    let claims = json!({
-      "iss": client_email,
-      "scope": "https://www.googleapis.com/auth/admin.reports.audit.readonly https://www.googleapis.com/auth/apps.alerts",
-      "aud": "https://oauth2.googleapis.com/token",
-      "iat": now,
-      "exp": now + 3600,
-      "sub": admin_email
-    });
-    let key = EncodingKey::from_rsa_pem(private_key.as_bytes())?;
-    let token = encode(&Header::new(Algorithm::RS256), &claims, &key)?;
-
-    let access_token = client
-        .post("https://oauth2.googleapis.com/token")
-        .form(&[
-            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
-            ("assertion", &token),
-        ])
-        .send()
-        .await?
-        .json::<serde_json::Value>()
-        .await?
-        .get_mut("access_token")
-        .and_then(|v| v.take().into_str())
-        .context("Missing access token")?;
-
-    Ok(access_token)
-}
-
 async fn list_resource(
     client: reqwest::Client,
+    ctx: &PullLogsContext,
     resource: &str,
     table: &str,
     access_token: &str,
@@ -225,12 +183,8 @@ async fn list_resource(
         if let Some(token) = next_token.as_ref() {
             qs.push(("pageToken", token));
         }
-        let res = client
-            .get(&url)
-            .bearer_auth(access_token)
-            .query(&qs)
-            .send()
-            .await?;
+        let req = client.get(&url).bearer_auth(access_token).query(&qs);
+        let res = ctx.send(&client, req).await?;
 
         let is_failure = !res.status().is_success();
         if is_failure {
@@ -239,7 +193,7 @@ async fn list_resource(
             return Err(anyhow!(msg));
         }
 
-        let mut body = res.json::<serde_json::Value>().await?;
+        let mut body: serde_json::Value = ctx.response_json(res).await?;
         let items = body
             .get_mut("items")
             .and_then(|v| v.take().into_array())
@@ -281,6 +235,7 @@ async fn list_resource(
 const ALERT_CENTER_URL: &str = "https://alertcenter.googleapis.com/v1beta1/alerts";
 async fn list_alerts(
     client: reqwest::Client,
+    ctx: &PullLogsContext,
     table: &str,
     access_token: &str,
     start_time: String,
@@ -305,12 +260,11 @@ async fn list_alerts(
         if let Some(token) = next_token.as_ref() {
             qs.push(("pageToken", token));
         }
-        let res = client
+        let req = client
             .get(ALERT_CENTER_URL)
             .bearer_auth(access_token)
-            .query(&qs)
-            .send()
-            .await?;
+            .query(&qs);
+        let res = ctx.send(&client, req).await?;
 
         let is_failure = !res.status().is_success();
         if is_failure {
@@ -319,7 +273,7 @@ async fn list_alerts(
             return Err(anyhow!(msg));
         }
 
-        let mut body = res.json::<serde_json::Value>().await?;
+        let mut body: serde_json::Value = ctx.response_json(res).await?;
         let items = body
             .get_mut("alerts")
             .and_then(|v| v.take().into_array())
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+use super::custom_api::{lookup_json_path, ApiAuth, TimeFormat};
+use super::pagination::{lookup_paging_value, DEFAULT_MAX_PAGES};
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
+
+/// Generic puller for GraphQL APIs. Runs a user supplied query with the pull window passed
+/// as variables, and follows a cursor read from the response until there are no more pages.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: graphql
+///   properties:
+///     endpoint: https://api.linear.app/graphql
+///     auth_type: header
+///     query: |
+///       query($start: DateTimeOrDuration!, $after: String) {
+///         auditEntries(first: 100, after: $after, filter: { createdAt: { gte: $start } }) {
+///           nodes { id type createdAt actor { email } }
+///           pageInfo { hasNextPage endCursor }
+///         }
+///       }
+///     start_time_variable: start
+///     cursor_variable: after
+///     records_path: data.auditEntries.nodes
+///     cursor_path: data.auditEntries.pageInfo.endCursor
+///     has_next_page_path: data.auditEntries.pageInfo.hasNextPage
+/// ```
+#[derive(Clone)]
+pub struct GraphqlPuller;
+
+#[derive(Debug, Clone)]
+struct GraphqlConfig {
+    endpoint: String,
+    query: String,
+    auth: ApiAuth,
+    variables: serde_json::Map<String, Value>,
+    start_time_variable: Option<String>,
+    end_time_variable: Option<String>,
+    time_format: TimeFormat,
+    records_path: String,
+    cursor_variable: Option<String>,
+    cursor_path: Option<String>,
+    has_next_page_path: Option<String>,
+    max_pages: usize,
+}
+
+impl GraphqlConfig {
+    fn from_config(config: &HashMap<String, String>) -> Result<GraphqlConfig> {
+        let get = |k: &str| {
+            config
+                .get(k)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+
+        // Static variables, ex: `variables: '{"first": 100}'`
+        let variables = match get("variables") {
+            Some(s) => serde_json::from_str::<Value>(&s)
+                .context("variables must be a JSON object")?
+                .as_object()
+                .cloned()
+                .context("variables must be a JSON object")?,
+            None => serde_json::Map::new(),
+        };
+
+        let cursor_variable = get("cursor_variable");
+        let cursor_path = get("cursor_path");
+        if cursor_variable.is_some() != cursor_path.is_some() {
+            return Err(anyhow!(
+                "cursor_variable and cursor_path must be set together"
+            ));
+        }
+
+        Ok(GraphqlConfig {
+            endpoint: get("endpoint").context("Missing endpoint")?,
+            query: get("query").context("Missing query")?,
+            auth: ApiAuth::from_config(config)?,
+            variables,
+            start_time_variable: get("start_time_variable"),
+            end_time_variable: get("end_time_variable"),
+            time_format: TimeFormat::from_config(config),
+            records_path: get("records_path").context("Missing records_path")?,
+            cursor_variable,
+            cursor_path,
+            has_next_page_path: get("has_next_page_path"),
+            max_pages: get("max_pages")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(DEFAULT_MAX_PAGES),
+        })
+    }
+}
+
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required(
+        "endpoint",
+        PropertyType::Url,
+        "https://api.example.com/graphql",
+    ),
+    PropertySpec::required(
+        "query",
+        PropertyType::String,
+        "query($cursor: String) { ... }",
+    ),
+    PropertySpec::required("records_path", PropertyType::String, "data.events.nodes"),
+    PropertySpec::optional("max_pages", PropertyType::Integer, "100"),
+    PropertySpec::optional("variables", PropertyType::Json, "{\"first\": 100}"),
+];
+
+#[async_trait]
+impl PullLogs for GraphqlPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    async fn pull_logs(
+        self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!("Pulling GraphQL logs for {}....", ctx.log_source_name);
+
+        let gql_config = GraphqlConfig::from_config(ctx.config())?;
+
+        let (headers, auth_query) = match gql_config.auth.resolve(&client, ctx).await? {
+            Some(auth) => auth,
+            None => return Ok(vec![]),
+        };
+
+        let mut variables = gql_config.variables.clone();
+        if let Some(var) = gql_config.start_time_variable.as_ref() {
+            variables.insert(var.clone(), gql_config.time_format.format(&start_dt).into());
+        }
+        if let Some(var) = gql_config.end_time_variable.as_ref() {
+            variables.insert(var.clone(), gql_config.time_format.format(&end_dt).into());
+        }
+
+        let mut ret: Vec<u8> = vec![];
+        let mut cursor: Option<String> = None;
+        let mut page_num: usize = 0;
+
+        loop {
+            if page_num >= gql_config.max_pages {
+                return Err(anyhow!(
+                    "Exceeded max_pages ({}) for {}",
+                    gql_config.max_pages,
+                    ctx.log_source_name
+                ));
+            }
+
+            if let Some(var) = gql_config.cursor_variable.as_ref() {
+                variables.insert(var.clone(), cursor.clone().into());
+            }
+
+            let body = json!({
+                "query": gql_config.query,
+                "variables": variables,
+            });
+
+            let req = client
+                .post(&gql_config.endpoint)
+                .headers(headers.clone())
+                .query(&auth_query)
+                .json(&body);
+            let response = ctx.send(&client, req).await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let msg = response.text().await.unwrap_or_default();
+                return Err(anyhow!(
+                    "Error calling {}, status: {}, response: {}",
+                    &gql_config.endpoint,
+                    status,
+                    msg
+                ));
+            }
+
+            let body: Value = ctx.response_json(response).await?;
+
+            // GraphQL servers report query errors with a 200 status.
+            if let Some(errors) = body.get("errors").and_then(|v| v.as_array()) {
+                if !errors.is_empty() {
+                    return Err(anyhow!(
+                        "GraphQL query failed for {}: {}",
+                        ctx.log_source_name,
+                        Value::Array(errors.to_owned())
+                    ));
+                }
+            }
+
+            let records: &[Value] = match lookup_json_path(&body, &gql_config.records_path) {
+                Some(Value::Array(arr)) => arr.as_slice(),
+                Some(Value::Null) | None => &[],
+                Some(_) => {
+                    return Err(anyhow!(
+                        "records_path: {} is not an array",
+                        &gql_config.records_path
+                    ))
+                }
+            };
+            let num_records = records.len();
+
+            for record in records {
+                ret.extend(serde_json::to_vec(record)?);
+                ret.push(b'\n');
+            }
+
+            page_num += 1;
+            debug!(
+                "Loaded page {} with {} records for {}",
+                page_num, num_records, ctx.log_source_name
+            );
+
+            let next_cursor = gql_config
+                .cursor_path
+                .as_ref()
+                .and_then(|p| lookup_paging_value(&body, p));
+            let has_next_page = match gql_config.has_next_page_path.as_ref() {
+                Some(p) => lookup_json_path(&body, p)
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false),
+                None => num_records > 0,
+            };
+
+            // stop if the cursor did not move, otherwise we'd loop forever
+            if !has_next_page || next_cursor.is_none() || next_cursor == cursor {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        // Remove last newline
+        if ret.last() == Some(&b'\n') {
+            ret.pop();
+        }
+
+        Ok(ret)
+    }
+}
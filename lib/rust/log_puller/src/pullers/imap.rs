@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use futures_util::stream::StreamExt;
+use regex::Regex;
+use serde_json::json;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+use tracing::{debug, info, warn};
+
+use super::errors::PullerError;
+use super::payload::payload_to_ndjson;
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
+
+/// Pulls attachments (CSV, JSON/NDJSON, or zip archives of those) from messages in an IMAP mailbox.
+/// Some vendors only deliver reports by email. Attachments that can't be converted are
+/// quarantined and skipped.
+#[derive(Clone)]
+pub struct ImapPuller;
+
+/// Limit the number of messages fetched per invocation, the rest are picked up on the next run.
+const MAX_MESSAGES_PER_RUN: usize = 200;
+
+struct ImapConfig {
+    host: String,
+    port: u16,
+    username: String,
+    folder: String,
+    from_filter: Option<String>,
+    subject_filter: Option<String>,
+    attachment_regex: Option<Regex>,
+}
+
+impl ImapConfig {
+    fn from_config(config: &HashMap<String, String>) -> Result<ImapConfig> {
+        Ok(ImapConfig {
+            host: config.get("host").context("Missing host")?.to_string(),
+            port: config
+                .get("port")
+                .map(|p| p.parse())
+                .transpose()
+                .context("port must be a number")?
+                .unwrap_or(993),
+            username: config
+                .get("username")
+                .context("Missing username")?
+                .to_string(),
+            folder: config
+                .get("folder")
+                .cloned()
+                .unwrap_or_else(|| "INBOX".to_string()),
+            from_filter: config.get("from_filter").cloned(),
+            subject_filter: config.get("subject_filter").cloned(),
+            attachment_regex: config
+                .get("attachment_filename_regex")
+                .map(|r| Regex::new(r))
+                .transpose()
+                .context("Invalid attachment_filename_regex")?,
+        })
+    }
+}
+
+/// TLS client config trusting the native root certificates, for pullers speaking raw TCP protocols.
+pub(crate) fn native_tls_config() -> Result<Arc<rustls::ClientConfig>> {
+    let mut root_store = rustls::RootCertStore::empty();
+    for cert in rustls_native_certs::load_native_certs()? {
+        root_store.add(&rustls::Certificate(cert.0))?;
+    }
+    let tls_config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    Ok(Arc::new(tls_config))
+}
+
+/// Quotes a value for use in an IMAP SEARCH command.
+fn imap_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required("host", PropertyType::String, "imap.example.com"),
+    PropertySpec::required("username", PropertyType::String, "reports@example.com"),
+    PropertySpec::optional("port", PropertyType::Integer, "993"),
+];
+
+#[async_trait]
+impl PullLogs for ImapPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    fn counts_requests(&self) -> bool {
+        false
+    }
+
+    async fn pull_logs(
+        self,
+        _client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        _end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!("Pulling IMAP mailbox for {}....", ctx.log_source_name);
+
+        let imap_config = ImapConfig::from_config(ctx.config())?;
+
+        let password = ctx
+            .get_secret_field("password")
+            .await?
+            .context("Missing IMAP password")?;
+
+        // skip early if password is equal <placeholder>
+        if password == "<placeholder>" {
+            info!("Skipping imap because secret is still <placeholder>");
+            return Ok(vec![]);
+        }
+
+        let mut checkpoint_json = ctx.checkpoint_json.lock().await;
+        let checkpoint_uid_validity = checkpoint_json
+            .as_ref()
+            .and_then(|v| v["uid_validity"].as_u64());
+        let checkpoint_last_uid = checkpoint_json
+            .as_ref()
+            .and_then(|v| v["last_uid"].as_u64());
+
+        let tcp = TcpStream::connect((imap_config.host.as_str(), imap_config.port)).await?;
+        let server_name = rustls::ServerName::try_from(imap_config.host.as_str())
+            .map_err(|e| anyhow!("Invalid IMAP host: {}", e))?;
+        let tls = tokio_rustls::TlsConnector::from(native_tls_config()?)
+            .connect(server_name, tcp)
+            .await?;
+
+        let mut session = async_imap::Client::new(tls)
+            .login(&imap_config.username, &password)
+            .await
+            .map_err(|(e, _)| anyhow!(e).context("IMAP login failed"))?;
+
+        let mailbox = session.select(&imap_config.folder).await?;
+        let uid_validity = mailbox.uid_validity.map(|v| v as u64);
+
+        // UIDs are only stable for the same UIDVALIDITY, otherwise start over.
+        let last_uid = if uid_validity == checkpoint_uid_validity {
+            checkpoint_last_uid
+        } else {
+            None
+        };
+
+        let mut criteria = vec![];
+        match last_uid {
+            Some(uid) => criteria.push(format!("UID {}:*", uid + 1)),
+            None => criteria.push(format!("SINCE {}", start_dt.format("%d-%b-%Y"))),
+        }
+        if let Some(from) = imap_config.from_filter.as_ref() {
+            criteria.push(format!("FROM {}", imap_quote(from)));
+        }
+        if let Some(subject) = imap_config.subject_filter.as_ref() {
+            criteria.push(format!("SUBJECT {}", imap_quote(subject)));
+        }
+
+        let mut uids = session
+            .uid_search(criteria.join(" "))
+            .await?
+            .into_iter()
+            // `UID n:*` always matches the last message, even if n is past it.
+            .filter(|uid| last_uid.map_or(true, |last| *uid as u64 > last))
+            .collect::<Vec<_>>();
+        uids.sort_unstable();
+        uids.truncate(MAX_MESSAGES_PER_RUN);
+
+        info!(
+            "Found {} new messages for {}",
+            uids.len(),
+            ctx.log_source_name
+        );
+
+        let mut ret: Vec<u8> = vec![];
+        let mut max_uid = last_uid;
+
+        if !uids.is_empty() {
+            let uid_set = uids
+                .iter()
+                .map(|u| u.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let mut messages = session.uid_fetch(uid_set, "(UID RFC822)").await?;
+            while let Some(fetch) = messages.next().await {
+                let fetch = fetch?;
+                let body = match fetch.body() {
+                    Some(b) => b,
+                    None => continue,
+                };
+                let parsed = mailparse::parse_mail(body)?;
+                for (name, data) in attachments(&parsed) {
+                    let is_match = imap_config
+                        .attachment_regex
+                        .as_ref()
+                        .map_or(true, |r| r.is_match(&name));
+                    if !is_match {
+                        debug!("Skipping attachment: {}", name);
+                        continue;
+                    }
+                    // Converted apart, so a failed attachment doesn't leave partial records.
+                    let mut records = vec![];
+                    match payload_to_ndjson(&name, &data, &mut records) {
+                        Ok(_) => ret.extend(records),
+                        Err(e) => {
+                            let source = format!(
+                                "imap://{}/{}/{}/{}",
+                                imap_config.host,
+                                imap_config.folder,
+                                fetch.uid.unwrap_or_default(),
+                                name
+                            );
+                            let e = ctx.quarantine(&source, &data, e).await;
+                            // The message is only skipped once its attachment is quarantined.
+                            if PullerError::of(&e) != Some(PullerError::Parse) {
+                                return Err(e);
+                            }
+                            warn!("Skipping attachment {}: {:#}", source, e);
+                        }
+                    }
+                }
+                if let Some(uid) = fetch.uid {
+                    max_uid = max_uid.max(Some(uid as u64));
+                }
+            }
+        }
+        session.logout().await?;
+
+        // Remove last newline
+        if ret.last() == Some(&b'\n') {
+            ret.pop();
+        }
+
+        // update checkpoint
+        *checkpoint_json = Some(json!({
+            "uid_validity": uid_validity,
+            "last_uid": max_uid,
+        }));
+
+        Ok(ret)
+    }
+}
+
+/// Returns (filename, decoded content) of every attachment in a message, recursively.
+fn attachments(mail: &mailparse::ParsedMail) -> Vec<(String, Vec<u8>)> {
+    let mut ret = vec![];
+    let disposition = mail.get_content_disposition();
+    let filename = disposition
+        .params
+        .get("filename")
+        .or_else(|| mail.ctype.params.get("name"))
+        .cloned();
+
+    if let Some(filename) = filename {
+        if let Ok(data) = mail.get_body_raw() {
+            ret.push((filename, data));
+        }
+    }
+    for part in mail.subparts.iter() {
+        ret.extend(attachments(part));
+    }
+    ret
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use rskafka::client::partition::{OffsetAt, UnknownTopicHandling};
+use rskafka::client::{ClientBuilder, SaslConfig};
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use super::errors::PullerError;
+use super::imap::native_tls_config;
+use super::record_decoder::RecordDecoder;
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
+
+/// Consumes a set of Kafka topics up to the high watermark observed at the start of the invocation.
+///
+/// Offsets are stored in the puller checkpoint rather than committed to a consumer group. The checkpoint is
+/// only written after the pulled data has been uploaded to S3, so records are never skipped on failure.
+///
+/// Avro or Protobuf encoded topics are decoded to JSON with `value_format`, see `RecordDecoder`.
+/// Messages that can't be decoded are quarantined and skipped, so they don't block the partition. If
+/// quarantining fails, the pull fails instead.
+#[derive(Clone)]
+pub struct KafkaPuller;
+
+/// Stop consuming once this many bytes have been read, the rest are picked up on the next run.
+const MAX_BYTES_PER_RUN: usize = 256 * 1024 * 1024;
+const FETCH_MAX_BYTES: i32 = 8 * 1024 * 1024;
+const FETCH_MAX_WAIT_MS: i32 = 500;
+
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required(
+        "bootstrap_servers",
+        PropertyType::String,
+        "broker-1:9093,broker-2:9093",
+    ),
+    PropertySpec::required("topics", PropertyType::String, "audit-events"),
+    PropertySpec::optional("tls", PropertyType::Boolean, "true"),
+    PropertySpec::optional("value_format", PropertyType::String, "avro"),
+    PropertySpec::optional(
+        "schema_registry_url",
+        PropertyType::Url,
+        "https://schema-registry.internal:8081",
+    ),
+];
+
+#[async_trait]
+impl PullLogs for KafkaPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    fn counts_requests(&self) -> bool {
+        false
+    }
+
+    async fn pull_logs(
+        self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        _start_dt: DateTime<FixedOffset>,
+        _end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!("Pulling Kafka topics for {}....", ctx.log_source_name);
+
+        let config = ctx.config();
+
+        let bootstrap_servers = config
+            .get("bootstrap_servers")
+            .context("Missing bootstrap_servers")?
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        let topics = config
+            .get("topics")
+            .context("Missing topics")?
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        let use_tls = config.get("tls").map_or(true, |v| v != "false");
+
+        let mut builder = ClientBuilder::new(bootstrap_servers);
+        if use_tls {
+            builder = builder.tls_config(native_tls_config()?);
+        }
+        if let Some(username) = config.get("username") {
+            let password = ctx
+                .get_secret_field("password")
+                .await?
+                .context("Missing Kafka password")?;
+            // skip early if password is equal <placeholder>
+            if password == "<placeholder>" {
+                info!("Skipping kafka because secret is still <placeholder>");
+                return Ok(vec![]);
+            }
+            let mechanism = config
+                .get("sasl_mechanism")
+                .map(|s| s.to_uppercase())
+                .unwrap_or_else(|| "PLAIN".to_string());
+            if mechanism != "PLAIN" {
+                return Err(anyhow!("Unsupported sasl_mechanism: {}", mechanism));
+            }
+            builder = builder.sasl_config(SaslConfig::Plain {
+                username: username.to_string(),
+                password,
+            });
+        }
+        let registry_password = match config.get("schema_registry_username") {
+            Some(_) => ctx.get_secret_field("schema_registry_password").await?,
+            None => None,
+        };
+        let decoder = RecordDecoder::from_config(config, registry_password, client)?;
+
+        let kafka = builder
+            .build()
+            .await
+            .map_err(|e| anyhow!(e).context("Failed to connect to Kafka"))?;
+
+        let mut checkpoint_json = ctx.checkpoint_json.lock().await;
+        let mut offsets = checkpoint_json
+            .as_ref()
+            .and_then(|v| v["offsets"].as_object().cloned())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, v.as_i64()?)))
+            .collect::<HashMap<_, _>>();
+
+        let topic_partitions = kafka
+            .list_topics()
+            .await?
+            .into_iter()
+            .filter(|t| topics.contains(&t.name))
+            .flat_map(|t| {
+                let name = t.name;
+                t.partitions.into_iter().map(move |p| (name.clone(), p))
+            })
+            .collect::<Vec<_>>();
+
+        let mut ret: Vec<u8> = vec![];
+
+        'partitions: for (topic, partition) in topic_partitions {
+            let partition_client = kafka
+                .partition_client(topic.as_str(), partition, UnknownTopicHandling::Error)
+                .await?;
+            let offset_key = format!("{}:{}", topic, partition);
+
+            // Only consume up to what exists now so a busy topic can't keep the invocation running.
+            let high_watermark = partition_client.get_offset(OffsetAt::Latest).await?;
+            // Start new partitions from the earliest retained offset. The checkpoint is only saved when
+            // there is data, so starting from the latest offset could skip records between invocations.
+            let mut offset = match offsets.get(&offset_key) {
+                Some(o) => *o,
+                None => partition_client.get_offset(OffsetAt::Earliest).await?,
+            };
+
+            while offset < high_watermark {
+                let (records, _) = partition_client
+                    .fetch_records(offset, 1..FETCH_MAX_BYTES, FETCH_MAX_WAIT_MS)
+                    .await?;
+                if records.is_empty() {
+                    break;
+                }
+                for record in records {
+                    if record.offset >= high_watermark {
+                        break;
+                    }
+                    match (record.record.value, decoder.as_ref()) {
+                        (Some(value), Some(decoder)) => match decoder.decode(ctx, &value).await {
+                            Ok(decoded) => {
+                                serde_json::to_writer(&mut ret, &decoded)?;
+                                ret.push(b'\n');
+                            }
+                            Err(e) => {
+                                let source = format!("kafka://{}/{}", offset_key, record.offset);
+                                let e = ctx.quarantine(&source, &value, e).await;
+                                // Not skipped unless quarantined, the offset would advance past it.
+                                if PullerError::of(&e) != Some(PullerError::Parse) {
+                                    return Err(e);
+                                }
+                                warn!("Skipping undecodable message {}: {:#}", source, e);
+                            }
+                        },
+                        (Some(value), None) => {
+                            ret.extend(value);
+                            if ret.last() != Some(&b'\n') {
+                                ret.push(b'\n');
+                            }
+                        }
+                        (None, _) => {}
+                    }
+                    offset = record.offset + 1;
+                }
+                if ret.len() >= MAX_BYTES_PER_RUN {
+                    offsets.insert(offset_key, offset);
+                    info!("Reached max bytes for {}, stopping", ctx.log_source_name);
+                    break 'partitions;
+                }
+            }
+            debug!(
+                "Consumed {} up to offset {} (high watermark {})",
+                &offset_key, offset, high_watermark
+            );
+            offsets.insert(offset_key, offset);
+        }
+
+        // Remove last newline
+        if ret.last() == Some(&b'\n') {
+            ret.pop();
+        }
+
+        // update checkpoint
+        *checkpoint_json = Some(json!({
+            "offsets": offsets
+                .into_iter()
+                .map(|(k, v)| (k, Value::from(v)))
+                .collect::<serde_json::Map<_, _>>(),
+        }));
+
+        Ok(ret)
+    }
+}
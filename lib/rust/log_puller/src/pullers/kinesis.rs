@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use lazy_static::lazy_static;
+use tracing::{error, info, warn};
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref KINESIS_CLIENT: AsyncOnce<aws_sdk_kinesis::Client> =
+        AsyncOnce::new(async { aws_sdk_kinesis::Client::new(AWS_CONFIG.get().await) });
+    static ref FIREHOSE_CLIENT: AsyncOnce<aws_sdk_firehose::Client> =
+        AsyncOnce::new(async { aws_sdk_firehose::Client::new(AWS_CONFIG.get().await) });
+}
+
+/// Attempts for a batch with failed records, e.g. when throttled.
+const MAX_BATCH_ATTEMPTS: u32 = 5;
+
+/// Where pulled events are sent besides (or instead of) the ingestion bucket.
+#[derive(Debug, Clone)]
+enum Target {
+    /// A Kinesis Data Stream, by name or ARN.
+    Stream(String),
+    /// A Kinesis Data Firehose delivery stream, by name.
+    Firehose(String),
+}
+
+impl Target {
+    /// PutRecords/PutRecordBatch limits: (records per batch, bytes per batch, bytes per record).
+    fn limits(&self) -> (usize, usize, usize) {
+        match self {
+            Target::Stream(_) => (500, 5 * 1024 * 1024, 1024 * 1024),
+            Target::Firehose(_) => (500, 4 * 1024 * 1024, 1000 * 1024),
+        }
+    }
+}
+
+/// Sends pulled events to Kinesis, one record per event, for near real time consumers
+/// alongside the lake. Records to a data stream are spread across shards with a random
+/// partition key. Records to Firehose are newline terminated, so delivered objects are NDJSON.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     kinesis_stream_name: security-events
+///     # or
+///     firehose_delivery_stream_name: security-events-to-siem
+///     # optional, set to false to only send to Kinesis, defaults to true
+///     output_to_s3: false
+/// ```
+#[derive(Debug, Clone)]
+pub struct KinesisOutput {
+    targets: Vec<Target>,
+    output_to_s3: bool,
+}
+
+impl KinesisOutput {
+    /// Returns None if no stream is configured.
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<KinesisOutput>> {
+        let get = |prop: &str| {
+            config
+                .get(prop)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let mut targets = vec![];
+        if let Some(stream) = get("kinesis_stream_name") {
+            targets.push(Target::Stream(stream));
+        }
+        if let Some(stream) = get("firehose_delivery_stream_name") {
+            targets.push(Target::Firehose(stream));
+        }
+        let output_to_s3 = get("output_to_s3")
+            .map(|s| s.parse::<bool>())
+            .transpose()
+            .context("output_to_s3 must be true or false")?
+            .unwrap_or(true);
+        if targets.is_empty() {
+            if !output_to_s3 {
+                return Err(anyhow!(
+                    "output_to_s3 is false but no kinesis_stream_name or firehose_delivery_stream_name is set"
+                ));
+            }
+            return Ok(None);
+        }
+        Ok(Some(KinesisOutput {
+            targets,
+            output_to_s3,
+        }))
+    }
+
+    /// Whether the data should also be uploaded to the ingestion bucket.
+    pub fn output_to_s3(&self) -> bool {
+        self.output_to_s3
+    }
+
+    /// Sends each NDJSON line as a record to every target.
+    pub async fn send(&self, log_source: &str, data: &[u8]) -> Result<()> {
+        let lines = data
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        for target in &self.targets {
+            let (max_records, max_batch_size, max_record_size) = target.limits();
+            let mut batch: Vec<&[u8]> = vec![];
+            let mut batch_size = 0;
+            let mut skipped = 0;
+            for line in &lines {
+                // Leaves room for the partition key or newline.
+                let size = line.len() + 64;
+                if size > max_record_size {
+                    skipped += 1;
+                    continue;
+                }
+                if batch.len() >= max_records || batch_size + size > max_batch_size {
+                    send_batch(target, std::mem::take(&mut batch)).await?;
+                    batch_size = 0;
+                }
+                batch.push(line);
+                batch_size += size;
+            }
+            if !batch.is_empty() {
+                send_batch(target, batch).await?;
+            }
+            if skipped > 0 {
+                error!(
+                    "Skipped {} events for {} larger than the Kinesis record limit",
+                    skipped, log_source
+                );
+            }
+        }
+        info!("Sent {} events for {} to Kinesis", lines.len(), log_source);
+        Ok(())
+    }
+}
+
+/// Sends a batch, retrying records that failed until all succeed.
+async fn send_batch(target: &Target, mut records: Vec<&[u8]>) -> Result<()> {
+    let mut attempt = 0;
+    while !records.is_empty() {
+        if attempt >= MAX_BATCH_ATTEMPTS {
+            return Err(anyhow!(
+                "Failed to send {} records to {:?}",
+                records.len(),
+                target
+            ));
+        }
+        if attempt > 0 {
+            let wait = std::time::Duration::from_millis(100 * 2u64.pow(attempt));
+            tokio::time::sleep(wait).await;
+        }
+        let failed = match target {
+            Target::Stream(stream) => put_records(stream, &records).await?,
+            Target::Firehose(stream) => put_record_batch(stream, &records).await?,
+        };
+        if !failed.is_empty() {
+            warn!("{} records to {:?} failed, retrying", failed.len(), target);
+        }
+        records = failed.into_iter().map(|i| records[i]).collect();
+        attempt += 1;
+    }
+    Ok(())
+}
+
+/// Returns the indexes of records that failed.
+async fn put_records(stream: &str, records: &[&[u8]]) -> Result<Vec<usize>> {
+    let entries = records
+        .iter()
+        .map(|record| {
+            aws_sdk_kinesis::model::PutRecordsRequestEntry::builder()
+                .data(aws_sdk_kinesis::types::Blob::new(record.to_vec()))
+                .partition_key(uuid::Uuid::new_v4().to_string())
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let request = KINESIS_CLIENT.get().await.put_records();
+    let request = if stream.starts_with("arn:") {
+        request.stream_arn(stream)
+    } else {
+        request.stream_name(stream)
+    };
+    let res = request
+        .set_records(Some(entries))
+        .send()
+        .await
+        .with_context(|| format!("Error sending records to Kinesis stream {}", stream))?;
+    if res.failed_record_count().unwrap_or(0) == 0 {
+        return Ok(vec![]);
+    }
+    let failed = res
+        .records()
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.error_code().is_some())
+        .map(|(i, _)| i)
+        .collect();
+    Ok(failed)
+}
+
+/// Returns the indexes of records that failed.
+async fn put_record_batch(stream: &str, records: &[&[u8]]) -> Result<Vec<usize>> {
+    let entries = records
+        .iter()
+        .map(|record| {
+            let mut data = Vec::with_capacity(record.len() + 1);
+            data.extend_from_slice(record);
+            data.push(b'\n');
+            aws_sdk_firehose::model::Record::builder()
+                .data(aws_sdk_firehose::types::Blob::new(data))
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let res = FIREHOSE_CLIENT
+        .get()
+        .await
+        .put_record_batch()
+        .delivery_stream_name(stream)
+        .set_records(Some(entries))
+        .send()
+        .await
+        .with_context(|| format!("Error sending records to Firehose stream {}", stream))?;
+    if res.failed_put_count().unwrap_or(0) == 0 {
+        return Ok(vec![]);
+    }
+    let failed = res
+        .request_responses()
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.error_code().is_some())
+        .map(|(i, _)| i)
+        .collect();
+    Ok(failed)
+}
@@ -0,0 +1,156 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use serde_json::json;
+use tracing::warn;
+
+use super::errors::METRICS_NAMESPACE;
+use super::PullLogsContext;
+
+lazy_static! {
+    /// Durations in milliseconds of the last `DURATION_WINDOW` pulls by log source, kept while
+    /// the Lambda is warm.
+    static ref RECENT_DURATIONS: Mutex<HashMap<String, VecDeque<u64>>> = Mutex::new(HashMap::new());
+    /// Durations and slow pull counts of the pulls in the current invocation, see
+    /// `emit_latency_metrics`.
+    static ref INVOCATION_DURATIONS: Mutex<HashMap<String, (Vec<u64>, usize)>> =
+        Mutex::new(HashMap::new());
+}
+
+const DURATION_WINDOW: usize = 100;
+const DEFAULT_SLOW_PULL_THRESHOLD_SECS: f64 = 60.0;
+
+/// A vendor request of a pull, including its retries.
+#[derive(Debug, Clone)]
+pub struct RequestTiming {
+    pub method: String,
+    /// Without the query, which may hold credentials.
+    pub url: String,
+    pub status: Option<u16>,
+    pub duration: Duration,
+}
+
+/// Pulls taking longer than this are logged with the timing of each of their requests, to
+/// catch vendors degrading before pulls start timing out. Defaults to 60 seconds.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     slow_pull_threshold_seconds: 30
+/// ```
+pub(crate) fn slow_pull_threshold(config: &HashMap<String, String>) -> Result<Duration> {
+    let secs = match config.get("slow_pull_threshold_seconds") {
+        Some(v) => v
+            .trim()
+            .parse::<f64>()
+            .context("slow_pull_threshold_seconds must be a number")?,
+        None => DEFAULT_SLOW_PULL_THRESHOLD_SECS,
+    };
+    if !secs.is_finite() || secs <= 0.0 {
+        return Err(anyhow!("slow_pull_threshold_seconds must be positive"));
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Records how long a pull took, and warns with the breakdown of its requests if it was slow.
+pub fn record_pull_duration(ctx: &PullLogsContext, duration: Duration) {
+    let timings = ctx.take_request_timings();
+    let millis = duration.as_millis() as u64;
+    let is_slow = duration > ctx.slow_pull_threshold;
+
+    {
+        let mut recent = RECENT_DURATIONS.lock().unwrap();
+        let durations = recent.entry(ctx.log_source_name.clone()).or_default();
+        if durations.len() == DURATION_WINDOW {
+            durations.pop_front();
+        }
+        durations.push_back(millis);
+    }
+    {
+        let mut invocation = INVOCATION_DURATIONS.lock().unwrap();
+        let (durations, slow_pulls) = invocation.entry(ctx.log_source_name.clone()).or_default();
+        durations.push(millis);
+        if is_slow {
+            *slow_pulls += 1;
+        }
+    }
+
+    if is_slow {
+        let request_time = timings.iter().map(|t| t.duration).sum::<Duration>();
+        let breakdown = timings
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let status = t.status.map_or("error".to_string(), |s| s.to_string());
+                format!(
+                    "#{} {} {} -> {} in {:?}",
+                    i + 1,
+                    t.method,
+                    t.url,
+                    status,
+                    t.duration
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        warn!(
+            "Slow pull for {}: took {:?} (threshold {:?}), {} requests took {:?}, other work {:?}: [{}]",
+            ctx.log_source_name,
+            duration,
+            ctx.slow_pull_threshold,
+            timings.len(),
+            request_time,
+            duration.saturating_sub(request_time),
+            breakdown
+        );
+    }
+}
+
+/// Emits the pull durations of this invocation, with their p50 and p95 over the recent pulls of
+/// each log source, in CloudWatch embedded metric format.
+pub fn emit_latency_metrics() {
+    let invocation = std::mem::take(&mut *INVOCATION_DURATIONS.lock().unwrap());
+    let recent = RECENT_DURATIONS.lock().unwrap();
+    for (log_source, (durations, slow_pulls)) in invocation {
+        let mut sorted = recent
+            .get(&log_source)
+            .map(|d| d.iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        sorted.sort_unstable();
+        let metric = json!({
+            "_aws": {
+                "Timestamp": chrono::Utc::now().timestamp_millis(),
+                "CloudWatchMetrics": [{
+                    "Namespace": METRICS_NAMESPACE,
+                    "Dimensions": [["log_source"]],
+                    "Metrics": [
+                        {"Name": "PullDuration", "Unit": "Milliseconds"},
+                        {"Name": "PullDurationP50", "Unit": "Milliseconds"},
+                        {"Name": "PullDurationP95", "Unit": "Milliseconds"},
+                        {"Name": "SlowPulls", "Unit": "Count"},
+                    ],
+                }],
+            },
+            "log_source": log_source,
+            "PullDuration": durations,
+            "PullDurationP50": percentile(&sorted, 50),
+            "PullDurationP95": percentile(&sorted, 95),
+            "SlowPulls": slow_pulls,
+        });
+        // Embedded metrics must be their own log line, not wrapped by the log formatter.
+        println!("{}", metric);
+    }
+}
+
+/// Nearest rank percentile of sorted values.
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p * sorted.len() + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
@@ -1,28 +1,127 @@
-use std::{collections::HashMap, sync::atomic::AtomicBool};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::{collections::HashMap, time::Instant};
 
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use aws_sdk_s3::types::ByteStream;
 use chrono::{DateTime, FixedOffset};
 use enum_dispatch::enum_dispatch;
-use log::{debug, error, info};
+use futures::{Stream, StreamExt};
+use reqwest::StatusCode;
 use serde_json::Value;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{debug, error, field, info, info_span, warn, Instrument};
 
-use shared::secrets::load_secret;
+use shared::secrets::{load_secret, load_secret_versioned};
+use shared::zstd_dictionary::ZstdDictionary;
+
+pub use audit::{flush_pull_attempts, record_pull_attempt, PullAttempt, PullOutcome};
+pub use catch_up::{
+    delay_retry, enqueue_catch_up, enqueue_continuation, is_fifo_queue, requeue,
+    CATCH_UP_WINDOW_MINUTES,
+};
+pub use checkpoint::Checkpointer;
+pub use circuit_breaker::CircuitBreaker;
+use connection::HttpConnectionOptions;
+pub use csv_records::{CsvColumn, CsvMapping, CsvType};
+pub use dead_letter::{is_final_attempt, send_to_dlq, PullFailure};
+pub use deadline::set_invocation_deadline;
+use debug::DebugOptions;
+use dedup::Deduplicator;
+pub use errors::{PullerError, RetryAction, DELAYED_RETRY_SECONDS};
+pub use eventbridge::EventBridgeOutput;
+pub use kinesis::KinesisOutput;
+pub use latency::{emit_latency_metrics, record_pull_duration, RequestTiming};
+pub use parquet_writer::ParquetOutput;
+pub use payload::add_json_field;
+pub use pre_transform::PreTransform;
+pub use properties::{validate_properties, PropertySpec, PropertyType};
+pub use rate_limit::RateLimitStats;
+pub use redact::Redaction;
+pub use registry::{register_puller, DynPullLogs};
+use request_budget::RequestBudget;
+pub use retry::RetryPolicy;
+pub use routing::TableRouting;
+pub use schema::RecordSchema;
+pub use spill::{spill_threshold, SpillBuffer};
+pub use stats::PullStats;
+pub use timeouts::HttpTimeouts;
+use token_bucket::DistributedTokenBucket;
+pub(crate) use vault::VaultSecrets;
+#[cfg(feature = "wasm")]
+pub use wasm::WasmPuller;
+pub use xml_records::XmlMapping;
 
 mod abusech;
 mod amazon_inspector;
+mod audit;
+mod aws_findings;
+mod aws_role;
+mod azure_blob;
+mod catch_up;
+mod checkpoint;
+mod circuit_breaker;
+mod connection;
+mod csv_records;
+mod custom_api;
+mod dead_letter;
+mod deadline;
+mod debug;
+mod dedup;
 mod duo;
+mod elasticsearch;
+mod errors;
+mod eventbridge;
+/// In-house pullers, added with the `extensions` feature, see `registry`.
+#[cfg(feature = "extensions")]
+pub mod extensions;
+mod external_s3;
+mod gcs;
+mod google_auth;
 mod google_workspace;
+mod graphql;
+mod imap;
+mod kafka;
+mod kinesis;
+mod latency;
 mod msft;
 mod o365;
+mod oauth2;
 mod okta;
 mod onepassword;
 mod otx;
+mod pagination;
+mod parquet_writer;
+mod payload;
+mod pre_transform;
+mod properties;
+mod quarantine;
+mod rate_limit;
+mod record_decoder;
+mod redact;
+mod registry;
+mod request_budget;
+mod retry;
+mod routing;
+mod schema;
+mod signing;
+mod sigv4;
 mod snyk;
+mod spill;
+mod splunk;
 mod cisa_kev;
+mod sql;
+mod stats;
+mod timeouts;
+mod token_bucket;
+mod vault;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod xml_records;
+
+/// Default for how long a loaded secret is reused, see `PullLogsContext::secret_cache_ttl`.
+const DEFAULT_SECRET_CACHE_TTL_SECS: u64 = 300;
 
 #[derive(Clone)]
 pub struct PullerCache {
@@ -46,95 +145,334 @@ impl PullerCache {
         let expiry = (chrono::Utc::now() + duration).timestamp();
         self.cache.insert(k.to_string(), (v, expiry));
     }
+
+    pub fn remove(&mut self, k: &str) {
+        self.cache.remove(k);
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
 }
 
 pub struct PullLogsContext {
     pub log_source_name: String,
-    secret_cache: Arc<Mutex<Option<HashMap<String, String>>>>,
+    /// Set when the log source has multiple tenants, each with its own context.
+    pub tenant_id: Option<String>,
+    secret_cache: Arc<Mutex<Option<(HashMap<String, String>, Instant)>>>,
+    /// Set when the secret is known to be outdated, e.g. after an auth failure.
+    secret_stale: Arc<AtomicBool>,
+    /// None for log sources without credentials, e.g. public feeds configured with
+    /// `managed.secret: false`, whose secret fields are all missing.
     secret_arn: Option<String>,
+    /// Secret fields given directly, e.g. when running a puller locally, instead of the secret.
+    local_secrets: Option<HashMap<String, String>>,
+    /// Requests are sent to this server instead, keeping their path and query, e.g. a mock
+    /// server when running a puller locally.
+    redirect_url: Option<reqwest::Url>,
+    /// Read instead of the secret if set, see `VaultSecrets`.
+    vault: Option<Arc<VaultSecrets>>,
     pub log_source_type: LogSource,
     config: HashMap<String, String>,
     tables_config: HashMap<String, config::Config>,
+    /// Access tokens, reused by warm invocations as contexts are built once per Lambda. Cleared
+    /// on auth errors.
     cache: Arc<Mutex<PullerCache>>,
+    /// Held while requesting an access token, so concurrent pulls wait for one token request
+    /// instead of each sending their own, see `oauth2::cached_token`.
+    token_requests: Arc<Mutex<()>>,
     s3: aws_sdk_s3::Client,
     pub checkpoint_json: Arc<Mutex<Option<Value>>>,
+    checkpointer: Checkpointer,
+    signer: Arc<Mutex<Option<Arc<dyn signing::RequestSigner>>>>,
+    http_client: Arc<Mutex<Option<reqwest::Client>>>,
+    rate_limiter: Arc<rate_limit::RateLimiter>,
+    /// Limits requests per day, from the `daily_request_budget` property.
+    request_budget: Option<Arc<RequestBudget>>,
+    pub retry_policy: RetryPolicy,
+    http_timeouts: HttpTimeouts,
+    http_connection: HttpConnectionOptions,
+    circuit_breaker: CircuitBreaker,
+    dedup: Option<Deduplicator>,
+    debug: DebugOptions,
+    /// Compresses uploaded objects, if the log source has one, see `shared::zstd_dictionary`.
+    zstd_dictionary: Option<Arc<ZstdDictionary>>,
+    /// Run on pulled records, if set, see `PreTransform`.
+    pre_transform: Option<PreTransform>,
+    /// Pulled records are validated against it, if set, see `RecordSchema`.
+    record_schema: Option<Arc<RecordSchema>>,
+    /// Set when a request got a 401/403, reset before each pull.
+    auth_failed: Arc<AtomicBool>,
+    /// Status of the last error response, 0 if none, reset before each pull.
+    last_error_status: Arc<AtomicU16>,
+    /// Whether the stored failure count may be non zero, to avoid a reset write after every pull.
+    circuit_breaker_dirty: Arc<AtomicBool>,
+    /// Limits concurrent pulls of the log source, from the `max_concurrent_pulls` property.
+    pull_limit: Option<Arc<Semaphore>>,
+    /// Set by pullers that stopped early and saved where to resume in the checkpoint.
+    continuation_requested: Arc<AtomicBool>,
+    /// Requests of the current pull, reset before each pull.
+    request_timings: Arc<std::sync::Mutex<Vec<RequestTiming>>>,
+    /// Pulls taking longer are logged with their request timings, see `slow_pull_threshold`.
+    slow_pull_threshold: std::time::Duration,
 }
 
 impl PullLogsContext {
     pub fn new(
         log_source_name: String,
+        tenant_id: Option<String>,
         secret_arn: Option<String>,
         log_source_type: LogSource,
         config: HashMap<String, String>,
         tables_config: HashMap<String, config::Config>,
         s3: aws_sdk_s3::Client,
     ) -> PullLogsContext {
+        let retry_policy = RetryPolicy::from_config(&config).unwrap_or_else(|e| {
+            error!(
+                "Invalid retry config for {}, using defaults: {:#}",
+                log_source_name, e
+            );
+            RetryPolicy::default()
+        });
+        let http_timeouts = HttpTimeouts::from_config(&config).unwrap_or_else(|e| {
+            error!(
+                "Invalid HTTP timeout config for {}, using defaults: {:#}",
+                log_source_name, e
+            );
+            HttpTimeouts::default()
+        });
+        let http_connection = HttpConnectionOptions::from_config(&config).unwrap_or_else(|e| {
+            error!(
+                "Invalid HTTP connection config for {}, using defaults: {:#}",
+                log_source_name, e
+            );
+            HttpConnectionOptions::default()
+        });
+        let circuit_breaker = CircuitBreaker::from_config(&config).unwrap_or_else(|e| {
+            error!(
+                "Invalid circuit breaker config for {}, using defaults: {:#}",
+                log_source_name, e
+            );
+            CircuitBreaker::from_config(&HashMap::new()).unwrap()
+        });
+        let shared_budget = DistributedTokenBucket::from_config(&log_source_name, &config)
+            .unwrap_or_else(|e| {
+                error!(
+                    "Invalid rate limit config for {}, ignoring: {:#}",
+                    log_source_name, e
+                );
+                None
+            });
+        let request_budget = RequestBudget::from_config(&config)
+            .unwrap_or_else(|e| {
+                error!(
+                    "Invalid request budget for {}, ignoring: {:#}",
+                    log_source_name, e
+                );
+                None
+            })
+            .map(Arc::new);
+        let dedup = Deduplicator::from_config(&config).unwrap_or_else(|e| {
+            error!(
+                "Invalid dedup config for {}, ignoring: {:#}",
+                log_source_name, e
+            );
+            None
+        });
+        let debug = DebugOptions::from_config(&log_source_name, &config).unwrap_or_else(|e| {
+            error!(
+                "Invalid debug config for {}, ignoring: {:#}",
+                log_source_name, e
+            );
+            DebugOptions::default()
+        });
+        let slow_pull_threshold = latency::slow_pull_threshold(&config).unwrap_or_else(|e| {
+            error!(
+                "Invalid slow pull threshold for {}, using default: {:#}",
+                log_source_name, e
+            );
+            latency::slow_pull_threshold(&HashMap::new()).unwrap()
+        });
+        let zstd_dictionary = shared::zstd_dictionary::load(&log_source_name).unwrap_or_else(|e| {
+            error!(
+                "Invalid zstd dictionary for {}, ignoring: {:#}",
+                log_source_name, e
+            );
+            None
+        });
+        let record_schema = RecordSchema::from_config(&log_source_name, &config)
+            .unwrap_or_else(|e| {
+                error!(
+                    "Invalid record schema for {}, ignoring: {:#}",
+                    log_source_name, e
+                );
+                None
+            })
+            .map(Arc::new);
+        let pre_transform = PreTransform::from_config(&config).unwrap_or_else(|e| {
+            error!(
+                "Invalid pre_transform for {}, ignoring: {:#}",
+                log_source_name, e
+            );
+            None
+        });
+        let vault = VaultSecrets::from_config(&config)
+            .unwrap_or_else(|e| {
+                error!(
+                    "Invalid Vault config for {}, ignoring: {:#}",
+                    log_source_name, e
+                );
+                None
+            })
+            .map(Arc::new);
+        let checkpointer = Checkpointer::new(s3.clone());
         PullLogsContext {
             log_source_name,
+            tenant_id,
             secret_cache: Arc::new(Mutex::new(None)),
+            secret_stale: Arc::new(AtomicBool::new(false)),
             secret_arn,
+            local_secrets: None,
+            redirect_url: None,
+            vault,
             log_source_type,
             config,
             tables_config,
             cache: Arc::new(Mutex::new(PullerCache::new())),
+            token_requests: Arc::new(Mutex::new(())),
             s3,
             checkpoint_json: Arc::new(Mutex::new(None)),
+            checkpointer,
+            signer: Arc::new(Mutex::new(None)),
+            http_client: Arc::new(Mutex::new(None)),
+            rate_limiter: Arc::new(rate_limit::RateLimiter::new(shared_budget)),
+            request_budget,
+            retry_policy,
+            http_timeouts,
+            http_connection,
+            circuit_breaker,
+            dedup,
+            debug,
+            zstd_dictionary,
+            pre_transform,
+            record_schema,
+            auth_failed: Arc::new(AtomicBool::new(false)),
+            last_error_status: Arc::new(AtomicU16::new(0)),
+            circuit_breaker_dirty: Arc::new(AtomicBool::new(true)),
+            pull_limit: None,
+            continuation_requested: Arc::new(AtomicBool::new(false)),
+            request_timings: Arc::new(std::sync::Mutex::new(vec![])),
+            slow_pull_threshold,
         }
     }
 
+    pub fn with_pull_limit(mut self, pull_limit: Option<Arc<Semaphore>>) -> PullLogsContext {
+        self.pull_limit = pull_limit;
+        self
+    }
+
+    pub fn pull_limit(&self) -> Option<&Arc<Semaphore>> {
+        self.pull_limit.as_ref()
+    }
+
+    pub fn with_local_secrets(mut self, secrets: HashMap<String, String>) -> PullLogsContext {
+        self.local_secrets = Some(secrets);
+        self
+    }
+
+    pub fn with_redirect_url(mut self, redirect_url: reqwest::Url) -> PullLogsContext {
+        self.redirect_url = Some(redirect_url);
+        self
+    }
+
     pub async fn get_secret_field(&self, key: &str) -> Result<Option<String>> {
-        if self.secret_arn.is_none() {
+        if let Some(secrets) = self.local_secrets.as_ref() {
+            return Ok(secrets.get(key).cloned());
+        }
+        if self.secret_arn.is_none() && self.vault.is_none() {
             return Ok(None);
         }
-        let secret_arn = self.secret_arn.as_ref().unwrap();
 
-        let secret_cache_ref = self.secret_cache.clone();
-        let mut secret_cache_opt = secret_cache_ref.lock().await;
-        let secrets_val = if secret_cache_opt.as_ref().is_none() {
-            let secrets = load_secret(secret_arn.clone()).await?;
-            let sec_val = secrets.get(key).cloned();
-            if let Some(v) = sec_val.as_ref() {
-                if !v.contains("placeholder") {
-                    *secret_cache_opt = Some(secrets);
-                }
-            }
-            sec_val
-        } else {
-            secret_cache_opt
+        let mut secret_cache = self.secret_cache.lock().await;
+        let is_fresh = secret_cache.as_ref().map_or(false, |(_, loaded_at)| {
+            loaded_at.elapsed() < self.secret_cache_ttl()
+        });
+        if is_fresh && !self.secret_stale.load(Ordering::SeqCst) {
+            return Ok(secret_cache
                 .as_ref()
-                .and_then(|v| v.get(key).map(|v| v.clone()))
-        };
+                .and_then(|(secrets, _)| secrets.get(key).cloned()));
+        }
 
-        Ok(secrets_val)
+        // Bypass the shared (time based) cache too if the secret is known to be outdated.
+        let span = info_span!("secret_fetch", log_source = %self.log_source_name);
+        let secrets = async {
+            let is_stale = self.secret_stale.swap(false, Ordering::SeqCst);
+            if let Some(vault) = self.vault.as_ref() {
+                return vault.read_fields().await;
+            }
+            let secret_arn = self.secret_arn.as_ref().unwrap();
+            if is_stale {
+                anyhow::Ok(load_secret_versioned(secret_arn).await?.fields)
+            } else {
+                load_secret(secret_arn.clone()).await
+            }
+        }
+        .instrument(span)
+        .await?;
+        let sec_val = secrets.get(key).cloned();
+        if let Some(v) = sec_val.as_ref() {
+            if !v.contains("placeholder") {
+                *secret_cache = Some((secrets, Instant::now()));
+            }
+        }
+
+        Ok(sec_val)
     }
 
-    /// Returns true if a checkpoint was loaded, false if this is the initial run. Useful for e.g. pulling more logs on first run.
-    pub async fn load_checkpoint(&self) -> Result<bool> {
-        let bucket = std::env::var("INGESTION_BUCKET_NAME").context("need bucket!")?;
+    /// How long a loaded secret is reused before reading it again, so rotated secrets are picked
+    /// up by warm Lambdas. Set with the `secret_cache_ttl_seconds` property or `PULLER_SECRET_CACHE_TTL_SECS`.
+    fn secret_cache_ttl(&self) -> std::time::Duration {
+        let ttl_secs = self
+            .config
+            .get("secret_cache_ttl_seconds")
+            .cloned()
+            .or_else(|| std::env::var("PULLER_SECRET_CACHE_TTL_SECS").ok())
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(DEFAULT_SECRET_CACHE_TTL_SECS);
+        std::time::Duration::from_secs(ttl_secs)
+    }
 
-        let initial_run_key = "__puller_last_run_checkpoint__";
-        let s3_key = format!("{}/{}.json", initial_run_key, self.log_source_name);
+    /// Drops the loaded secret, so the next `get_secret_field` reads the latest version, e.g. after
+    /// writing to it or an auth failure.
+    pub async fn clear_secret_cache(&self) {
+        *self.secret_cache.lock().await = None;
+        self.secret_stale.store(true, Ordering::SeqCst);
+    }
 
-        let res = self
-            .s3
-            .get_object()
-            .bucket(&bucket)
-            .key(&s3_key)
-            .send()
-            .await;
-        let checkpoint_json = match res {
-            Ok(output) => Ok(Some(
-                serde_json::from_slice(&output.body.collect().await?.into_bytes())
-                    .context("failed to parse last checkpoint file as json")?,
-            )),
-            Err(e) => {
-                let se = e.into_service_error();
-                match se.kind {
-                    aws_sdk_s3::error::GetObjectErrorKind::NoSuchKey(_) => Ok(None),
-                    _ => Err(se),
-                }
-            }
-        }?;
+    /// The loaded secret values, redacted from debug logs.
+    async fn known_secrets(&self) -> Vec<String> {
+        if let Some(secrets) = self.local_secrets.as_ref() {
+            return secrets.values().cloned().collect();
+        }
+        self.secret_cache
+            .lock()
+            .await
+            .as_ref()
+            .map(|(secrets, _)| secrets.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Tenants of the same log source are checkpointed separately.
+    fn checkpoint_name(&self) -> String {
+        match self.tenant_id.as_ref() {
+            Some(tenant_id) => format!("{}/{}", self.log_source_name, tenant_id),
+            None => self.log_source_name.clone(),
+        }
+    }
+
+    /// Returns true if a checkpoint was loaded, false if this is the initial run. Useful for e.g. pulling more logs on first run.
+    pub async fn load_checkpoint(&self) -> Result<bool> {
+        let checkpoint_json = self.checkpointer.load(&self.checkpoint_name()).await?;
 
         if checkpoint_json.is_none() {
             info!(
@@ -149,18 +487,12 @@ impl PullLogsContext {
     }
 
     pub async fn upload_checkpoint(&self, checkpoint_json: &Value) -> Result<()> {
-        let bucket = std::env::var("INGESTION_BUCKET_NAME").context("need bucket!")?;
-
-        let initial_run_key = "__puller_last_run_checkpoint__";
-        let s3_key = format!("{}/{}.json", initial_run_key, self.log_source_name);
-
-        // write checkpoint to s3
-        self.s3
-            .put_object()
-            .bucket(&bucket)
-            .key(&s3_key)
-            .body(ByteStream::from(serde_json::to_vec(checkpoint_json)?))
-            .send()
+        let name = self.checkpoint_name();
+        let name = &name;
+        self.retry_policy
+            .retry("Checkpoint upload", || async move {
+                self.checkpointer.save(name, checkpoint_json).await
+            })
             .await?;
 
         // sync local checkpoint state
@@ -169,6 +501,58 @@ impl PullLogsContext {
         Ok(())
     }
 
+    /// End of the last successfully pulled window, if tracked.
+    pub async fn last_pulled_at(&self) -> Result<Option<DateTime<FixedOffset>>> {
+        self.checkpointer
+            .last_pulled_at(&self.checkpoint_name())
+            .await
+    }
+
+    pub async fn set_last_pulled_at(&self, dt: DateTime<FixedOffset>) -> Result<()> {
+        self.checkpointer
+            .set_last_pulled_at(&self.checkpoint_name(), dt)
+            .await
+    }
+
+    /// Hash of the last uploaded snapshot, for `skip_unchanged_snapshots`.
+    pub async fn snapshot_hash(&self) -> Result<Option<String>> {
+        self.checkpointer
+            .snapshot_hash(&self.checkpoint_name())
+            .await
+    }
+
+    pub async fn set_snapshot_hash(&self, hash: &str) -> Result<()> {
+        self.checkpointer
+            .set_snapshot_hash(&self.checkpoint_name(), hash)
+            .await
+    }
+
+    /// Claims the pull of a window, returns false if it's a duplicate delivery.
+    pub async fn claim_pull(
+        &self,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<bool> {
+        self.checkpointer
+            .claim_pull(&self.checkpoint_name(), &pull_window_key(start_dt, end_dt))
+            .await
+    }
+
+    pub async fn finish_pull(
+        &self,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+        succeeded: bool,
+    ) -> Result<()> {
+        self.checkpointer
+            .finish_pull(
+                &self.checkpoint_name(),
+                &pull_window_key(start_dt, end_dt),
+                succeeded,
+            )
+            .await
+    }
+
     pub fn config(&self) -> &HashMap<String, String> {
         &self.config
     }
@@ -180,11 +564,714 @@ impl PullLogsContext {
     pub fn cache(&self) -> Arc<Mutex<PullerCache>> {
         self.cache.clone()
     }
+
+    pub(crate) fn token_requests(&self) -> Arc<Mutex<()>> {
+        self.token_requests.clone()
+    }
+
+    pub fn zstd_dictionary(&self) -> Option<&Arc<ZstdDictionary>> {
+        self.zstd_dictionary.as_ref()
+    }
+
+    /// Returns the HTTP client of this log source, built once and reused so its connections are
+    /// pooled across pulls. Connection settings are described in `HttpConnectionOptions`, the
+    /// other supported settings are:
+    ///
+    /// - secret `client_cert` (+ optional `client_key`): PEM client certificate for mutual TLS.
+    /// - secret `ca_cert` or property `tls_ca_cert`: PEM root CA(s) to trust, e.g. for appliances with private CAs.
+    /// - property `tls_min_version`: `"1.2"` or `"1.3"`.
+    /// - property `tls_insecure_skip_verify`: `"true"` disables certificate verification, for lab use only.
+    /// - property `proxy_url` (+ optional `proxy_username` and secret `proxy_password`): egress proxy
+    ///   for this log source, instead of the global `PULLER_PROXY_URL`.
+    pub async fn http_client(&self) -> Result<reqwest::Client> {
+        let mut http_client = self.http_client.lock().await;
+        if let Some(client) = http_client.as_ref() {
+            return Ok(client.clone());
+        }
+
+        let client = self.build_client().await?;
+        *http_client = Some(client.clone());
+        Ok(client)
+    }
+
+    async fn build_client(&self) -> Result<reqwest::Client> {
+        let secret_field = |key: &'static str| async move {
+            self.get_secret_field(key)
+                .await
+                .map(|v| v.filter(|v| v != "<placeholder>"))
+        };
+
+        let client_cert = secret_field("client_cert").await?;
+        let ca_cert = match secret_field("ca_cert").await? {
+            Some(ca_cert) => Some(ca_cert),
+            None => self.config.get("tls_ca_cert").cloned(),
+        };
+        let min_tls_version = match self.config.get("tls_min_version").map(|s| s.trim()) {
+            None => None,
+            Some("1.2") => Some(reqwest::tls::Version::TLS_1_2),
+            Some("1.3") => Some(reqwest::tls::Version::TLS_1_3),
+            Some(v) => return Err(anyhow!("Unsupported tls_min_version: {}", v)),
+        };
+        let insecure = self
+            .config
+            .get("tls_insecure_skip_verify")
+            .map_or(false, |v| v.trim() == "true");
+
+        let proxy = match self.config.get("proxy_url") {
+            Some(proxy_url) => {
+                let mut proxy =
+                    reqwest::Proxy::all(proxy_url.trim()).context("Invalid proxy_url")?;
+                if let Some(username) = self.config.get("proxy_username") {
+                    let password = secret_field("proxy_password")
+                        .await?
+                        .context("Missing proxy_password")?;
+                    proxy = proxy.basic_auth(username, &password);
+                }
+                Some(proxy)
+            }
+            None => None,
+        };
+
+        let mut builder = self
+            .http_connection
+            .apply(reqwest::Client::builder().use_rustls_tls());
+        match proxy {
+            Some(proxy) => builder = builder.proxy(proxy),
+            None => {
+                if let Some(proxy) = global_proxy()? {
+                    builder = builder.proxy(proxy);
+                }
+            }
+        }
+        if let Some(client_cert) = client_cert {
+            // The key can be included in `client_cert` or stored separately.
+            let mut identity_pem = client_cert.into_bytes();
+            if let Some(key) = secret_field("client_key").await? {
+                identity_pem.push(b'\n');
+                identity_pem.extend(key.into_bytes());
+            }
+            let identity = reqwest::Identity::from_pem(&identity_pem)
+                .context("Invalid client_cert/client_key, must be PEM encoded")?;
+            builder = builder.identity(identity);
+        }
+        if let Some(ca_cert) = ca_cert {
+            let ca_cert = reqwest::Certificate::from_pem(ca_cert.as_bytes())
+                .context("Invalid CA certificate, must be PEM encoded")?;
+            builder = builder.add_root_certificate(ca_cert);
+        }
+        if let Some(timeout) = self.http_timeouts.request {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.http_timeouts.connect {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(version) = min_tls_version {
+            builder = builder.min_tls_version(version);
+        }
+        if insecure {
+            warn!(
+                "TLS certificate verification is disabled for {}",
+                self.log_source_name
+            );
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        debug!(
+            "Built HTTP client for {}: {:?}",
+            self.log_source_name, self.http_connection
+        );
+        Ok(builder.build()?)
+    }
+
+    /// Sets the signer applied by `send` to every request of this log source.
+    pub(crate) async fn set_signer(&self, signer: Option<Arc<dyn signing::RequestSigner>>) {
+        *self.signer.lock().await = signer;
+    }
+
+    /// Sends the request, signing it first if the puller set a signer. Auth failures mark the
+    /// secret as outdated. Pullers send their API requests with this, so the retry policy, rate
+    /// limits and request budget apply to all of them.
+    ///
+    /// Each request (e.g. an API page), including its retries, is traced as an `api_request` span.
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let request = request.build()?;
+        let mut url = request.url().clone();
+        url.set_query(None);
+        let span = info_span!(
+            "api_request",
+            log_source = %self.log_source_name,
+            method = %request.method(),
+            url = %url,
+            status = field::Empty,
+        );
+        let method = request.method().to_string();
+        let started_at = Instant::now();
+        let res = self
+            .send_with_retries(client, request)
+            .instrument(span.clone())
+            .await;
+        if let Ok(res) = res.as_ref() {
+            span.record("status", res.status().as_u16());
+        }
+        self.request_timings.lock().unwrap().push(RequestTiming {
+            method,
+            url: url.to_string(),
+            status: res.as_ref().ok().map(|r| r.status().as_u16()),
+            duration: started_at.elapsed(),
+        });
+        res
+    }
+
+    async fn send_with_retries(
+        &self,
+        client: &reqwest::Client,
+        mut request: reqwest::Request,
+    ) -> Result<reqwest::Response> {
+        let mut rate_limit_attempt = 0;
+        let mut attempt = 0;
+        loop {
+            self.rate_limiter
+                .wait_for_reset(&self.log_source_name)
+                .await?;
+            self.take_request_budget()?;
+
+            // Streaming bodies can't be cloned, those requests aren't retried.
+            let retry_request = request.try_clone();
+            let can_retry = retry_request.is_some() && attempt + 1 < self.retry_policy.max_attempts;
+            let res = match self.execute(client, request).await {
+                Ok(res) => res,
+                Err(e) => {
+                    let is_retryable = e.is::<tokio::time::error::Elapsed>()
+                        || e.downcast_ref::<reqwest::Error>()
+                            .map_or(false, |err| self.retry_policy.is_retryable_error(err));
+                    match retry_request {
+                        Some(retry_request) if can_retry && is_retryable => {
+                            let delay = self.retry_policy.delay(attempt);
+                            warn!(
+                                "Request failed for {}, retrying in {:?}: {:#}",
+                                self.log_source_name, delay, e
+                            );
+                            tokio::time::sleep(delay).await;
+                            request = retry_request;
+                            attempt += 1;
+                            continue;
+                        }
+                        _ => return Err(e),
+                    }
+                }
+            };
+            self.rate_limiter.observe(res.headers()).await;
+
+            if res.status() == StatusCode::TOO_MANY_REQUESTS
+                && rate_limit_attempt < rate_limit::MAX_RATE_LIMIT_RETRIES
+            {
+                if let Some(retry_request) = retry_request {
+                    self.rate_limiter
+                        .wait_for_retry(res.headers(), rate_limit_attempt, &self.log_source_name)
+                        .await?;
+                    request = retry_request;
+                    rate_limit_attempt += 1;
+                    continue;
+                }
+            }
+
+            if can_retry && self.retry_policy.is_retryable_status(res.status()) {
+                let delay = self.retry_policy.delay(attempt);
+                warn!(
+                    "Got {} for {}, retrying in {:?}",
+                    res.status(),
+                    self.log_source_name,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                request = retry_request.unwrap();
+                attempt += 1;
+                continue;
+            }
+
+            // The credentials may have been rotated or the access tokens revoked, make sure the
+            // next request uses the latest ones instead of what's cached by the warm Lambda.
+            if res.status() == StatusCode::UNAUTHORIZED || res.status() == StatusCode::FORBIDDEN {
+                info!(
+                    "Got {} for {}, reloading secret and access tokens on next use",
+                    res.status(),
+                    self.log_source_name
+                );
+                self.clear_secret_cache().await;
+                self.cache.lock().await.clear();
+                self.auth_failed.store(true, Ordering::SeqCst);
+            }
+            if res.status().is_client_error() || res.status().is_server_error() {
+                self.last_error_status
+                    .store(res.status().as_u16(), Ordering::SeqCst);
+            }
+            if res.status() == StatusCode::TOO_MANY_REQUESTS {
+                self.rate_limiter.observe_rate_limited(res.headers());
+            }
+
+            return Ok(res);
+        }
+    }
+
+    /// Signs (each attempt of) a request if a signer is set, and sends it. Requests and
+    /// responses are logged if debug logging is enabled, see `DebugOptions`.
+    async fn execute(
+        &self,
+        client: &reqwest::Client,
+        mut request: reqwest::Request,
+    ) -> Result<reqwest::Response> {
+        let signer = self.signer.lock().await.clone();
+        if let Some(signer) = signer {
+            signer.sign(&mut request).await?;
+        }
+        // Redirected after signing, so requests are signed as they would be for the vendor.
+        if let Some(redirect_url) = self.redirect_url.as_ref() {
+            let url = request.url_mut();
+            url.set_scheme(redirect_url.scheme())
+                .map_err(|_| anyhow!("Can't redirect {} to {}", url, redirect_url))?;
+            url.set_host(redirect_url.host_str())?;
+            url.set_port(redirect_url.port())
+                .map_err(|_| anyhow!("Can't redirect {} to {}", url, redirect_url))?;
+        }
+        if !self.debug.verbose {
+            return self.execute_with_timeout(client, request).await;
+        }
+
+        let secrets = self.known_secrets().await;
+        info!(
+            "Request for {}: {} {}, headers: [{}], body: {}",
+            self.log_source_name,
+            request.method(),
+            debug::redact_text(request.url().as_str(), &secrets),
+            debug::redact_headers(request.headers(), &secrets),
+            request
+                .body()
+                .and_then(|b| b.as_bytes())
+                .map_or("<none>".to_string(), |b| debug::redact_body(b, &secrets)),
+        );
+        let started_at = Instant::now();
+        let res = self.execute_with_timeout(client, request).await;
+        match res.as_ref() {
+            Ok(res) => info!(
+                "Response for {}: {} in {:?}, headers: [{}]",
+                self.log_source_name,
+                res.status(),
+                started_at.elapsed(),
+                debug::redact_headers(res.headers(), &secrets),
+            ),
+            Err(e) => info!(
+                "Request for {} failed after {:?}: {}",
+                self.log_source_name,
+                started_at.elapsed(),
+                debug::redact_text(&format!("{:#}", e), &secrets),
+            ),
+        }
+        res
+    }
+
+    async fn execute_with_timeout(
+        &self,
+        client: &reqwest::Client,
+        request: reqwest::Request,
+    ) -> Result<reqwest::Response> {
+        match self.http_timeouts.read {
+            Some(read_timeout) => {
+                let url = request.url().clone();
+                let res = tokio::time::timeout(read_timeout, client.execute(request))
+                    .await
+                    .with_context(|| {
+                        format!("No response from {} after {:?}", url, read_timeout)
+                    })?;
+                Ok(res?)
+            }
+            None => Ok(client.execute(request).await?),
+        }
+    }
+
+    /// Reads a response body, decoding gzip/deflate `Content-Encoding`. The body is logged or
+    /// sampled if enabled, see `DebugOptions`.
+    pub async fn read_body(&self, res: reqwest::Response) -> Result<Vec<u8>> {
+        let encoding = res
+            .headers()
+            .get(reqwest::header::CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let source = response_source(&res);
+        let body = res.bytes().await?.to_vec();
+        let body = payload::decode_content_encoding(encoding.as_deref(), body)?;
+        if self.debug.verbose {
+            let secrets = self.known_secrets().await;
+            info!(
+                "Response body from {} for {}: {}",
+                source,
+                self.log_source_name,
+                debug::redact_body(&body, &secrets)
+            );
+        }
+        if self.debug.sample_payload() {
+            let res = debug::write_payload_sample(
+                &self.log_source_name,
+                self.tenant_id.as_deref(),
+                &source,
+                &body,
+            )
+            .await;
+            if let Err(e) = res {
+                warn!("{:#}", e);
+            }
+        }
+        Ok(body)
+    }
+
+    /// Reads a response body as NDJSON records, expanding compressed and zip archive payloads
+    /// (e.g. Mimecast or Salesforce event log files) and converting CSV/JSON files within.
+    pub async fn response_to_ndjson(&self, res: reqwest::Response) -> Result<Vec<u8>> {
+        let name = payload::response_file_name(&res);
+        let source = response_source(&res);
+        let body = self.read_body(res).await?;
+        let mut ret = vec![];
+        let res = payload::payload_to_ndjson(&name, &body, &mut ret)
+            .with_context(|| format!("Failed to read records from {}", name));
+        if let Err(e) = res {
+            return Err(self.quarantine(&source, &body, e).await);
+        }
+        Ok(ret)
+    }
+
+    /// Reads a CSV response body as NDJSON records typed by `mapping`, see `CsvMapping`.
+    pub async fn response_csv_to_ndjson(
+        &self,
+        res: reqwest::Response,
+        mapping: &CsvMapping,
+    ) -> Result<Vec<u8>> {
+        let source = response_source(&res);
+        let body = self.read_body(res).await?;
+        let mut ret = vec![];
+        if let Err(e) = mapping.to_ndjson(&body, &mut ret) {
+            return Err(self.quarantine(&source, &body, e).await);
+        }
+        Ok(ret)
+    }
+
+    /// Reads an XML response body as NDJSON records, see `XmlMapping`.
+    pub async fn response_xml_to_ndjson(
+        &self,
+        res: reqwest::Response,
+        mapping: &XmlMapping,
+    ) -> Result<Vec<u8>> {
+        let source = response_source(&res);
+        let body = self.read_body(res).await?;
+        let mut ret = vec![];
+        if let Err(e) = mapping.to_ndjson(&body, &mut ret) {
+            return Err(self.quarantine(&source, &body, e).await);
+        }
+        Ok(ret)
+    }
+
+    /// Parses a JSON response body. If it isn't valid, the raw body is quarantined first.
+    pub async fn response_json<T: serde::de::DeserializeOwned>(
+        &self,
+        res: reqwest::Response,
+    ) -> Result<T> {
+        let source = response_source(&res);
+        let body = self.read_body(res).await?;
+        self.parse_json(&source, &body).await
+    }
+
+    /// Parses a JSON payload from `source`, quarantining it if it isn't valid.
+    pub async fn parse_json<T: serde::de::DeserializeOwned>(
+        &self,
+        source: &str,
+        body: &[u8],
+    ) -> Result<T> {
+        match serde_json::from_slice(body) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                let e = anyhow!(e).context(format!("Invalid JSON response from {}", source));
+                Err(self.quarantine(source, body, e).await)
+            }
+        }
+    }
+
+    /// Preserves a payload that couldn't be parsed, see `quarantine_payload`, and returns the
+    /// parse error to fail the pull with. It's marked `PullerError::Parse` if the payload was
+    /// preserved, failures to quarantine are only logged.
+    pub async fn quarantine(
+        &self,
+        source: &str,
+        payload: &[u8],
+        error: anyhow::Error,
+    ) -> anyhow::Error {
+        let res = quarantine::quarantine_payload(
+            &self.log_source_name,
+            self.tenant_id.as_deref(),
+            source,
+            payload,
+            &error,
+        )
+        .await;
+        match res {
+            Ok(_) => error.context(PullerError::Parse),
+            Err(e) => {
+                error!("{:#}", e);
+                error
+            }
+        }
+    }
+
+    /// Returns when pulls resume if the circuit breaker is open for this log source.
+    pub async fn circuit_open_until(&self) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+        self.auth_failed.store(false, Ordering::SeqCst);
+        self.last_error_status.store(0, Ordering::SeqCst);
+        self.rate_limiter.reset_retry_after();
+        self.request_timings.lock().unwrap().clear();
+        self.circuit_breaker
+            .open_until(&self.checkpoint_name())
+            .await
+    }
+
+    /// Counts a request against the log source's daily request budget.
+    fn take_request_budget(&self) -> Result<()> {
+        match self.request_budget.as_ref() {
+            Some(request_budget) => request_budget.take(&self.log_source_name),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns when pulls resume if the log source's daily request budget is used up, see
+    /// `RequestBudget`. Otherwise requests of the pull are counted from here.
+    pub async fn request_budget_exhausted_until(
+        &self,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+        let request_budget = match self.request_budget.as_ref() {
+            Some(request_budget) => request_budget,
+            None => return Ok(None),
+        };
+        let used = self
+            .checkpointer
+            .requests_on(&self.checkpoint_name(), &RequestBudget::day())
+            .await?;
+        if request_budget.start(used) {
+            return Ok(None);
+        }
+        request_budget.emit_exhausted_metric(&self.log_source_name);
+        Ok(Some(RequestBudget::resets_at()))
+    }
+
+    /// Adds the requests of the pull to the log source's daily count, if it has a budget.
+    pub async fn record_requests(&self) -> Result<()> {
+        let request_budget = match self.request_budget.as_ref() {
+            Some(request_budget) => request_budget,
+            None => return Ok(()),
+        };
+        self.checkpointer
+            .add_requests(
+                &self.checkpoint_name(),
+                &RequestBudget::day(),
+                request_budget.take_requests(),
+            )
+            .await
+    }
+
+    /// Status of the last error response of the current (or last) pull, if any.
+    pub fn last_error_status(&self) -> Option<StatusCode> {
+        StatusCode::from_u16(self.last_error_status.load(Ordering::SeqCst)).ok()
+    }
+
+    /// How long the vendor asked to wait when it last rate limited the current (or last) pull,
+    /// from `Retry-After` or `X-RateLimit-Reset`.
+    pub fn rate_limit_retry_after(&self) -> Option<std::time::Duration> {
+        self.rate_limiter.retry_after()
+    }
+
+    /// Classifies a failed pull by the class attached to the error, its causes, or else the
+    /// last error response, see `PullerError`.
+    pub fn classify_error(&self, e: &anyhow::Error) -> PullerError {
+        PullerError::of(e)
+            .or_else(|| PullerError::infer(e))
+            .or_else(|| self.last_error_status().and_then(PullerError::from_status))
+            .unwrap_or(PullerError::Other)
+    }
+
+    /// Updates the circuit breaker with the result of a pull. Only auth failures count
+    /// towards opening it, other errors are assumed to be transient.
+    pub async fn record_pull_result<T>(&self, res: &Result<T>) -> Result<()> {
+        let key = self.checkpoint_name();
+        match res {
+            Err(e) if self.auth_failed.load(Ordering::SeqCst) => {
+                self.circuit_breaker_dirty.store(true, Ordering::SeqCst);
+                self.circuit_breaker.record_auth_failure(&key, e).await
+            }
+            Err(_) => Ok(()),
+            Ok(_) => {
+                if self.circuit_breaker_dirty.swap(false, Ordering::SeqCst) {
+                    self.circuit_breaker.record_success(&key).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether pullers should stop paging to leave enough time to upload what they have. Pullers
+    /// that stop early save where they left off in the checkpoint and request a continuation
+    /// pull, see `request_continuation`.
+    pub fn pull_deadline_reached(&self) -> bool {
+        deadline::remaining_time(deadline::PULL_DEADLINE_MARGIN).map_or(false, |r| r.is_zero())
+    }
+
+    /// Requests a continuation pull right after this one, for pullers that stopped before the
+    /// deadline and saved where to resume in the checkpoint.
+    pub fn request_continuation(&self) {
+        self.continuation_requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns and resets whether a continuation pull was requested.
+    pub fn take_continuation_request(&self) -> bool {
+        self.continuation_requested.swap(false, Ordering::SeqCst)
+    }
+
+    /// Returns and resets the timings of the requests since the last pull.
+    pub fn take_request_timings(&self) -> Vec<RequestTiming> {
+        std::mem::take(&mut *self.request_timings.lock().unwrap())
+    }
+
+    /// Returns and resets the rate limit stats since the last pull.
+    pub fn take_rate_limit_stats(&self) -> RateLimitStats {
+        self.rate_limiter.take_stats()
+    }
+
+    /// Converts pulled data to NDJSON records, expanding top level arrays and, if
+    /// `records_envelope_path` is set, records wrapped in an envelope object.
+    ///
+    /// ex:
+    /// ```yaml
+    /// managed:
+    ///   properties:
+    ///     # e.g. for responses like {"items": [...], "next": "..."}
+    ///     records_envelope_path: items
+    /// ```
+    pub fn normalize_records(&self, data: Vec<u8>) -> Vec<u8> {
+        let envelope_path = self
+            .config()
+            .get("records_envelope_path")
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty());
+        payload::normalize_ndjson(data, envelope_path)
+    }
+
+    /// Runs the log source's `pre_transform` on the records, if set, see `PreTransform`.
+    pub async fn pre_transform(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self.pre_transform.as_ref() {
+            Some(pre_transform) if !data.is_empty() => pre_transform.apply(data).await,
+            _ => Ok(data),
+        }
+    }
+
+    /// Redacts the log source's `redact_fields` of the records, if set, see `Redaction`. The
+    /// property is parsed here so a pull fails rather than storing the data unredacted.
+    pub async fn redact(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let redaction = match Redaction::from_config(self.config())? {
+            Some(redaction) if !data.is_empty() => redaction,
+            _ => return Ok(data),
+        };
+        let hash_key = self.get_secret_field("redaction_key").await?;
+        redaction.apply(data, hash_key.as_ref().map(|k| k.as_bytes()))
+    }
+
+    /// Sets the table of the records by the log source's `table` or `table_routes`, if set, see
+    /// `TableRouting`.
+    pub fn route_tables(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match TableRouting::from_config(self.config())? {
+            Some(routing) if !data.is_empty() => routing.apply(data),
+            _ => Ok(data),
+        }
+    }
+
+    /// The log source objects are written under, the log source's `destination_log_source` if
+    /// set, e.g. for a puller writing to the tables of another log source.
+    pub fn destination_log_source(&self) -> &str {
+        self.config()
+            .get("destination_log_source")
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .unwrap_or(&self.log_source_name)
+    }
+
+    /// Quarantines records that don't match the log source's `record_schema`, if set, returning
+    /// the valid ones. Fails if they couldn't be quarantined, so they aren't lost.
+    pub async fn validate_records(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let schema = match self.record_schema.as_ref() {
+            Some(schema) => schema,
+            None => return Ok(data),
+        };
+        let (valid, invalid) = schema.validate(data);
+        if invalid.is_empty() {
+            return Ok(valid);
+        }
+        let count = invalid.split(|b| *b == b'\n').count();
+        let error = anyhow!(
+            "{} records failed validation against {}",
+            count,
+            schema.file()
+        );
+        quarantine::quarantine_payload(
+            &self.log_source_name,
+            self.tenant_id.as_deref(),
+            &format!("record_schema:{}", schema.file()),
+            &invalid,
+            &error,
+        )
+        .await?;
+        warn!("{} for log_source: {}", error, self.log_source_name);
+        Ok(valid)
+    }
+
+    /// Drops events that were already pulled, if `dedup_id_field` is set. Returns the remaining
+    /// data and the new event IDs, to record with `record_event_ids` once uploaded.
+    pub async fn dedup(&self, data: Vec<u8>) -> Result<(Vec<u8>, Vec<String>)> {
+        match self.dedup.as_ref() {
+            Some(dedup) => dedup.filter(&self.checkpoint_name(), data).await,
+            None => Ok((data, vec![])),
+        }
+    }
+
+    pub async fn record_event_ids(&self, ids: &[String]) -> Result<()> {
+        match self.dedup.as_ref() {
+            Some(dedup) if !ids.is_empty() => dedup.record(&self.checkpoint_name(), ids).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+fn pull_window_key(start_dt: DateTime<FixedOffset>, end_dt: DateTime<FixedOffset>) -> String {
+    format!("{}_{}", start_dt.timestamp(), end_dt.timestamp())
+}
+
+/// The URL of a response without its query, which may hold credentials.
+pub(crate) fn response_source(res: &reqwest::Response) -> String {
+    let mut url = res.url().clone();
+    url.set_query(None);
+    url.to_string()
+}
+
+/// The egress proxy for all pullers from `PULLER_PROXY_URL`, if configured.
+pub fn global_proxy() -> Result<Option<reqwest::Proxy>> {
+    match std::env::var("PULLER_PROXY_URL") {
+        Ok(proxy_url) if !proxy_url.is_empty() => Ok(Some(reqwest::Proxy::all(proxy_url)?)),
+        _ => Ok(None),
+    }
 }
 
+/// Chunks of NDJSON records as they're pulled, e.g. a page or a downloaded file each.
+pub type RecordChunks<'a> = Pin<Box<dyn Stream<Item = Result<Vec<u8>>> + Send + 'a>>;
+
 #[async_trait]
 #[enum_dispatch]
-pub trait PullLogs {
+pub trait PullLogs: Sized + Send + 'static {
     async fn pull_logs(
         self,
         client: reqwest::Client,
@@ -192,12 +1279,67 @@ pub trait PullLogs {
         start_dt: DateTime<FixedOffset>,
         end_dt: DateTime<FixedOffset>,
     ) -> Result<Vec<u8>>;
+
+    /// Pulls the window as a stream of record chunks, which are compressed and uploaded as
+    /// they come in, so memory use doesn't grow with the size of the pull. Pullers that can
+    /// produce their records incrementally override this, the default is a single chunk of
+    /// `pull_logs`.
+    ///
+    /// The checkpoint is saved once the stream ends. If it fails part way, chunks already
+    /// uploaded are pulled again by the retry, as with any failed pull.
+    fn pull_log_chunks<'a>(
+        self,
+        client: reqwest::Client,
+        ctx: &'a PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> RecordChunks<'a> {
+        Box::pin(futures::stream::once(async move {
+            self.pull_logs(client, ctx, start_dt, end_dt).await
+        }))
+    }
+
+    /// The `managed.properties` the puller reads, checked when its log sources are loaded.
+    fn properties(&self) -> &'static [PropertySpec] {
+        &[]
+    }
+
+    /// How long after they occur the vendor's events can be queried, the default for the
+    /// `pull_lag_minutes` property.
+    fn default_lag(&self) -> chrono::Duration {
+        chrono::Duration::zero()
+    }
+
+    /// Whether the puller sends its vendor requests with `PullLogsContext::send`, so they count
+    /// against the `daily_request_budget`. It's rejected for pullers with their own clients (e.g.
+    /// the AWS SDK or Kafka), which can't limit their requests.
+    fn counts_requests(&self) -> bool {
+        true
+    }
+}
+
+/// Joins record chunks into NDJSON, for pullers whose `pull_logs` is built on `pull_log_chunks`.
+pub(crate) async fn collect_chunks(mut chunks: RecordChunks<'_>) -> Result<Vec<u8>> {
+    let mut ret: Vec<u8> = vec![];
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        if chunk.is_empty() {
+            continue;
+        }
+        if !ret.is_empty() {
+            ret.push(b'\n');
+        }
+        ret.extend(chunk);
+    }
+    Ok(ret)
 }
 
 #[derive(Clone)]
 #[enum_dispatch(PullLogs)]
 pub enum LogSource {
     AmazonInspectorPuller(amazon_inspector::AmazonInspectorPuller),
+    GuardDutyPuller(aws_findings::GuardDutyPuller),
+    SecurityHubPuller(aws_findings::SecurityHubPuller),
     O365Puller(o365::O365Puller),
     MicrosoftGraphPuller(msft::MicrosoftGraphPuller),
     GoogleWorkspacePuller(google_workspace::GoogleWorkspacePuller),
@@ -207,9 +1349,21 @@ pub enum LogSource {
     Otx(otx::OtxPuller),
     Snyk(snyk::SnykPuller),
     CisaKevPuller(cisa_kev::CisaKevPuller),
+    CustomApiPuller(custom_api::CustomApiPuller),
     AbuseChUrlhausPuller(abusech::AbuseChUrlhausPuller),
     AbuseChMalwareBazaarPuller(abusech::AbuseChMalwareBazaarPuller),
     AbuseChThreatfoxPuller(abusech::AbuseChThreatfoxPuller),
+    GraphqlPuller(graphql::GraphqlPuller),
+    ImapPuller(imap::ImapPuller),
+    AzureBlobPuller(azure_blob::AzureBlobPuller),
+    GcsPuller(gcs::GcsPuller),
+    KafkaPuller(kafka::KafkaPuller),
+    SqlPuller(sql::SqlPuller),
+    ElasticsearchPuller(elasticsearch::ElasticsearchPuller),
+    SplunkPuller(splunk::SplunkPuller),
+    ExternalS3Puller(external_s3::ExternalS3Puller),
+    /// A puller registered at startup, see `registry`.
+    Registered(registry::RegisteredPuller),
 }
 
 impl LogSource {
@@ -218,6 +1372,10 @@ impl LogSource {
             "aws_inspector" => Some(LogSource::AmazonInspectorPuller(
                 amazon_inspector::AmazonInspectorPuller {},
             )),
+            "aws_guardduty" => Some(LogSource::GuardDutyPuller(aws_findings::GuardDutyPuller {})),
+            "aws_securityhub" => Some(LogSource::SecurityHubPuller(
+                aws_findings::SecurityHubPuller {},
+            )),
             "o365" => Some(LogSource::O365Puller(o365::O365Puller {})),
             "msft" => Some(LogSource::MicrosoftGraphPuller(
                 msft::MicrosoftGraphPuller {},
@@ -244,12 +1402,28 @@ impl LogSource {
             "cisa_kev" => Some(LogSource::CisaKevPuller(
                 cisa_kev::CisaKevPuller {},
             )),
-            _ => None,
+            "custom_api" => Some(LogSource::CustomApiPuller(custom_api::CustomApiPuller {})),
+            "graphql" => Some(LogSource::GraphqlPuller(graphql::GraphqlPuller {})),
+            "imap" => Some(LogSource::ImapPuller(imap::ImapPuller {})),
+            "azure_blob" => Some(LogSource::AzureBlobPuller(azure_blob::AzureBlobPuller {})),
+            "gcs" => Some(LogSource::GcsPuller(gcs::GcsPuller {})),
+            "kafka" => Some(LogSource::KafkaPuller(kafka::KafkaPuller {})),
+            "sql" => Some(LogSource::SqlPuller(sql::SqlPuller {})),
+            "elasticsearch" => Some(LogSource::ElasticsearchPuller(
+                elasticsearch::ElasticsearchPuller {},
+            )),
+            "splunk" => Some(LogSource::SplunkPuller(splunk::SplunkPuller {})),
+            "external_s3" => Some(LogSource::ExternalS3Puller(
+                external_s3::ExternalS3Puller {},
+            )),
+            _ => registry::RegisteredPuller::lookup(s).map(LogSource::Registered),
         }
     }
     pub fn to_str(&self) -> &str {
         match self {
             LogSource::AmazonInspectorPuller(_) => "aws_inspector",
+            LogSource::GuardDutyPuller(_) => "aws_guardduty",
+            LogSource::SecurityHubPuller(_) => "aws_securityhub",
             LogSource::DuoPuller(_) => "duo",
             LogSource::OktaPuller(_) => "okta",
             LogSource::O365Puller(_) => "o365",
@@ -261,7 +1435,18 @@ impl LogSource {
             LogSource::AbuseChUrlhausPuller(_) => "abusech_urlhaus",
             LogSource::AbuseChMalwareBazaarPuller(_) => "abusech_malwarebazaar",
             LogSource::AbuseChThreatfoxPuller(_) => "abusech_threatfox",
-            LogSource::CisaKevPuller(_) => "cisa_kev"
+            LogSource::CisaKevPuller(_) => "cisa_kev",
+            LogSource::CustomApiPuller(_) => "custom_api",
+            LogSource::GraphqlPuller(_) => "graphql",
+            LogSource::ImapPuller(_) => "imap",
+            LogSource::AzureBlobPuller(_) => "azure_blob",
+            LogSource::GcsPuller(_) => "gcs",
+            LogSource::KafkaPuller(_) => "kafka",
+            LogSource::SqlPuller(_) => "sql",
+            LogSource::ElasticsearchPuller(_) => "elasticsearch",
+            LogSource::SplunkPuller(_) => "splunk",
+            LogSource::ExternalS3Puller(_) => "external_s3",
+            LogSource::Registered(puller) => puller.managed_type(),
         }
     }
 }
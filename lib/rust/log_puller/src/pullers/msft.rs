@@ -10,10 +10,12 @@ use futures::{future::join_all, Stream};
 use futures_util::pin_mut;
 use futures_util::stream::StreamExt;
 use lazy_static::lazy_static;
-use log::{debug, error, info};
 use regex::Regex;
+use tracing::{debug, error, info};
 
-use super::{PullLogs, PullLogsContext};
+use super::oauth2::{ClientCredentials, TokenSource};
+use super::pagination::{NextLink, Page, PageRequest, Pages};
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
 use shared::{convert_json_array_str_to_ndjson, JsonValueExt};
 
 #[derive(Clone)]
@@ -21,6 +23,8 @@ pub struct MicrosoftGraphPuller;
 
 const GRAPH_ENDPOINT: &str = "https://graph.microsoft.com/v1.0";
 const INITIAL_INTERVAL_DAYS: i64 = 14;
+/// Initial pulls cover weeks of sign-ins, so allow many more pages than the default.
+const MAX_PAGES: usize = 10000;
 
 struct GraphResourceProps {
     resource: String,
@@ -53,8 +57,25 @@ lazy_static! {
     static ref TABLE_RESOURCE_MAP: HashMap<String, GraphResourceProps> = table_resource_map();
 }
 
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required(
+        "tenant_id",
+        PropertyType::String,
+        "00000000-0000-0000-0000-000000000000",
+    ),
+    PropertySpec::required(
+        "client_id",
+        PropertyType::String,
+        "00000000-0000-0000-0000-000000000000",
+    ),
+];
+
 #[async_trait]
 impl PullLogs for MicrosoftGraphPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
     async fn pull_logs(
         self,
         client: reqwest::Client,
@@ -65,7 +86,6 @@ impl PullLogs for MicrosoftGraphPuller {
         info!("Pulling Microsoft Graph logs....");
 
         let config = ctx.config();
-        let cache = ctx.cache();
 
         let tenant_id = config.get("tenant_id").context("Missing tenant_id")?;
         let client_id = config.get("client_id").context("Missing client_id")?;
@@ -79,18 +99,15 @@ impl PullLogs for MicrosoftGraphPuller {
             return Ok(vec![]);
         }
 
-        let access_token = {
-            let mut cache = cache.lock().await;
-            match cache.get("access_token") {
-                Some(token) => token.to_owned(),
-                None => {
-                    let token =
-                        get_access_token(&client, tenant_id, client_id, &client_secret).await?;
-                    cache.set("access_token", token.clone(), None);
-                    token
-                }
-            }
-        };
+        let creds = ClientCredentials::new(
+            format!(
+                "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+                tenant_id
+            ),
+            client_id,
+            client_secret,
+        )
+        .scope("https://graph.microsoft.com/.default");
 
         let checkpoint_json = ctx.checkpoint_json.lock().await;
         let is_initial_run = checkpoint_json.is_none();
@@ -117,10 +134,11 @@ impl PullLogs for MicrosoftGraphPuller {
             .map(|(table, resource)| {
                 get_graph_results(
                     client.clone(),
+                    ctx,
+                    &creds,
                     &tenant_id,
                     table,
                     resource,
-                    &access_token,
                     &start_time,
                     &end_time,
                 )
@@ -139,43 +157,13 @@ impl PullLogs for MicrosoftGraphPuller {
     }
 }
 
-async fn get_access_token(
-    client: &reqwest::Client,
-    tenant_id: &str,
-    client_id: &str,
-    client_secret: &str,
-) -> Result<String> {
-    info!("Getting access token");
-    let url = format!(
-        "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
-        tenant_id
-    );
-    let authres = client
-        .post(url)
-        .form(&[
-            ("scope", "https://graph.microsoft.com/.default"),
-            ("grant_type", "client_credentials"),
-            ("client_id", client_id),
-            ("client_secret", client_secret),
-        ])
-        .send()
-        .await?
-        .error_for_status()?;
-    let auth_body = authres.json::<serde_json::Value>().await?;
-    let access_token = auth_body
-        .as_object()
-        .and_then(|o| o.get("access_token")?.as_str())
-        .context("Missing access token")?
-        .to_string();
-    Ok(access_token)
-}
-
 async fn get_graph_results(
     client: reqwest::Client,
+    ctx: &PullLogsContext,
+    creds: &ClientCredentials,
     tenant_id: &str,
     table: &str,
     resource: &GraphResourceProps,
-    access_token: &str,
     start_time: &str,
     end_time: &str,
 ) -> Result<Vec<u8>> {
@@ -186,15 +174,20 @@ async fn get_graph_results(
     let url = format!("{}/{}?{}", GRAPH_ENDPOINT, resource.resource, query_str);
     info!("Getting results for {} from {}", table, url);
 
-    let mut next_url = None;
-    let mut first = true;
+    // Graph can return empty pages with a nextLink while results are still being produced.
+    let mut pages =
+        Pages::new(Box::new(NextLink::new("@odata.nextLink")), MAX_PAGES).allow_empty_pages();
 
     let mut ret = vec![];
 
-    while first || next_url.is_some() {
-        let url = next_url.unwrap_or(url.clone());
-        let res = client.get(&url).bearer_auth(access_token).send().await?;
+    while let Some(page_req) = pages.next_request()? {
+        let url = match page_req {
+            PageRequest::Url(next_url) => next_url,
+            PageRequest::Params(_) => url.clone(),
+        };
+        let res = creds.send(&client, ctx, |c| c.get(&url)).await?;
         let is_failure = !res.status().is_success();
+        let headers = res.headers().clone();
 
         let body = res.bytes().await?;
         if is_failure {
@@ -210,7 +203,9 @@ async fn get_graph_results(
             .and_then(|v| v.take().into_array())
             .context("Missing value array")?
             .into_iter()
-            .filter_map(|v| v.into_object());
+            .filter_map(|v| v.into_object())
+            .collect::<Vec<_>>();
+        let num_records = records.len();
 
         for mut record in records {
             record.insert("_table".to_string(), table.into());
@@ -221,10 +216,11 @@ async fn get_graph_results(
             ret.extend(b"\n");
         }
 
-        next_url = body
-            .get_mut("@odata.nextLink")
-            .and_then(|v| v.take().into_str());
-        first = false;
+        pages.advance(&Page {
+            headers: &headers,
+            body: &body,
+            num_records,
+        });
     }
 
     Ok(ret)
@@ -1,6 +1,3 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
 use anyhow::{anyhow, Context as AnyhowContext, Error, Result};
 use async_stream::{stream, try_stream};
 use async_trait::async_trait;
@@ -9,17 +6,40 @@ use futures::{future::join_all, Stream};
 use futures_util::pin_mut;
 use futures_util::stream::StreamExt;
 use lazy_static::lazy_static;
-use log::{debug, error, info};
 use regex::Regex;
+use tracing::{debug, error, info};
 
-use super::{PullLogs, PullLogsContext};
+use super::oauth2::{ClientCredentials, TokenSource};
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
 use shared::{convert_json_array_str_to_ndjson, JsonValueExt};
 
 #[derive(Clone)]
 pub struct O365Puller;
 
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required(
+        "tenant_id",
+        PropertyType::String,
+        "00000000-0000-0000-0000-000000000000",
+    ),
+    PropertySpec::required(
+        "client_id",
+        PropertyType::String,
+        "00000000-0000-0000-0000-000000000000",
+    ),
+];
+
 #[async_trait]
 impl PullLogs for O365Puller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    /// Audit content is usually listed within 30 minutes, but can take longer.
+    fn default_lag(&self) -> chrono::Duration {
+        chrono::Duration::minutes(30)
+    }
+
     async fn pull_logs(
         self,
         client: reqwest::Client,
@@ -30,7 +50,6 @@ impl PullLogs for O365Puller {
         info!("Pulling o365 logs....");
 
         let config = ctx.config();
-        let cache = ctx.cache();
 
         let tenant_id = config.get("tenant_id").context("Missing tenant_id")?;
         let client_id = config.get("client_id").context("Missing client_id")?;
@@ -45,18 +64,14 @@ impl PullLogs for O365Puller {
             return Ok(vec![]);
         }
 
-        let access_token = {
-            let mut cache = cache.lock().await;
-            match cache.get("access_token") {
-                Some(token) => token.to_owned(),
-                None => {
-                    let token =
-                        get_access_token(&client, tenant_id, client_id, &client_secret).await?;
-                    cache.set("access_token", token.clone(), None);
-                    token
-                }
-            }
-        };
+        let access_token = ClientCredentials::new(
+            format!("https://login.windows.net/{}/oauth2/token", tenant_id),
+            client_id,
+            client_secret,
+        )
+        .param("resource", "https://manage.office.com")
+        .access_token(&client, ctx)
+        .await?;
 
         let start_time = start_dt.format("%Y-%m-%dT%H:%M:%S").to_string();
         let end_time = end_dt.format("%Y-%m-%dT%H:%M:%S").to_string();
@@ -65,6 +80,7 @@ impl PullLogs for O365Puller {
 
         let stream = list_entries_stream(
             client.clone(),
+            ctx,
             &access_token,
             tenant_id,
             &start_time,
@@ -74,19 +90,14 @@ impl PullLogs for O365Puller {
 
         pin_mut!(stream);
 
-        let client_ref = Arc::new(Mutex::new(client.clone()));
-
-        let mut handles = vec![];
+        let mut requests = vec![];
         while let Some(uri_result) = stream.next().await {
             let uri = uri_result?;
-            let handle = tokio::spawn(pull_entry(client_ref.clone(), uri, access_token.clone()));
-            handles.push(handle);
+            requests.push(pull_entry(&client, ctx, uri, &access_token));
         }
 
-        let results = join_all(handles).await;
-        let results = results
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?
+        let results = join_all(requests)
+            .await
             .into_iter()
             .collect::<Result<Vec<_>, _>>()?;
 
@@ -107,37 +118,9 @@ impl PullLogs for O365Puller {
     }
 }
 
-async fn get_access_token(
-    client: &reqwest::Client,
-    tenant_id: &str,
-    client_id: &str,
-    client_secret: &str,
-) -> Result<String> {
-    info!("Getting access token");
-    let url = format!("https://login.windows.net/{}/oauth2/token", tenant_id);
-    let authres = client
-        .post(url)
-        .form(&[
-            ("resource", "https://manage.office.com"),
-            ("grant_type", "client_credentials"),
-            ("client_id", client_id),
-            ("client_secret", client_secret),
-        ])
-        .send()
-        .await?
-        .error_for_status()?;
-    let auth_body = authres.json::<serde_json::Value>().await?;
-    let access_token = auth_body
-        .as_object()
-        .and_then(|o| o.get("access_token"))
-        .and_then(|v| v.as_str())
-        .context("Missing access token")?
-        .to_owned();
-    Ok(access_token)
-}
-
 async fn list_entries(
     client: reqwest::Client,
+    ctx: &PullLogsContext,
     access_token: &str,
     tenant_id: &str,
     start_time: &str,
@@ -156,14 +139,12 @@ async fn list_entries(
     if let Some(n_page) = next_page {
         query.push(("nextPage", n_page));
     }
-    let res = client
+    let req = client
         .get(url)
         .query(&query)
         .bearer_auth(&access_token)
-        .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .send()
-        .await?
-        .error_for_status()?;
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    let res = ctx.send(&client, req).await?.error_for_status()?;
 
     lazy_static! {
         static ref URI_RE: Regex = Regex::new(r"nextPage=([\w\d]+)").unwrap();
@@ -191,6 +172,7 @@ async fn list_entries(
 
 fn list_entries_stream<'a>(
     client: reqwest::Client,
+    ctx: &'a PullLogsContext,
     access_token: &'a str,
     tenant_id: &'a str,
     start_time: &'a str,
@@ -203,6 +185,7 @@ fn list_entries_stream<'a>(
             let token = maybe_next_token.as_ref().clone().map(|s| s.as_str());
             let (new_uris, new_next_token) = list_entries(
                 client.clone(),
+                ctx,
                 &access_token,
                 tenant_id,
                 &start_time,
@@ -221,18 +204,16 @@ fn list_entries_stream<'a>(
 }
 
 async fn pull_entry(
-    client: Arc<Mutex<reqwest::Client>>,
+    client: &reqwest::Client,
+    ctx: &PullLogsContext,
     uri: String,
-    access_token: String,
+    access_token: &str,
 ) -> Result<Vec<u8>> {
-    let client = client.lock().await;
-    let res = client
+    let req = client
         .get(&uri)
         .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .bearer_auth(access_token)
-        .send()
-        .await?
-        .error_for_status()?;
+        .bearer_auth(access_token);
+    let res = ctx.send(client, req).await?.error_for_status()?;
     let resp = res.bytes().await?.to_vec();
     Ok(resp)
 }
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use super::PullLogsContext;
+use shared::secrets::{load_secret_versioned, update_secret_fields};
+
+/// Secret field holding the refresh token for `RefreshTokenGrant`.
+const REFRESH_TOKEN_SECRET_FIELD: &str = "refresh_token";
+/// Retries of the secret write-back when a different field of the secret changed concurrently.
+const MAX_WRITE_BACK_ATTEMPTS: usize = 3;
+
+/// Refresh tokens a bit before they actually expire to allow for clock skew and slow requests.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// A source of OAuth2 access tokens, cached on the puller context.
+#[async_trait]
+pub(crate) trait TokenSource: Sync {
+    /// Returns a cached access token, or requests a new one if it's missing or expired.
+    async fn access_token(&self, client: &reqwest::Client, ctx: &PullLogsContext)
+        -> Result<String>;
+
+    /// Drops the cached access token so the next call requests a fresh one.
+    async fn invalidate(&self, ctx: &PullLogsContext);
+
+    /// Sends a request with the access token as a bearer token. On a 401 the token is
+    /// assumed to be revoked or expired early, so it's refreshed and the request retried once.
+    async fn send<F>(
+        &self,
+        client: &reqwest::Client,
+        ctx: &PullLogsContext,
+        build_request: F,
+    ) -> Result<reqwest::Response>
+    where
+        F: Fn(&reqwest::Client) -> reqwest::RequestBuilder + Send + Sync,
+    {
+        let token = self.access_token(client, ctx).await?;
+        let res = ctx
+            .send(client, build_request(client).bearer_auth(&token))
+            .await?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+
+        debug!(
+            "Got 401 for {}, retrying with a new access token",
+            ctx.log_source_name
+        );
+        self.invalidate(ctx).await;
+        let token = self.access_token(client, ctx).await?;
+        ctx.send(client, build_request(client).bearer_auth(&token))
+            .await
+    }
+}
+
+/// OAuth2 client credentials grant. The access token is cached on the puller context until it expires.
+///
+/// ex:
+/// ```ignore
+/// let creds = ClientCredentials::new(token_url, client_id, client_secret)
+///     .scope("https://graph.microsoft.com/.default");
+/// let res = creds.send(&client, ctx, |c| c.get(&url)).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct ClientCredentials {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scope: Option<String>,
+    /// Extra form params for the token request, ex: `resource` for the Azure AD v1 endpoint.
+    pub extra_params: Vec<(String, String)>,
+}
+
+impl ClientCredentials {
+    pub fn new(
+        token_url: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> ClientCredentials {
+        ClientCredentials {
+            token_url: token_url.into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            scope: None,
+            extra_params: vec![],
+        }
+    }
+
+    pub fn scope(mut self, scope: impl Into<String>) -> ClientCredentials {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    pub fn param(mut self, key: impl Into<String>, value: impl Into<String>) -> ClientCredentials {
+        self.extra_params.push((key.into(), value.into()));
+        self
+    }
+
+    fn cache_key(&self) -> String {
+        format!(
+            "oauth2_access_token:{}:{}:{}",
+            self.token_url,
+            self.client_id,
+            self.scope.as_deref().unwrap_or_default()
+        )
+    }
+
+    async fn request_token(
+        &self,
+        client: &reqwest::Client,
+        ctx: &PullLogsContext,
+    ) -> Result<(String, Option<chrono::Duration>)> {
+        info!("Getting access token");
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+        ];
+        if let Some(scope) = self.scope.as_ref() {
+            form.push(("scope", scope.as_str()));
+        }
+        form.extend(
+            self.extra_params
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        );
+
+        let res = ctx
+            .send(client, client.post(&self.token_url).form(&form))
+            .await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let msg = res.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "Failed to get access token, status: {}, response: {}",
+                status,
+                msg
+            ));
+        }
+        let auth_body = res.json::<Value>().await?;
+
+        let access_token = auth_body
+            .get("access_token")
+            .and_then(|v| v.as_str())
+            .context("Missing access token")?
+            .to_string();
+        Ok((access_token, parse_expires_in(&auth_body)))
+    }
+}
+
+#[async_trait]
+impl TokenSource for ClientCredentials {
+    async fn access_token(
+        &self,
+        client: &reqwest::Client,
+        ctx: &PullLogsContext,
+    ) -> Result<String> {
+        cached_token(ctx, &self.cache_key(), || self.request_token(client, ctx)).await
+    }
+
+    async fn invalidate(&self, ctx: &PullLogsContext) {
+        ctx.cache().lock().await.remove(&self.cache_key());
+    }
+}
+
+/// OAuth2 refresh token grant, for providers that rotate the refresh token on every use
+/// (e.g. Dropbox). The refresh token is read from the `refresh_token` field of the log source
+/// secret, and rotated tokens are written back to the secret.
+///
+/// The write back only succeeds if the secret wasn't updated since it was read, so concurrent
+/// invocations can't clobber each other's rotated token.
+#[derive(Debug, Clone)]
+pub(crate) struct RefreshTokenGrant {
+    pub token_url: String,
+    pub client_id: String,
+    /// Not needed by public clients.
+    pub client_secret: Option<String>,
+}
+
+impl RefreshTokenGrant {
+    pub fn new(
+        token_url: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: Option<String>,
+    ) -> RefreshTokenGrant {
+        RefreshTokenGrant {
+            token_url: token_url.into(),
+            client_id: client_id.into(),
+            client_secret,
+        }
+    }
+
+    fn cache_key(&self) -> String {
+        format!(
+            "oauth2_refresh_access_token:{}:{}",
+            self.token_url, self.client_id
+        )
+    }
+
+    async fn request_token(
+        &self,
+        client: &reqwest::Client,
+        ctx: &PullLogsContext,
+        refresh_token: &str,
+    ) -> Result<(String, Option<String>, Option<chrono::Duration>)> {
+        info!("Refreshing access token");
+        let mut form = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", self.client_id.as_str()),
+        ];
+        if let Some(client_secret) = self.client_secret.as_ref() {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+
+        let res = ctx
+            .send(client, client.post(&self.token_url).form(&form))
+            .await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let msg = res.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "Failed to refresh access token, status: {}, response: {}",
+                status,
+                msg
+            ));
+        }
+        let auth_body = res.json::<Value>().await?;
+
+        let access_token = auth_body
+            .get("access_token")
+            .and_then(|v| v.as_str())
+            .context("Missing access token")?
+            .to_string();
+        let new_refresh_token = auth_body
+            .get("refresh_token")
+            .and_then(|v| v.as_str())
+            .filter(|t| *t != refresh_token)
+            .map(|t| t.to_string());
+        Ok((
+            access_token,
+            new_refresh_token,
+            parse_expires_in(&auth_body),
+        ))
+    }
+
+    /// Persists a rotated refresh token, retrying if only other fields of the secret changed.
+    async fn write_back(
+        &self,
+        ctx: &PullLogsContext,
+        secret_arn: &str,
+        used_refresh_token: &str,
+        new_refresh_token: String,
+    ) -> Result<()> {
+        let mut secret = load_secret_versioned(secret_arn).await?;
+        for _ in 0..MAX_WRITE_BACK_ATTEMPTS {
+            if secret
+                .fields
+                .get(REFRESH_TOKEN_SECRET_FIELD)
+                .map(|s| s.as_str())
+                != Some(used_refresh_token)
+            {
+                // Another invocation already rotated the token, keep its version.
+                warn!(
+                    "Refresh token for {} was rotated concurrently, not writing back",
+                    ctx.log_source_name
+                );
+                return Ok(());
+            }
+            let updates = HashMap::from([(
+                REFRESH_TOKEN_SECRET_FIELD.to_string(),
+                new_refresh_token.clone(),
+            )]);
+            if update_secret_fields(secret_arn, &secret, updates)
+                .await?
+                .is_some()
+            {
+                info!(
+                    "Wrote back rotated refresh token for {}",
+                    ctx.log_source_name
+                );
+                ctx.clear_secret_cache().await;
+                return Ok(());
+            }
+            secret = load_secret_versioned(secret_arn).await?;
+        }
+        Err(anyhow!(
+            "Failed to write back rotated refresh token for {}, secret kept changing",
+            ctx.log_source_name
+        ))
+    }
+}
+
+#[async_trait]
+impl TokenSource for RefreshTokenGrant {
+    async fn access_token(
+        &self,
+        client: &reqwest::Client,
+        ctx: &PullLogsContext,
+    ) -> Result<String> {
+        // Serialized by `cached_token`, so a rotated refresh token is only used once.
+        cached_token(ctx, &self.cache_key(), || async {
+            let secret_arn = ctx.secret_arn.as_deref().context("Missing secret")?;
+            // Always read the latest version, a cached refresh token may already be rotated.
+            let secret = load_secret_versioned(secret_arn).await?;
+            let refresh_token = secret
+                .fields
+                .get(REFRESH_TOKEN_SECRET_FIELD)
+                .context("Missing refresh_token")?;
+
+            let (token, new_refresh_token, expires_in) =
+                self.request_token(client, ctx, refresh_token).await?;
+            if let Some(new_refresh_token) = new_refresh_token {
+                self.write_back(ctx, secret_arn, refresh_token, new_refresh_token)
+                    .await?;
+            }
+            Ok((token, expires_in))
+        })
+        .await
+    }
+
+    async fn invalidate(&self, ctx: &PullLogsContext) {
+        ctx.cache().lock().await.remove(&self.cache_key());
+    }
+}
+
+/// Returns the access token cached under `key`, or requests and caches a new one. The cache
+/// isn't locked during the request, as token requests are sent with `PullLogsContext::send`,
+/// which clears the cache on auth failures. Token requests of the context are serialized
+/// instead, so concurrent pulls reuse the first one's token.
+pub(crate) async fn cached_token<F, Fut>(
+    ctx: &PullLogsContext,
+    key: &str,
+    request_token: F,
+) -> Result<String>
+where
+    F: FnOnce() -> Fut + Send,
+    Fut: Future<Output = Result<(String, Option<chrono::Duration>)>> + Send,
+{
+    if let Some(token) = ctx.cache().lock().await.get(key) {
+        return Ok(token.to_owned());
+    }
+    let token_requests = ctx.token_requests();
+    let _token_request = token_requests.lock().await;
+    if let Some(token) = ctx.cache().lock().await.get(key) {
+        return Ok(token.to_owned());
+    }
+    let (token, expires_in) = request_token().await?;
+    ctx.cache().lock().await.set(key, token.clone(), expires_in);
+    Ok(token)
+}
+
+/// `expires_in` is a number per the spec, but some providers (e.g. Azure AD v1) send a string.
+pub(crate) fn parse_expires_in(auth_body: &Value) -> Option<chrono::Duration> {
+    let expires_in = match auth_body.get("expires_in")? {
+        Value::Number(n) => n.as_i64()?,
+        Value::String(s) => s.parse().ok()?,
+        _ => return None,
+    };
+    Some(chrono::Duration::seconds(
+        (expires_in - EXPIRY_MARGIN_SECS).max(0),
+    ))
+}
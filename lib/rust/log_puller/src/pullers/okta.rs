@@ -6,12 +6,12 @@ use anyhow::{anyhow, Context as AnyhowContext, Error, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, Utc};
 use lazy_static::lazy_static;
-use log::{debug, error, info};
 use regex::Regex;
+use tracing::{debug, error, info};
 
 use reqwest::header;
 
-use super::{PullLogs, PullLogsContext};
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
 use shared::JsonValueExt;
 
 #[derive(Clone)]
@@ -77,8 +77,18 @@ fn api_headers(auth_token: &Option<String>) -> Result<header::HeaderMap> {
     Ok(headers)
 }
 
+const PROPERTIES: &[PropertySpec] = &[PropertySpec::required(
+    "base_url",
+    PropertyType::Url,
+    "https://my-org.okta.com",
+)];
+
 #[async_trait]
 impl PullLogs for OktaPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
     async fn pull_logs(
         self,
         client: reqwest::Client,
@@ -150,18 +160,15 @@ impl PullLogs for OktaPuller {
         let newline_u8 = "\n".to_string().into_bytes();
 
         while url != "" {
-            let response = client
-                .get(url.clone())
-                .headers(headers.clone())
-                .send()
-                .await?;
+            let req = client.get(url.clone()).headers(headers.clone());
+            let response = ctx.send(&client, req).await?;
 
             let headers = response.headers().clone();
 
             let status = response.status();
             if !response.status().is_success() {
                 let msg = response.text().await.unwrap_or_default();
-                log::error!("{}", msg);
+                tracing::error!("{}", msg);
                 if status.is_client_error() {
                     anyhow::bail!(format!("Client error: {}", msg));
                 } else {
@@ -169,7 +176,7 @@ impl PullLogs for OktaPuller {
                 }
             }
 
-            let response_json: Vec<serde_json::Value> = response.json().await?;
+            let response_json: Vec<serde_json::Value> = ctx.response_json(response).await?;
 
             // handle Okta paged responses containing `Link` header for 'self' and 'next'
             let links = headers.get_all(reqwest::header::LINK);
@@ -6,9 +6,9 @@ use tokio::sync::Mutex;
 use anyhow::{anyhow, Context as AnyhowContext, Error, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, Utc};
-use log::{debug, error, info};
+use tracing::{debug, error, info};
 
-use super::{PullLogs, PullLogsContext};
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
 use reqwest::header;
 
 #[derive(Clone)]
@@ -33,8 +33,18 @@ fn api_headers(auth_token: &Option<String>) -> Result<header::HeaderMap> {
     Ok(headers)
 }
 
+const PROPERTIES: &[PropertySpec] = &[PropertySpec::required(
+    "events_api_url",
+    PropertyType::Url,
+    "https://events.1password.com",
+)];
+
 #[async_trait]
 impl PullLogs for OnePasswordPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
     async fn pull_logs(
         self,
         client: reqwest::Client,
@@ -115,11 +125,9 @@ impl PullLogs for OnePasswordPuller {
             };
 
             // POST
-            let response = client
-                .post(&url)
-                .headers(headers.clone())
-                .json(&body)
-                .send()
+            let req = client.post(&url).headers(headers.clone()).json(&body);
+            let response = ctx
+                .send(&client, req)
                 .await
                 .context("Failed to send request")?;
 
@@ -128,8 +136,8 @@ impl PullLogs for OnePasswordPuller {
                 return Err(anyhow!("Failed to get logs: {}", status));
             }
 
-            let mut body_json: serde_json::Value = response
-                .json()
+            let mut body_json: serde_json::Value = ctx
+                .response_json(response)
                 .await
                 .context("Failed to parse response body")?;
 
@@ -170,11 +178,9 @@ impl PullLogs for OnePasswordPuller {
             };
 
             // POST
-            let response = client
-                .post(&url)
-                .headers(headers.clone())
-                .json(&body)
-                .send()
+            let req = client.post(&url).headers(headers.clone()).json(&body);
+            let response = ctx
+                .send(&client, req)
                 .await
                 .context("Failed to send request")?;
 
@@ -183,8 +189,8 @@ impl PullLogs for OnePasswordPuller {
                 return Err(anyhow!("Failed to get logs: {}", status));
             }
 
-            let mut body_json: serde_json::Value = response
-                .json()
+            let mut body_json: serde_json::Value = ctx
+                .response_json(response)
                 .await
                 .context("Failed to parse response body")?;
 
@@ -6,7 +6,7 @@ use chrono::{DateTime, FixedOffset};
 use futures::future::join_all;
 use futures_util::stream::StreamExt;
 use lazy_static::lazy_static;
-use log::{debug, error, info};
+use tracing::{debug, error, info};
 
 use super::{PullLogs, PullLogsContext};
 use shared::JsonValueExt;
@@ -54,13 +54,10 @@ impl PullLogs for OtxPuller {
                 next_url.unwrap()
             };
 
-            let res = client
-                .get(url)
-                .header("X-OTX-API-KEY", &api_key)
-                .send()
-                .await?;
+            let req = client.get(url).header("X-OTX-API-KEY", &api_key);
+            let res = ctx.send(&client, req).await?;
 
-            let body: serde_json::Value = res.json().await?;
+            let body: serde_json::Value = ctx.response_json(res).await?;
 
             next_url = body
                 .as_object()
@@ -0,0 +1,389 @@
+use std::future::Future;
+
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use reqwest::header::{self, HeaderMap};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+
+use super::custom_api::lookup_json_path;
+use super::okta::find_rel_next_link;
+
+/// Guard against APIs that never stop returning a next page.
+pub(crate) const DEFAULT_MAX_PAGES: usize = 1000;
+
+/// The next page to request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum PageRequest {
+    /// Request the base URL with these (paging) query params added.
+    Params(Vec<(String, String)>),
+    /// Request an absolute URL as is.
+    Url(String),
+}
+
+/// A fetched page, used to work out the next request.
+pub(crate) struct Page<'a> {
+    pub headers: &'a HeaderMap,
+    pub body: &'a Value,
+    pub num_records: usize,
+}
+
+/// A pagination strategy. Pullers pick one and let `Pages` drive the request loop.
+pub(crate) trait Paginator: Send + Sync {
+    /// Paging params for the first request.
+    fn first(&mut self) -> PageRequest {
+        PageRequest::Params(vec![])
+    }
+
+    /// Returns the next page to request, or None if `page` was the last one.
+    fn next(&mut self, page: &Page) -> Option<PageRequest>;
+
+    /// The request for the zero-based `index`th page, for strategies where it doesn't depend
+    /// on earlier responses. These can be fetched concurrently, see `fetch_pages_concurrently`.
+    fn nth(&self, _index: usize) -> Option<PageRequest> {
+        None
+    }
+
+    /// Restores the paginator's state when resuming from a saved `next` request.
+    fn resume(&mut self, _next: &PageRequest) {}
+}
+
+/// A single request, no pagination.
+pub(crate) struct NoPagination;
+
+impl Paginator for NoPagination {
+    fn next(&mut self, _page: &Page) -> Option<PageRequest> {
+        None
+    }
+}
+
+/// Incrementing page number, ex: `?page=1`, `?page=2`, ...
+pub(crate) struct PageNumber {
+    param: String,
+    page: i64,
+}
+
+impl PageNumber {
+    pub fn new(param: impl Into<String>, start: i64) -> PageNumber {
+        PageNumber {
+            param: param.into(),
+            page: start,
+        }
+    }
+
+    fn request(&self) -> PageRequest {
+        PageRequest::Params(vec![(self.param.clone(), self.page.to_string())])
+    }
+}
+
+impl Paginator for PageNumber {
+    fn first(&mut self) -> PageRequest {
+        self.request()
+    }
+
+    fn next(&mut self, _page: &Page) -> Option<PageRequest> {
+        self.page += 1;
+        Some(self.request())
+    }
+
+    fn resume(&mut self, next: &PageRequest) {
+        if let Some(page) = param_value(next, &self.param).and_then(|p| p.parse().ok()) {
+            self.page = page;
+        }
+    }
+
+    fn nth(&self, index: usize) -> Option<PageRequest> {
+        let page = self.page + index as i64;
+        Some(PageRequest::Params(vec![(
+            self.param.clone(),
+            page.to_string(),
+        )]))
+    }
+}
+
+/// Incrementing record offset with a fixed page size, stops on a short page.
+pub(crate) struct OffsetLimit {
+    offset_param: String,
+    limit: usize,
+    offset: usize,
+}
+
+impl OffsetLimit {
+    pub fn new(offset_param: impl Into<String>, limit: usize) -> OffsetLimit {
+        OffsetLimit {
+            offset_param: offset_param.into(),
+            limit,
+            offset: 0,
+        }
+    }
+
+    fn request(&self) -> PageRequest {
+        PageRequest::Params(vec![(self.offset_param.clone(), self.offset.to_string())])
+    }
+}
+
+impl Paginator for OffsetLimit {
+    fn first(&mut self) -> PageRequest {
+        self.request()
+    }
+
+    fn next(&mut self, page: &Page) -> Option<PageRequest> {
+        if page.num_records < self.limit {
+            return None;
+        }
+        self.offset += page.num_records;
+        Some(self.request())
+    }
+
+    fn resume(&mut self, next: &PageRequest) {
+        if let Some(offset) = param_value(next, &self.offset_param).and_then(|o| o.parse().ok()) {
+            self.offset = offset;
+        }
+    }
+
+    fn nth(&self, index: usize) -> Option<PageRequest> {
+        let offset = self.offset + index * self.limit;
+        Some(PageRequest::Params(vec![(
+            self.offset_param.clone(),
+            offset.to_string(),
+        )]))
+    }
+}
+
+/// Opaque cursor read from the response body and passed back as a query param.
+pub(crate) struct CursorToken {
+    param: String,
+    path: String,
+}
+
+impl CursorToken {
+    pub fn new(param: impl Into<String>, path: impl Into<String>) -> CursorToken {
+        CursorToken {
+            param: param.into(),
+            path: path.into(),
+        }
+    }
+}
+
+impl Paginator for CursorToken {
+    fn next(&mut self, page: &Page) -> Option<PageRequest> {
+        let cursor = lookup_paging_value(page.body, &self.path)?;
+        Some(PageRequest::Params(vec![(self.param.clone(), cursor)]))
+    }
+}
+
+/// Full next page URL read from the response body, ex: `@odata.nextLink` for Microsoft Graph.
+pub(crate) struct NextLink {
+    path: String,
+}
+
+impl NextLink {
+    pub fn new(path: impl Into<String>) -> NextLink {
+        NextLink { path: path.into() }
+    }
+}
+
+impl Paginator for NextLink {
+    fn next(&mut self, page: &Page) -> Option<PageRequest> {
+        // Keys like `@odata.nextLink` contain dots, so try a top level key first.
+        let url = match page.body.get(&self.path) {
+            Some(Value::String(s)) if !s.is_empty() => Some(s.to_owned()),
+            _ => lookup_paging_value(page.body, &self.path),
+        };
+        url.map(PageRequest::Url)
+    }
+}
+
+/// RFC 8288 `Link: <...>; rel="next"` response header.
+pub(crate) struct LinkHeader;
+
+impl Paginator for LinkHeader {
+    fn next(&mut self, page: &Page) -> Option<PageRequest> {
+        page.headers
+            .get_all(header::LINK)
+            .iter()
+            .filter_map(|link| find_rel_next_link(link.to_str().ok()?))
+            .next()
+            .map(|s| PageRequest::Url(s.to_string()))
+    }
+}
+
+fn param_value<'a>(req: &'a PageRequest, name: &str) -> Option<&'a str> {
+    match req {
+        PageRequest::Params(params) => params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str()),
+        PageRequest::Url(_) => None,
+    }
+}
+
+/// Extracts a string-like paging value (cursor, url, ...) from a response, treating empty values as absent.
+pub(crate) fn lookup_paging_value(value: &Value, path: &str) -> Option<String> {
+    match lookup_json_path(value, path)? {
+        Value::String(s) if !s.is_empty() => Some(s.to_owned()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Drives a paginator, stopping on an empty page, when there are no more pages,
+/// or erroring after `max_pages` so a misbehaving API can't loop forever.
+///
+/// ex:
+/// ```ignore
+/// let mut pages = Pages::new(Box::new(LinkHeader), max_pages);
+/// while let Some(page_req) = pages.next_request()? {
+///     // fetch the page...
+///     pages.advance(&Page { headers: &headers, body: &body, num_records });
+/// }
+/// ```
+pub(crate) struct Pages {
+    paginator: Box<dyn Paginator>,
+    next: Option<PageRequest>,
+    page_num: usize,
+    max_pages: usize,
+    stop_on_empty_page: bool,
+    /// Last request, to stop if the API keeps returning the same next page.
+    last: Option<PageRequest>,
+}
+
+impl Pages {
+    pub fn new(mut paginator: Box<dyn Paginator>, max_pages: usize) -> Pages {
+        let next = Some(paginator.first());
+        Pages {
+            paginator,
+            next,
+            page_num: 0,
+            max_pages,
+            stop_on_empty_page: true,
+            last: None,
+        }
+    }
+
+    /// Continues from a `next` request saved by an earlier, interrupted run.
+    pub fn resume(mut paginator: Box<dyn Paginator>, next: PageRequest, max_pages: usize) -> Pages {
+        paginator.resume(&next);
+        Pages {
+            paginator,
+            next: Some(next),
+            page_num: 0,
+            max_pages,
+            stop_on_empty_page: true,
+            last: None,
+        }
+    }
+
+    /// Keep paging past empty pages, for APIs that return empty pages with a valid cursor
+    /// while results are still being produced.
+    pub fn allow_empty_pages(mut self) -> Pages {
+        self.stop_on_empty_page = false;
+        self
+    }
+
+    /// Number of pages fetched so far.
+    pub fn page_num(&self) -> usize {
+        self.page_num
+    }
+
+    /// Returns the next page to request, or None when done.
+    pub fn next_request(&mut self) -> Result<Option<PageRequest>> {
+        let next = match self.next.take() {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        if self.page_num >= self.max_pages {
+            return Err(anyhow!("Exceeded max_pages ({})", self.max_pages));
+        }
+        if self.last.as_ref() == Some(&next) {
+            return Ok(None);
+        }
+        self.last = Some(next.clone());
+        Ok(Some(next))
+    }
+
+    /// Records a fetched page and works out the next request.
+    pub fn advance(&mut self, page: &Page) {
+        self.page_num += 1;
+        self.next = if self.stop_on_empty_page && page.num_records == 0 {
+            None
+        } else {
+            self.paginator.next(page)
+        };
+    }
+}
+
+/// A page fetched by `fetch_pages_concurrently`.
+pub(crate) struct FetchedPage {
+    pub body: Value,
+    pub records: Vec<Value>,
+}
+
+/// Fetches pages of a paginator that supports `nth` in batches of `parallelism` concurrent
+/// requests, returning them in order.
+///
+/// Stops at the first empty or short (fewer than `page_size` records) page, any pages fetched
+/// after it in the same batch are dropped. If `total_pages` returns the number of pages from
+/// the first page (e.g. from a total count in the response), no pages past it are requested.
+pub(crate) async fn fetch_pages_concurrently<F, Fut>(
+    paginator: &dyn Paginator,
+    parallelism: usize,
+    max_pages: usize,
+    page_size: Option<usize>,
+    total_pages: impl Fn(&Value) -> Option<usize>,
+    fetch: F,
+) -> Result<Vec<FetchedPage>>
+where
+    F: Fn(PageRequest) -> Fut,
+    Fut: Future<Output = Result<FetchedPage>>,
+{
+    let parallelism = parallelism.max(1);
+    let mut ret: Vec<FetchedPage> = vec![];
+    let mut num_pages: Option<usize> = None;
+
+    // The first page is fetched alone, it may tell us how many pages there are.
+    let mut batch_start = 0;
+    let mut batch_end = 1;
+    loop {
+        let reqs = (batch_start..batch_end)
+            .map(|i| {
+                paginator
+                    .nth(i)
+                    .ok_or_else(|| anyhow!("Pagination doesn't support concurrent fetching"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        debug!(
+            "Fetching pages {} to {} concurrently",
+            batch_start,
+            batch_end - 1
+        );
+        let pages = join_all(reqs.into_iter().map(&fetch))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        if batch_start == 0 {
+            num_pages = pages.first().and_then(|p| total_pages(&p.body));
+        }
+
+        for page in pages {
+            let num_records = page.records.len();
+            ret.push(page);
+            if num_records == 0 || page_size.map_or(false, |size| num_records < size) {
+                return Ok(ret);
+            }
+        }
+
+        batch_start = batch_end;
+        if num_pages.map_or(false, |n| batch_start >= n) {
+            return Ok(ret);
+        }
+        if batch_start >= max_pages {
+            return Err(anyhow!("Exceeded max_pages ({})", max_pages));
+        }
+        batch_end = (batch_start + parallelism)
+            .min(num_pages.unwrap_or(usize::MAX))
+            .min(max_pages);
+    }
+}
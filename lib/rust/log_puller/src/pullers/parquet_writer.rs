@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use arrow::array::{
+    ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, TimestampMicrosecondArray,
+};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_writer::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde_json::{Map, Value};
+
+/// Column types of a Parquet output schema.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    Boolean,
+    Int64,
+    Float64,
+    /// RFC3339 strings or epoch milliseconds, stored as microseconds.
+    Timestamp,
+    /// Strings as is, other values as JSON text.
+    String,
+}
+
+impl ColumnType {
+    fn from_name(name: &str) -> Result<ColumnType> {
+        match name.trim().to_lowercase().as_str() {
+            "boolean" | "bool" => Ok(ColumnType::Boolean),
+            "int64" | "long" | "int" => Ok(ColumnType::Int64),
+            "float64" | "double" | "float" => Ok(ColumnType::Float64),
+            "timestamp" => Ok(ColumnType::Timestamp),
+            "string" | "json" => Ok(ColumnType::String),
+            t => Err(anyhow!("Unsupported parquet_schema type: {}", t)),
+        }
+    }
+
+    fn of(value: &Value) -> Option<ColumnType> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(ColumnType::Boolean),
+            Value::Number(n) if n.is_i64() => Some(ColumnType::Int64),
+            Value::Number(_) => Some(ColumnType::Float64),
+            _ => Some(ColumnType::String),
+        }
+    }
+
+    /// The type that can hold values of both types.
+    fn merge(self, other: ColumnType) -> ColumnType {
+        use ColumnType::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Int64, Float64) | (Float64, Int64) => Float64,
+            _ => String,
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            ColumnType::Boolean => DataType::Boolean,
+            ColumnType::Int64 => DataType::Int64,
+            ColumnType::Float64 => DataType::Float64,
+            ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, None),
+            ColumnType::String => DataType::Utf8,
+        }
+    }
+}
+
+/// Writes pulled records as zstd compressed Parquet instead of zstd compressed JSON, which
+/// is smaller to store and cheaper to read for very high volume sources. Top level fields
+/// become columns, nested values are stored as JSON text.
+///
+/// The schema is a JSON object of field names to types (`string`, `int64`, `float64`,
+/// `boolean`, `timestamp` or `json`). Fields not in the schema are dropped. Without a
+/// schema, it's inferred from each pull's records, with fields of mixed types as strings.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     output_format: parquet
+///     # optional, inferred from the records if not set
+///     parquet_schema: '{"id": "string", "created": "timestamp", "severity": "int64", "details": "json"}'
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParquetOutput {
+    schema: Option<Vec<(String, ColumnType)>>,
+}
+
+impl ParquetOutput {
+    /// Returns None unless `output_format` is `parquet`.
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<ParquetOutput>> {
+        match config.get("output_format").map(|f| f.trim().to_lowercase()) {
+            Some(f) if f == "parquet" => (),
+            Some(f) if f == "json" || f.is_empty() => return Ok(None),
+            Some(f) => return Err(anyhow!("Unsupported output_format: {}", f)),
+            None => return Ok(None),
+        }
+        let schema = match config.get("parquet_schema") {
+            Some(s) if !s.trim().is_empty() => {
+                let fields: Map<String, Value> =
+                    serde_json::from_str(s).context("parquet_schema must be a JSON object")?;
+                let fields = fields
+                    .into_iter()
+                    .map(|(name, t)| {
+                        let t = t.as_str().context("parquet_schema types must be strings")?;
+                        Ok((name, ColumnType::from_name(t)?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Some(fields)
+            }
+            _ => None,
+        };
+        Ok(Some(ParquetOutput { schema }))
+    }
+
+    /// Serializes NDJSON records to a Parquet file.
+    pub fn serialize(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let records = data
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| match serde_json::from_slice(line) {
+                Ok(Value::Object(obj)) => Ok(obj),
+                _ => Err(anyhow!("Parquet output requires JSON object records")),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let columns = match self.schema.as_ref() {
+            Some(schema) => schema.clone(),
+            None => infer_columns(&records),
+        };
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|(name, t)| Field::new(name, t.data_type(), true))
+                .collect(),
+        ));
+        let arrays = columns
+            .iter()
+            .map(|(name, t)| build_array(&records, name, *t))
+            .collect::<Vec<_>>();
+        let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD)
+            .build();
+        let buf = std::io::Cursor::new(vec![]);
+        let mut writer = ArrowWriter::try_new(buf, schema, Some(props))?;
+        writer.write(&batch)?;
+        Ok(writer.into_inner()?.into_inner())
+    }
+}
+
+/// Columns in order of first appearance, with the type that fits all their values.
+fn infer_columns(records: &[Map<String, Value>]) -> Vec<(String, ColumnType)> {
+    let mut columns: Vec<(String, Option<ColumnType>)> = vec![];
+    let mut indexes = HashMap::new();
+    for record in records {
+        for (name, value) in record {
+            let i = *indexes.entry(name.clone()).or_insert_with(|| {
+                columns.push((name.clone(), None));
+                columns.len() - 1
+            });
+            let column_type = &mut columns[i].1;
+            *column_type = match (*column_type, ColumnType::of(value)) {
+                (Some(a), Some(b)) => Some(a.merge(b)),
+                (a, b) => a.or(b),
+            };
+        }
+    }
+    columns
+        .into_iter()
+        .map(|(name, t)| (name, t.unwrap_or(ColumnType::String)))
+        .collect()
+}
+
+fn build_array(records: &[Map<String, Value>], name: &str, t: ColumnType) -> ArrayRef {
+    let values = records.iter().map(|r| r.get(name).filter(|v| !v.is_null()));
+    match t {
+        ColumnType::Boolean => Arc::new(
+            values
+                .map(|v| v.and_then(|v| v.as_bool()))
+                .collect::<BooleanArray>(),
+        ),
+        ColumnType::Int64 => Arc::new(
+            values
+                .map(|v| v.and_then(|v| v.as_i64()))
+                .collect::<Int64Array>(),
+        ),
+        ColumnType::Float64 => Arc::new(
+            values
+                .map(|v| v.and_then(|v| v.as_f64()))
+                .collect::<Float64Array>(),
+        ),
+        ColumnType::Timestamp => Arc::new(
+            values
+                .map(|v| v.and_then(timestamp_micros))
+                .collect::<TimestampMicrosecondArray>(),
+        ),
+        ColumnType::String => Arc::new(
+            values
+                .map(|v| {
+                    v.map(|v| match v {
+                        Value::String(s) => s.clone(),
+                        v => v.to_string(),
+                    })
+                })
+                .collect::<StringArray>(),
+        ),
+    }
+}
+
+fn timestamp_micros(value: &Value) -> Option<i64> {
+    match value {
+        Value::String(s) => chrono::DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|dt| dt.timestamp() * 1_000_000 + dt.timestamp_subsec_micros() as i64),
+        Value::Number(n) => n.as_i64().map(|ms| ms * 1000),
+        _ => None,
+    }
+}
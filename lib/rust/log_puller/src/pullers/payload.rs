@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::{DeserializeSeed, IgnoredAny, MapAccess, Visitor};
+use serde_json::value::RawValue;
+use serde_json::Value;
+use tracing::debug;
+
+use super::csv_records::CsvMapping;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// Decodes a response body according to its `Content-Encoding`. Other encodings are rejected
+/// rather than passing on garbage.
+pub(crate) fn decode_content_encoding(encoding: Option<&str>, body: Vec<u8>) -> Result<Vec<u8>> {
+    let encoding = match encoding.map(|e| e.trim().to_lowercase()) {
+        Some(e) if !e.is_empty() && e != "identity" => e,
+        _ => return Ok(body),
+    };
+    let mut decoded = vec![];
+    match encoding.as_str() {
+        "gzip" | "x-gzip" => {
+            flate2::read::MultiGzDecoder::new(body.as_slice())
+                .read_to_end(&mut decoded)
+                .context("Invalid gzip response body")?;
+        }
+        // Should be zlib wrapped, but some servers send raw deflate.
+        "deflate" => {
+            if flate2::read::ZlibDecoder::new(body.as_slice())
+                .read_to_end(&mut decoded)
+                .is_err()
+            {
+                decoded.clear();
+                flate2::read::DeflateDecoder::new(body.as_slice())
+                    .read_to_end(&mut decoded)
+                    .context("Invalid deflate response body")?;
+            }
+        }
+        e => return Err(anyhow!("Unsupported Content-Encoding: {}", e)),
+    }
+    Ok(decoded)
+}
+
+/// Whether the payload is a gzip or zip archive, judging by its content.
+pub(crate) fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(GZIP_MAGIC) || data.starts_with(ZIP_MAGIC)
+}
+
+/// Expands a payload (CSV, JSON/NDJSON, or gzip/zip archives of those) to NDJSON records.
+/// `name` is the file name if known, used to tell the format when the content doesn't.
+pub(crate) fn payload_to_ndjson(name: &str, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let lower = name.to_lowercase();
+    if data.starts_with(ZIP_MAGIC) || lower.ends_with(".zip") {
+        let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if file.is_dir() {
+                continue;
+            }
+            let file_name = file.name().to_string();
+            let mut contents = vec![];
+            file.read_to_end(&mut contents)?;
+            payload_to_ndjson(&file_name, &contents, out)?;
+        }
+    } else if data.starts_with(GZIP_MAGIC) || lower.ends_with(".gz") {
+        let mut decoded = vec![];
+        flate2::read::MultiGzDecoder::new(data).read_to_end(&mut decoded)?;
+        let inner_name = lower.strip_suffix(".gz").unwrap_or(&lower);
+        payload_to_ndjson(inner_name, &decoded, out)?;
+    } else if lower.ends_with(".csv") || lower.ends_with(".tsv") {
+        let delimiter = if lower.ends_with(".tsv") { b'\t' } else { b',' };
+        CsvMapping::new(vec![])
+            .with_delimiter(delimiter)
+            .to_ndjson(data, out)?;
+    } else if is_json_name(&lower) {
+        // Either a JSON array, a single JSON object, or newline delimited JSON.
+        let stream = serde_json::Deserializer::from_slice(data).into_iter::<serde_json::Value>();
+        for value in stream {
+            match value? {
+                serde_json::Value::Array(arr) => {
+                    for v in arr {
+                        out.extend(serde_json::to_vec(&v)?);
+                        out.push(b'\n');
+                    }
+                }
+                v => {
+                    out.extend(serde_json::to_vec(&v)?);
+                    out.push(b'\n');
+                }
+            }
+        }
+    } else {
+        debug!("Skipping unsupported payload type: {}", name);
+    }
+    Ok(())
+}
+
+/// Converts pulled data to one JSON record per line: top level arrays and records wrapped in
+/// an envelope (e.g. `{"items": [...]}` with `envelope_path` `items`) are expanded, and pretty
+/// printed JSON is compacted. Data that isn't JSON (e.g. plain text logs) is returned as is.
+///
+/// Records are only validated, not parsed, and copied as is unless they span lines, as
+/// parsing dominates the time spent on large responses.
+pub(crate) fn normalize_ndjson(data: Vec<u8>, envelope_path: Option<&str>) -> Vec<u8> {
+    let is_ndjson = data
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .all(|line| line.starts_with(b"{") && line.ends_with(b"}"));
+    if is_ndjson && envelope_path.is_none() {
+        return data;
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    let stream = serde_json::Deserializer::from_slice(&data).into_iter::<&RawValue>();
+    for value in stream {
+        let value = match value {
+            Ok(v) => v,
+            Err(_) => {
+                debug!("Pulled data isn't JSON, not normalizing");
+                return data;
+            }
+        };
+        let records = match envelope_path.and_then(|path| lookup_raw_json_path(value, path)) {
+            Some(records) if records.get().starts_with('[') => records,
+            _ => value,
+        };
+        match raw_array(records) {
+            Some(records) => records.into_iter().for_each(|r| push_record(&mut out, r)),
+            None => push_record(&mut out, records),
+        }
+    }
+    out.pop();
+    out
+}
+
+/// The elements of a JSON array, None if it's not an array.
+fn raw_array(value: &RawValue) -> Option<Vec<&RawValue>> {
+    if !value.get().starts_with('[') {
+        return None;
+    }
+    serde_json::from_str(value.get()).ok()
+}
+
+/// Appends a record and a newline, compacting it if it's pretty printed. Newlines can't be
+/// in JSON strings unescaped, so a record without any is already on one line.
+fn push_record(out: &mut Vec<u8>, record: &RawValue) {
+    let text = record.get();
+    if !text.contains(|c| c == '\n' || c == '\r') {
+        out.extend_from_slice(text.as_bytes());
+    } else if let Ok(value) = serde_json::from_str::<Value>(text) {
+        // Serializing a Value can't fail.
+        out.extend(serde_json::to_vec(&value).unwrap_or_default());
+    }
+    out.push(b'\n');
+}
+
+/// Looks up a dotted path (e.g. `data.items`, see `lookup_json_path`) without parsing more of
+/// the value than the objects and arrays along the path.
+pub(crate) fn lookup_raw_json_path<'a>(value: &'a RawValue, path: &str) -> Option<&'a RawValue> {
+    let mut value = value;
+    for segment in path
+        .trim_start_matches('.')
+        .split('.')
+        .filter(|s| !s.is_empty())
+    {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        value = match value.get().as_bytes().first()? {
+            b'{' => serde_json::from_str::<HashMap<String, &RawValue>>(value.get())
+                .ok()?
+                .remove(&segment)?,
+            b'[' => {
+                let index = segment.parse::<usize>().ok()?;
+                raw_array(value)?.get(index).copied()?
+            }
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Adds a field to each JSON object line, e.g. `_tenant_id`. Objects without the field have
+/// it spliced in after being validated, instead of being parsed and serialized again. Lines
+/// that aren't JSON objects are kept as is.
+pub fn add_json_field(data: Vec<u8>, key: &str, value: &Value) -> Result<Vec<u8>> {
+    if data.is_empty() {
+        return Ok(data);
+    }
+    let mut field = serde_json::to_vec(key)?;
+    field.push(b':');
+    field.extend(serde_json::to_vec(value)?);
+
+    let mut ret = Vec::with_capacity(data.len());
+    for line in data.split(|b| *b == b'\n') {
+        if !ret.is_empty() {
+            ret.push(b'\n');
+        }
+        let mut de = serde_json::Deserializer::from_slice(line);
+        let has_key = HasKey(key)
+            .deserialize(&mut de)
+            .and_then(|has_key| de.end().map(|_| has_key));
+        match has_key {
+            Ok(false) => {
+                // Valid objects start with `{` and end with `}`, ignoring whitespace.
+                let open = line.iter().position(|b| *b == b'{').unwrap();
+                let close = line.iter().rposition(|b| *b == b'}').unwrap();
+                let is_empty = line[open + 1..close]
+                    .iter()
+                    .all(|b| b.is_ascii_whitespace());
+                ret.extend_from_slice(&line[..close]);
+                if !is_empty {
+                    ret.push(b',');
+                }
+                ret.extend_from_slice(&field);
+                ret.extend_from_slice(&line[close..]);
+            }
+            Ok(true) => {
+                let mut obj = serde_json::from_slice::<serde_json::Map<String, Value>>(line)?;
+                obj.insert(key.to_string(), value.clone());
+                ret.extend(serde_json::to_vec(&obj)?);
+            }
+            Err(_) => ret.extend_from_slice(line),
+        }
+    }
+    Ok(ret)
+}
+
+/// Validates a JSON object and tells whether it has a top level key, skipping the values.
+struct HasKey<'a>(&'a str);
+
+impl<'de> DeserializeSeed<'de> for HasKey<'_> {
+    type Value = bool;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<bool, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for HasKey<'_> {
+    type Value = bool;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<bool, A::Error> {
+        let mut has_key = false;
+        while let Some(key) = map.next_key::<String>()? {
+            has_key |= key == self.0;
+            map.next_value::<IgnoredAny>()?;
+        }
+        Ok(has_key)
+    }
+}
+
+/// Names without an extension (e.g. from a URL path) are assumed to be JSON.
+fn is_json_name(lower: &str) -> bool {
+    let file_name = lower.rsplit('/').next().unwrap_or(lower);
+    !file_name.contains('.')
+        || [".json", ".ndjson", ".jsonl", ".log", ".txt"]
+            .iter()
+            .any(|ext| file_name.ends_with(ext))
+}
+
+/// File name of a response, from `Content-Disposition` or the URL path, with an extension
+/// from `Content-Type` if neither has one.
+pub(crate) fn response_file_name(res: &reqwest::Response) -> String {
+    let disposition_name = res
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.split(';')
+                .map(|p| p.trim())
+                .find_map(|p| p.strip_prefix("filename="))
+                .map(|name| name.trim_matches('"').to_string())
+        });
+    let name = disposition_name.unwrap_or_else(|| {
+        res.url()
+            .path_segments()
+            .and_then(|s| s.last())
+            .unwrap_or_default()
+            .to_string()
+    });
+    if name.contains('.') {
+        return name;
+    }
+
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+    let ext = if content_type.contains("zip") && !content_type.contains("gzip") {
+        ".zip"
+    } else if content_type.contains("gzip") {
+        ".gz"
+    } else if content_type.contains("csv") {
+        ".csv"
+    } else if content_type.contains("tab-separated-values") {
+        ".tsv"
+    } else {
+        ""
+    };
+    format!("{}{}", name, ext)
+}
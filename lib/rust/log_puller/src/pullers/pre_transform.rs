@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use shared::vrl_util;
+use vrl::Program;
+
+/// A VRL program run on each pulled record before it's uploaded, so noisy fields can be
+/// dropped before they're stored. Set with the `pre_transform` property. The record is `.`,
+/// and records the program `abort`s are dropped.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     pre_transform: |
+///       if .eventType == "system.heartbeat" { abort }
+///       del(.debugContext)
+///       .actor_id = del(.actor.id)
+/// ```
+#[derive(Clone)]
+pub struct PreTransform {
+    program: Arc<Program>,
+}
+
+impl PreTransform {
+    /// Compiles the `pre_transform` property, if set. Fails if it isn't a valid program, so the
+    /// log source is rejected when it's loaded rather than on each pull.
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<PreTransform>> {
+        let source = match config.get("pre_transform").map(|p| p.trim()) {
+            Some(p) if !p.is_empty() => p,
+            _ => return Ok(None),
+        };
+        let program = vrl_util::compile(source).context("Invalid pre_transform")?;
+        Ok(Some(PreTransform {
+            program: Arc::new(program),
+        }))
+    }
+
+    /// Runs the program on each NDJSON record, on a blocking thread as it's CPU bound.
+    pub async fn apply(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let program = self.program.clone();
+        tokio::task::spawn_blocking(move || apply_program(&program, &data)).await?
+    }
+}
+
+fn apply_program(program: &Program, data: &[u8]) -> Result<Vec<u8>> {
+    let mut ret = Vec::with_capacity(data.len());
+    for (i, line) in data
+        .split(|b| *b == b'\n')
+        .filter(|l| !l.is_empty())
+        .enumerate()
+    {
+        let record: serde_json::Value = serde_json::from_slice(line)
+            .with_context(|| format!("Invalid JSON record {} for pre_transform", i))?;
+        let mut value = ::value::Value::from(record);
+        let transformed = vrl_util::resolve_opt(program, &mut value)
+            .map_err(|e| anyhow!("Failed to run pre_transform on record {}: {}", i, e))?;
+        if transformed.is_none() {
+            continue;
+        }
+        if !ret.is_empty() {
+            ret.push(b'\n');
+        }
+        serde_json::to_writer(&mut ret, &value)?;
+    }
+    Ok(ret)
+}
@@ -0,0 +1,120 @@
+//! The `managed.properties` each puller reads, checked when a log source's contexts are built,
+//! so a missing or malformed property is reported with the log source at fault instead of
+//! failing part way through a pull.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+/// The kind of value a property takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyType {
+    String,
+    Integer,
+    Boolean,
+    Url,
+    /// A JSON document, e.g. a query.
+    Json,
+}
+
+/// A property a puller reads.
+#[derive(Debug, Clone, Copy)]
+pub struct PropertySpec {
+    pub name: &'static str,
+    pub property_type: PropertyType,
+    pub required: bool,
+    /// Shown when the property is missing or invalid, ex: `https://my-org.okta.com`.
+    pub example: &'static str,
+}
+
+impl PropertySpec {
+    pub const fn required(
+        name: &'static str,
+        property_type: PropertyType,
+        example: &'static str,
+    ) -> PropertySpec {
+        PropertySpec {
+            name,
+            property_type,
+            required: true,
+            example,
+        }
+    }
+
+    pub const fn optional(
+        name: &'static str,
+        property_type: PropertyType,
+        example: &'static str,
+    ) -> PropertySpec {
+        PropertySpec {
+            name,
+            property_type,
+            required: false,
+            example,
+        }
+    }
+
+    /// Why the value isn't of the property's type, if it isn't.
+    fn check(&self, value: &str) -> Option<&'static str> {
+        let value = value.trim();
+        let is_valid = match self.property_type {
+            PropertyType::String => true,
+            PropertyType::Integer => value.parse::<i64>().is_ok(),
+            PropertyType::Boolean => value == "true" || value == "false",
+            PropertyType::Url => url::Url::parse(value).is_ok(),
+            PropertyType::Json => serde_json::from_str::<serde_json::Value>(value).is_ok(),
+        };
+        match (is_valid, self.property_type) {
+            (true, _) => None,
+            (false, PropertyType::Integer) => Some("an integer"),
+            (false, PropertyType::Boolean) => Some("true or false"),
+            (false, PropertyType::Url) => Some("a URL"),
+            (false, _) => Some("JSON"),
+        }
+    }
+}
+
+/// Properties of the puller itself, read for every log source.
+const COMMON_PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::optional("max_concurrent_pulls", PropertyType::Integer, "2"),
+    PropertySpec::optional("memory_budget_mb", PropertyType::Integer, "512"),
+    PropertySpec::optional("max_catch_up_hours", PropertyType::Integer, "24"),
+    PropertySpec::optional("catch_up_threshold_minutes", PropertyType::Integer, "60"),
+    PropertySpec::optional("backfill_window_minutes", PropertyType::Integer, "60"),
+    PropertySpec::optional("lookback_overlap_minutes", PropertyType::Integer, "5"),
+    PropertySpec::optional("pull_lag_minutes", PropertyType::Integer, "5"),
+    PropertySpec::optional("pull_window_minutes", PropertyType::Integer, "15"),
+    PropertySpec::optional("pull_shards", PropertyType::Integer, "4"),
+    PropertySpec::optional("daily_request_budget", PropertyType::Integer, "500"),
+];
+
+/// Checks a log source's properties against those its puller declares, with every problem in
+/// one error, e.g. "okta source 'corp' missing property 'base_url' (ex: https://my-org.okta.com)".
+pub fn validate_properties(
+    log_source_type: &str,
+    log_source: &str,
+    specs: &[PropertySpec],
+    properties: &HashMap<String, String>,
+) -> Result<()> {
+    let problems = COMMON_PROPERTIES
+        .iter()
+        .chain(specs.iter())
+        .filter_map(|spec| match properties.get(spec.name) {
+            None if spec.required => Some(format!(
+                "{} source '{}' missing property '{}' (ex: {})",
+                log_source_type, log_source, spec.name, spec.example
+            )),
+            None => None,
+            Some(value) => spec.check(value).map(|expected| {
+                format!(
+                    "{} source '{}' property '{}' must be {}, got '{}' (ex: {})",
+                    log_source_type, log_source, spec.name, expected, value, spec.example
+                )
+            }),
+        })
+        .collect::<Vec<_>>();
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow!(problems.join("; "))),
+    }
+}
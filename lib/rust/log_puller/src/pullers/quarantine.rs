@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_s3::types::ByteStream;
+use lazy_static::lazy_static;
+use tracing::warn;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref S3_CLIENT: AsyncOnce<aws_sdk_s3::Client> =
+        AsyncOnce::new(async { crate::s3_client(AWS_CONFIG.get().await, None) });
+}
+
+/// Not under a log source prefix, so quarantined payloads aren't ingested.
+const QUARANTINE_PREFIX: &str = "__puller_quarantine__";
+/// S3 limits user metadata to 2KB in total.
+const MAX_ERROR_METADATA_LEN: usize = 1024;
+
+/// Writes a vendor response that couldn't be parsed, or records that didn't match the log
+/// source's `record_schema`, to the ingestion bucket under
+/// `__puller_quarantine__/{log_source}/{date}/`, with the error as object metadata, so the
+/// data isn't lost and the puller can be debugged from the real payload.
+pub(crate) async fn quarantine_payload(
+    log_source: &str,
+    tenant_id: Option<&str>,
+    source: &str,
+    payload: &[u8],
+    error: &anyhow::Error,
+) -> Result<String> {
+    let bucket = std::env::var("INGESTION_BUCKET_NAME")?;
+    let key = format!(
+        "{}/{}/{}/{}",
+        QUARANTINE_PREFIX,
+        log_source,
+        chrono::Utc::now().format("%Y-%m-%d"),
+        uuid::Uuid::new_v4()
+    );
+    S3_CLIENT
+        .get()
+        .await
+        .put_object()
+        .bucket(bucket)
+        .key(&key)
+        .body(ByteStream::from(payload.to_vec()))
+        .metadata("matano-log-source", log_source)
+        .metadata("matano-tenant-id", tenant_id.unwrap_or("default"))
+        .metadata("matano-source", metadata_value(source))
+        .metadata("matano-error", metadata_value(&format!("{:#}", error)))
+        .send()
+        .await
+        .with_context(|| format!("Failed to quarantine payload for {}", log_source))?;
+    warn!(
+        "Quarantined payload from {} for {} to {}",
+        source, log_source, key
+    );
+    Ok(key)
+}
+
+/// Metadata values are sent as headers, so only printable ASCII is kept.
+fn metadata_value(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '?'
+            }
+        })
+        .take(MAX_ERROR_METADATA_LEN)
+        .collect()
+}
@@ -0,0 +1,196 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use super::deadline::{remaining_time, DEADLINE_MARGIN};
+use super::errors::PullerError;
+use super::token_bucket::DistributedTokenBucket;
+
+/// Retries of a single request that keeps getting 429s.
+pub(crate) const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+/// Used when a 429 doesn't say how long to wait, doubled on each retry.
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Tracks rate limiting of a log source's API, so requests wait for a reset instead of failing.
+pub(crate) struct RateLimiter {
+    /// Set when the API reported no remaining requests, the next request waits until then.
+    limited_until: Mutex<Option<DateTime<Utc>>>,
+    /// Budget shared with other invocations, if configured.
+    shared_budget: Option<DistributedTokenBucket>,
+    throttled_count: AtomicU64,
+    waited_ms: AtomicU64,
+    /// How long the last 429 response asked to wait, 0 if none, see `retry_after`.
+    retry_after_ms: AtomicU64,
+}
+
+/// Rate limit stats of a log source, logged after each pull.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimitStats {
+    /// Number of 429 responses.
+    pub throttled_count: u64,
+    /// Total time spent waiting for rate limits.
+    pub waited_ms: u64,
+}
+
+impl RateLimiter {
+    pub fn new(shared_budget: Option<DistributedTokenBucket>) -> RateLimiter {
+        RateLimiter {
+            limited_until: Mutex::new(None),
+            shared_budget,
+            throttled_count: AtomicU64::new(0),
+            waited_ms: AtomicU64::new(0),
+            retry_after_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits for a previously observed rate limit reset, if any, and for a token from the
+    /// shared budget.
+    pub async fn wait_for_reset(&self, log_source_name: &str) -> Result<()> {
+        let limited_until = self.limited_until.lock().await.take();
+        if let Some(wait) = limited_until.and_then(|t| (t - Utc::now()).to_std().ok()) {
+            info!(
+                "Rate limit exhausted for {}, waiting {:?} for reset",
+                log_source_name, wait
+            );
+            self.sleep(wait, log_source_name).await?;
+        }
+
+        if let Some(shared_budget) = self.shared_budget.as_ref() {
+            let wait = shared_budget.take().await?;
+            if !wait.is_zero() {
+                self.sleep(wait, log_source_name).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records `X-RateLimit-*` headers of a response, so the next request waits if none are left.
+    pub async fn observe(&self, headers: &HeaderMap) {
+        if rate_limit_remaining(headers) != Some(0) {
+            return;
+        }
+        if let Some(reset) = rate_limit_reset(headers) {
+            let until = Utc::now() + chrono::Duration::from_std(reset).unwrap_or_default();
+            *self.limited_until.lock().await = Some(until);
+        }
+    }
+
+    /// Waits before retrying a 429 response, for as long as the response asks or with backoff.
+    pub async fn wait_for_retry(
+        &self,
+        headers: &HeaderMap,
+        attempt: u32,
+        log_source_name: &str,
+    ) -> Result<()> {
+        self.throttled_count.fetch_add(1, Ordering::SeqCst);
+        let wait = self
+            .observe_rate_limited(headers)
+            .unwrap_or_else(|| DEFAULT_BACKOFF * 2u32.pow(attempt));
+        warn!(
+            "Rate limited for {}, retrying in {:?} (attempt {})",
+            log_source_name,
+            wait,
+            attempt + 1
+        );
+        self.sleep(wait, log_source_name).await
+    }
+
+    /// Records how long a 429 response asks to wait, so a pull that gives up can be retried
+    /// once the limit resets.
+    pub fn observe_rate_limited(&self, headers: &HeaderMap) -> Option<Duration> {
+        let wait = retry_after(headers).or_else(|| rate_limit_reset(headers));
+        let wait_ms = wait.map_or(0, |w| w.as_millis() as u64);
+        self.retry_after_ms.store(wait_ms, Ordering::SeqCst);
+        wait
+    }
+
+    /// How long the last 429 response asked to wait, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self.retry_after_ms.load(Ordering::SeqCst) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    pub fn reset_retry_after(&self) {
+        self.retry_after_ms.store(0, Ordering::SeqCst);
+    }
+
+    /// Sleeps, unless the wait would outlast the invocation. Then it's better to fail the
+    /// record and let it be retried later than to time out midway.
+    async fn sleep(&self, wait: Duration, log_source_name: &str) -> Result<()> {
+        if let Some(remaining) = remaining_time(DEADLINE_MARGIN) {
+            if wait > remaining {
+                return Err(anyhow!(
+                    "Rate limited for {}, need to wait {:?} but only {:?} left in this invocation",
+                    log_source_name,
+                    wait,
+                    remaining
+                ))
+                .context(PullerError::RateLimited);
+            }
+        }
+        tokio::time::sleep(wait).await;
+        self.waited_ms
+            .fetch_add(wait.as_millis() as u64, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Returns and resets the stats since the last call.
+    pub fn take_stats(&self) -> RateLimitStats {
+        RateLimitStats {
+            throttled_count: self.throttled_count.swap(0, Ordering::SeqCst),
+            waited_ms: self.waited_ms.swap(0, Ordering::SeqCst),
+        }
+    }
+}
+
+/// `Retry-After` as either delay seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+fn header_u64(headers: &HeaderMap, names: &[&str]) -> Option<u64> {
+    names
+        .iter()
+        .filter_map(|name| {
+            headers
+                .get(*name)?
+                .to_str()
+                .ok()?
+                .trim()
+                .parse::<f64>()
+                .ok()
+        })
+        .map(|v| v.max(0.0) as u64)
+        .next()
+}
+
+fn rate_limit_remaining(headers: &HeaderMap) -> Option<u64> {
+    header_u64(headers, &["x-ratelimit-remaining", "ratelimit-remaining"])
+}
+
+/// `X-RateLimit-Reset` is either an epoch timestamp (e.g. GitHub, Okta) or delay seconds.
+fn rate_limit_reset(headers: &HeaderMap) -> Option<Duration> {
+    let reset = header_u64(headers, &["x-ratelimit-reset", "ratelimit-reset"])?;
+    let now = Utc::now().timestamp() as u64;
+    if reset > 1_000_000_000 {
+        Some(Duration::from_secs(reset.saturating_sub(now)))
+    } else {
+        Some(Duration::from_secs(reset))
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use apache_avro::Schema;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use super::PullLogsContext;
+
+/// Confluent framed payloads start with a zero byte and the 4 byte big endian schema ID.
+const CONFLUENT_MAGIC: u8 = 0;
+const CONFLUENT_HEADER_LEN: usize = 5;
+
+/// Decodes binary encoded records (e.g. Kafka message values) to JSON, set with the
+/// `value_format` property, `json` (default, not decoded), `avro` or `protobuf`.
+///
+/// Avro records are decoded with the writer schema from a Confluent compatible
+/// `schema_registry_url` (with `schema_registry_username` and the `schema_registry_password`
+/// secret field, if set), by the schema ID of each record, or else with a fixed `avro_schema`
+/// for records without the registry framing. The registry is requested with the log source's
+/// HTTP client, so its CA, client certificate, proxy and timeouts apply.
+///
+/// Protobuf records are decoded as the `protobuf_message` from the base64
+/// `protobuf_descriptor_set` (`protoc --include_imports --descriptor_set_out`), and use the
+/// proto3 JSON mapping. Confluent framing is skipped if present, the message indexes are
+/// ignored, so topics should have a single message type.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: kafka
+///   properties:
+///     value_format: avro
+///     schema_registry_url: https://schema-registry.internal:8081
+/// ```
+pub(crate) enum RecordDecoder {
+    Avro(AvroDecoder),
+    Protobuf(MessageDescriptor),
+}
+
+pub(crate) struct AvroDecoder {
+    registry: Option<SchemaRegistry>,
+    schema: Option<Arc<Schema>>,
+}
+
+struct SchemaRegistry {
+    url: String,
+    credentials: Option<(String, String)>,
+    client: reqwest::Client,
+    /// Schemas are immutable once registered, so they're cached by ID for the invocation.
+    schemas: Mutex<HashMap<u32, Arc<Schema>>>,
+}
+
+impl RecordDecoder {
+    pub fn from_config(
+        config: &HashMap<String, String>,
+        registry_password: Option<String>,
+        client: reqwest::Client,
+    ) -> Result<Option<RecordDecoder>> {
+        let get = |k: &str| {
+            config
+                .get(k)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let format = get("value_format").unwrap_or_else(|| "json".to_string());
+        match format.to_lowercase().as_str() {
+            "json" => Ok(None),
+            "avro" => {
+                let registry = get("schema_registry_url").map(|url| SchemaRegistry {
+                    url: url.trim_end_matches('/').to_string(),
+                    credentials: get("schema_registry_username")
+                        .map(|username| (username, registry_password.unwrap_or_default())),
+                    client,
+                    schemas: Mutex::new(HashMap::new()),
+                });
+                let schema = get("avro_schema")
+                    .map(|s| Schema::parse_str(&s).context("Invalid avro_schema"))
+                    .transpose()?
+                    .map(Arc::new);
+                if registry.is_none() && schema.is_none() {
+                    return Err(anyhow!(
+                        "schema_registry_url or avro_schema is required for avro"
+                    ));
+                }
+                Ok(Some(RecordDecoder::Avro(AvroDecoder { registry, schema })))
+            }
+            "protobuf" => {
+                let descriptor_set =
+                    get("protobuf_descriptor_set").context("Missing protobuf_descriptor_set")?;
+                let descriptor_set = base64::decode(descriptor_set)
+                    .context("protobuf_descriptor_set must be base64")?;
+                let pool = DescriptorPool::decode(descriptor_set.as_slice())
+                    .context("Invalid protobuf_descriptor_set")?;
+                let name = get("protobuf_message").context("Missing protobuf_message")?;
+                let message = pool
+                    .get_message_by_name(&name)
+                    .with_context(|| format!("Unknown protobuf_message: {}", name))?;
+                Ok(Some(RecordDecoder::Protobuf(message)))
+            }
+            f => Err(anyhow!(
+                "Invalid value_format: {}, expected json, avro or protobuf",
+                f
+            )),
+        }
+    }
+
+    pub async fn decode(&self, ctx: &PullLogsContext, data: &[u8]) -> Result<Value> {
+        match self {
+            RecordDecoder::Avro(avro) => avro.decode(ctx, data).await,
+            RecordDecoder::Protobuf(message) => {
+                let payload = match confluent_schema_id(data) {
+                    Some(_) => skip_message_indexes(&data[CONFLUENT_HEADER_LEN..])?,
+                    None => data,
+                };
+                let message = DynamicMessage::decode(message.clone(), payload)?;
+                Ok(serde_json::to_value(&message)?)
+            }
+        }
+    }
+}
+
+impl AvroDecoder {
+    async fn decode(&self, ctx: &PullLogsContext, data: &[u8]) -> Result<Value> {
+        let (schema, mut payload) = match (confluent_schema_id(data), self.registry.as_ref()) {
+            (Some(id), Some(registry)) => (
+                registry.schema(ctx, id).await?,
+                &data[CONFLUENT_HEADER_LEN..],
+            ),
+            _ => (
+                self.schema
+                    .clone()
+                    .context("Record isn't registry framed and avro_schema isn't set")?,
+                data,
+            ),
+        };
+        let value = apache_avro::from_avro_datum(&schema, &mut payload, None)?;
+        Value::try_from(value).context("Failed to convert Avro record to JSON")
+    }
+}
+
+impl SchemaRegistry {
+    async fn schema(&self, ctx: &PullLogsContext, id: u32) -> Result<Arc<Schema>> {
+        let mut schemas = self.schemas.lock().await;
+        if let Some(schema) = schemas.get(&id) {
+            return Ok(schema.clone());
+        }
+        let mut req = self
+            .client
+            .get(format!("{}/schemas/ids/{}", self.url, id))
+            .header(
+                reqwest::header::ACCEPT,
+                "application/vnd.schemaregistry.v1+json",
+            );
+        if let Some((username, password)) = self.credentials.as_ref() {
+            req = req.basic_auth(username, Some(password));
+        }
+        let res = ctx.send(&self.client, req).await?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "Failed to get schema {} from registry: {} {}",
+                id,
+                status,
+                body
+            ));
+        }
+        let body: Value = res.json().await?;
+        let schema = body["schema"]
+            .as_str()
+            .with_context(|| format!("Missing schema {} in registry response", id))?;
+        let schema = Arc::new(Schema::parse_str(schema)?);
+        debug!("Loaded schema {} from registry", id);
+        schemas.insert(id, schema.clone());
+        Ok(schema)
+    }
+}
+
+fn confluent_schema_id(data: &[u8]) -> Option<u32> {
+    match data {
+        [CONFLUENT_MAGIC, a, b, c, d, ..] => Some(u32::from_be_bytes([*a, *b, *c, *d])),
+        _ => None,
+    }
+}
+
+/// Skips the message indexes of a Confluent framed Protobuf payload, a zigzag varint count
+/// followed by that many indexes, or a single zero for the first message.
+fn skip_message_indexes(mut data: &[u8]) -> Result<&[u8]> {
+    let count = read_varint(&mut data)?;
+    let count = (count >> 1) as i64 ^ -((count & 1) as i64);
+    if count < 0 {
+        return Err(anyhow!("Invalid Protobuf message index count: {}", count));
+    }
+    for _ in 0..count {
+        read_varint(&mut data)?;
+    }
+    Ok(data)
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = data
+            .split_first()
+            .context("Truncated Protobuf message indexes")?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("Invalid Protobuf message indexes"))
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use ring::{digest, hmac};
+use serde_json::Value;
+
+use super::signing::hmac_sign;
+
+const MASKED_VALUE: &str = "[REDACTED]";
+
+/// What to do with a redacted field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactAction {
+    /// Replaced by the hex SHA-256 of its value, keyed with the `redaction_key` secret field
+    /// if set, so it can still be joined on without being readable.
+    Hash,
+    /// Replaced by `[REDACTED]`.
+    Mask,
+    /// Removed.
+    Drop,
+}
+
+/// Redacts fields of pulled records before they're uploaded, e.g. PII of log sources subject
+/// to privacy constraints. Set with the `redact_fields` property, as comma separated
+/// `path:action` pairs. Paths are dot separated and apply to each element of arrays on the way.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     redact_fields: "client.ipAddress:hash, message:mask, debugContext:drop"
+/// ```
+pub struct Redaction {
+    fields: Vec<(Vec<String>, RedactAction)>,
+}
+
+impl Redaction {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<Redaction>> {
+        let spec = match config.get("redact_fields").map(|s| s.trim()) {
+            Some(spec) if !spec.is_empty() => spec,
+            _ => return Ok(None),
+        };
+        let fields = spec
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|field| {
+                let (path, action) = field
+                    .rsplit_once(':')
+                    .with_context(|| format!("Invalid redact_fields entry: {}", field))?;
+                let action = match action.trim().to_lowercase().as_str() {
+                    "hash" => RedactAction::Hash,
+                    "mask" => RedactAction::Mask,
+                    "drop" => RedactAction::Drop,
+                    a => return Err(anyhow!("Invalid redact_fields action: {}", a)),
+                };
+                let path = path
+                    .trim()
+                    .trim_start_matches('.')
+                    .split('.')
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>();
+                if path.iter().any(|s| s.is_empty()) {
+                    return Err(anyhow!("Invalid redact_fields path: {}", field));
+                }
+                Ok((path, action))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(Redaction { fields }))
+    }
+
+    /// Redacts the fields of each NDJSON record.
+    pub fn apply(&self, data: Vec<u8>, hash_key: Option<&[u8]>) -> Result<Vec<u8>> {
+        let mut ret = Vec::with_capacity(data.len());
+        for line in data.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+            let mut record: Value =
+                serde_json::from_slice(line).context("Invalid JSON record to redact")?;
+            for (path, action) in self.fields.iter() {
+                redact_path(&mut record, path, *action, hash_key);
+            }
+            if !ret.is_empty() {
+                ret.push(b'\n');
+            }
+            serde_json::to_writer(&mut ret, &record)?;
+        }
+        Ok(ret)
+    }
+}
+
+fn redact_path(value: &mut Value, path: &[String], action: RedactAction, hash_key: Option<&[u8]>) {
+    match value {
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|v| redact_path(v, path, action, hash_key)),
+        Value::Object(map) if path.len() == 1 => match action {
+            RedactAction::Drop => {
+                map.remove(&path[0]);
+            }
+            _ => {
+                if let Some(v) = map.get_mut(&path[0]).filter(|v| !v.is_null()) {
+                    *v = redacted_value(v, action, hash_key);
+                }
+            }
+        },
+        Value::Object(map) => {
+            if let Some(v) = map.get_mut(&path[0]) {
+                redact_path(v, &path[1..], action, hash_key);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn redacted_value(value: &Value, action: RedactAction, hash_key: Option<&[u8]>) -> Value {
+    match action {
+        RedactAction::Hash => {
+            // Strings are hashed as is, so the same value hashes the same elsewhere.
+            let data = match value {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            let hash = match hash_key {
+                Some(key) => hmac_sign(hmac::HMAC_SHA256, key, data.as_bytes()),
+                None => digest::digest(&digest::SHA256, data.as_bytes())
+                    .as_ref()
+                    .to_vec(),
+            };
+            Value::String(hex::encode(hash))
+        }
+        _ => Value::String(MASKED_VALUE.to_string()),
+    }
+}
@@ -0,0 +1,139 @@
+//! Pullers registered at startup rather than built in as a `LogSource` variant, e.g. for an
+//! in-house API, looked up by their managed type like the built in ones.
+//!
+//! A registered puller implements `DynPullLogs` and is registered in `extensions::register`
+//! (`src/pullers/extensions/mod.rs`), which is built with the `extensions` feature, so it can be
+//! added without changing the built in pullers.
+//!
+//! The log source then uses it with `managed.type: acme`, and the type is scheduled with
+//! `log_puller: { extension_types: { acme: 5 } }` (its rate in minutes) in `matano.config.yml`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use lazy_static::lazy_static;
+
+use super::{PropertySpec, PullLogs, PullLogsContext, RecordChunks};
+
+lazy_static! {
+    static ref REGISTRY: RwLock<HashMap<String, Arc<dyn DynPullLogs>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// `PullLogs` for registered pullers, which are shared by their log sources.
+#[async_trait]
+pub trait DynPullLogs: Send + Sync + 'static {
+    async fn pull_logs(
+        &self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>>;
+
+    /// See `PullLogs::pull_log_chunks`, the default is a single chunk of `pull_logs`.
+    fn pull_log_chunks<'a>(
+        self: Arc<Self>,
+        client: reqwest::Client,
+        ctx: &'a PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> RecordChunks<'a> {
+        Box::pin(futures::stream::once(async move {
+            self.pull_logs(client, ctx, start_dt, end_dt).await
+        }))
+    }
+
+    /// See `PullLogs::properties`.
+    fn properties(&self) -> &'static [PropertySpec] {
+        &[]
+    }
+
+    /// See `PullLogs::default_lag`.
+    fn default_lag(&self) -> chrono::Duration {
+        chrono::Duration::zero()
+    }
+
+    /// See `PullLogs::counts_requests`.
+    fn counts_requests(&self) -> bool {
+        true
+    }
+}
+
+/// Registers a puller for a managed type, replacing one registered before. Built in types
+/// can't be overridden.
+#[cfg_attr(not(any(feature = "extensions", feature = "wasm")), allow(dead_code))]
+pub fn register_puller(managed_type: &str, puller: impl DynPullLogs) {
+    REGISTRY
+        .write()
+        .unwrap()
+        .insert(managed_type.to_lowercase(), Arc::new(puller));
+}
+
+/// Registers pullers by managed type, see `register_puller`.
+#[macro_export]
+macro_rules! register_pullers {
+    ($($managed_type:literal => $puller:expr),* $(,)?) => {
+        $($crate::pullers::register_puller($managed_type, $puller);)*
+    };
+}
+
+/// A registered puller, as a `LogSource`.
+#[derive(Clone)]
+pub struct RegisteredPuller {
+    managed_type: String,
+    puller: Arc<dyn DynPullLogs>,
+}
+
+impl RegisteredPuller {
+    pub(crate) fn lookup(managed_type: &str) -> Option<RegisteredPuller> {
+        let managed_type = managed_type.to_lowercase();
+        let puller = REGISTRY.read().unwrap().get(&managed_type)?.clone();
+        Some(RegisteredPuller {
+            managed_type,
+            puller,
+        })
+    }
+
+    pub fn managed_type(&self) -> &str {
+        &self.managed_type
+    }
+}
+
+#[async_trait]
+impl PullLogs for RegisteredPuller {
+    async fn pull_logs(
+        self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        self.puller.pull_logs(client, ctx, start_dt, end_dt).await
+    }
+
+    fn pull_log_chunks<'a>(
+        self,
+        client: reqwest::Client,
+        ctx: &'a PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> RecordChunks<'a> {
+        self.puller.pull_log_chunks(client, ctx, start_dt, end_dt)
+    }
+
+    fn properties(&self) -> &'static [PropertySpec] {
+        self.puller.properties()
+    }
+
+    fn default_lag(&self) -> chrono::Duration {
+        self.puller.default_lag()
+    }
+
+    fn counts_requests(&self) -> bool {
+        self.puller.counts_requests()
+    }
+}
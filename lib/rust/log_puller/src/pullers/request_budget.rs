@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+
+use super::errors::{PullerError, METRICS_NAMESPACE};
+
+/// A daily limit on requests to a log source's API, for metered APIs billed per call (e.g.
+/// VirusTotal or Recorded Future). Every request counts, including retries and access token
+/// requests. Only pullers of HTTP APIs count their requests, see `PullLogs::counts_requests`.
+///
+/// Requests are counted in the checkpoint store per UTC day. Once `daily_request_budget` is
+/// used up, pulls are skipped until the next day, when the skipped windows are caught up, and a
+/// `RequestBudgetExhausted` metric is emitted. A pull that uses up the budget midway fails and
+/// is retried later. Pulls of the log source running at once may go over by a few requests.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     daily_request_budget: 500
+/// ```
+#[derive(Debug)]
+pub(crate) struct RequestBudget {
+    limit: u64,
+    /// Requests counted in the store when the current pull started.
+    used: AtomicU64,
+    /// Requests of the current pull, added to the store after it.
+    requests: AtomicU64,
+}
+
+impl RequestBudget {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<RequestBudget>> {
+        let limit = match config.get("daily_request_budget").map(|s| s.trim()) {
+            Some(s) if !s.is_empty() => s
+                .parse::<u64>()
+                .context("daily_request_budget must be an integer")?,
+            _ => return Ok(None),
+        };
+        Ok(Some(RequestBudget {
+            limit,
+            used: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+        }))
+    }
+
+    /// The day requests are currently counted under.
+    pub fn day() -> String {
+        Utc::now().format("%Y-%m-%d").to_string()
+    }
+
+    /// When the budget resets, the start of the next UTC day.
+    pub fn resets_at() -> DateTime<Utc> {
+        let today = Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+        DateTime::from_utc(today, Utc) + Duration::days(1)
+    }
+
+    /// Starts a pull with `used` requests already made today, returns false if none are left.
+    pub fn start(&self, used: u64) -> bool {
+        self.used.store(used, Ordering::SeqCst);
+        self.requests.store(0, Ordering::SeqCst);
+        used < self.limit
+    }
+
+    /// Counts a request, failing if the budget is used up.
+    pub fn take(&self, log_source_name: &str) -> Result<()> {
+        let requests = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.used.load(Ordering::SeqCst) + requests > self.limit {
+            self.requests.fetch_sub(1, Ordering::SeqCst);
+            return Err(anyhow!(
+                "Daily request budget of {} used up for {}, pulls resume at {}",
+                self.limit,
+                log_source_name,
+                Self::resets_at()
+            ))
+            .context(PullerError::RateLimited);
+        }
+        Ok(())
+    }
+
+    /// Returns and resets the requests of the current pull.
+    pub fn take_requests(&self) -> u64 {
+        self.requests.swap(0, Ordering::SeqCst)
+    }
+
+    /// Emits a `RequestBudgetExhausted` metric for the log source, in CloudWatch embedded metric
+    /// format, see `PullerError::emit_metric`.
+    pub fn emit_exhausted_metric(&self, log_source: &str) {
+        let metric = json!({
+            "_aws": {
+                "Timestamp": Utc::now().timestamp_millis(),
+                "CloudWatchMetrics": [{
+                    "Namespace": METRICS_NAMESPACE,
+                    "Dimensions": [["log_source"]],
+                    "Metrics": [{"Name": "RequestBudgetExhausted", "Unit": "Count"}],
+                }],
+            },
+            "log_source": log_source,
+            "daily_request_budget": self.limit,
+            "RequestBudgetExhausted": 1,
+        });
+        println!("{}", metric);
+    }
+}
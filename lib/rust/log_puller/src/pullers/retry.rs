@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use reqwest::StatusCode;
+use tracing::warn;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_BASE_DELAY_MS: u64 = 500;
+const DEFAULT_MAX_DELAY_MS: u64 = 20_000;
+/// 429s are handled separately by the rate limiter.
+const DEFAULT_RETRYABLE_STATUS_CODES: &[u16] = &[500, 502, 503, 504];
+
+/// Retries of transient failures for a log source's requests and uploads, with exponential
+/// backoff and full jitter.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     retry_max_attempts: 5
+///     retry_base_delay_ms: 1000
+///     retry_status_codes: 500,502,503,504,520
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub retryable_status_codes: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MS),
+            max_delay: Duration::from_millis(DEFAULT_MAX_DELAY_MS),
+            retryable_status_codes: DEFAULT_RETRYABLE_STATUS_CODES.to_vec(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<RetryPolicy> {
+        let get = |k: &str| config.get(k).map(|s| s.trim());
+        let default = RetryPolicy::default();
+
+        let max_attempts = get("retry_max_attempts")
+            .map(|s| s.parse::<u32>())
+            .transpose()
+            .context("retry_max_attempts must be an integer")?
+            .unwrap_or(default.max_attempts)
+            .max(1);
+        let base_delay = get("retry_base_delay_ms")
+            .map(|s| s.parse::<u64>())
+            .transpose()
+            .context("retry_base_delay_ms must be an integer")?
+            .map_or(default.base_delay, Duration::from_millis);
+        let max_delay = get("retry_max_delay_ms")
+            .map(|s| s.parse::<u64>())
+            .transpose()
+            .context("retry_max_delay_ms must be an integer")?
+            .map_or(default.max_delay, Duration::from_millis);
+        let retryable_status_codes = match get("retry_status_codes") {
+            Some(codes) => codes
+                .split(',')
+                .map(|c| c.trim())
+                .filter(|c| !c.is_empty())
+                .map(|c| c.parse::<u16>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .context("retry_status_codes must be a comma separated list of status codes")?,
+            None => default.retryable_status_codes,
+        };
+
+        Ok(RetryPolicy {
+            max_attempts,
+            base_delay,
+            max_delay,
+            retryable_status_codes,
+        })
+    }
+
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retryable_status_codes.contains(&status.as_u16())
+    }
+
+    /// Connection errors and timeouts are retried, errors building the request aren't.
+    pub fn is_retryable_error(&self, err: &reqwest::Error) -> bool {
+        err.is_connect() || err.is_timeout() || err.is_request()
+    }
+
+    /// Delay before the retry after the zero-based `attempt`, drawn uniformly
+    /// from `[0, min(max_delay, base_delay * 2^attempt)]`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        let millis = cap.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+
+    /// Runs `f` until it succeeds or attempts run out, for operations like S3 uploads
+    /// where any error is assumed to be transient.
+    pub async fn retry<T, F, Fut>(&self, op_name: &str, f: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Ok(v) => return Ok(v),
+                Err(e) if attempt + 1 < self.max_attempts => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{} failed (attempt {}), retrying in {:?}: {:#}",
+                        op_name,
+                        attempt + 1,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
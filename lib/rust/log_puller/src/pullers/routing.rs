@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde_json::Value;
+
+/// The field the ingestion `select_table_from_payload` expression of managed log sources reads
+/// the table from, e.g. `string!(del(.json._table))`.
+const TABLE_FIELD: &str = "_table";
+
+/// Routes pulled records to tables of the log source, e.g. to split an API's events into
+/// tables by event type. Set with `table` (every record) or `table_field` and `table_routes`,
+/// comma separated `pattern:table` pairs matched in order against the field's value, with `*`
+/// matching anything. Records no route matches go to the default table. Records already routed
+/// by their puller keep their table.
+///
+/// The log source must select the table from the payload, and have the tables, e.g. with
+/// `select_table_from_payload: string!(del(.json._table))`. With `destination_log_source`, the
+/// records are written under another log source, e.g. one with the tables of several pullers.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: okta
+///   properties:
+///     table_field: eventType
+///     table_routes: "user.session.*:sessions, policy.*:admin, system.*:admin"
+/// ```
+pub struct TableRouting {
+    field: Option<Vec<String>>,
+    routes: Vec<(Regex, String)>,
+}
+
+impl TableRouting {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<TableRouting>> {
+        let get = |k: &str| {
+            config
+                .get(k)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        if let Some(table) = get("table") {
+            return Ok(Some(TableRouting {
+                field: None,
+                routes: vec![(Regex::new(".*")?, table)],
+            }));
+        }
+        let field = match get("table_field") {
+            Some(field) => field
+                .trim_start_matches('.')
+                .split('.')
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>(),
+            None => return Ok(None),
+        };
+        let routes = get("table_routes")
+            .context("table_routes is required with table_field")?
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|route| {
+                let (pattern, table) = route
+                    .rsplit_once(':')
+                    .with_context(|| format!("Invalid table_routes entry: {}", route))?;
+                let (pattern, table) = (pattern.trim(), table.trim());
+                if table.is_empty() {
+                    return Err(anyhow!("Invalid table_routes entry: {}", route));
+                }
+                let pattern = format!("^{}$", regex::escape(pattern).replace("\\*", ".*"));
+                Ok((Regex::new(&pattern)?, table.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(TableRouting {
+            field: Some(field),
+            routes,
+        }))
+    }
+
+    /// Sets the table of each NDJSON record.
+    pub fn apply(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let mut ret = Vec::with_capacity(data.len());
+        for line in data.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+            let mut record: Value =
+                serde_json::from_slice(line).context("Invalid JSON record to route")?;
+            if let Some(table) = self.table(&record) {
+                if let Some(obj) = record.as_object_mut() {
+                    obj.entry(TABLE_FIELD)
+                        .or_insert_with(|| Value::String(table.to_string()));
+                }
+            }
+            serde_json::to_writer(&mut ret, &record)?;
+            ret.push(b'\n');
+        }
+        ret.pop();
+        Ok(ret)
+    }
+
+    fn table(&self, record: &Value) -> Option<&str> {
+        let value = match self.field.as_ref() {
+            Some(path) => {
+                let value = path.iter().try_fold(record, |v, key| v.get(key))?;
+                match value {
+                    Value::String(s) => s.clone(),
+                    Value::Null => return None,
+                    v => v.to_string(),
+                }
+            }
+            None => String::new(),
+        };
+        self.routes
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(&value))
+            .map(|(_, table)| table.as_str())
+    }
+}
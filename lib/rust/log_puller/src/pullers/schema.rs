@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use jsonschema::JSONSchema;
+use serde_json::{json, Value};
+use tracing::warn;
+
+/// Validation errors kept per record, the rest are counted.
+const MAX_RECORD_ERRORS: usize = 5;
+
+/// Validates pulled records against a JSON Schema, so records a vendor changed the shape of
+/// are set aside instead of breaking the transformer and table writes. Set with the
+/// `record_schema` property, a file next to the log source's `log_source.yml`.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     record_schema: record_schema.json
+/// ```
+///
+/// Records failing validation are quarantined (see `quarantine_payload`) as NDJSON of
+/// `{"record": ..., "errors": [...]}`, the valid ones are uploaded.
+pub struct RecordSchema {
+    file: String,
+    schema: JSONSchema,
+}
+
+impl RecordSchema {
+    pub fn from_config(
+        log_source: &str,
+        config: &HashMap<String, String>,
+    ) -> Result<Option<RecordSchema>> {
+        let file = match config.get("record_schema").map(|s| s.trim()) {
+            Some(file) if !file.is_empty() => file.to_string(),
+            _ => return Ok(None),
+        };
+        let path = shared::log_sources_config_dir()
+            .join(log_source)
+            .join(&file);
+        let data = std::fs::read(&path)
+            .with_context(|| format!("Failed to read record schema {}", path.display()))?;
+        let schema: Value = serde_json::from_slice(&data)
+            .with_context(|| format!("Invalid record schema {}", path.display()))?;
+        let schema = JSONSchema::compile(&schema)
+            .map_err(|e| anyhow!("Invalid record schema {}: {}", path.display(), e))?;
+        Ok(Some(RecordSchema { file, schema }))
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// Splits NDJSON into the valid records and the invalid ones, with their errors. Lines that
+    /// aren't JSON are invalid too.
+    pub fn validate(&self, data: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+        let mut valid = Vec::with_capacity(data.len());
+        let mut invalid = vec![];
+        for line in data.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+            let errors = match serde_json::from_slice::<Value>(line) {
+                Ok(record) => match self.schema.validate(&record) {
+                    Ok(()) => vec![],
+                    Err(errors) => errors
+                        .take(MAX_RECORD_ERRORS)
+                        .map(|e| format!("{}: {}", e.instance_path, e))
+                        .collect(),
+                },
+                Err(e) => vec![format!("Invalid JSON: {}", e)],
+            };
+            let out = match errors.is_empty() {
+                true => &mut valid,
+                false => &mut invalid,
+            };
+            if !out.is_empty() {
+                out.push(b'\n');
+            }
+            if errors.is_empty() {
+                out.extend_from_slice(line);
+                continue;
+            }
+            let record = serde_json::from_slice::<Value>(line)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(line).into_owned()));
+            let quarantined = json!({ "record": record, "errors": errors });
+            if let Err(e) = serde_json::to_writer(&mut *out, &quarantined) {
+                warn!("Failed to serialize invalid record: {}", e);
+            }
+        }
+        (valid, invalid)
+    }
+}
@@ -4,7 +4,7 @@ use tokio::sync::Mutex;
 use anyhow::{anyhow, Context as AnyhowContext, Error, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, Utc};
-use log::{debug, error, info};
+use tracing::{debug, error, info};
 
 use reqwest::header;
 use serde_json::json;
@@ -111,11 +111,12 @@ impl PullLogs for SnykPuller {
 
                 let body = json!({});
 
-                let response = client
+                let req = client
                     .post(url.clone())
                     .headers(headers.clone())
-                    .json(&body)
-                    .send()
+                    .json(&body);
+                let response = ctx
+                    .send(&client, req)
                     .await
                     .context("Failed to send request")?;
 
@@ -124,7 +125,7 @@ impl PullLogs for SnykPuller {
                     error!("Failed to get logs: {}", status)
                 }
 
-                let response_json: Vec<serde_json::Value> = response.json().await?;
+                let response_json: Vec<serde_json::Value> = ctx.response_json(response).await?;
 
                 let length = response_json.len();
 
@@ -165,11 +166,12 @@ impl PullLogs for SnykPuller {
 
                 let body = json!({});
 
-                let response = client
+                let req = client
                     .post(url.clone())
                     .headers(headers.clone())
-                    .json(&body)
-                    .send()
+                    .json(&body);
+                let response = ctx
+                    .send(&client, req)
                     .await
                     .context("Failed to send request")?;
 
@@ -178,7 +180,7 @@ impl PullLogs for SnykPuller {
                     error!("Failed to get logs: {}", status)
                 }
 
-                let response_json: Vec<serde_json::Value> = response.json().await?;
+                let response_json: Vec<serde_json::Value> = ctx.response_json(response).await?;
 
                 let length = response_json.len();
 
@@ -230,14 +232,13 @@ impl PullLogs for SnykPuller {
                     }
                 });
 
-                let response = client
+                let req = client
                     .post(url.clone())
                     .headers(headers.clone())
-                    .json(&body)
-                    .send()
-                    .await?;
+                    .json(&body);
+                let response = ctx.send(&client, req).await?;
 
-                let response_json: Vec<serde_json::Value> = response.json().await?;
+                let response_json: Vec<serde_json::Value> = ctx.response_json(response).await?;
                 let length = response_json.len();
 
                 for mut value in response_json {
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter};
+use tracing::{info, warn};
+
+/// Lambda ephemeral storage.
+const SPILL_DIR: &str = "/tmp";
+const DEFAULT_SPILL_THRESHOLD_MB: usize = 256;
+/// Spilled data is read back in chunks of about this size, extended to a line boundary.
+const SPILL_READ_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// How much pulled data is buffered in memory before spilling to ephemeral storage, from the
+/// `spill_threshold_mb` property or `PULLER_SPILL_THRESHOLD_MB`. Defaults to 256 MB.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     spill_threshold_mb: 512
+/// ```
+pub(crate) fn spill_threshold(config: &HashMap<String, String>) -> Result<usize> {
+    let mb = match config
+        .get("spill_threshold_mb")
+        .cloned()
+        .or_else(|| std::env::var("PULLER_SPILL_THRESHOLD_MB").ok())
+    {
+        Some(mb) => mb
+            .trim()
+            .parse::<usize>()
+            .context("spill_threshold_mb must be an integer")?,
+        None => DEFAULT_SPILL_THRESHOLD_MB,
+    };
+    Ok(mb.max(1) * 1024 * 1024)
+}
+
+/// NDJSON data buffered in memory up to a threshold, then in a file in ephemeral storage, so
+/// an unexpectedly large pull doesn't get the Lambda killed for running out of memory. The
+/// file is removed when the buffer is dropped.
+pub struct SpillBuffer {
+    name: String,
+    threshold: usize,
+    memory: Vec<u8>,
+    file: Option<(PathBuf, BufWriter<File>)>,
+    len: usize,
+}
+
+impl SpillBuffer {
+    pub fn new(name: &str, threshold: usize) -> SpillBuffer {
+        SpillBuffer {
+            name: name.to_string(),
+            threshold,
+            memory: vec![],
+            file: None,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends NDJSON lines, separated from the previous ones by a newline.
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let separator: &[u8] = match self.len {
+            0 => b"",
+            _ => b"\n",
+        };
+        if self.file.is_none() && self.len + separator.len() + data.len() > self.threshold {
+            self.spill().await?;
+        }
+        match self.file.as_mut() {
+            Some((path, file)) => {
+                for data in [separator, data] {
+                    file.write_all(data)
+                        .await
+                        .with_context(|| format!("Failed to write to {}", path.display()))?;
+                }
+            }
+            None => {
+                self.memory.extend_from_slice(separator);
+                self.memory.extend_from_slice(data);
+            }
+        }
+        self.len += separator.len() + data.len();
+        Ok(())
+    }
+
+    /// Moves the buffered data to a file, where the rest is written.
+    async fn spill(&mut self) -> Result<()> {
+        let path = PathBuf::from(SPILL_DIR).join(format!("puller-spill-{}", uuid::Uuid::new_v4()));
+        info!(
+            "Spilling {} buffered bytes for {} to {}",
+            self.len,
+            self.name,
+            path.display()
+        );
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Failed to create spill file {}", path.display()))?;
+        let file = BufWriter::new(file);
+        let memory = std::mem::take(&mut self.memory);
+        self.file = Some((path, file));
+        let (path, file) = self.file.as_mut().unwrap();
+        file.write_all(&memory)
+            .await
+            .with_context(|| format!("Failed to write to {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Reads the data back in chunks of whole lines.
+    pub async fn into_chunks(mut self) -> Result<SpillChunks> {
+        let file = match self.file.take() {
+            Some((path, mut file)) => {
+                let res = async {
+                    file.flush().await?;
+                    let mut file = file.into_inner();
+                    file.seek(std::io::SeekFrom::Start(0)).await?;
+                    std::io::Result::Ok(BufReader::new(file))
+                }
+                .await;
+                match res {
+                    Ok(reader) => Some(SpilledFile { path, reader }),
+                    Err(e) => {
+                        remove_spill_file(&path);
+                        return Err(e)
+                            .with_context(|| format!("Failed to read {}", path.display()));
+                    }
+                }
+            }
+            None => None,
+        };
+        Ok(SpillChunks {
+            memory: Some(std::mem::take(&mut self.memory)).filter(|m| !m.is_empty()),
+            file,
+        })
+    }
+}
+
+impl Drop for SpillBuffer {
+    fn drop(&mut self) {
+        if let Some((path, _)) = self.file.take() {
+            remove_spill_file(&path);
+        }
+    }
+}
+
+/// The chunks of a `SpillBuffer`, see `SpillBuffer::into_chunks`.
+pub struct SpillChunks {
+    memory: Option<Vec<u8>>,
+    file: Option<SpilledFile>,
+}
+
+struct SpilledFile {
+    path: PathBuf,
+    reader: BufReader<File>,
+}
+
+impl Drop for SpilledFile {
+    fn drop(&mut self) {
+        remove_spill_file(&self.path);
+    }
+}
+
+impl SpillChunks {
+    /// The next chunk of about `SPILL_READ_CHUNK_SIZE` bytes, without its trailing newline.
+    pub async fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        if let Some(memory) = self.memory.take() {
+            return Ok(Some(memory));
+        }
+        let spilled = match self.file.as_mut() {
+            Some(spilled) => spilled,
+            None => return Ok(None),
+        };
+        let reader = &mut spilled.reader;
+        let mut chunk = Vec::with_capacity(SPILL_READ_CHUNK_SIZE);
+        let res = async {
+            (&mut *reader)
+                .take(SPILL_READ_CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)
+                .await?;
+            if !chunk.is_empty() && chunk.last() != Some(&b'\n') {
+                reader.read_until(b'\n', &mut chunk).await?;
+            }
+            std::io::Result::Ok(())
+        }
+        .await;
+        res.with_context(|| format!("Failed to read {}", spilled.path.display()))?;
+        if chunk.last() == Some(&b'\n') {
+            chunk.pop();
+        }
+        if chunk.is_empty() {
+            self.file = None;
+            return Ok(None);
+        }
+        Ok(Some(chunk))
+    }
+}
+
+fn remove_spill_file(path: &std::path::Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        warn!("Failed to remove spill file {}: {}", path.display(), e);
+    }
+}
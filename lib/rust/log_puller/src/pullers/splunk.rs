@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use super::custom_api::{ApiAuth, AuthType};
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
+
+/// Runs a saved or ad-hoc Splunk search over the pull window using the streaming
+/// `search/jobs/export` endpoint. Useful for migrating data out of an existing Splunk deployment.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   type: splunk
+///   properties:
+///     base_url: https://splunk.example.com:8089
+///     search: index=main sourcetype=WinEventLog
+///     # or
+///     saved_search: "Failed Logins"
+///     app: search
+/// ```
+#[derive(Clone)]
+pub struct SplunkPuller;
+
+struct SplunkConfig {
+    base_url: String,
+    search: String,
+    owner: String,
+    app: String,
+    auth: ApiAuth,
+}
+
+impl SplunkConfig {
+    fn from_config(config: &HashMap<String, String>) -> Result<SplunkConfig> {
+        let get = |k: &str| config.get(k).map(|s| s.trim().to_string());
+
+        let search = match (get("search"), get("saved_search")) {
+            (Some(s), None) => {
+                // Ad-hoc searches must start with a command, default to `search`.
+                if s.starts_with('|') || s.starts_with("search ") {
+                    s
+                } else {
+                    format!("search {}", s)
+                }
+            }
+            (None, Some(name)) => format!("| savedsearch \"{}\"", name.replace('"', "\\\"")),
+            (Some(_), Some(_)) => {
+                return Err(anyhow!("Only one of search or saved_search can be set"))
+            }
+            (None, None) => return Err(anyhow!("Missing search or saved_search")),
+        };
+
+        // Splunk tokens are sent as bearer tokens by default.
+        let mut auth = ApiAuth::from_config(config)?;
+        if !config.contains_key("auth_type") {
+            auth.auth_type = AuthType::Bearer;
+        }
+
+        Ok(SplunkConfig {
+            base_url: get("base_url")
+                .context("Missing base_url")?
+                .trim_end_matches('/')
+                .to_string(),
+            search,
+            owner: get("owner").unwrap_or_else(|| "-".to_string()),
+            app: get("app").unwrap_or_else(|| "search".to_string()),
+            auth,
+        })
+    }
+
+    fn export_url(&self) -> String {
+        format!(
+            "{}/servicesNS/{}/{}/search/jobs/export",
+            self.base_url, self.owner, self.app
+        )
+    }
+}
+
+const PROPERTIES: &[PropertySpec] = &[PropertySpec::required(
+    "base_url",
+    PropertyType::Url,
+    "https://splunk.example.com:8089",
+)];
+
+#[async_trait]
+impl PullLogs for SplunkPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    async fn pull_logs(
+        self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!("Pulling Splunk search for {}....", ctx.log_source_name);
+
+        let splunk_config = SplunkConfig::from_config(ctx.config())?;
+        let (headers, auth_query) = match splunk_config.auth.resolve(&client, ctx).await? {
+            Some(auth) => auth,
+            None => return Ok(vec![]),
+        };
+
+        let form = [
+            ("search", splunk_config.search.clone()),
+            ("output_mode", "json".to_string()),
+            ("earliest_time", start_dt.timestamp().to_string()),
+            ("latest_time", end_dt.timestamp().to_string()),
+        ];
+
+        let req = client
+            .post(splunk_config.export_url())
+            .headers(headers)
+            .query(&auth_query)
+            .form(&form);
+        let mut res = ctx.send(&client, req).await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let msg = res.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "Splunk export failed, status: {}, response: {}",
+                status,
+                msg
+            ));
+        }
+
+        // The export endpoint streams one JSON object per line, process it as it arrives.
+        let mut ret: Vec<u8> = vec![];
+        let mut buf: Vec<u8> = vec![];
+        let mut num_results = 0;
+        while let Some(chunk) = res.chunk().await? {
+            buf.extend_from_slice(&chunk);
+            while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                let line = buf.drain(..=pos).collect::<Vec<_>>();
+                num_results += process_line(&line, &mut ret)?;
+            }
+        }
+        num_results += process_line(&buf, &mut ret)?;
+        debug!("Loaded {} results for {}", num_results, ctx.log_source_name);
+
+        // Remove last newline
+        if ret.last() == Some(&b'\n') {
+            ret.pop();
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Appends the result in an export line to `ret`, returning the number of results written.
+fn process_line(line: &[u8], ret: &mut Vec<u8>) -> Result<usize> {
+    let line = std::str::from_utf8(line)?.trim();
+    if line.is_empty() {
+        return Ok(0);
+    }
+    let mut row: Value =
+        serde_json::from_str(line).context("Failed to parse Splunk export line")?;
+
+    // Messages are sent inline, e.g. for search syntax errors.
+    if let Some(messages) = row.get("messages").and_then(|v| v.as_array()) {
+        for msg in messages {
+            let msg_type = msg.get("type").and_then(|v| v.as_str()).unwrap_or_default();
+            let text = msg.get("text").and_then(|v| v.as_str()).unwrap_or_default();
+            match msg_type {
+                "FATAL" | "ERROR" => return Err(anyhow!("Splunk search error: {}", text)),
+                _ => warn!("Splunk search message: {}: {}", msg_type, text),
+            }
+        }
+    }
+
+    if row.get("preview").and_then(|v| v.as_bool()) == Some(true) {
+        return Ok(0);
+    }
+    match row.get_mut("result").map(|v| v.take()) {
+        Some(result) => {
+            ret.extend(serde_json::to_vec(&result)?);
+            ret.push(b'\n');
+            Ok(1)
+        }
+        None => Ok(0),
+    }
+}
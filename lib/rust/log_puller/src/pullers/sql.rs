@@ -0,0 +1,228 @@
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{json, Value};
+use sqlx::{Column, ConnectOptions, Row};
+use tracing::info;
+
+use super::{PropertySpec, PropertyType, PullLogs, PullLogsContext};
+
+/// Runs an incremental query against a Postgres or MySQL database (e.g. application audit tables, pgaudit views),
+/// using a monotonically increasing column as the cursor.
+///
+/// The query must filter on the cursor using a bind parameter (`$1` for Postgres, `?` for MySQL), ex:
+/// ```yaml
+/// managed:
+///   type: sql
+///   properties:
+///     driver: postgres
+///     host: db.internal
+///     database: app
+///     username: matano_reader
+///     query: "SELECT * FROM audit_log WHERE id > $1::bigint"
+///     cursor_column: id
+/// ```
+#[derive(Clone)]
+pub struct SqlPuller;
+
+const DEFAULT_BATCH_SIZE: i64 = 10000;
+
+lazy_static! {
+    static ref IDENTIFIER_RE: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+}
+
+const PROPERTIES: &[PropertySpec] = &[
+    PropertySpec::required("driver", PropertyType::String, "postgres"),
+    PropertySpec::required("host", PropertyType::String, "db.example.com"),
+    PropertySpec::required("database", PropertyType::String, "audit"),
+    PropertySpec::required("username", PropertyType::String, "matano"),
+    PropertySpec::required(
+        "query",
+        PropertyType::String,
+        "SELECT * FROM events WHERE id > $1 ORDER BY id",
+    ),
+    PropertySpec::optional("port", PropertyType::Integer, "5432"),
+    PropertySpec::optional("batch_size", PropertyType::Integer, "1000"),
+];
+
+#[async_trait]
+impl PullLogs for SqlPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    fn counts_requests(&self) -> bool {
+        false
+    }
+
+    async fn pull_logs(
+        self,
+        _client: reqwest::Client,
+        ctx: &PullLogsContext,
+        _start_dt: DateTime<FixedOffset>,
+        _end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        info!("Pulling SQL rows for {}....", ctx.log_source_name);
+
+        let config = ctx.config();
+
+        let driver = config
+            .get("driver")
+            .map(|s| s.to_lowercase())
+            .context("Missing driver")?;
+        let host = config.get("host").context("Missing host")?;
+        let database = config.get("database").context("Missing database")?;
+        let username = config.get("username").context("Missing username")?;
+        let query = config.get("query").context("Missing query")?;
+        let cursor_column = config
+            .get("cursor_column")
+            .context("Missing cursor_column")?;
+        if !IDENTIFIER_RE.is_match(cursor_column) {
+            return Err(anyhow!("Invalid cursor_column: {}", cursor_column));
+        }
+        let batch_size = config
+            .get("batch_size")
+            .map(|s| s.parse::<i64>())
+            .transpose()
+            .context("batch_size must be an integer")?
+            .unwrap_or(DEFAULT_BATCH_SIZE);
+        let port = config
+            .get("port")
+            .map(|s| s.parse::<u16>())
+            .transpose()
+            .context("port must be a number")?;
+
+        let password = ctx
+            .get_secret_field("password")
+            .await?
+            .context("Missing database password")?;
+
+        // skip early if password is equal <placeholder>
+        if password == "<placeholder>" {
+            info!("Skipping sql because secret is still <placeholder>");
+            return Ok(vec![]);
+        }
+
+        let mut checkpoint_json = ctx.checkpoint_json.lock().await;
+        let cursor = checkpoint_json
+            .as_ref()
+            .and_then(|v| cursor_to_string(&v["cursor"]))
+            .or_else(|| config.get("initial_cursor").cloned())
+            .unwrap_or_else(|| "0".to_string());
+
+        let rows = match driver.as_str() {
+            "postgres" | "postgresql" => {
+                let mut conn = sqlx::postgres::PgConnectOptions::new()
+                    .host(host)
+                    .port(port.unwrap_or(5432))
+                    .username(username)
+                    .password(&password)
+                    .database(database)
+                    .connect()
+                    .await
+                    .context("Failed to connect to Postgres")?;
+                // Let postgres do the row serialization, it knows all of its types.
+                let sql = format!(
+                    "SELECT row_to_json(matano_q)::text FROM ({}) AS matano_q ORDER BY matano_q.{} LIMIT {}",
+                    query, cursor_column, batch_size
+                );
+                sqlx::query_scalar::<_, String>(&sql)
+                    .bind(&cursor)
+                    .fetch_all(&mut conn)
+                    .await?
+                    .into_iter()
+                    .map(|s| serde_json::from_str::<Value>(&s).map_err(|e| anyhow!(e)))
+                    .collect::<Result<Vec<_>>>()?
+            }
+            "mysql" | "mariadb" => {
+                let mut conn = sqlx::mysql::MySqlConnectOptions::new()
+                    .host(host)
+                    .port(port.unwrap_or(3306))
+                    .username(username)
+                    .password(&password)
+                    .database(database)
+                    .connect()
+                    .await
+                    .context("Failed to connect to MySQL")?;
+                let sql = format!(
+                    "SELECT * FROM ({}) AS matano_q ORDER BY matano_q.{} LIMIT {}",
+                    query, cursor_column, batch_size
+                );
+                sqlx::query(&sql)
+                    .bind(&cursor)
+                    .fetch_all(&mut conn)
+                    .await?
+                    .iter()
+                    .map(mysql_row_to_json)
+                    .collect::<Vec<_>>()
+            }
+            d => return Err(anyhow!("Unsupported driver: {}", d)),
+        };
+
+        info!("Pulled {} rows for {}", rows.len(), ctx.log_source_name);
+
+        let new_cursor = rows
+            .last()
+            .and_then(|row| cursor_to_string(&row[cursor_column.as_str()]));
+
+        let mut ret: Vec<u8> = vec![];
+        for row in rows {
+            ret.extend(serde_json::to_vec(&row)?);
+            ret.push(b'\n');
+        }
+
+        // Remove last newline
+        if ret.last() == Some(&b'\n') {
+            ret.pop();
+        }
+
+        // update checkpoint
+        if let Some(new_cursor) = new_cursor {
+            *checkpoint_json = Some(json!({ "cursor": new_cursor }));
+        }
+
+        Ok(ret)
+    }
+}
+
+fn cursor_to_string(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.to_owned()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// MySQL has no `row_to_json`, so decode each column by trying the common types.
+fn mysql_row_to_json(row: &sqlx::mysql::MySqlRow) -> Value {
+    let mut obj = serde_json::Map::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        let value = if let Ok(v) = row.try_get::<Option<i64>, _>(i) {
+            json!(v)
+        } else if let Ok(v) = row.try_get::<Option<u64>, _>(i) {
+            json!(v)
+        } else if let Ok(v) = row.try_get::<Option<f64>, _>(i) {
+            json!(v)
+        } else if let Ok(v) = row.try_get::<Option<bool>, _>(i) {
+            json!(v)
+        } else if let Ok(v) = row.try_get::<Option<Value>, _>(i) {
+            v.unwrap_or(Value::Null)
+        } else if let Ok(v) = row.try_get::<Option<DateTime<Utc>>, _>(i) {
+            json!(v.map(|d| d.to_rfc3339()))
+        } else if let Ok(v) = row.try_get::<Option<NaiveDateTime>, _>(i) {
+            json!(v.map(|d| d.to_string()))
+        } else if let Ok(v) = row.try_get::<Option<NaiveDate>, _>(i) {
+            json!(v.map(|d| d.to_string()))
+        } else if let Ok(v) = row.try_get::<Option<String>, _>(i) {
+            json!(v)
+        } else if let Ok(v) = row.try_get::<Option<Vec<u8>>, _>(i) {
+            json!(v.map(|b| String::from_utf8_lossy(&b).to_string()))
+        } else {
+            Value::Null
+        };
+        obj.insert(column.name().to_string(), value);
+    }
+    Value::Object(obj)
+}
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tracing::{error, info};
+
+use super::audit::PullAttempt;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref SNS_CLIENT: AsyncOnce<aws_sdk_sns::Client> =
+        AsyncOnce::new(async { aws_sdk_sns::Client::new(AWS_CONFIG.get().await) });
+    static ref SQS_CLIENT: AsyncOnce<aws_sdk_sqs::Client> =
+        AsyncOnce::new(async { aws_sdk_sqs::Client::new(AWS_CONFIG.get().await) });
+    static ref EVENTBRIDGE_CLIENT: AsyncOnce<aws_sdk_eventbridge::Client> =
+        AsyncOnce::new(async { aws_sdk_eventbridge::Client::new(AWS_CONFIG.get().await) });
+}
+
+/// Totals of a log source's pulls (over all its tenants) in an invocation.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SourceStats {
+    pub pulls: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub events: usize,
+    pub bytes: usize,
+    /// Summed over pulls, which run concurrently.
+    pub duration_ms: u64,
+}
+
+/// What an invocation pulled, by log source.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PullStats {
+    pub sources: BTreeMap<String, SourceStats>,
+}
+
+impl PullStats {
+    pub fn from_attempts(attempts: &[PullAttempt]) -> PullStats {
+        let mut sources = BTreeMap::new();
+        for attempt in attempts {
+            let stats: &mut SourceStats = sources.entry(attempt.log_source.clone()).or_default();
+            stats.pulls += 1;
+            match attempt.outcome {
+                "success" => stats.succeeded += 1,
+                "failure" => stats.failed += 1,
+                _ => stats.skipped += 1,
+            }
+            stats.events += attempt.event_count;
+            stats.bytes += attempt.uploaded_bytes;
+            stats.duration_ms += attempt.duration_ms;
+        }
+        PullStats { sources }
+    }
+
+    /// Publishes the stats to `PULLER_STATS_DESTINATION_ARN` if set, an SNS topic, SQS queue
+    /// or EventBridge bus ARN, so orchestration can verify throughput without reading logs.
+    /// Lambda destinations don't apply to SQS triggered invocations. Errors are only logged.
+    pub async fn publish(&self) {
+        let destination = match std::env::var("PULLER_STATS_DESTINATION_ARN") {
+            Ok(arn) if !arn.is_empty() => arn,
+            _ => return,
+        };
+        if self.sources.is_empty() {
+            return;
+        }
+        match publish_to(&destination, self).await {
+            Ok(()) => info!("Published pull stats to {}", destination),
+            Err(e) => error!("Failed to publish pull stats to {}: {:#}", destination, e),
+        }
+    }
+}
+
+async fn publish_to(arn: &str, stats: &PullStats) -> Result<()> {
+    let message = serde_json::to_string(stats)?;
+    // arn:partition:service:region:account:resource
+    let parts = arn.splitn(6, ':').collect::<Vec<_>>();
+    if parts.len() != 6 {
+        return Err(anyhow!("Invalid ARN"));
+    }
+    match parts[2] {
+        "sns" => {
+            SNS_CLIENT
+                .get()
+                .await
+                .publish()
+                .topic_arn(arn)
+                .message(message)
+                .send()
+                .await
+                .context("Error publishing to SNS")?;
+        }
+        "sqs" => {
+            let queue_url = format!(
+                "https://sqs.{}.amazonaws.com/{}/{}",
+                parts[3], parts[4], parts[5]
+            );
+            SQS_CLIENT
+                .get()
+                .await
+                .send_message()
+                .queue_url(queue_url)
+                .message_body(message)
+                .send()
+                .await
+                .context("Error sending to SQS")?;
+        }
+        "events" => {
+            let entry = aws_sdk_eventbridge::model::PutEventsRequestEntry::builder()
+                .event_bus_name(arn)
+                .source("matano.log_puller")
+                .detail_type("Matano Puller Stats")
+                .detail(message)
+                .build();
+            let res = EVENTBRIDGE_CLIENT
+                .get()
+                .await
+                .put_events()
+                .entries(entry)
+                .send()
+                .await
+                .context("Error sending to EventBridge")?;
+            let entries = res.entries().unwrap_or_default();
+            if entries.iter().any(|e| e.error_code().is_some()) {
+                return Err(anyhow!("EventBridge rejected the event"));
+            }
+        }
+        service => return Err(anyhow!("Unsupported destination service: {}", service)),
+    }
+    Ok(())
+}
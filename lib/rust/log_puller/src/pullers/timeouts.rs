@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+/// HTTP timeouts for a log source's requests, for slow APIs (e.g. on-prem appliances) that
+/// would otherwise hang until the Lambda times out. Unset timeouts use the reqwest defaults.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     # whole request, including reading the body
+///     http_timeout_seconds: 120
+///     http_connect_timeout_seconds: 10
+///     # waiting for the response headers after sending the request
+///     http_read_timeout_seconds: 60
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HttpTimeouts {
+    pub request: Option<Duration>,
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+}
+
+impl HttpTimeouts {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<HttpTimeouts> {
+        Ok(HttpTimeouts {
+            request: parse_seconds(config, "http_timeout_seconds")?,
+            connect: parse_seconds(config, "http_connect_timeout_seconds")?,
+            read: parse_seconds(config, "http_read_timeout_seconds")?,
+        })
+    }
+}
+
+fn parse_seconds(config: &HashMap<String, String>, key: &str) -> Result<Option<Duration>> {
+    let secs = match config.get(key) {
+        Some(v) => v
+            .trim()
+            .parse::<f64>()
+            .with_context(|| format!("{} must be a number", key))?,
+        None => return Ok(None),
+    };
+    if !secs.is_finite() || secs <= 0.0 {
+        return Err(anyhow!("{} must be positive", key));
+    }
+    Ok(Some(Duration::from_secs_f64(secs)))
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::model::AttributeValue;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use rand::Rng;
+use tracing::debug;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref DDB_CLIENT: AsyncOnce<aws_sdk_dynamodb::Client> =
+        AsyncOnce::new(async { aws_sdk_dynamodb::Client::new(AWS_CONFIG.get().await) });
+}
+
+/// Attempts to take a token when other invocations keep updating the bucket concurrently.
+const MAX_CONFLICT_RETRIES: usize = 10;
+/// Buckets that aren't used for a day are cleaned up.
+const BUCKET_TTL_SECS: i64 = 24 * 60 * 60;
+
+/// A requests-per-minute budget shared by all puller invocations, for APIs pulled by several
+/// log sources or tenants at once (e.g. multiple Okta orgs behind one rate limit).
+///
+/// The bucket is stored in the `PULLER_RATE_LIMIT_TABLE_NAME` DynamoDB table and refills
+/// continuously, holding at most one minute of requests.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     rate_limit_requests_per_minute: 600
+///     # optional, log sources with the same key share a budget, defaults to the log source name
+///     rate_limit_key: okta
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct DistributedTokenBucket {
+    table_name: String,
+    key: String,
+    per_minute: f64,
+}
+
+/// What happened when trying to take a token.
+enum Take {
+    Taken,
+    /// Not enough tokens, retry after the wait.
+    Empty(Duration),
+    /// Another invocation updated the bucket first.
+    Conflict,
+}
+
+impl DistributedTokenBucket {
+    /// Returns None if no budget is configured or the table isn't set up.
+    pub fn from_config(
+        log_source_name: &str,
+        config: &HashMap<String, String>,
+    ) -> Result<Option<DistributedTokenBucket>> {
+        let per_minute = match config.get("rate_limit_requests_per_minute") {
+            Some(v) => v
+                .trim()
+                .parse::<f64>()
+                .context("rate_limit_requests_per_minute must be a number")?,
+            None => return Ok(None),
+        };
+        if per_minute <= 0.0 {
+            return Err(anyhow!("rate_limit_requests_per_minute must be positive"));
+        }
+        let table_name = match std::env::var("PULLER_RATE_LIMIT_TABLE_NAME") {
+            Ok(t) => t,
+            Err(_) => return Ok(None),
+        };
+        let key = config
+            .get("rate_limit_key")
+            .map(|k| k.trim().to_string())
+            .unwrap_or_else(|| log_source_name.to_string());
+
+        Ok(Some(DistributedTokenBucket {
+            table_name,
+            key,
+            per_minute,
+        }))
+    }
+
+    /// Takes a token, returning how long to wait first if the bucket is empty. The token is
+    /// reserved by letting the balance go negative, so waiters are served in order.
+    pub async fn take(&self) -> Result<Duration> {
+        for _ in 0..MAX_CONFLICT_RETRIES {
+            match self.try_take().await? {
+                Take::Taken => return Ok(Duration::ZERO),
+                Take::Empty(wait) => return Ok(wait),
+                Take::Conflict => {
+                    let jitter = rand::thread_rng().gen_range(5..50);
+                    tokio::time::sleep(Duration::from_millis(jitter)).await;
+                }
+            }
+        }
+        Err(anyhow!(
+            "Failed to take a rate limit token for {}, too much contention",
+            self.key
+        ))
+    }
+
+    async fn try_take(&self) -> Result<Take> {
+        let ddb = DDB_CLIENT.get().await;
+        let res = ddb
+            .get_item()
+            .table_name(&self.table_name)
+            .key("pk", AttributeValue::S(self.key.clone()))
+            .consistent_read(true)
+            .send()
+            .await
+            .context("Failed to load rate limit bucket")?;
+
+        let get_n = |name: &str| {
+            res.item()
+                .and_then(|item| item.get(name))
+                .and_then(|v| v.as_n().ok())
+        };
+        let now_ms = Utc::now().timestamp_millis();
+        // Kept as is for the condition, so the comparison is exact.
+        let prev_updated_at = get_n("updated_at").cloned();
+        let per_ms = self.per_minute / 60_000.0;
+        let tokens = match (
+            get_n("tokens").and_then(|n| n.parse::<f64>().ok()),
+            prev_updated_at.as_ref().and_then(|n| n.parse::<i64>().ok()),
+        ) {
+            (Some(tokens), Some(updated_at)) => {
+                let elapsed_ms = (now_ms - updated_at).max(0) as f64;
+                (tokens + elapsed_ms * per_ms).min(self.per_minute)
+            }
+            _ => self.per_minute,
+        };
+        let new_tokens = tokens - 1.0;
+
+        let mut req = ddb
+            .put_item()
+            .table_name(&self.table_name)
+            .item("pk", AttributeValue::S(self.key.clone()))
+            .item("tokens", AttributeValue::N(new_tokens.to_string()))
+            .item("updated_at", AttributeValue::N(now_ms.to_string()))
+            .item(
+                "ttl",
+                AttributeValue::N((now_ms / 1000 + BUCKET_TTL_SECS).to_string()),
+            );
+        req = match prev_updated_at {
+            Some(prev) => req
+                .condition_expression("updated_at = :prev")
+                .expression_attribute_values(":prev", AttributeValue::N(prev)),
+            None => req.condition_expression("attribute_not_exists(pk)"),
+        };
+        match req.send().await {
+            Ok(_) => {}
+            Err(e) => {
+                let se = e.into_service_error();
+                if se.is_conditional_check_failed_exception() {
+                    return Ok(Take::Conflict);
+                }
+                return Err(se).context("Failed to update rate limit bucket");
+            }
+        }
+
+        if new_tokens >= 0.0 {
+            Ok(Take::Taken)
+        } else {
+            let wait_ms = (-new_tokens / per_ms).ceil() as u64;
+            debug!(
+                "Rate limit bucket {} is empty, waiting {}ms",
+                self.key, wait_ms
+            );
+            Ok(Take::Empty(Duration::from_millis(wait_ms)))
+        }
+    }
+}
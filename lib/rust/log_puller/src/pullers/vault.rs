@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use super::signing::RequestSigner;
+use super::sigv4::AwsSigV4Signer;
+use super::{global_proxy, HttpTimeouts};
+
+/// Tokens are renewed (or logged in again) when they expire within this.
+const TOKEN_RENEW_MARGIN: Duration = Duration::from_secs(60);
+/// Vault requests time out after this unless `http_timeout_seconds` is set, so an unreachable
+/// Vault fails the pull instead of hanging until the Lambda times out.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Vault AWS auth verifies a signed `sts:GetCallerIdentity` request to the global endpoint.
+const STS_URL: &str = "https://sts.amazonaws.com/";
+const STS_BODY: &str = "Action=GetCallerIdentity&Version=2011-06-15";
+
+/// How the puller logs in to Vault.
+#[derive(Debug, Clone)]
+enum VaultAuth {
+    /// AWS IAM auth with the puller's role, bound to the Vault `role`.
+    Aws {
+        mount: String,
+        role: String,
+        /// Sent as `X-Vault-AWS-IAM-Server-ID`, if the auth method requires it.
+        server_id: Option<String>,
+    },
+    AppRole {
+        mount: String,
+        role_id: String,
+        secret_id: String,
+    },
+}
+
+#[derive(Debug)]
+struct VaultToken {
+    client_token: String,
+    renewable: bool,
+    expires_at: Option<Instant>,
+}
+
+impl VaultToken {
+    fn from_auth(auth: &Value) -> Result<VaultToken> {
+        let client_token = auth["client_token"]
+            .as_str()
+            .context("Missing Vault client token")?
+            .to_string();
+        // Root and other non expiring tokens have a lease duration of 0.
+        let expires_at = match auth["lease_duration"].as_u64().unwrap_or_default() {
+            0 => None,
+            secs => Some(Instant::now() + Duration::from_secs(secs)),
+        };
+        Ok(VaultToken {
+            client_token,
+            renewable: auth["renewable"].as_bool().unwrap_or_default(),
+            expires_at,
+        })
+    }
+
+    fn expires_soon(&self) -> bool {
+        self.expires_at
+            .map_or(false, |t| t <= Instant::now() + TOKEN_RENEW_MARGIN)
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at.map_or(false, |t| t <= Instant::now())
+    }
+}
+
+/// Reads a log source's secret fields from a HashiCorp Vault KV v2 secret instead of Secrets
+/// Manager, for orgs that can't store vendor credentials in AWS. Enabled by the `vault_path`
+/// property, the puller logs in with AWS IAM auth (its own role) or AppRole, and renews its
+/// token while it's warm. Secrets are only read, rotated OAuth2 refresh tokens aren't written
+/// back.
+///
+/// Vault is requested with its own client, as the log source's client may need secret fields
+/// (e.g. `client_cert`). It trusts the PEM `vault_ca_cert` (or `VAULT_CACERT` file, or
+/// `tls_ca_cert`), goes through the `proxy_url` or `PULLER_PROXY_URL` proxy, and uses the log
+/// source's HTTP timeouts, see `HttpTimeouts`.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     vault_addr: https://vault.internal:8200 # or VAULT_ADDR
+///     vault_ca_cert: ${VAULT_CA_CERT}
+///     vault_path: matano/okta # under the `vault_kv_mount`, default `secret`
+///     vault_auth: aws # default, or approle
+///     vault_role: matano-puller
+///     # for approle, e.g. from `log_puller: { environment: ... }`
+///     vault_role_id: ${VAULT_ROLE_ID}
+///     vault_secret_id: ${VAULT_SECRET_ID}
+/// ```
+#[derive(Debug)]
+pub(crate) struct VaultSecrets {
+    addr: String,
+    namespace: Option<String>,
+    kv_mount: String,
+    path: String,
+    auth: VaultAuth,
+    client: reqwest::Client,
+    token: Mutex<Option<VaultToken>>,
+}
+
+impl VaultSecrets {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<VaultSecrets>> {
+        let get = |k: &str| {
+            config
+                .get(k)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let path = match get("vault_path") {
+            Some(path) => path.trim_matches('/').to_string(),
+            None => return Ok(None),
+        };
+        let addr = get("vault_addr")
+            .or_else(|| std::env::var("VAULT_ADDR").ok())
+            .context("Missing vault_addr")?
+            .trim_end_matches('/')
+            .to_string();
+        let auth = match get("vault_auth").as_deref().unwrap_or("aws") {
+            "aws" => VaultAuth::Aws {
+                mount: get("vault_auth_mount").unwrap_or_else(|| "aws".to_string()),
+                role: get("vault_role").context("Missing vault_role")?,
+                server_id: get("vault_aws_server_id"),
+            },
+            "approle" => VaultAuth::AppRole {
+                mount: get("vault_auth_mount").unwrap_or_else(|| "approle".to_string()),
+                role_id: get("vault_role_id").context("Missing vault_role_id")?,
+                secret_id: get("vault_secret_id").context("Missing vault_secret_id")?,
+            },
+            a => {
+                return Err(anyhow!(
+                    "Invalid vault_auth: {}, expected aws or approle",
+                    a
+                ))
+            }
+        };
+        Ok(Some(VaultSecrets {
+            addr,
+            namespace: get("vault_namespace").or_else(|| std::env::var("VAULT_NAMESPACE").ok()),
+            kv_mount: get("vault_kv_mount")
+                .unwrap_or_else(|| "secret".to_string())
+                .trim_matches('/')
+                .to_string(),
+            path,
+            auth,
+            client: vault_client(config)?,
+            token: Mutex::new(None),
+        }))
+    }
+
+    /// The fields of the latest version of the secret. Non string values are read as JSON.
+    pub async fn read_fields(&self) -> Result<HashMap<String, String>> {
+        let url = format!("{}/v1/{}/data/{}", self.addr, self.kv_mount, self.path);
+        let mut res = self.get(&url).await?;
+        // The token may have been revoked, log in again once.
+        if res.status() == StatusCode::FORBIDDEN {
+            warn!("Vault token was rejected, logging in again");
+            *self.token.lock().await = None;
+            res = self.get(&url).await?;
+        }
+        let body: Value = error_for_status(res, "read Vault secret").await?;
+        let data = body["data"]["data"]
+            .as_object()
+            .with_context(|| format!("Missing data in Vault secret {}", self.path))?;
+        Ok(data
+            .iter()
+            .map(|(k, v)| match v {
+                Value::String(s) => (k.clone(), s.clone()),
+                v => (k.clone(), v.to_string()),
+            })
+            .collect())
+    }
+
+    async fn get(&self, url: &str) -> Result<reqwest::Response> {
+        let token = self.token().await?;
+        let mut req = self.client.get(url).header("X-Vault-Token", token);
+        if let Some(namespace) = self.namespace.as_ref() {
+            req = req.header("X-Vault-Namespace", namespace);
+        }
+        Ok(req.send().await?)
+    }
+
+    /// A valid token, renewing the current one if it expires soon or logging in if it can't be.
+    async fn token(&self) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some(current) = token.as_ref().filter(|t| !t.expires_soon()) {
+            return Ok(current.client_token.clone());
+        }
+        let renewable = token
+            .as_ref()
+            .filter(|t| t.renewable && !t.is_expired())
+            .map(|t| t.client_token.clone());
+        if let Some(client_token) = renewable {
+            match self.renew(&client_token).await {
+                Ok(renewed) => {
+                    let client_token = renewed.client_token.clone();
+                    *token = Some(renewed);
+                    return Ok(client_token);
+                }
+                Err(e) => warn!("Failed to renew Vault token, logging in again: {:#}", e),
+            }
+        }
+        let new_token = self.login().await?;
+        let client_token = new_token.client_token.clone();
+        *token = Some(new_token);
+        Ok(client_token)
+    }
+
+    async fn renew(&self, client_token: &str) -> Result<VaultToken> {
+        let mut req = self
+            .client
+            .post(format!("{}/v1/auth/token/renew-self", self.addr))
+            .header("X-Vault-Token", client_token);
+        if let Some(namespace) = self.namespace.as_ref() {
+            req = req.header("X-Vault-Namespace", namespace);
+        }
+        let body: Value = error_for_status(req.send().await?, "renew Vault token").await?;
+        VaultToken::from_auth(&body["auth"])
+    }
+
+    async fn login(&self) -> Result<VaultToken> {
+        let (mount, payload) = match &self.auth {
+            VaultAuth::Aws {
+                mount,
+                role,
+                server_id,
+            } => {
+                let payload = aws_login_payload(&self.client, role, server_id.as_deref()).await?;
+                (mount, payload)
+            }
+            VaultAuth::AppRole {
+                mount,
+                role_id,
+                secret_id,
+            } => (mount, json!({"role_id": role_id, "secret_id": secret_id})),
+        };
+        let mut req = self
+            .client
+            .post(format!("{}/v1/auth/{}/login", self.addr, mount))
+            .json(&payload);
+        if let Some(namespace) = self.namespace.as_ref() {
+            req = req.header("X-Vault-Namespace", namespace);
+        }
+        let body: Value = error_for_status(req.send().await?, "log in to Vault").await?;
+        let token = VaultToken::from_auth(&body["auth"])?;
+        info!("Logged in to Vault at {} with auth/{}", self.addr, mount);
+        Ok(token)
+    }
+}
+
+/// The login payload of Vault AWS IAM auth, a `sts:GetCallerIdentity` request signed with the
+/// puller's credentials, which Vault sends to STS to find out who's logging in.
+async fn aws_login_payload(
+    client: &reqwest::Client,
+    role: &str,
+    server_id: Option<&str>,
+) -> Result<Value> {
+    let config = HashMap::from([
+        ("aws_service".to_string(), "sts".to_string()),
+        ("aws_region".to_string(), "us-east-1".to_string()),
+    ]);
+    let signer = AwsSigV4Signer::from_config(&config)?;
+    let mut req = client
+        .post(STS_URL)
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded; charset=utf-8",
+        )
+        .body(STS_BODY);
+    if let Some(server_id) = server_id {
+        req = req.header("X-Vault-AWS-IAM-Server-ID", server_id);
+    }
+    let mut req = req.build()?;
+    signer.sign(&mut req).await?;
+
+    let mut headers: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (name, value) in req.headers().iter() {
+        headers
+            .entry(name.as_str())
+            .or_default()
+            .push(value.to_str()?);
+    }
+    Ok(json!({
+        "role": role,
+        "iam_http_request_method": "POST",
+        "iam_request_url": base64::encode(STS_URL),
+        "iam_request_body": base64::encode(STS_BODY),
+        "iam_request_headers": base64::encode(serde_json::to_string(&headers)?),
+    }))
+}
+
+fn vault_client(config: &HashMap<String, String>) -> Result<reqwest::Client> {
+    let get = |k: &str| config.get(k).map(|s| s.trim()).filter(|s| !s.is_empty());
+    let ca_cert = match get("vault_ca_cert") {
+        Some(ca_cert) => Some(ca_cert.to_string()),
+        None => match std::env::var("VAULT_CACERT").ok().filter(|p| !p.is_empty()) {
+            Some(path) => Some(
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read VAULT_CACERT {}", path))?,
+            ),
+            None => get("tls_ca_cert").map(|s| s.to_string()),
+        },
+    };
+    let timeouts = HttpTimeouts::from_config(config)?;
+
+    let mut builder = reqwest::Client::builder()
+        .use_rustls_tls()
+        .timeout(timeouts.request.unwrap_or(DEFAULT_TIMEOUT))
+        .connect_timeout(timeouts.connect.unwrap_or(DEFAULT_CONNECT_TIMEOUT));
+    if let Some(ca_cert) = ca_cert {
+        let ca_cert = reqwest::Certificate::from_pem(ca_cert.as_bytes())
+            .context("Invalid Vault CA certificate, must be PEM encoded")?;
+        builder = builder.add_root_certificate(ca_cert);
+    }
+    // The log source's proxy credentials are secret fields, which are read from Vault.
+    let proxy = match get("proxy_url").filter(|_| get("proxy_username").is_none()) {
+        Some(proxy_url) => Some(reqwest::Proxy::all(proxy_url).context("Invalid proxy_url")?),
+        None => global_proxy()?,
+    };
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build()?)
+}
+
+async fn error_for_status(res: reqwest::Response, action: &str) -> Result<Value> {
+    let status = res.status();
+    if !status.is_success() {
+        // Vault errors are `{"errors": [...]}`, without secrets.
+        let body = res.text().await.unwrap_or_default();
+        return Err(anyhow!("Failed to {}: {} {}", action, status, body));
+    }
+    Ok(res.json().await?)
+}
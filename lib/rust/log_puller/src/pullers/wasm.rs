@@ -0,0 +1,351 @@
+//! Experimental pullers written as a WASM module, so a custom integration can be shipped as
+//! config instead of a change to the puller. Built with the `wasm` feature, which registers
+//! the `wasm` managed type (see `registry`).
+//!
+//! ex:
+//! ```yaml
+//! name: acme
+//! managed:
+//!   type: wasm
+//!   properties:
+//!     wasm_module: acme.wasm
+//!     base_url: https://api.acme.com
+//! ```
+//!
+//! The module is read from the log source's directory, next to its `log_source.yml`. It has no
+//! WASI, and talks to the puller with JSON through its memory:
+//!
+//! - It exports `memory`, `alloc(len: i32) -> i32` (returning a buffer the puller writes
+//!   `len` bytes to) and `pull(ptr: i32, len: i32) -> i64`.
+//! - `pull` is given `{"properties", "start_time", "end_time", "checkpoint"}` and returns
+//!   `{"records": [...], "checkpoint"}` or `{"error"}`.
+//! - It may import `matano.http_request(ptr, len) -> i64`, given
+//!   `{"method", "url", "headers", "body"}` and returning `{"status", "headers", "body"}` or
+//!   `{"error"}`, sent with the log source's client (retries, rate limits, proxy).
+//! - It may import `matano.secret(ptr, len) -> i64`, given a secret field name and returning
+//!   `{"value"}` (null if it's unset) or `{"error"}`.
+//!
+//! Returned buffers are an `i64` of `ptr << 32 | len`, allocated with the module's `alloc`.
+//!
+//! The module is interrupted when the pull deadline of the invocation is reached (or after
+//! `MAX_RUN_TIME` if it's unknown), and its memory is limited to `MAX_MEMORY`.
+
+use std::collections::HashMap;
+use std::sync::mpsc as std_mpsc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tracing::info;
+use wasmtime::{
+    Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, Val,
+};
+
+use super::deadline::{remaining_time, PULL_DEADLINE_MARGIN};
+use super::registry::DynPullLogs;
+use super::{PropertySpec, PropertyType, PullLogsContext};
+
+/// Requests of the module to the puller, answered on the async side while the module runs on
+/// a blocking thread.
+enum HostCall {
+    Http(HttpRequest, oneshot::Sender<Value>),
+    Secret(String, oneshot::Sender<Value>),
+}
+
+#[derive(Deserialize, Debug)]
+struct HttpRequest {
+    #[serde(default = "default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+#[derive(Serialize, Debug)]
+struct PullInput<'a> {
+    properties: &'a HashMap<String, String>,
+    start_time: String,
+    end_time: String,
+    checkpoint: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PullOutput {
+    #[serde(default)]
+    records: Vec<Value>,
+    #[serde(default)]
+    checkpoint: Option<Value>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+struct HostState {
+    calls: mpsc::Sender<HostCall>,
+    limits: StoreLimits,
+}
+
+/// Memory a module may grow to.
+const MAX_MEMORY: usize = 256 * 1024 * 1024;
+/// How long a module may run when the invocation deadline is unknown.
+const MAX_RUN_TIME: Duration = Duration::from_secs(10 * 60);
+
+const PROPERTIES: &[PropertySpec] = &[PropertySpec::required(
+    "wasm_module",
+    PropertyType::String,
+    "acme.wasm",
+)];
+
+#[derive(Clone)]
+pub struct WasmPuller;
+
+#[async_trait]
+impl DynPullLogs for WasmPuller {
+    fn properties(&self) -> &'static [PropertySpec] {
+        PROPERTIES
+    }
+
+    async fn pull_logs(
+        &self,
+        client: reqwest::Client,
+        ctx: &PullLogsContext,
+        start_dt: DateTime<FixedOffset>,
+        end_dt: DateTime<FixedOffset>,
+    ) -> Result<Vec<u8>> {
+        let module_file = ctx
+            .config()
+            .get("wasm_module")
+            .map(|s| s.trim().to_string())
+            .context("Missing wasm_module")?;
+        let path = shared::log_sources_config_dir()
+            .join(&ctx.log_source_name)
+            .join(&module_file);
+        let wasm = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read WASM module {}", path.display()))?;
+
+        let checkpoint = ctx.checkpoint_json.lock().await.clone();
+        let input = serde_json::to_vec(&PullInput {
+            properties: ctx.config(),
+            start_time: start_dt.to_rfc3339_opts(SecondsFormat::Secs, true),
+            end_time: end_dt.to_rfc3339_opts(SecondsFormat::Secs, true),
+            checkpoint,
+        })?;
+
+        let (calls_tx, mut calls_rx) = mpsc::channel(1);
+        let run = tokio::task::spawn_blocking(move || run_module(&wasm, &input, calls_tx));
+        // The module's calls are answered until it returns, which drops the sender.
+        while let Some(call) = calls_rx.recv().await {
+            match call {
+                HostCall::Http(req, reply) => {
+                    let _ = reply.send(http_request(&client, ctx, req).await);
+                }
+                HostCall::Secret(name, reply) => {
+                    let res = match ctx.get_secret_field(&name).await {
+                        Ok(value) => json!({ "value": value }),
+                        Err(e) => json!({ "error": format!("{:#}", e) }),
+                    };
+                    let _ = reply.send(res);
+                }
+            }
+        }
+        let output = run.await??;
+        let output: PullOutput = serde_json::from_slice(&output)
+            .with_context(|| format!("Invalid output of WASM module {}", module_file))?;
+        if let Some(error) = output.error {
+            return Err(anyhow!("WASM module {} failed: {}", module_file, error));
+        }
+        if output.checkpoint.is_some() {
+            *ctx.checkpoint_json.lock().await = output.checkpoint;
+        }
+
+        info!(
+            "WASM module {} pulled {} records for {}",
+            module_file,
+            output.records.len(),
+            ctx.log_source_name
+        );
+        let mut ret = vec![];
+        for record in output.records {
+            if !ret.is_empty() {
+                ret.push(b'\n');
+            }
+            serde_json::to_writer(&mut ret, &record)?;
+        }
+        Ok(ret)
+    }
+}
+
+async fn http_request(client: &reqwest::Client, ctx: &PullLogsContext, req: HttpRequest) -> Value {
+    let res = async {
+        let method = reqwest::Method::from_bytes(req.method.to_uppercase().as_bytes())?;
+        let mut builder = client.request(method, &req.url);
+        for (k, v) in req.headers.iter() {
+            builder = builder.header(k, v);
+        }
+        if let Some(body) = req.body {
+            builder = builder.body(body);
+        }
+        let res = ctx.send(client, builder).await?;
+        let status = res.status().as_u16();
+        let headers = res
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+            .collect::<HashMap<_, _>>();
+        let body = ctx.read_body(res).await?;
+        anyhow::Ok(json!({
+            "status": status,
+            "headers": headers,
+            "body": String::from_utf8_lossy(&body),
+        }))
+    };
+    res.await
+        .unwrap_or_else(|e| json!({ "error": format!("{:#}", e) }))
+}
+
+/// Instantiates the module and calls its `pull`, on a blocking thread.
+fn run_module(wasm: &[u8], input: &[u8], calls: mpsc::Sender<HostCall>) -> Result<Vec<u8>> {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(&engine, wasm).context("Invalid WASM module")?;
+    let mut linker = Linker::new(&engine);
+    linker.func_wrap(
+        "matano",
+        "http_request",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> i64 {
+            host_call(&mut caller, ptr, len, |data, reply| {
+                let req = serde_json::from_slice::<HttpRequest>(data)?;
+                Ok(HostCall::Http(req, reply))
+            })
+        },
+    )?;
+    linker.func_wrap(
+        "matano",
+        "secret",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> i64 {
+            host_call(&mut caller, ptr, len, |data, reply| {
+                let name = std::str::from_utf8(data)?.to_string();
+                Ok(HostCall::Secret(name, reply))
+            })
+        },
+    )?;
+
+    let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+    let mut store = Store::new(&engine, HostState { calls, limits });
+    store.limiter(|state| &mut state.limits);
+    // The module traps once the epoch is incremented, when the deadline passes. Dropping
+    // `_done` when the module returns stops the timer.
+    store.set_epoch_deadline(1);
+    let run_time = remaining_time(PULL_DEADLINE_MARGIN).unwrap_or(MAX_RUN_TIME);
+    let (_done, done_rx) = std_mpsc::channel::<()>();
+    let timer_engine = engine.clone();
+    std::thread::spawn(move || {
+        if let Err(std_mpsc::RecvTimeoutError::Timeout) = done_rx.recv_timeout(run_time) {
+            timer_engine.increment_epoch();
+        }
+    });
+
+    let instance = linker.instantiate(&mut store, &module)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .context("WASM module doesn't export memory")?;
+    let input_ptr = alloc(&mut store, &instance, input.len())?;
+    memory.write(&mut store, input_ptr, input)?;
+
+    let pull = instance
+        .get_func(&mut store, "pull")
+        .context("WASM module doesn't export pull")?;
+    let mut results = [Val::I64(0)];
+    pull.call(
+        &mut store,
+        &[Val::I32(input_ptr as i32), Val::I32(input.len() as i32)],
+        &mut results,
+    )?;
+    let packed = results[0].i64().context("pull must return an i64")?;
+    read_packed(&memory, &store, packed)
+}
+
+/// Runs a host call with the JSON the module passed, returning the reply written to its memory.
+fn host_call(
+    caller: &mut Caller<'_, HostState>,
+    ptr: i32,
+    len: i32,
+    to_call: impl FnOnce(&[u8], oneshot::Sender<Value>) -> Result<HostCall>,
+) -> i64 {
+    let res = (|| {
+        let memory = caller_memory(caller)?;
+        let start = usize::try_from(ptr).context("Negative pointer")?;
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+            .context("Out of bounds")?;
+        let data = memory
+            .data(&*caller)
+            .get(start..end)
+            .context("Out of bounds")?
+            .to_vec();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let call = to_call(&data, reply_tx)?;
+        caller
+            .data()
+            .calls
+            .blocking_send(call)
+            .map_err(|_| anyhow!("Puller stopped"))?;
+        anyhow::Ok(reply_rx.blocking_recv()?)
+    })();
+    let reply = res.unwrap_or_else(|e| json!({ "error": format!("{:#}", e) }));
+    write_reply(caller, &serde_json::to_vec(&reply).unwrap_or_default()).unwrap_or(0)
+}
+
+fn write_reply(caller: &mut Caller<'_, HostState>, data: &[u8]) -> Result<i64> {
+    let alloc = caller
+        .get_export("alloc")
+        .and_then(Extern::into_func)
+        .context("WASM module doesn't export alloc")?;
+    let mut results = [Val::I32(0)];
+    alloc.call(&mut *caller, &[Val::I32(data.len() as i32)], &mut results)?;
+    let ptr = results[0].i32().context("alloc must return an i32")? as usize;
+    caller_memory(caller)?.write(&mut *caller, ptr, data)?;
+    Ok(((ptr as i64) << 32) | data.len() as i64)
+}
+
+fn caller_memory(caller: &mut Caller<'_, HostState>) -> Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .context("WASM module doesn't export memory")
+}
+
+fn alloc(store: &mut Store<HostState>, instance: &Instance, len: usize) -> Result<usize> {
+    let alloc = instance
+        .get_func(&mut *store, "alloc")
+        .context("WASM module doesn't export alloc")?;
+    let mut results = [Val::I32(0)];
+    alloc.call(&mut *store, &[Val::I32(len as i32)], &mut results)?;
+    Ok(results[0].i32().context("alloc must return an i32")? as usize)
+}
+
+fn read_packed(memory: &Memory, store: &Store<HostState>, packed: i64) -> Result<Vec<u8>> {
+    let ptr = (packed >> 32) as u32 as usize;
+    let len = packed as u32 as usize;
+    let end = ptr
+        .checked_add(len)
+        .context("pull returned an out of bounds buffer")?;
+    memory
+        .data(store)
+        .get(ptr..end)
+        .map(|data| data.to_vec())
+        .context("pull returned an out of bounds buffer")
+}
@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::{Map, Value};
+
+const ATTRIBUTE_PREFIX: &str = "@";
+const TEXT_FIELD: &str = "#text";
+
+/// Converts XML responses (e.g. Qualys or some OCI APIs) to JSON. An element becomes its text,
+/// or an object of its attributes (prefixed with `@`), child elements and text (as `#text`) if
+/// it has either. Repeated child elements become arrays, as do those in `xml_array_elements`
+/// even when there's one, so records have the same shape either way. Values are kept as
+/// strings, and namespace prefixes are dropped.
+///
+/// As records, the elements at `xml_records_path` (dot separated element names below the root
+/// element) are written one per line, default the children of the root element. Elements
+/// outside of records are ignored.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     xml_records_path: RESPONSE.HOST_LIST.HOST
+///     xml_array_elements: DETECTION,TAG
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct XmlMapping {
+    records_path: Option<Vec<String>>,
+    array_elements: HashSet<String>,
+}
+
+/// An element being read, until its end tag.
+struct Element {
+    name: String,
+    fields: Map<String, Value>,
+    text: String,
+}
+
+impl Element {
+    fn add_child(&mut self, name: String, value: Value, force_array: bool) {
+        match self.fields.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+            None if force_array => {
+                self.fields.insert(name, Value::Array(vec![value]));
+            }
+            None => {
+                self.fields.insert(name, value);
+            }
+        }
+    }
+
+    fn into_value(self) -> Value {
+        if self.fields.is_empty() {
+            return match self.text.is_empty() {
+                true => Value::Null,
+                false => Value::String(self.text),
+            };
+        }
+        let mut fields = self.fields;
+        if !self.text.is_empty() {
+            fields.insert(TEXT_FIELD.to_string(), Value::String(self.text));
+        }
+        Value::Object(fields)
+    }
+}
+
+impl XmlMapping {
+    pub fn new(records_path: Option<&str>) -> XmlMapping {
+        XmlMapping {
+            records_path: records_path
+                .map(|path| {
+                    path.split('.')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .collect::<Vec<_>>()
+                })
+                .filter(|path| !path.is_empty()),
+            array_elements: HashSet::new(),
+        }
+    }
+
+    /// Elements always converted to arrays.
+    pub fn with_array_elements<S: Into<String>>(
+        mut self,
+        names: impl IntoIterator<Item = S>,
+    ) -> XmlMapping {
+        self.array_elements
+            .extend(names.into_iter().map(|s| s.into()));
+        self
+    }
+
+    pub fn from_config(config: &HashMap<String, String>) -> XmlMapping {
+        let get = |k: &str| {
+            config
+                .get(k)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let array_elements = get("xml_array_elements")
+            .map(|s| {
+                s.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        XmlMapping::new(get("xml_records_path").as_deref()).with_array_elements(array_elements)
+    }
+
+    /// Converts the XML document to JSON, as the value of its root element.
+    pub fn to_json(&self, data: &[u8]) -> Result<Value> {
+        self.convert(data, None)
+    }
+
+    /// Converts each record of the XML document to an NDJSON record.
+    pub fn to_ndjson(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let mut emit = |record: Value| -> Result<()> {
+            serde_json::to_writer(&mut *out, &record)?;
+            out.push(b'\n');
+            Ok(())
+        };
+        self.convert(data, Some(&mut emit))?;
+        Ok(())
+    }
+
+    /// Reads the document, passing records to `emit` if set instead of keeping them.
+    fn convert(
+        &self,
+        data: &[u8],
+        mut emit: Option<&mut dyn FnMut(Value) -> Result<()>>,
+    ) -> Result<Value> {
+        let mut reader = Reader::from_reader(data);
+        reader.trim_text(true);
+        let mut stack: Vec<Element> = vec![];
+        let mut root = Value::Null;
+
+        loop {
+            let event = reader
+                .read_event()
+                .with_context(|| format!("Invalid XML at position {}", reader.buffer_position()))?;
+            let element = match event {
+                Event::Start(e) => {
+                    stack.push(start_element(&e)?);
+                    continue;
+                }
+                Event::Empty(e) => start_element(&e)?,
+                Event::End(_) => stack.pop().context("Unbalanced XML end tag")?,
+                Event::Text(e) => {
+                    if let Some(current) = stack.last_mut() {
+                        current.text.push_str(&e.unescape()?);
+                    }
+                    continue;
+                }
+                Event::CData(e) => {
+                    if let Some(current) = stack.last_mut() {
+                        current.text.push_str(std::str::from_utf8(&e.into_inner())?);
+                    }
+                    continue;
+                }
+                Event::Eof => break,
+                _ => continue,
+            };
+
+            // Depth below the root element.
+            let depth = stack.len();
+            let record_depth = self.records_path.as_ref().map_or(1, |p| p.len());
+            match emit.as_mut() {
+                _ if depth == 0 => root = element.into_value(),
+                Some(emit) if depth == record_depth && self.is_record(&stack, &element) => {
+                    emit(into_record(element))?
+                }
+                Some(_) if depth <= record_depth => {}
+                _ => self.add_child(&mut stack, element),
+            }
+        }
+        Ok(root)
+    }
+
+    fn add_child(&self, stack: &mut [Element], element: Element) {
+        if let Some(parent) = stack.last_mut() {
+            let force_array = self.array_elements.contains(&element.name);
+            let name = element.name.clone();
+            parent.add_child(name, element.into_value(), force_array);
+        }
+    }
+
+    /// Whether the element, closed within the elements of `stack`, is at the records path.
+    fn is_record(&self, stack: &[Element], element: &Element) -> bool {
+        let path = match self.records_path.as_ref() {
+            Some(path) => path,
+            None => return true,
+        };
+        stack[1..]
+            .iter()
+            .map(|e| e.name.as_str())
+            .chain(std::iter::once(element.name.as_str()))
+            .eq(path.iter().map(|s| s.as_str()))
+    }
+}
+
+/// Whether a response is XML, by its `Content-Type` or else its content.
+pub(crate) fn is_xml(content_type: Option<&str>, data: &[u8]) -> bool {
+    match content_type {
+        Some(content_type) if content_type.contains("xml") => true,
+        Some(content_type) if content_type.contains("json") => false,
+        _ => data
+            .iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .map_or(false, |b| *b == b'<'),
+    }
+}
+
+/// Records are objects, a record element with only text is wrapped as `{"<name>": "<text>"}`.
+fn into_record(element: Element) -> Value {
+    let name = element.name.clone();
+    match element.into_value() {
+        Value::Object(fields) => Value::Object(fields),
+        v => Value::Object(Map::from_iter([(name, v)])),
+    }
+}
+
+fn start_element(e: &BytesStart) -> Result<Element> {
+    let name = std::str::from_utf8(e.local_name().as_ref())?.to_string();
+    let mut fields = Map::new();
+    for attr in e.attributes() {
+        let attr = attr?;
+        if attr.key.as_ref().starts_with(b"xmlns") {
+            continue;
+        }
+        let key = std::str::from_utf8(attr.key.local_name().as_ref())?;
+        fields.insert(
+            format!("{}{}", ATTRIBUTE_PREFIX, key),
+            Value::String(attr.unescape_value()?.into_owned()),
+        );
+    }
+    Ok(Element {
+        name,
+        fields,
+        text: String::new(),
+    })
+}
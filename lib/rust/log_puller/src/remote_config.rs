@@ -0,0 +1,206 @@
+//! Log source configs read from an S3 prefix instead of the configuration layer, so managed
+//! log sources can be added or edited without republishing the layer. The prefix is set with
+//! `PULLER_CONFIG_S3_URI` and laid out like the `log_sources/` directory, with a directory per
+//! log source.
+//!
+//! ex:
+//! ```text
+//! s3://my-config-bucket/matano/log_sources/okta/log_source.yml
+//! s3://my-config-bucket/matano/log_sources/custom_api_acme/log_source.yml
+//! ```
+//!
+//! The prefix is listed at most every `PULLER_CONFIG_REFRESH_SECONDS` (default 60). The configs
+//! are only downloaded, and the contexts rebuilt, when an object was added, removed or changed
+//! (by its ETag). Other files of a log source (e.g. a zstd dictionary) are still read from the
+//! layer.
+
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use futures::{StreamExt, TryStreamExt};
+use tracing::info;
+
+/// Configs are synced to a directory per version under this one.
+const SYNC_DIR: &str = "/tmp/puller_config";
+const DEFAULT_REFRESH_SECONDS: u64 = 60;
+/// Objects downloaded at once when the configs change.
+const DOWNLOAD_CONCURRENCY: usize = 8;
+
+#[derive(Debug)]
+pub(crate) struct RemoteConfig {
+    bucket: String,
+    /// Without a trailing slash, may be empty.
+    prefix: String,
+    /// How long configs are used before the prefix is listed again.
+    pub refresh_interval: Duration,
+}
+
+/// Configs synced from S3.
+pub(crate) struct SyncedConfig {
+    /// Identifies the listed keys and ETags, unchanged until an object is.
+    pub version: String,
+    /// The synced `log_sources/` directory.
+    pub dir: PathBuf,
+}
+
+impl RemoteConfig {
+    /// None unless `PULLER_CONFIG_S3_URI` is set.
+    pub fn from_env() -> Result<Option<RemoteConfig>> {
+        let uri = match std::env::var("PULLER_CONFIG_S3_URI") {
+            Ok(uri) if !uri.trim().is_empty() => uri,
+            _ => return Ok(None),
+        };
+        let (bucket, prefix) = uri
+            .trim()
+            .strip_prefix("s3://")
+            .map(|s| s.split_once('/').unwrap_or((s, "")))
+            .filter(|(bucket, _)| !bucket.is_empty())
+            .with_context(|| format!("Invalid PULLER_CONFIG_S3_URI: {}", uri))?;
+        let refresh_seconds = match std::env::var("PULLER_CONFIG_REFRESH_SECONDS") {
+            Ok(s) => s
+                .trim()
+                .parse::<u64>()
+                .with_context(|| format!("Invalid PULLER_CONFIG_REFRESH_SECONDS: {}", s))?,
+            Err(_) => DEFAULT_REFRESH_SECONDS,
+        };
+        Ok(Some(RemoteConfig {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            refresh_interval: Duration::from_secs(refresh_seconds),
+        }))
+    }
+
+    /// Downloads the configs if they changed from `current_version`, None if they didn't.
+    pub async fn sync(
+        &self,
+        s3: &aws_sdk_s3::Client,
+        current_version: Option<&str>,
+    ) -> Result<Option<SyncedConfig>> {
+        let objects = self.list(s3).await?;
+        if objects.is_empty() {
+            // Likely a wrong URI or an upload in progress, rather than no log sources at all.
+            return Err(anyhow!(
+                "No log source configs under s3://{}/{}",
+                self.bucket,
+                self.prefix
+            ));
+        }
+        let version = listing_version(&objects);
+        if current_version == Some(version.as_str()) {
+            return Ok(None);
+        }
+
+        info!(
+            "Downloading {} log source config objects from s3://{}/{} (version: {})",
+            objects.len(),
+            self.bucket,
+            self.prefix,
+            version
+        );
+        let dir = Path::new(SYNC_DIR).join(&version);
+        futures::stream::iter(objects.iter())
+            .map(|(key, _)| self.download(s3, key, &dir))
+            .buffer_unordered(DOWNLOAD_CONCURRENCY)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // Older versions aren't used anymore, don't let them fill up /tmp.
+        if let Ok(mut entries) = tokio::fs::read_dir(SYNC_DIR).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                if entry.file_name() != version.as_str() {
+                    let _ = tokio::fs::remove_dir_all(entry.path()).await;
+                }
+            }
+        }
+
+        Ok(Some(SyncedConfig { version, dir }))
+    }
+
+    /// Keys (relative to the prefix) and ETags of the config objects.
+    async fn list(&self, s3: &aws_sdk_s3::Client) -> Result<Vec<(String, String)>> {
+        let key_prefix = match self.prefix.as_str() {
+            "" => String::new(),
+            prefix => format!("{}/", prefix),
+        };
+        let mut objects = vec![];
+        let mut continuation_token = None;
+        loop {
+            let res = s3
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&key_prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .with_context(|| format!("Failed to list s3://{}/{}", self.bucket, key_prefix))?;
+            for object in res.contents().unwrap_or_default() {
+                let key = match object.key() {
+                    Some(key) if !key.ends_with('/') => key,
+                    _ => continue,
+                };
+                let relative_key = key[key_prefix.len()..].to_string();
+                objects.push((relative_key, object.e_tag().unwrap_or_default().to_string()));
+            }
+            continuation_token = res.next_continuation_token().map(|s| s.to_string());
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        objects.sort();
+        Ok(objects)
+    }
+
+    async fn download(
+        &self,
+        s3: &aws_sdk_s3::Client,
+        relative_key: &str,
+        dir: &Path,
+    ) -> Result<()> {
+        let relative_path = Path::new(relative_key);
+        if relative_path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(anyhow!("Invalid log source config key: {}", relative_key));
+        }
+        let key = match self.prefix.as_str() {
+            "" => relative_key.to_string(),
+            prefix => format!("{}/{}", prefix, relative_key),
+        };
+        let data = s3
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .with_context(|| format!("Failed to download s3://{}/{}", self.bucket, key))?
+            .body
+            .collect()
+            .await?
+            .into_bytes();
+
+        let path = dir.join(relative_path);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, data)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Hex SHA256 prefix of the listed keys and ETags.
+fn listing_version(objects: &[(String, String)]) -> String {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    for (key, e_tag) in objects {
+        ctx.update(key.as_bytes());
+        ctx.update(b"\t");
+        ctx.update(e_tag.as_bytes());
+        ctx.update(b"\n");
+    }
+    ctx.finish().as_ref()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
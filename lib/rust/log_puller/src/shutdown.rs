@@ -0,0 +1,87 @@
+//! Flushes what's pending when the Lambda's execution environment shuts down, e.g. on scale
+//! down or a deploy. Lambda only sends the runtime SIGTERM if an extension is registered, so
+//! an internal extension without events is registered at startup.
+//!
+//! On SIGTERM, pulls still in progress are stopped as at the invocation deadline, so pullers
+//! upload what they pulled and save their checkpoints, and pending audit events and spooled
+//! objects (see `upload_failover`) are uploaded. Lambda allows around 500ms for this before
+//! the environment is stopped. Turned off with `PULLER_GRACEFUL_SHUTDOWN=false`.
+
+use std::future::Future;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{debug, error, warn};
+
+const EXTENSION_NAME: &str = "log_puller_shutdown";
+const EXTENSION_API_VERSION: &str = "2020-01-01";
+
+pub(crate) fn is_enabled() -> bool {
+    std::env::var("PULLER_GRACEFUL_SHUTDOWN").as_deref() != Ok("false")
+}
+
+/// Registers the internal extension, must be done before the runtime polls for its first
+/// event. A no-op outside of Lambda.
+pub(crate) async fn register_extension() -> Result<()> {
+    let runtime_api = match std::env::var("AWS_LAMBDA_RUNTIME_API") {
+        Ok(runtime_api) => runtime_api,
+        Err(_) => return Ok(()),
+    };
+    let base_url = format!("http://{}/{}/extension", runtime_api, EXTENSION_API_VERSION);
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("{}/register", base_url))
+        .header("Lambda-Extension-Name", EXTENSION_NAME)
+        .json(&serde_json::json!({ "events": [] }))
+        .send()
+        .await
+        .context("Failed to register shutdown extension")?;
+    if !res.status().is_success() {
+        let status = res.status();
+        let body = res.text().await.unwrap_or_default();
+        return Err(anyhow!(
+            "Failed to register shutdown extension: {} {}",
+            status,
+            body
+        ));
+    }
+    let id = res
+        .headers()
+        .get("Lambda-Extension-Identifier")
+        .and_then(|v| v.to_str().ok())
+        .context("Missing Lambda-Extension-Identifier")?
+        .to_string();
+
+    // Polling marks the extension as initialized. Without events, the poll doesn't return.
+    tokio::spawn(async move {
+        loop {
+            let res = client
+                .get(format!("{}/event/next", base_url))
+                .header("Lambda-Extension-Identifier", &id)
+                .send()
+                .await;
+            if let Err(e) = res {
+                error!("Shutdown extension failed to poll: {}", e);
+                return;
+            }
+        }
+    });
+    debug!("Registered shutdown extension");
+    Ok(())
+}
+
+/// Runs `flush` when SIGTERM is received. The runtime keeps running meanwhile, until the
+/// environment is stopped.
+pub(crate) fn on_sigterm<F, Fut>(flush: F) -> Result<()>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        sigterm.recv().await;
+        warn!("Received SIGTERM, flushing before shutdown");
+        flush().await;
+    });
+    Ok(())
+}
@@ -0,0 +1,186 @@
+//! Keeps uploads going during a regional S3 incident. With a secondary ingestion bucket in
+//! another region set (`INGESTION_FAILOVER_BUCKET_NAME` and `INGESTION_FAILOVER_BUCKET_REGION`),
+//! uploads go there for `PULLER_FAILOVER_MINUTES` (default 5) after an upload to the ingestion
+//! bucket fails, then the ingestion bucket is tried again. With `notify_ingestion_queue`, objects
+//! uploaded to the secondary bucket are ingested from there, see `ingestion_notify`, or else it
+//! should be replicated to the ingestion bucket.
+//!
+//! Objects that can't be uploaded to either bucket are spooled to ephemeral storage, up to
+//! `PULLER_SPOOL_MAX_MB` (default 1024), and uploaded by later invocations once S3 recovers,
+//! or when the Lambda's execution environment shuts down (see `shutdown`). Spooled objects are
+//! lost if they still can't be uploaded then.
+//!
+//! ex:
+//! ```yaml
+//! log_puller:
+//!   failover_bucket:
+//!     name: matano-ingestion-us-west-2
+//!     region: us-west-2
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+const SPOOL_DIR: &str = "/tmp/upload_spool";
+const DEFAULT_FAILOVER_MINUTES: u64 = 5;
+const DEFAULT_SPOOL_MAX_MB: u64 = 1024;
+
+lazy_static! {
+    /// When uploads to a bucket last failed, by bucket.
+    static ref FAILED_AT: std::sync::Mutex<HashMap<String, Instant>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+/// The secondary ingestion bucket, for uploads to the ingestion bucket.
+#[derive(Debug, Clone)]
+pub(crate) struct FailoverBucket {
+    pub bucket: String,
+    pub region: Option<String>,
+}
+
+impl FailoverBucket {
+    pub fn from_env() -> Option<FailoverBucket> {
+        let bucket = std::env::var("INGESTION_FAILOVER_BUCKET_NAME")
+            .ok()
+            .filter(|s| !s.trim().is_empty())?;
+        let region = std::env::var("INGESTION_FAILOVER_BUCKET_REGION")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+        Some(FailoverBucket {
+            bucket: bucket.trim().to_string(),
+            region: region.map(|r| r.trim().to_string()),
+        })
+    }
+}
+
+fn failover_duration() -> Duration {
+    let minutes = std::env::var("PULLER_FAILOVER_MINUTES")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_FAILOVER_MINUTES);
+    Duration::from_secs(minutes * 60)
+}
+
+/// Records a failed upload to the bucket, see `is_failing`.
+pub(crate) fn mark_failed(bucket: &str) {
+    warn!(
+        "Uploads to {} failed, failing over for {:?}",
+        bucket,
+        failover_duration()
+    );
+    FAILED_AT
+        .lock()
+        .unwrap()
+        .insert(bucket.to_string(), Instant::now());
+}
+
+/// Whether an upload to the bucket failed within the failover duration.
+pub(crate) fn is_failing(bucket: &str) -> bool {
+    let mut failed_at = FAILED_AT.lock().unwrap();
+    match failed_at.get(bucket) {
+        Some(t) if t.elapsed() < failover_duration() => true,
+        Some(_) => {
+            failed_at.remove(bucket);
+            false
+        }
+        None => false,
+    }
+}
+
+/// An object that couldn't be uploaded, with what's needed to upload it later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SpooledObject {
+    pub bucket: String,
+    pub region: Option<String>,
+    pub key: String,
+    pub content_encoding: Option<String>,
+    pub metadata: HashMap<String, String>,
+    pub tagging: String,
+    pub sse_kms_key_id: Option<String>,
+}
+
+/// Writes the object to the spool, failing if the spool is full.
+pub(crate) async fn spool(object: &SpooledObject, body: &[u8]) -> Result<()> {
+    tokio::fs::create_dir_all(SPOOL_DIR).await?;
+    let max_bytes = std::env::var("PULLER_SPOOL_MAX_MB")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_SPOOL_MAX_MB)
+        * 1024
+        * 1024;
+    let spooled_bytes = spool_size().await?;
+    if spooled_bytes + body.len() as u64 > max_bytes {
+        return Err(anyhow!(
+            "Upload spool is full ({} bytes), can't spool {}",
+            spooled_bytes,
+            object.key
+        ));
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    let dir = Path::new(SPOOL_DIR);
+    // The body is written first, so a spooled object is complete once it has metadata.
+    tokio::fs::write(dir.join(format!("{}.data", id)), body).await?;
+    tokio::fs::write(
+        dir.join(format!("{}.json", id)),
+        serde_json::to_vec(object)?,
+    )
+    .await?;
+    warn!(
+        "Spooled s3://{}/{} ({} bytes) to ephemeral storage",
+        object.bucket,
+        object.key,
+        body.len()
+    );
+    Ok(())
+}
+
+/// The spooled objects, by the path of their metadata, oldest first.
+pub(crate) async fn spooled() -> Result<Vec<(PathBuf, SpooledObject)>> {
+    let mut entries = match tokio::fs::read_dir(SPOOL_DIR).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut ret = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let modified = entry.metadata().await?.modified()?;
+        let object: SpooledObject = serde_json::from_slice(&tokio::fs::read(&path).await?)
+            .with_context(|| format!("Invalid spooled object: {}", path.display()))?;
+        ret.push((modified, path, object));
+    }
+    ret.sort_by_key(|(modified, _, _)| *modified);
+    Ok(ret
+        .into_iter()
+        .map(|(_, path, object)| (path, object))
+        .collect())
+}
+
+pub(crate) async fn read_body(path: &Path) -> Result<Vec<u8>> {
+    Ok(tokio::fs::read(path.with_extension("data")).await?)
+}
+
+/// Removes an uploaded object from the spool.
+pub(crate) async fn remove(path: &Path) -> Result<()> {
+    tokio::fs::remove_file(path).await?;
+    tokio::fs::remove_file(path.with_extension("data")).await?;
+    Ok(())
+}
+
+async fn spool_size() -> Result<u64> {
+    let mut entries = tokio::fs::read_dir(SPOOL_DIR).await?;
+    let mut size = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        size += entry.metadata().await?.len();
+    }
+    Ok(size)
+}
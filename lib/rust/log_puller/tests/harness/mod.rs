@@ -0,0 +1,225 @@
+//! Golden tests for pullers: a puller is run with the `cli` binary against a mock server
+//! replaying recorded vendor responses, and the records it pulls are compared to a golden file.
+//! The puller's requests are redirected to the mock server (`--redirect`), so vendor hosts in
+//! the config and responses can be kept as recorded.
+//!
+//! Each case is a directory under `tests/fixtures` with:
+//!
+//! - `log_source.yml`, the log source config. `{{mock_url}}` is replaced by the mock server's URL.
+//! - `tables/*.yml`, optionally, for pullers that pull the tables the log source defines.
+//! - `case.json`, the pulled window, secrets, and the responses the puller is expected to
+//!   request, in order. Each response is served once, `{{mock_url}}` in its body and headers is
+//!   replaced too.
+//! - `expected.ndjson`, the records the puller should produce.
+//!
+//! ex:
+//! ```json
+//! {
+//!   "start": "2023-04-01T00:00:00Z",
+//!   "end": "2023-04-01T01:00:00Z",
+//!   "secrets": {"api_token": "test-token"},
+//!   "responses": [
+//!     {
+//!       "path": "/v1/events",
+//!       "query": {"cursor": "page2"},
+//!       "headers": {"authorization": "Bearer test-token"},
+//!       "body": {"data": [], "meta": {}}
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Run with `cargo test --features cli`. After an intended change in a puller's output, set
+//! `UPDATE_GOLDEN=1` to rewrite the golden files instead of comparing against them.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::Value;
+use wiremock::matchers::{header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const MOCK_URL: &str = "{{mock_url}}";
+
+#[derive(Deserialize, Debug)]
+struct Case {
+    start: String,
+    end: String,
+    #[serde(default)]
+    tenant_id: Option<String>,
+    #[serde(default)]
+    secrets: HashMap<String, String>,
+    responses: Vec<RecordedResponse>,
+}
+
+/// A vendor response, served for the request it matches.
+#[derive(Deserialize, Debug)]
+struct RecordedResponse {
+    #[serde(default = "default_method")]
+    method: String,
+    path: String,
+    /// Query params the request must have, others are ignored.
+    #[serde(default)]
+    query: HashMap<String, String>,
+    /// Headers the request must have, e.g. to check auth.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default = "default_status")]
+    status: u16,
+    #[serde(default)]
+    response_headers: HashMap<String, String>,
+    /// A JSON body, or `raw_body` for other content, or `base64_body` for binary content (e.g.
+    /// a zip file), which is served as is.
+    #[serde(default)]
+    body: Option<Value>,
+    #[serde(default)]
+    raw_body: Option<String>,
+    #[serde(default)]
+    base64_body: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Runs the golden test in `tests/fixtures/<name>`.
+pub async fn run_golden(name: &str) {
+    let fixture_dir = fixtures_dir().join(name);
+    let case: Case = read_json(&fixture_dir.join("case.json"));
+
+    let server = MockServer::start().await;
+    let mock_url = server.uri();
+    for response in case.responses.iter() {
+        mount(&server, response, &mock_url).await;
+    }
+
+    // The log source dir is named after the case, under its own config dir.
+    let work_dir = std::env::temp_dir().join(format!("puller-golden-{}", uuid::Uuid::new_v4()));
+    let config_dir = work_dir.join(name);
+    std::fs::create_dir_all(&config_dir).unwrap();
+    let log_source_yml = std::fs::read_to_string(fixture_dir.join("log_source.yml")).unwrap();
+    std::fs::write(
+        config_dir.join("log_source.yml"),
+        log_source_yml.replace(MOCK_URL, &mock_url),
+    )
+    .unwrap();
+    let tables_dir = fixture_dir.join("tables");
+    if tables_dir.is_dir() {
+        std::fs::create_dir_all(config_dir.join("tables")).unwrap();
+        for entry in std::fs::read_dir(&tables_dir).unwrap() {
+            let path = entry.unwrap().path();
+            std::fs::copy(
+                &path,
+                config_dir.join("tables").join(path.file_name().unwrap()),
+            )
+            .unwrap();
+        }
+    }
+    let secrets_path = work_dir.join("secrets.json");
+    std::fs::write(&secrets_path, serde_json::to_vec(&case.secrets).unwrap()).unwrap();
+    let output_path = work_dir.join("output.ndjson");
+
+    let mut command = tokio::process::Command::new(env!("CARGO_BIN_EXE_log_puller"));
+    command
+        .arg("pull")
+        .arg("--config")
+        .arg(&config_dir)
+        .args(["--start", &case.start, "--end", &case.end])
+        .arg("--secrets")
+        .arg(&secrets_path)
+        .arg("--output")
+        .arg(&output_path)
+        .args(["--redirect", &mock_url])
+        .env("LOG_SOURCES_CONFIG_DIR", &work_dir)
+        .env("AWS_REGION", "us-east-1")
+        .env("AWS_EC2_METADATA_DISABLED", "true");
+    if let Some(tenant_id) = case.tenant_id.as_ref() {
+        command.args(["--tenant", tenant_id]);
+    }
+    let res = command.output().await.unwrap();
+    let output = std::fs::read(&output_path);
+    let _ = std::fs::remove_dir_all(&work_dir);
+    assert!(
+        res.status.success(),
+        "Puller failed for {}:\n{}{}",
+        name,
+        String::from_utf8_lossy(&res.stdout),
+        String::from_utf8_lossy(&res.stderr)
+    );
+    let output = output.unwrap();
+
+    let expected_path = fixture_dir.join("expected.ndjson");
+    if std::env::var("UPDATE_GOLDEN").map_or(false, |v| v == "1") {
+        std::fs::write(&expected_path, &output).unwrap();
+        return;
+    }
+    let expected = std::fs::read(&expected_path).unwrap_or_default();
+    assert_eq!(
+        parse_records(&output),
+        parse_records(&expected),
+        "Records of {} differ from {}, run with UPDATE_GOLDEN=1 if the change is intended",
+        name,
+        expected_path.display()
+    );
+
+    // Fails if a recorded response wasn't requested.
+    server.verify().await;
+}
+
+async fn mount(server: &MockServer, response: &RecordedResponse, mock_url: &str) {
+    let mut mock = Mock::given(method(response.method.as_str())).and(path(response.path.as_str()));
+    for (k, v) in response.query.iter() {
+        mock = mock.and(query_param(k.as_str(), v.as_str()));
+    }
+    for (k, v) in response.headers.iter() {
+        mock = mock.and(header(k.as_str(), v.as_str()));
+    }
+
+    let body = match (
+        response.body.as_ref(),
+        response.raw_body.as_ref(),
+        response.base64_body.as_ref(),
+    ) {
+        (Some(body), _, _) => body.to_string().replace(MOCK_URL, mock_url).into_bytes(),
+        (None, Some(raw_body), _) => raw_body.replace(MOCK_URL, mock_url).into_bytes(),
+        (None, None, Some(base64_body)) => base64::decode(base64_body).unwrap(),
+        (None, None, None) => vec![],
+    };
+    let content_type = response
+        .response_headers
+        .get("content-type")
+        .map_or("application/json", |c| c.as_str());
+    let mut template = ResponseTemplate::new(response.status).set_body_raw(body, content_type);
+    for (k, v) in response.response_headers.iter() {
+        template = template.insert_header(k.as_str(), v.replace(MOCK_URL, mock_url).as_str());
+    }
+
+    // Mocks match in the order they're mounted, each response is served once.
+    mock.respond_with(template)
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+/// Records parsed as JSON, so key order and formatting don't matter.
+fn parse_records(data: &[u8]) -> Vec<Value> {
+    data.split(|b| *b == b'\n')
+        .filter(|line| !line.iter().all(|b| b.is_ascii_whitespace()))
+        .map(|line| serde_json::from_slice(line).expect("Record isn't valid JSON"))
+        .collect()
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> T {
+    let data = std::fs::read(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    serde_json::from_slice(&data).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
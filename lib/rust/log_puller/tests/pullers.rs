@@ -0,0 +1,91 @@
+//! Golden tests of pullers, see `harness`. A new puller should come with a case for its
+//! pagination and auth, under `tests/fixtures/<case>`.
+
+mod harness;
+
+use harness::run_golden;
+
+#[tokio::test]
+async fn abusech_malwarebazaar() {
+    run_golden("abusech_malwarebazaar").await;
+}
+
+#[tokio::test]
+async fn abusech_threatfox() {
+    run_golden("abusech_threatfox").await;
+}
+
+#[tokio::test]
+async fn abusech_urlhaus() {
+    run_golden("abusech_urlhaus").await;
+}
+
+#[tokio::test]
+async fn azure_blob() {
+    run_golden("azure_blob").await;
+}
+
+#[tokio::test]
+async fn custom_api_cursor() {
+    run_golden("custom_api_cursor").await;
+}
+
+#[tokio::test]
+async fn custom_api_link_header() {
+    run_golden("custom_api_link_header").await;
+}
+
+#[tokio::test]
+async fn duo() {
+    run_golden("duo").await;
+}
+
+#[tokio::test]
+async fn elasticsearch() {
+    run_golden("elasticsearch").await;
+}
+
+#[tokio::test]
+async fn gcs() {
+    run_golden("gcs").await;
+}
+
+#[tokio::test]
+async fn google_workspace() {
+    run_golden("google_workspace").await;
+}
+
+#[tokio::test]
+async fn graphql() {
+    run_golden("graphql").await;
+}
+
+#[tokio::test]
+async fn o365() {
+    run_golden("o365").await;
+}
+
+#[tokio::test]
+async fn okta() {
+    run_golden("okta").await;
+}
+
+#[tokio::test]
+async fn onepassword() {
+    run_golden("onepassword").await;
+}
+
+#[tokio::test]
+async fn otx() {
+    run_golden("otx").await;
+}
+
+#[tokio::test]
+async fn snyk() {
+    run_golden("snyk").await;
+}
+
+#[tokio::test]
+async fn splunk() {
+    run_golden("splunk").await;
+}
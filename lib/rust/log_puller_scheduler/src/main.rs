@@ -0,0 +1,237 @@
+//! Schedules pulls of the log puller, in place of an EventBridge rule per rate, so schedules
+//! are read from the same log source configs as the pullers. Invoked every minute, it enqueues
+//! a `PullerRequest` for each log source due then to the puller queue.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_lambda_events::event::cloudwatch_events::CloudWatchEvent;
+use aws_sdk_sqs::model::SendMessageBatchRequestEntry;
+use chrono::{SecondsFormat, Timelike};
+use lambda_runtime::{run, service_fn, Error as LambdaError, LambdaEvent};
+use lazy_static::lazy_static;
+use log::{error, info};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use shared::setup_logging;
+
+mod schedule;
+use schedule::Schedule;
+
+#[global_allocator]
+static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref SQS_CLIENT: AsyncOnce<aws_sdk_sqs::Client> =
+        AsyncOnce::new(async { aws_sdk_sqs::Client::new(AWS_CONFIG.get().await) });
+}
+
+/// SQS delays messages by at most 15 minutes.
+const MAX_JITTER_SECONDS: i64 = 900;
+/// SQS sends at most 10 messages per batch.
+const SEND_BATCH_SIZE: usize = 10;
+
+/// A scheduled pull, as sent by the EventBridge rules, see the puller's `PullerRequest`.
+#[derive(Serialize, Debug)]
+struct PullerRequest {
+    log_source_name: String,
+    time: String,
+    rate_minutes: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct LogSourceFile {
+    name: String,
+    #[serde(default)]
+    managed: Option<ManagedConfig>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ManagedConfig {
+    #[serde(default)]
+    properties: HashMap<String, Option<serde_yaml::Value>>,
+    enabled: Option<bool>,
+}
+
+/// A log source's schedule, from its `schedule` and `schedule_jitter_seconds` properties.
+#[derive(Debug)]
+struct LogSourceSchedule {
+    log_source_name: String,
+    schedule: Schedule,
+    /// Pulls are delayed by up to this, so log sources sharing a schedule (and a vendor's
+    /// rate limits) aren't all pulled at once. Not supported by FIFO queues.
+    jitter_seconds: i64,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), LambdaError> {
+    setup_logging();
+
+    let func = service_fn(handler);
+    run(func).await?;
+
+    Ok(())
+}
+
+async fn handler(event: LambdaEvent<CloudWatchEvent>) -> Result<()> {
+    // Runs are on the minute, the event may be delivered a little after.
+    let time = event
+        .payload
+        .time
+        .with_second(0)
+        .and_then(|t| t.with_nanosecond(0))
+        .context("Invalid event time")?;
+
+    let default_rates: HashMap<String, i64> = env_json("PULLER_LOG_SOURCE_RATES")?;
+    let config_dir = shared::log_sources_config_dir();
+    let schedules = load_schedules(&config_dir, &default_rates)?;
+
+    let requests = schedules
+        .iter()
+        .filter_map(|s| {
+            let rate_minutes = s.schedule.window_minutes(time)?;
+            let request = PullerRequest {
+                log_source_name: s.log_source_name.clone(),
+                time: time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                rate_minutes,
+            };
+            let delay_seconds = match s.jitter_seconds {
+                0 => 0,
+                j => rand::thread_rng().gen_range(0..=j),
+            };
+            Some((request, delay_seconds))
+        })
+        .collect::<Vec<_>>();
+    info!(
+        "Scheduling {} of {} log sources for {}",
+        requests.len(),
+        schedules.len(),
+        time
+    );
+
+    send_requests(&requests).await
+}
+
+/// The schedules of the log sources in `PULLER_LOG_SOURCE_RATES`, which maps each to its
+/// default rate in minutes. Log sources whose config is invalid are logged and skipped, so
+/// they don't stop the others from being scheduled.
+fn load_schedules(
+    config_dir: &Path,
+    default_rates: &HashMap<String, i64>,
+) -> Result<Vec<LogSourceSchedule>> {
+    let mut ret = vec![];
+    for entry in std::fs::read_dir(config_dir)
+        .with_context(|| format!("Failed to read {}", config_dir.display()))?
+    {
+        let path = entry?.path().join("log_source.yml");
+        if !path.is_file() {
+            continue;
+        }
+        match load_schedule(&path, default_rates) {
+            Ok(Some(schedule)) => ret.push(schedule),
+            Ok(None) => (),
+            Err(e) => error!("Skipping {}: {:#}", path.display(), e),
+        }
+    }
+    Ok(ret)
+}
+
+fn load_schedule(
+    path: &Path,
+    default_rates: &HashMap<String, i64>,
+) -> Result<Option<LogSourceSchedule>> {
+    let file = std::fs::File::open(path)?;
+    let config: LogSourceFile = serde_yaml::from_reader(std::io::BufReader::new(file))?;
+    let default_rate = match default_rates.get(&config.name) {
+        Some(rate) => *rate,
+        None => return Ok(None),
+    };
+    let managed = config.managed.unwrap_or_default();
+    if managed.enabled == Some(false) {
+        return Ok(None);
+    }
+    let property = |name: &str| -> Result<Option<String>> {
+        match managed.properties.get(name).cloned().flatten() {
+            Some(serde_yaml::Value::String(s)) => Ok(Some(s)),
+            Some(serde_yaml::Value::Number(n)) => Ok(Some(n.to_string())),
+            Some(_) => Err(anyhow!("Invalid managed.properties.{}", name)),
+            None => Ok(None),
+        }
+    };
+
+    let schedule = match property("schedule")? {
+        Some(expr) => Schedule::parse(&expr)?,
+        None => Schedule::Rate(default_rate),
+    };
+    let jitter_seconds = match property("schedule_jitter_seconds")? {
+        Some(s) => s
+            .trim()
+            .parse::<i64>()
+            .context("Invalid schedule_jitter_seconds")?
+            .clamp(0, MAX_JITTER_SECONDS),
+        None => 0,
+    };
+    Ok(Some(LogSourceSchedule {
+        log_source_name: config.name,
+        schedule,
+        jitter_seconds,
+    }))
+}
+
+async fn send_requests(requests: &[(PullerRequest, i64)]) -> Result<()> {
+    let queue_url = std::env::var("PULLER_QUEUE_URL")?;
+    // FIFO queues deliver each log source's messages in order, but can't delay single messages.
+    let is_fifo = queue_url.ends_with(".fifo");
+    let sqs = SQS_CLIENT.get().await;
+    let mut failed = vec![];
+    for batch in requests.chunks(SEND_BATCH_SIZE) {
+        let entries = batch
+            .iter()
+            .enumerate()
+            .map(|(i, (request, delay_seconds))| {
+                let entry = SendMessageBatchRequestEntry::builder()
+                    .id(i.to_string())
+                    .message_body(serde_json::to_string(request)?);
+                let entry = match is_fifo {
+                    true => entry.message_group_id(&request.log_source_name),
+                    false => entry.delay_seconds(*delay_seconds as i32),
+                };
+                Ok(entry.build())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let res = sqs
+            .send_message_batch()
+            .queue_url(&queue_url)
+            .set_entries(Some(entries))
+            .send()
+            .await?;
+        for f in res.failed().unwrap_or_default() {
+            let request = f
+                .id()
+                .and_then(|id| id.parse::<usize>().ok())
+                .and_then(|i| batch.get(i));
+            if let Some((request, _)) = request {
+                error!(
+                    "Failed to schedule log source: {}: {}",
+                    request.log_source_name,
+                    f.message().unwrap_or_default()
+                );
+                failed.push(request.log_source_name.clone());
+            }
+        }
+    }
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow!("Failed to schedule: {}", failed.join(", "))),
+    }
+}
+
+fn env_json<T: serde::de::DeserializeOwned>(name: &str) -> Result<T> {
+    let value = std::env::var(name).with_context(|| format!("Missing {}", name))?;
+    serde_json::from_str(&value).with_context(|| format!("Invalid {}", name))
+}
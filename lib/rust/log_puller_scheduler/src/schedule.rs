@@ -0,0 +1,177 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+
+/// How far back the previous run of a cron schedule is looked for, to get its window.
+const MAX_CRON_LOOKBACK_MINUTES: i64 = 31 * 24 * 60;
+
+/// When a log source is pulled, set with the `schedule` property as an EventBridge style
+/// expression. Both are evaluated in UTC at minute granularity.
+///
+/// ex:
+/// ```yaml
+/// managed:
+///   properties:
+///     schedule: rate(15 minutes)
+///     # or, e.g. hourly at :05 during working hours
+///     schedule: cron(5 8-18 * * MON-FRI)
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every `n` minutes, on multiples of `n` since the epoch.
+    Rate(i64),
+    Cron(Cron),
+}
+
+impl Schedule {
+    pub fn parse(expr: &str) -> Result<Schedule> {
+        let expr = expr.trim();
+        if let Some(rate) = expr.strip_prefix("rate(").and_then(|s| s.strip_suffix(')')) {
+            return parse_rate(rate).map(Schedule::Rate);
+        }
+        if let Some(cron) = expr.strip_prefix("cron(").and_then(|s| s.strip_suffix(')')) {
+            return Cron::parse(cron).map(Schedule::Cron);
+        }
+        Err(anyhow!(
+            "Invalid schedule: {}, expected rate(...) or cron(...)",
+            expr
+        ))
+    }
+
+    /// The minutes pulled by a run at `time` (the time since the previous run), or None if the
+    /// schedule doesn't run then.
+    pub fn window_minutes(&self, time: DateTime<Utc>) -> Option<i64> {
+        match self {
+            Schedule::Rate(n) => (time.timestamp() / 60 % n == 0).then_some(*n),
+            Schedule::Cron(cron) if cron.matches(time) => (1..=MAX_CRON_LOOKBACK_MINUTES)
+                .find(|m| cron.matches(time - Duration::minutes(*m)))
+                .or(Some(MAX_CRON_LOOKBACK_MINUTES)),
+            Schedule::Cron(_) => None,
+        }
+    }
+}
+
+fn parse_rate(rate: &str) -> Result<i64> {
+    let (value, unit) = rate
+        .trim()
+        .split_once(' ')
+        .with_context(|| format!("Invalid rate: {}", rate))?;
+    let value = value
+        .parse::<i64>()
+        .with_context(|| format!("Invalid rate: {}", rate))?;
+    let minutes = match unit.trim() {
+        "minute" | "minutes" => value,
+        "hour" | "hours" => value * 60,
+        "day" | "days" => value * 60 * 24,
+        u => return Err(anyhow!("Invalid rate unit: {}", u)),
+    };
+    match minutes > 0 {
+        true => Ok(minutes),
+        false => Err(anyhow!("Invalid rate: {}", rate)),
+    }
+}
+
+/// A cron expression of minute, hour, day of month, month and day of week. The 6 field
+/// EventBridge form is accepted too, its year is ignored, `?` is the same as `*` and days of
+/// the week are numbered from 1 (Sunday).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days_of_month: Vec<u32>,
+    months: Vec<u32>,
+    days_of_week: Vec<u32>,
+    /// Cron runs on either day field matching when both are restricted.
+    any_day: bool,
+}
+
+impl Cron {
+    fn parse(expr: &str) -> Result<Cron> {
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 && fields.len() != 6 {
+            return Err(anyhow!("Invalid cron expression: {}", expr));
+        }
+        let is_wildcard = |f: &str| f == "*" || f == "?";
+        let field = |i: usize, min: u32, max: u32, names: &[&str]| {
+            parse_field(fields[i], min, max, names)
+                .with_context(|| format!("Invalid cron expression: {}", expr))
+        };
+        Ok(Cron {
+            minutes: field(0, 0, 59, &[])?,
+            hours: field(1, 0, 23, &[])?,
+            days_of_month: field(2, 1, 31, &[])?,
+            months: field(3, 1, 12, &MONTHS)?,
+            // Sunday is 0 (or 7), or 1 in the EventBridge form, which numbers days 1-7.
+            days_of_week: match fields.len() {
+                5 => field(4, 0, 7, &DAYS_OF_WEEK)?
+                    .into_iter()
+                    .map(|d| d % 7)
+                    .collect(),
+                _ => field(4, 1, 7, &DAYS_OF_WEEK)?
+                    .into_iter()
+                    .map(|d| d - 1)
+                    .collect(),
+            },
+            any_day: !is_wildcard(fields[2]) && !is_wildcard(fields[4]),
+        })
+    }
+
+    fn matches(&self, time: DateTime<Utc>) -> bool {
+        let day_of_month = self.days_of_month.contains(&time.day());
+        let day_of_week = self
+            .days_of_week
+            .contains(&time.weekday().num_days_from_sunday());
+        let day = match self.any_day {
+            true => day_of_month || day_of_week,
+            false => day_of_month && day_of_week,
+        };
+        day && self.minutes.contains(&time.minute())
+            && self.hours.contains(&time.hour())
+            && self.months.contains(&time.month())
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const DAYS_OF_WEEK: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// Parses a field of comma separated values, ranges (`a-b`) and steps (`*/n`, `a-b/n`).
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<Vec<u32>> {
+    let value = |s: &str| -> Result<u32> {
+        let upper = s.to_uppercase();
+        // Names start at the field's minimum, e.g. JAN is 1.
+        let v = match names.iter().position(|n| *n == upper) {
+            Some(i) => i as u32 + min,
+            None => s
+                .parse::<u32>()
+                .with_context(|| format!("Invalid value: {}", s))?,
+        };
+        match (min..=max).contains(&v) {
+            true => Ok(v),
+            false => Err(anyhow!("Value out of range: {}", s)),
+        }
+    };
+    let mut ret = vec![];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(anyhow!("Invalid step: {}", part));
+        }
+        let (start, end) = match range {
+            "*" | "?" => (min, max),
+            r => match r.split_once('-') {
+                Some((a, b)) => (value(a)?, value(b)?),
+                // `a/n` runs from `a` to the maximum.
+                None if step > 1 => (value(r)?, max),
+                None => (value(r)?, value(r)?),
+            },
+        };
+        ret.extend((start..=end).step_by(step));
+    }
+    ret.sort_unstable();
+    ret.dedup();
+    Ok(ret)
+}
@@ -0,0 +1,114 @@
+//! Client side envelope encryption of objects, with a data key from KMS.
+//!
+//! Data is encrypted with AES-256-GCM under a fresh data key, and the KMS encrypted data key
+//! and nonce are stored in the object metadata.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_sdk_kms::primitives::Blob;
+use aws_sdk_kms::types::DataKeySpec;
+use lazy_static::lazy_static;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref KMS_CLIENT: AsyncOnce<aws_sdk_kms::Client> =
+        AsyncOnce::new(async { aws_sdk_kms::Client::new(AWS_CONFIG.get().await) });
+}
+
+/// Object metadata keys (without the `x-amz-meta-` prefix).
+pub const ENVELOPE_KEY_METADATA: &str = "matano-envelope-key";
+pub const ENVELOPE_IV_METADATA: &str = "matano-envelope-iv";
+pub const ENVELOPE_ALG_METADATA: &str = "matano-envelope-alg";
+const ENVELOPE_ALG: &str = "AES256GCM";
+
+/// Whether an object with this metadata was encrypted with `encrypt`.
+pub fn is_encrypted(metadata: &HashMap<String, String>) -> bool {
+    metadata.contains_key(ENVELOPE_KEY_METADATA)
+}
+
+/// Encrypts `data` under a new data key from `kms_key_id`. Returns the ciphertext and the
+/// metadata to store with it.
+pub async fn encrypt(
+    kms_key_id: &str,
+    mut data: Vec<u8>,
+) -> Result<(Vec<u8>, HashMap<String, String>)> {
+    let res = KMS_CLIENT
+        .get()
+        .await
+        .generate_data_key()
+        .key_id(kms_key_id)
+        .key_spec(DataKeySpec::Aes256)
+        .send()
+        .await
+        .context("Failed to generate data key")?;
+    let plaintext_key = res.plaintext().context("Missing plaintext data key")?;
+    let encrypted_key = res
+        .ciphertext_blob()
+        .context("Missing encrypted data key")?;
+
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("Failed to generate nonce"))?;
+
+    let key = UnboundKey::new(&AES_256_GCM, plaintext_key.as_ref())
+        .map_err(|_| anyhow!("Invalid data key"))?;
+    LessSafeKey::new(key)
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .map_err(|_| anyhow!("Failed to encrypt data"))?;
+
+    let metadata = HashMap::from([
+        (
+            ENVELOPE_KEY_METADATA.to_string(),
+            base64::encode(encrypted_key.as_ref()),
+        ),
+        (ENVELOPE_IV_METADATA.to_string(), base64::encode(nonce)),
+        (ENVELOPE_ALG_METADATA.to_string(), ENVELOPE_ALG.to_string()),
+    ]);
+    Ok((data, metadata))
+}
+
+/// Decrypts data encrypted with `encrypt`, given the stored metadata.
+pub async fn decrypt(metadata: &HashMap<String, String>, mut data: Vec<u8>) -> Result<Vec<u8>> {
+    let alg = metadata
+        .get(ENVELOPE_ALG_METADATA)
+        .map(|s| s.as_str())
+        .unwrap_or(ENVELOPE_ALG);
+    if alg != ENVELOPE_ALG {
+        return Err(anyhow!("Unsupported envelope algorithm: {}", alg));
+    }
+    let encrypted_key = metadata
+        .get(ENVELOPE_KEY_METADATA)
+        .context("Missing encrypted data key")?;
+    let nonce = metadata
+        .get(ENVELOPE_IV_METADATA)
+        .context("Missing envelope nonce")?;
+    let nonce: [u8; NONCE_LEN] = base64::decode(nonce)?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid envelope nonce"))?;
+
+    let res = KMS_CLIENT
+        .get()
+        .await
+        .decrypt()
+        .ciphertext_blob(Blob::new(base64::decode(encrypted_key)?))
+        .send()
+        .await
+        .context("Failed to decrypt data key")?;
+    let plaintext_key = res.plaintext().context("Missing plaintext data key")?;
+
+    let key = UnboundKey::new(&AES_256_GCM, plaintext_key.as_ref())
+        .map_err(|_| anyhow!("Invalid data key"))?;
+    let plaintext_len = LessSafeKey::new(key)
+        .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .map_err(|_| anyhow!("Failed to decrypt data"))?
+        .len();
+    data.truncate(plaintext_len);
+    Ok(data)
+}
@@ -0,0 +1,92 @@
+//! JSON log lines for CloudWatch Logs Insights.
+
+use std::fmt;
+
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Formats each event as one JSON object, with the event's fields and the fields of all its
+/// enclosing spans at the top level, e.g.
+/// `{"level":"INFO","target":"log_puller","request_id":"...","log_source":"okta","message":"..."}`.
+///
+/// There is no timestamp, CloudWatch adds the ingestion time. Span fields must be formatted
+/// with `JsonFields`. Inner spans override fields of outer spans with the same name.
+pub(crate) struct FlatJsonFormat;
+
+impl<S, N> FormatEvent<S, N> for FlatJsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut record = Map::new();
+        record.insert("level".to_string(), metadata.level().to_string().into());
+        record.insert("target".to_string(), metadata.target().into());
+
+        if let Some(scope) = ctx.event_scope() {
+            let mut span_name = None;
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(fields) {
+                        record.extend(fields);
+                    }
+                }
+                span_name = Some(span.name());
+            }
+            if let Some(name) = span_name {
+                record.insert("span".to_string(), name.into());
+            }
+        }
+
+        event.record(&mut JsonVisitor(&mut record));
+        writeln!(writer, "{}", Value::Object(record))
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        // Added by `tracing-log` to events from the `log` crate, the target is already set.
+        if !field.name().starts_with("log.") {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value).into());
+    }
+}
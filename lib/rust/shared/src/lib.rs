@@ -1,5 +1,6 @@
 pub mod duplicates_util;
 mod functions;
+mod json_log;
 mod models;
 pub mod utils;
 
@@ -12,6 +13,9 @@ pub mod avro;
 pub mod avro_index;
 pub mod dynamodb_lock;
 pub mod enrichment;
+pub mod envelope;
+pub mod object_key;
 pub mod secrets;
 pub mod sqs_util;
 pub mod vrl_util;
+pub mod zstd_dictionary;
@@ -0,0 +1,30 @@
+//! Keys of raw objects written to the ingestion bucket, partitioned by hour so the raw data
+//! can be managed with lifecycle policies and queried by hour, e.g.
+//! `okta/ts_hour=2024-05-01-13/<uuid>.json.zst`.
+
+use chrono::{DateTime, FixedOffset};
+
+/// The default key template, see `object_key`.
+pub const DEFAULT_KEY_TEMPLATE: &str = "{log_source}/ts_hour={ts_hour}/{uuid}.json.zst";
+
+/// Builds an object key from a template, for example the default
+/// `{log_source}/ts_hour={ts_hour}/{uuid}.json.zst`. The time placeholders (`{ts_hour}`,
+/// `{year}`, `{month}`, `{day}`, `{hour}`) are of the given time, in UTC.
+/// Keys must start with the log source name, ingestion relies on the prefix.
+pub fn object_key(
+    template: &str,
+    log_source: &str,
+    tenant_id: Option<&str>,
+    dt: DateTime<FixedOffset>,
+) -> String {
+    let dt = dt.with_timezone(&chrono::Utc);
+    template
+        .replace("{log_source}", log_source)
+        .replace("{tenant_id}", tenant_id.unwrap_or("default"))
+        .replace("{ts_hour}", &dt.format("%Y-%m-%d-%H").to_string())
+        .replace("{year}", &dt.format("%Y").to_string())
+        .replace("{month}", &dt.format("%m").to_string())
+        .replace("{day}", &dt.format("%d").to_string())
+        .replace("{hour}", &dt.format("%H").to_string())
+        .replace("{uuid}", &uuid::Uuid::new_v4().to_string())
+}
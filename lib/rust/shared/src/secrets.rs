@@ -6,16 +6,82 @@ use cached::proc_macro::cached;
 use lazy_static::lazy_static;
 
 use aws_config::SdkConfig;
+use aws_sdk_ssm::types::ParameterType;
 
 lazy_static! {
     static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
         AsyncOnce::new(async { aws_config::load_from_env().await });
     static ref SECRETS_CLIENT: AsyncOnce<aws_sdk_secretsmanager::Client> =
-        AsyncOnce::new(async { aws_sdk_secretsmanager::Client::new(AWS_CONFIG.get().await) });
+        AsyncOnce::new(async { secrets_client(AWS_CONFIG.get().await) });
+    static ref SSM_CLIENT: AsyncOnce<aws_sdk_ssm::Client> =
+        AsyncOnce::new(async { ssm_client(AWS_CONFIG.get().await) });
 }
 
+fn secrets_client(sdk_config: &SdkConfig) -> aws_sdk_secretsmanager::Client {
+    let mut builder = aws_sdk_secretsmanager::config::Builder::from(sdk_config);
+    if let Some(endpoint_url) = crate::aws_endpoint_url("SECRETS_MANAGER") {
+        builder = builder.endpoint_url(endpoint_url);
+    }
+    aws_sdk_secretsmanager::Client::from_conf(builder.build())
+}
+
+fn ssm_client(sdk_config: &SdkConfig) -> aws_sdk_ssm::Client {
+    let mut builder = aws_sdk_ssm::config::Builder::from(sdk_config);
+    if let Some(endpoint_url) = crate::aws_endpoint_url("SSM") {
+        builder = builder.endpoint_url(endpoint_url);
+    }
+    aws_sdk_ssm::Client::from_conf(builder.build())
+}
+
+/// The SSM parameter name of a secret id that refers to a Parameter Store parameter instead of
+/// a Secrets Manager secret, i.e. a parameter ARN or `ssm:<name>`. The parameter is a
+/// `SecureString` holding the secret's fields as a JSON object, like a secret's string.
+///
+/// ex: `arn:aws:ssm:us-east-1:123456789012:parameter/matano/okta` or `ssm:/matano/okta`
+fn ssm_parameter_name(secret_id: &str) -> Option<String> {
+    if let Some(name) = secret_id.strip_prefix("ssm:") {
+        return Some(name.to_string());
+    }
+    let (_, name) = secret_id
+        .strip_prefix("arn:")
+        .filter(|arn| arn.split(':').nth(1) == Some("ssm"))?
+        .split_once(":parameter/")?;
+    // Hierarchical names keep their leading slash, which the ARN drops.
+    match name.contains('/') {
+        true => Some(format!("/{}", name)),
+        false => Some(name.to_string()),
+    }
+}
+
+async fn load_parameter(name: &str) -> Result<VersionedSecret> {
+    let client = SSM_CLIENT.get().await;
+    let response = client
+        .get_parameter()
+        .name(name)
+        .with_decryption(true)
+        .send()
+        .await?;
+    let parameter = response
+        .parameter()
+        .ok_or_else(|| anyhow!("Missing parameter {}", name))?;
+    let value = parameter
+        .value()
+        .ok_or_else(|| anyhow!("Missing parameter value"))?;
+    let fields: HashMap<String, String> = serde_json::from_str(value)?;
+
+    Ok(VersionedSecret {
+        version_id: parameter.version().to_string(),
+        fields,
+    })
+}
+
+/// Loads a secret's fields from Secrets Manager, or from Parameter Store for parameter ids,
+/// see `ssm_parameter_name`.
 #[cached(time = 60, result = true)]
 pub async fn load_secret(secret_id: String) -> Result<HashMap<String, String>> {
+    if let Some(name) = ssm_parameter_name(&secret_id) {
+        return Ok(load_parameter(&name).await?.fields);
+    }
     let client = SECRETS_CLIENT.get().await;
     let response = client
         .get_secret_value()
@@ -27,3 +93,98 @@ pub async fn load_secret(secret_id: String) -> Result<HashMap<String, String>> {
 
     Ok(secret)
 }
+
+/// A secret's fields along with the version they were read from.
+pub struct VersionedSecret {
+    pub version_id: String,
+    pub fields: HashMap<String, String>,
+}
+
+/// Loads the current version of a secret, bypassing the cache. Use with `update_secret_fields`.
+pub async fn load_secret_versioned(secret_id: &str) -> Result<VersionedSecret> {
+    if let Some(name) = ssm_parameter_name(secret_id) {
+        return load_parameter(&name).await;
+    }
+    let client = SECRETS_CLIENT.get().await;
+    let response = client
+        .get_secret_value()
+        .secret_id(secret_id)
+        .send()
+        .await?;
+    let version_id = response
+        .version_id()
+        .ok_or_else(|| anyhow!("Missing secret version id"))?
+        .to_owned();
+    let secret_string = response
+        .secret_string()
+        .ok_or_else(|| anyhow!("Missing secret string"))?;
+    let fields: HashMap<String, String> = serde_json::from_str(secret_string)?;
+
+    Ok(VersionedSecret { version_id, fields })
+}
+
+/// Writes `updates` over the fields of `secret` as a new secret version, only promoting it to
+/// `AWSCURRENT` if `secret.version_id` is still current (compare and swap). Returns the new
+/// version id, or None if another writer updated the secret first, in which case nothing changes.
+///
+/// Parameters have no version stages, they're only written if still at the version read, which
+/// narrows but doesn't close the race with other writers.
+pub async fn update_secret_fields(
+    secret_id: &str,
+    secret: &VersionedSecret,
+    updates: HashMap<String, String>,
+) -> Result<Option<String>> {
+    let mut fields = secret.fields.clone();
+    fields.extend(updates);
+
+    if let Some(name) = ssm_parameter_name(secret_id) {
+        if load_parameter(&name).await?.version_id != secret.version_id {
+            return Ok(None);
+        }
+        let response = SSM_CLIENT
+            .get()
+            .await
+            .put_parameter()
+            .name(&name)
+            .value(serde_json::to_string(&fields)?)
+            .r#type(ParameterType::SecureString)
+            .overwrite(true)
+            .send()
+            .await?;
+        return Ok(Some(response.version().to_string()));
+    }
+
+    let client = SECRETS_CLIENT.get().await;
+
+    // Stage the new version without making it current yet.
+    let new_version_id = uuid::Uuid::new_v4().to_string();
+    client
+        .put_secret_value()
+        .secret_id(secret_id)
+        .client_request_token(&new_version_id)
+        .secret_string(serde_json::to_string(&fields)?)
+        .version_stages("MATANO_PENDING")
+        .send()
+        .await?;
+
+    // Fails if AWSCURRENT was already moved off the version we read.
+    let res = client
+        .update_secret_version_stage()
+        .secret_id(secret_id)
+        .version_stage("AWSCURRENT")
+        .move_to_version_id(&new_version_id)
+        .remove_from_version_id(&secret.version_id)
+        .send()
+        .await;
+    match res {
+        Ok(_) => Ok(Some(new_version_id)),
+        Err(e) => {
+            let current = load_secret_versioned(secret_id).await?;
+            if current.version_id != secret.version_id {
+                Ok(None)
+            } else {
+                Err(anyhow!(e).context("Failed to promote new secret version"))
+            }
+        }
+    }
+}
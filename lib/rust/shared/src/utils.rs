@@ -10,19 +10,30 @@ use std::{
     sync::Arc,
 };
 use tracing::log::{debug, error, info, warn};
+use tracing_subscriber::fmt::format::{FmtSpan, JsonFields};
 use tracing_subscriber::EnvFilter;
 use walkdir::WalkDir;
 
+/// Logs one JSON object per line, with the fields of enclosing spans (e.g. a request or
+/// message id) on every line, so CloudWatch Logs Insights can filter and group by them.
 pub fn setup_logging() {
+    init_logging(FmtSpan::NONE);
+}
+
+/// Like `setup_logging`, but also logs when each span closes with how long it was busy and
+/// idle, so a slow invocation can be broken down from the logs.
+pub fn setup_tracing() {
+    init_logging(FmtSpan::CLOSE);
+}
+
+fn init_logging(span_events: FmtSpan) {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
         // Setup from the environment (RUST_LOG)
         .with_env_filter(EnvFilter::from_default_env())
-        // this needs to be set to false, otherwise ANSI color codes will
-        // show up in a confusing manner in CloudWatch logs.
-        .with_ansi(false)
-        // disabling time is handy because CloudWatch will add the ingestion time.
-        .without_time()
+        .with_span_events(span_events)
+        .fmt_fields(JsonFields::new())
+        .event_format(crate::json_log::FlatJsonFormat)
         .init();
 }
 
@@ -33,12 +44,27 @@ thread_local! {
     };
 }
 
-pub fn load_log_sources_configuration_map(
-) -> BTreeMap<String, crate::models::LogSourceConfiguration> {
-    let log_sources_configuration_path = match &var("LOG_SOURCES_CONFIG_DIR") {
+/// The `log_sources/` directory of the configuration, with a directory per log source.
+pub fn log_sources_config_dir() -> PathBuf {
+    match &var("LOG_SOURCES_CONFIG_DIR") {
         Ok(v) => PathBuf::from(v),
         Err(_) => Path::new(&var("LAMBDA_TASK_ROOT").unwrap().to_string()).join("log_sources"),
-    };
+    }
+}
+
+/// Endpoint override for an AWS service's client, e.g. to run against LocalStack. Read from
+/// `AWS_ENDPOINT_URL_<SERVICE>` (ex: `AWS_ENDPOINT_URL_S3`), else `AWS_ENDPOINT_URL` for
+/// every service.
+pub fn aws_endpoint_url(service: &str) -> Option<String> {
+    var(format!("AWS_ENDPOINT_URL_{}", service))
+        .or_else(|_| var("AWS_ENDPOINT_URL"))
+        .ok()
+        .filter(|url| !url.trim().is_empty())
+}
+
+pub fn load_log_sources_configuration_map(
+) -> BTreeMap<String, crate::models::LogSourceConfiguration> {
+    let log_sources_configuration_path = log_sources_config_dir();
     let mut log_sources_configuration_map: BTreeMap<String, crate::models::LogSourceConfiguration> =
         BTreeMap::new();
 
@@ -61,100 +87,104 @@ pub fn load_log_sources_configuration_map(
             continue;
         }
 
-        let log_source_folder_name = match log_source_dir_path.file_name() {
-            Some(v) => v,
-            None => {
-                error!("Invalid entry name under log_sources/.");
-                continue;
+        match load_log_source_configuration(log_source_dir_path) {
+            Ok((log_source_name, log_source_configuration)) => {
+                log_sources_configuration_map.insert(log_source_name, log_source_configuration);
             }
-        };
+            Err(e) => error!("{:#}", e),
+        }
+    }
+    log_sources_configuration_map
+}
 
-        let log_source_folder_name = match log_source_folder_name.to_str() {
-            Some(v) => v,
-            None => {
-                error!("Invalid entry name under log_sources/");
-                continue;
-            }
-        };
+/// Loads the configuration of the log source in `log_source_dir_path`, with its tables, and
+/// returns it with the log source's name. Tables that fail to load are skipped.
+pub fn load_log_source_configuration(
+    log_source_dir_path: &Path,
+) -> Result<(String, crate::models::LogSourceConfiguration)> {
+    let log_source_folder_name = log_source_dir_path
+        .file_name()
+        .and_then(|v| v.to_str())
+        .context("Invalid entry name under log_sources/")?;
 
-        let log_source_configuration_path = log_source_dir_path.join("log_source.yml");
-        let log_source_configuration_path =
-            log_source_configuration_path.as_path().to_str().unwrap();
-        let base_configuration_builder = Config::builder()
-            .add_source(File::with_name(log_source_configuration_path).required(true));
-        let base_configuration = base_configuration_builder.build().expect(
+    let log_source_configuration_path = log_source_dir_path.join("log_source.yml");
+    let log_source_configuration_path = log_source_configuration_path
+        .to_str()
+        .context("Invalid log_source.yml path")?;
+    let base_configuration = Config::builder()
+        .add_source(File::with_name(log_source_configuration_path).required(true))
+        .build()
+        .with_context(|| {
             format!(
                 "Failed to load base configuration for log_source: {}/",
                 log_source_folder_name
             )
-            .as_str(),
-        );
-
-        let mut log_source_configuration = crate::models::LogSourceConfiguration {
-            base: base_configuration,
-            tables: HashMap::new(),
-        };
-        let log_source_name = log_source_configuration.base.get_string("name").unwrap();
-
-        let tables_path = log_source_dir_path.join("tables");
-        if tables_path.is_dir() {
-            for entry in WalkDir::new(&tables_path).min_depth(1).max_depth(1) {
-                let tables_path = &tables_path.as_path().to_str().unwrap();
-
-                let table_configuration_path = entry.expect(
-                    format!(
-                        "Invalid entry while walking children for {} directory.",
-                        &tables_path
-                    )
-                    .as_str(),
-                );
-                let table_configuration_path = table_configuration_path.path();
+        })?;
 
-                let err_str = format!(
-                    "Invalid table entry: {}.",
-                    table_configuration_path.display()
-                );
-                let table_file_name = table_configuration_path
-                    .file_name()
-                    .expect(&err_str)
-                    .to_str()
-                    .expect(&err_str);
-
-                let extension = table_configuration_path
-                    .extension()
-                    .and_then(std::ffi::OsStr::to_str);
+    let mut log_source_configuration = crate::models::LogSourceConfiguration {
+        base: base_configuration,
+        tables: HashMap::new(),
+    };
+    let log_source_name = log_source_configuration
+        .base
+        .get_string("name")
+        .with_context(|| format!("Missing name for log_source: {}/", log_source_folder_name))?;
 
-                if !table_configuration_path.is_file()
-                    || !(extension == Some("yml") || extension == Some("yaml"))
-                {
+    let tables_path = log_source_dir_path.join("tables");
+    if tables_path.is_dir() {
+        for entry in WalkDir::new(&tables_path).min_depth(1).max_depth(1) {
+            let table_configuration_path = match entry {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(
+                        "Invalid entry while walking children for {} directory: {}",
+                        tables_path.display(),
+                        e
+                    );
                     continue;
                 }
+            };
+            let table_configuration_path = table_configuration_path.path();
 
-                let table_configuration_path = table_configuration_path.to_str().unwrap();
+            let extension = table_configuration_path
+                .extension()
+                .and_then(std::ffi::OsStr::to_str);
 
-                let table_configuration = Config::builder()
-                    .add_source(File::with_name(table_configuration_path).required(true))
-                    .build();
+            if !table_configuration_path.is_file()
+                || !(extension == Some("yml") || extension == Some("yaml"))
+            {
+                continue;
+            }
 
-                let table_configuration = match table_configuration {
-                    Ok(v) => v,
-                    Err(e) => {
-                        error!("Failed to load table configuration for log_source={}, table_path={}: {}", log_source_name, table_file_name, e);
-                        continue;
-                    }
-                };
+            let table_file_name = table_configuration_path.display();
+            let table_configuration = table_configuration_path
+                .to_str()
+                .context("Invalid table entry")
+                .and_then(|p| {
+                    Ok(Config::builder()
+                        .add_source(File::with_name(p).required(true))
+                        .build()?)
+                })
+                .and_then(|c| Ok((c.get_string("name")?, c)));
 
-                let table_name = table_configuration.get_string("name").unwrap();
+            let (table_name, table_configuration) = match table_configuration {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(
+                        "Failed to load table configuration for log_source={}, table_path={}: {:#}",
+                        log_source_name, table_file_name, e
+                    );
+                    continue;
+                }
+            };
 
-                // .add_source(Environment::with_prefix("app"));
-                log_source_configuration
-                    .tables
-                    .insert(table_name, table_configuration);
-            }
+            // .add_source(Environment::with_prefix("app"));
+            log_source_configuration
+                .tables
+                .insert(table_name, table_configuration);
         }
-        log_sources_configuration_map.insert(log_source_name, log_source_configuration);
     }
-    log_sources_configuration_map
+    Ok((log_source_name, log_source_configuration))
 }
 
 pub fn load_enrichment_config() -> Result<HashMap<String, serde_yaml::Value>> {
@@ -74,35 +74,59 @@ pub fn vrl_opt<'a>(
             },
         }?;
 
-        let mut metadata = ::value::Value::Object(BTreeMap::new());
-        let mut secrets = ::value::Secrets::new();
-        let mut target = TargetValueRef {
-            value: value,
-            metadata: &mut metadata,
-            secrets: &mut secrets,
-        };
-
-        let time_zone_str = Some("tt".to_string()).unwrap_or_default();
-
-        let time_zone = match TimeZone::parse(&time_zone_str) {
-            Some(tz) => tz,
-            None => TimeZone::Local,
-        };
-
-        let result = RUNTIME.with(|r| {
-            let mut runtime = r.borrow_mut();
-
-            match (*runtime).resolve(&mut target, &compiled, &time_zone) {
-                Ok(result) => Ok(Some(result)),
-                Err(Terminate::Abort(_)) => Ok(None),
-                Err(e) => Err(anyhow!(e)),
-            }
-        })?;
+        let result = resolve_opt(compiled, value)?;
 
         Ok(result.and_then(|output| Some((output, value))))
     })
 }
 
+/// Compiles a program with the stdlib and custom functions, for callers that run it many times.
+pub fn compile(program: &str) -> Result<Program> {
+    let mut functions = vrl_stdlib::all();
+    functions.append(&mut custom_vrl_functions());
+
+    match vrl::compile(program, &functions) {
+        Ok(result) => {
+            if result.warnings.len() > 0 {
+                warn!("{:?}", result.warnings);
+            }
+            Ok(result.program)
+        }
+        Err(diagnostics) => Err(anyhow!(Formatter::new(program, diagnostics).to_string())),
+    }
+}
+
+/// Runs a compiled program on the value. Returns None if program was aborted.
+pub fn resolve_opt(
+    compiled: &Program,
+    value: &mut ::value::Value,
+) -> Result<Option<::value::Value>> {
+    let mut metadata = ::value::Value::Object(BTreeMap::new());
+    let mut secrets = ::value::Secrets::new();
+    let mut target = TargetValueRef {
+        value: value,
+        metadata: &mut metadata,
+        secrets: &mut secrets,
+    };
+
+    let time_zone_str = Some("tt".to_string()).unwrap_or_default();
+
+    let time_zone = match TimeZone::parse(&time_zone_str) {
+        Some(tz) => tz,
+        None => TimeZone::Local,
+    };
+
+    RUNTIME.with(|r| {
+        let mut runtime = r.borrow_mut();
+
+        match (*runtime).resolve(&mut target, compiled, &time_zone) {
+            Ok(result) => Ok(Some(result)),
+            Err(Terminate::Abort(_)) => Ok(None),
+            Err(e) => Err(anyhow!(e)),
+        }
+    })
+}
+
 /// Fails if program aborted.
 pub fn vrl<'a>(
     program: &'a str,
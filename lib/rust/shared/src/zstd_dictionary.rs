@@ -0,0 +1,94 @@
+//! Per log source zstd dictionaries, for log sources pulled often (e.g. every minute) whose
+//! many small objects compress poorly on their own.
+//!
+//! A dictionary is trained on sample records of the log source, e.g. with
+//! `zstd --train samples/* -o zstd.dict`, and placed next to its `log_source.yml`, which ships
+//! it in the configuration layer. Objects compressed with it name it in their metadata, so
+//! readers decompress them with the same dictionary.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+
+use crate::log_sources_config_dir;
+
+lazy_static! {
+    /// Loaded dictionaries by log source, None if the log source has none.
+    static ref DICTIONARIES: Mutex<HashMap<String, Option<Arc<ZstdDictionary>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Object metadata key (without the `x-amz-meta-` prefix) naming the dictionary an object
+/// was compressed with.
+pub const ZSTD_DICTIONARY_METADATA: &str = "matano-zstd-dictionary";
+const DICTIONARY_FILE: &str = "zstd.dict";
+
+#[derive(Debug)]
+pub struct ZstdDictionary {
+    /// Hex SHA256 prefix of the dictionary, so a reader can tell it has a different one.
+    pub id: String,
+    pub data: Vec<u8>,
+}
+
+/// Loads the dictionary of a log source, once, None if it has none.
+pub fn load(log_source: &str) -> Result<Option<Arc<ZstdDictionary>>> {
+    if let Some(dictionary) = DICTIONARIES.lock().unwrap().get(log_source) {
+        return Ok(dictionary.clone());
+    }
+    let path = log_sources_config_dir()
+        .join(log_source)
+        .join(DICTIONARY_FILE);
+    let dictionary = match std::fs::read(&path) {
+        Ok(data) => {
+            let digest = ring::digest::digest(&ring::digest::SHA256, &data);
+            let id = digest.as_ref()[..8]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>();
+            Some(Arc::new(ZstdDictionary { id, data }))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read zstd dictionary {}", path.display()))
+        }
+    };
+    DICTIONARIES
+        .lock()
+        .unwrap()
+        .insert(log_source.to_string(), dictionary.clone());
+    Ok(dictionary)
+}
+
+/// The dictionary an object with this metadata was compressed with, if any.
+pub fn dictionary_id(metadata: &HashMap<String, String>) -> Option<&str> {
+    metadata.get(ZSTD_DICTIONARY_METADATA).map(|s| s.as_str())
+}
+
+/// Decompresses an object compressed with the log source's dictionary.
+pub fn decompress(
+    log_source: &str,
+    metadata: &HashMap<String, String>,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let id = dictionary_id(metadata).context("Object wasn't compressed with a dictionary")?;
+    let dictionary =
+        load(log_source)?.with_context(|| format!("Missing zstd dictionary for {}", log_source))?;
+    if dictionary.id != id {
+        return Err(anyhow!(
+            "Object was compressed with zstd dictionary {}, but {} has {}",
+            id,
+            log_source,
+            dictionary.id
+        ));
+    }
+    let mut decoder = zstd::stream::read::Decoder::with_dictionary(data, &dictionary.data)?;
+    let mut ret = vec![];
+    decoder
+        .read_to_end(&mut ret)
+        .context("Failed to decompress with zstd dictionary")?;
+    Ok(ret)
+}
@@ -92,6 +92,17 @@ lazy_static! {
         m
     };
     static ref CUSTOM_BUCKET_TO_ACCESS_ROLE_ARN_MAP: HashMap<String, String> = serde_json::from_str(&var("CUSTOM_BUCKET_TO_ACCESS_ROLE_ARN_MAP").unwrap()).unwrap();
+    /// The log puller's failover ingestion bucket, which may be in another region.
+    static ref FAILOVER_BUCKET_S3_CLIENT: AsyncOnce<Option<(String, aws_sdk_s3::Client)>> =
+        AsyncOnce::new(async {
+            let bucket = var("INGESTION_FAILOVER_BUCKET_NAME").ok().filter(|b| !b.is_empty())?;
+            let mut config = aws_sdk_s3::config::Builder::from(AWS_CONFIG.get().await);
+            let region = var("INGESTION_FAILOVER_BUCKET_REGION").ok().filter(|r| !r.is_empty());
+            if let Some(region) = region {
+                config = config.region(Region::new(region));
+            }
+            Some((bucket, aws_sdk_s3::Client::from_conf(config.build())))
+        });
     static ref CUSTOM_BUCKET_TO_REGION_CACHE: Mutex<HashMap<String, Region>> =  Mutex::new(HashMap::new());
     // cache of (role_arn, region) -> client
     static ref CUSTOM_ROLE_REGIONAL_S3_CLIENT_CACHE: Mutex<HashMap<(String, String), aws_sdk_s3::Client>> = {
@@ -108,6 +119,11 @@ fn bucket_location_constraint_to_aws_region(blc: &str) -> &str {
 }
 
 async fn get_s3_client_using_access_role_cached(bucket: &str) -> aws_sdk_s3::Client {
+    if let Some((failover_bucket, client)) = FAILOVER_BUCKET_S3_CLIENT.get().await {
+        if failover_bucket == bucket {
+            return client.clone();
+        }
+    }
     if let Some(access_role_arn) = CUSTOM_BUCKET_TO_ACCESS_ROLE_ARN_MAP.get(bucket) {
         let mut custom_bucket_to_region_cache = CUSTOM_BUCKET_TO_REGION_CACHE.lock().unwrap();
         let bucket_region = match custom_bucket_to_region_cache.get(bucket) {
@@ -370,11 +386,34 @@ async fn read_events_s3<'a>(
         .send()
         .await
         .map_err(|e| anyhow!(e).context(format!("Error downloading {} from S3", dec_key)));
-    let obj = res?;
-
-    let mut reader = tokio::io::BufReader::new(obj.body.into_async_read());
+    let mut obj = res?;
+
+    // Objects encrypted client side by the puller are decrypted whole before decompressing,
+    // and objects compressed with a zstd dictionary are decompressed whole, they're small.
+    let metadata = obj.metadata.take().unwrap_or_default();
+    let is_encrypted = shared::envelope::is_encrypted(&metadata);
+    let uses_dictionary = shared::zstd_dictionary::dictionary_id(&metadata).is_some();
+    let body: Box<dyn tokio::io::AsyncRead + Send + Unpin> = if is_encrypted || uses_dictionary {
+        let mut data = obj.body.collect().await?.into_bytes().to_vec();
+        if is_encrypted {
+            data = shared::envelope::decrypt(&metadata, data)
+                .await
+                .with_context(|| format!("Error decrypting {}", dec_key))?;
+        }
+        if uses_dictionary {
+            data = shared::zstd_dictionary::decompress(log_source, &metadata, &data)
+                .with_context(|| format!("Error decompressing {}", dec_key))?;
+        }
+        Box::new(std::io::Cursor::new(data))
+    } else {
+        Box::new(obj.body.into_async_read())
+    };
+    let mut reader = tokio::io::BufReader::new(body);
 
-    let compression = Compression::Auto;
+    let compression = match uses_dictionary {
+        true => Compression::None,
+        false => Compression::Auto,
+    };
     let compression = match compression {
         Compression::Auto => infer_compression(
             &mut reader,
@@ -533,6 +572,8 @@ async fn read_events_s3<'a>(
                             .collect::<Option<Vec<_>>>()
                     });
                 read_csv(headers, reader)
+            } else if dec_key.as_str().ends_with(".parquet") {
+                read_parquet(reader)
             } else {
                 FramedRead::new(reader, LinesCodec::new())
                     .map(|v| match v {
@@ -1517,6 +1558,40 @@ async fn handle_partial_failure(
     Ok(())
 }
 
+/// Reads Parquet rows (e.g. written by the puller with `output_format: parquet`) as JSON
+/// lines, the same as NDJSON objects.
+fn read_parquet(
+    mut reader: Box<dyn tokio::io::AsyncRead + Send + Unpin>,
+) -> Pin<Box<dyn Stream<Item = Result<Value, anyhow::Error>> + std::marker::Send>> {
+    Box::pin(stream! {
+        // Parquet metadata is at the end of the file, so it's read whole.
+        let mut data = vec![];
+        if let Err(e) = reader.read_to_end(&mut data).await {
+            yield Err(anyhow!(e).context("Failed to read parquet file"));
+            return;
+        }
+        match parquet_rows_to_json(data) {
+            Ok(lines) => {
+                for line in lines {
+                    yield Ok(Value::from(line));
+                }
+            }
+            Err(e) => yield Err(e),
+        }
+    })
+}
+
+fn parquet_rows_to_json(data: Vec<u8>) -> Result<Vec<String>> {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let reader = SerializedFileReader::new(bytes::Bytes::from(data))?;
+    let lines = reader
+        .get_row_iter(None)?
+        .map(|row| row.to_json_value().to_string())
+        .collect();
+    Ok(lines)
+}
+
 fn read_csv(
     headers: Option<Vec<String>>,
     reader: Box<dyn tokio::io::AsyncRead + Send + Unpin>,
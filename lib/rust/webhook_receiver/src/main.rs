@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::{anyhow, Context, Result};
+use async_once::AsyncOnce;
+use aws_config::SdkConfig;
+use aws_lambda_events::encodings::Body;
+use aws_lambda_events::event::apigw::{ApiGatewayV2httpRequest, ApiGatewayV2httpResponse};
+use aws_sdk_s3::types::ByteStream;
+use lambda_runtime::{run, service_fn, Error as LambdaError, LambdaEvent};
+use lazy_static::lazy_static;
+use log::{debug, error, info};
+use serde_json::json;
+use shared::object_key::{object_key, DEFAULT_KEY_TEMPLATE};
+use shared::secrets::load_secret;
+use shared::setup_logging;
+
+mod verify;
+use verify::Verification;
+
+#[global_allocator]
+static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+lazy_static! {
+    static ref AWS_CONFIG: AsyncOnce<SdkConfig> =
+        AsyncOnce::new(async { aws_config::load_from_env().await });
+    static ref S3_CLIENT: AsyncOnce<aws_sdk_s3::Client> =
+        AsyncOnce::new(async { aws_sdk_s3::Client::new(AWS_CONFIG.get().await) });
+}
+
+const WEBHOOK_TOKEN_HEADER: &str = "x-matano-webhook-token";
+
+/// The receiver's configuration, read from the environment once at startup.
+struct WebhookConfig {
+    ingestion_bucket: String,
+    /// Log sources that accept webhooks, mapped to the secret holding their `webhook_token`.
+    secret_arns: HashMap<String, String>,
+    /// How deliveries are authenticated, for log sources that don't use the default token.
+    verification: HashMap<String, Verification>,
+}
+
+impl WebhookConfig {
+    fn from_env() -> Result<WebhookConfig> {
+        let ingestion_bucket =
+            std::env::var("INGESTION_BUCKET_NAME").context("Missing INGESTION_BUCKET_NAME")?;
+        let secret_arns = std::env::var("WEBHOOK_LOG_SOURCE_TO_SECRET_ARN_MAP")
+            .context("Missing WEBHOOK_LOG_SOURCE_TO_SECRET_ARN_MAP")?;
+        let secret_arns = serde_json::from_str(&secret_arns)
+            .context("Invalid WEBHOOK_LOG_SOURCE_TO_SECRET_ARN_MAP")?;
+        let verification = match std::env::var("WEBHOOK_LOG_SOURCE_VERIFICATION") {
+            Ok(s) => serde_json::from_str(&s).context("Invalid WEBHOOK_LOG_SOURCE_VERIFICATION")?,
+            Err(_) => HashMap::new(),
+        };
+        Ok(WebhookConfig {
+            ingestion_bucket,
+            secret_arns,
+            verification,
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), LambdaError> {
+    setup_logging();
+
+    let config = WebhookConfig::from_env()?;
+    let config = &config;
+    let func = service_fn(move |event| handler(event, config));
+    run(func).await?;
+
+    Ok(())
+}
+
+fn response(status_code: i64, body: serde_json::Value) -> ApiGatewayV2httpResponse {
+    let mut headers = http::HeaderMap::new();
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("application/json"),
+    );
+    ApiGatewayV2httpResponse {
+        status_code,
+        headers,
+        body: Some(Body::Text(body.to_string())),
+        is_base64_encoded: Some(false),
+        ..Default::default()
+    }
+}
+
+async fn handler(
+    event: LambdaEvent<ApiGatewayV2httpRequest>,
+    config: &WebhookConfig,
+) -> Result<ApiGatewayV2httpResponse> {
+    let request = event.payload;
+
+    // Deliveries are routed by path, ex: POST https://<function-url>/<log_source_name>
+    let path = request
+        .raw_path
+        .clone()
+        .or_else(|| request.request_context.http.path.clone())
+        .unwrap_or_default();
+    let log_source_name = path.trim_matches('/').to_string();
+
+    let secret_arn = match config.secret_arns.get(&log_source_name) {
+        Some(arn) => arn,
+        None => {
+            debug!(
+                "Rejecting webhook for unknown log source: {}",
+                &log_source_name
+            );
+            return Ok(response(404, json!({ "message": "Not found" })));
+        }
+    };
+
+    let method = request.request_context.http.method.clone();
+    let verification = config
+        .verification
+        .get(&log_source_name)
+        .cloned()
+        .unwrap_or_default();
+
+    // Okta event hooks verify ownership with a one time GET challenge.
+    if method == http::Method::GET {
+        if let Some(challenge) = verify::okta_challenge(&request.headers) {
+            if verification.allows_challenge() {
+                return Ok(response(200, json!({ "verification": challenge })));
+            }
+        }
+        return Ok(response(405, json!({ "message": "Method not allowed" })));
+    }
+    if method != http::Method::POST {
+        return Ok(response(405, json!({ "message": "Method not allowed" })));
+    }
+
+    let body = match decode_body(&request) {
+        Ok(b) => b,
+        Err(e) => {
+            return Ok(response(400, json!({ "message": format!("{:#}", e) })));
+        }
+    };
+
+    match is_authorized(&request, &body, &verification, secret_arn).await {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!(
+                "Rejecting webhook for log source: {}, failed {:?} verification",
+                &log_source_name, verification
+            );
+            return Ok(response(401, json!({ "message": "Unauthorized" })));
+        }
+        Err(e) => {
+            error!(
+                "Failed to load webhook secret for {}: {:#}",
+                &log_source_name, e
+            );
+            return Ok(response(500, json!({ "message": "Internal error" })));
+        }
+    }
+
+    // Slack verifies the endpoint with a signed challenge.
+    if verification == Verification::Slack {
+        if let Some(challenge) = verify::slack_challenge(&body) {
+            return Ok(response(200, json!({ "challenge": challenge })));
+        }
+    }
+    let data = match to_ndjson(&body) {
+        Ok(d) => d,
+        Err(e) => {
+            return Ok(response(
+                400,
+                json!({ "message": format!("Invalid JSON payload: {:#}", e) }),
+            ));
+        }
+    };
+
+    if data.is_empty() {
+        return Ok(response(200, json!({ "message": "OK", "records": 0 })));
+    }
+
+    let num_records = data.iter().filter(|b| **b == b'\n').count() + 1;
+    upload_data(data, &config.ingestion_bucket, &log_source_name).await?;
+    info!(
+        "Wrote {} webhook records for log_source: {}",
+        num_records, &log_source_name
+    );
+
+    Ok(response(
+        200,
+        json!({ "message": "OK", "records": num_records }),
+    ))
+}
+
+/// Checks a delivery against the log source's webhook token, by default sent either as a
+/// bearer token or in the `x-matano-webhook-token` header, see `Verification`.
+async fn is_authorized(
+    request: &ApiGatewayV2httpRequest,
+    body: &[u8],
+    verification: &Verification,
+    secret_arn: &str,
+) -> Result<bool> {
+    let secret = load_secret(secret_arn.to_string()).await?;
+    let token = secret
+        .get("webhook_token")
+        .context("Missing webhook_token in secret")?;
+
+    Ok(verification.verify(&request.headers, body, token))
+}
+
+fn decode_body(request: &ApiGatewayV2httpRequest) -> Result<Vec<u8>> {
+    let body = request.body.as_deref().unwrap_or_default();
+    if request.is_base64_encoded {
+        base64::decode(body).map_err(|e| anyhow!(e).context("Invalid base64 body"))
+    } else {
+        Ok(body.as_bytes().to_vec())
+    }
+}
+
+/// Converts a JSON object, JSON array, or newline delimited JSON payload to NDJSON.
+fn to_ndjson(body: &[u8]) -> Result<Vec<u8>> {
+    let mut ret = vec![];
+    let stream = serde_json::Deserializer::from_slice(body).into_iter::<serde_json::Value>();
+    for value in stream {
+        let values = match value? {
+            serde_json::Value::Array(arr) => arr,
+            v => vec![v],
+        };
+        for v in values {
+            ret.extend(serde_json::to_vec(&v)?);
+            ret.push(b'\n');
+        }
+    }
+    // Remove last newline
+    if ret.last() == Some(&b'\n') {
+        ret.pop();
+    }
+    Ok(ret)
+}
+
+/// Writes a delivery's records to the ingestion bucket, as one object under the log source's
+/// hour partition, see `object_key`. Each delivery is written before it's acknowledged, so
+/// deliveries aren't batched together.
+async fn upload_data(data: Vec<u8>, bucket: &str, log_source: &str) -> Result<()> {
+    let now = chrono::Utc::now().into();
+    let key = object_key(DEFAULT_KEY_TEMPLATE, log_source, None, now);
+    let s3 = S3_CLIENT.get().await;
+    info!("Writing to s3://{}/{}", bucket, key);
+
+    let mut zencoder = zstd::Encoder::new(vec![], 0)?;
+    zencoder.write_all(data.as_slice())?;
+    let final_data = zencoder.finish()?;
+
+    s3.put_object()
+        .bucket(bucket)
+        .key(&key)
+        .body(ByteStream::from(final_data))
+        .content_encoding("application/zstd".to_string())
+        .send()
+        .await
+        .map_err(|e| anyhow!(e).context(format!("Error putting {} to S3", key)))?;
+
+    Ok(())
+}
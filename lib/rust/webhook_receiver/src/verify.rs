@@ -0,0 +1,158 @@
+use http::HeaderMap;
+use ring::hmac;
+use serde::Deserialize;
+
+/// Slack requests older than this are rejected, so captured requests can't be replayed.
+const SLACK_MAX_AGE_SECONDS: i64 = 300;
+
+/// How deliveries to a log source are authenticated, set with `ingest.webhook.verification`.
+/// Signatures are checked against the raw body, keyed with the `webhook_token` field of the log
+/// source's webhook secret, which should be replaced with the vendor's signing secret where the
+/// vendor generates one (e.g. Slack).
+///
+/// ex:
+/// ```yaml
+/// ingest:
+///   webhook:
+///     enabled: true
+///     verification:
+///       type: hmac
+///       header: x-signature
+///       algorithm: sha256
+///       encoding: base64
+/// ```
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Verification {
+    /// The token as a bearer token or in the `x-matano-webhook-token` header.
+    #[default]
+    Token,
+    /// GitHub's `X-Hub-Signature-256`, `sha256=` and the hex HMAC-SHA256 of the body.
+    Github,
+    /// Slack's `X-Slack-Signature`, `v0=` and the hex HMAC-SHA256 of
+    /// `v0:<X-Slack-Request-Timestamp>:<body>`.
+    Slack,
+    /// Okta event hooks, which send the token as is in the `Authorization` header and verify
+    /// the endpoint with a one time GET challenge.
+    Okta,
+    /// A generic HMAC of the body in a header.
+    Hmac(HmacVerification),
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HmacVerification {
+    header: String,
+    #[serde(default)]
+    algorithm: HmacAlgorithm,
+    #[serde(default)]
+    encoding: SignatureEncoding,
+    /// Stripped from the header value before it's decoded, e.g. `sha256=`.
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HmacAlgorithm {
+    Sha1,
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl Verification {
+    /// Whether a delivery is authentic, given its headers, raw body and the log source's token.
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8], token: &str) -> bool {
+        match self {
+            Verification::Token => {
+                let provided = header(headers, crate::WEBHOOK_TOKEN_HEADER).or_else(|| {
+                    header(headers, http::header::AUTHORIZATION.as_str())
+                        .and_then(|v| v.strip_prefix("Bearer "))
+                });
+                provided.map_or(false, |p| constant_time_eq(p.as_bytes(), token.as_bytes()))
+            }
+            Verification::Okta => header(headers, http::header::AUTHORIZATION.as_str())
+                .map_or(false, |p| constant_time_eq(p.as_bytes(), token.as_bytes())),
+            Verification::Github => header(headers, "x-hub-signature-256")
+                .and_then(|s| s.strip_prefix("sha256="))
+                .and_then(|s| hex::decode(s).ok())
+                .map_or(false, |signature| {
+                    verify_hmac(hmac::HMAC_SHA256, token, body, &signature)
+                }),
+            Verification::Slack => {
+                let timestamp = match header(headers, "x-slack-request-timestamp") {
+                    Some(t) => t,
+                    None => return false,
+                };
+                let is_recent = timestamp.parse::<i64>().map_or(false, |t| {
+                    (chrono::Utc::now().timestamp() - t).abs() <= SLACK_MAX_AGE_SECONDS
+                });
+                let signature = header(headers, "x-slack-signature")
+                    .and_then(|s| s.strip_prefix("v0="))
+                    .and_then(|s| hex::decode(s).ok());
+                let mut base = format!("v0:{}:", timestamp).into_bytes();
+                base.extend_from_slice(body);
+                is_recent
+                    && signature.map_or(false, |signature| {
+                        verify_hmac(hmac::HMAC_SHA256, token, &base, &signature)
+                    })
+            }
+            Verification::Hmac(config) => {
+                let signature = header(headers, &config.header.to_lowercase())
+                    .map(|s| s.strip_prefix(config.prefix.as_str()).unwrap_or(s))
+                    .and_then(|s| match config.encoding {
+                        SignatureEncoding::Hex => hex::decode(s).ok(),
+                        SignatureEncoding::Base64 => base64::decode(s).ok(),
+                    });
+                let algorithm = match config.algorithm {
+                    HmacAlgorithm::Sha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+                    HmacAlgorithm::Sha256 => hmac::HMAC_SHA256,
+                    HmacAlgorithm::Sha512 => hmac::HMAC_SHA512,
+                };
+                signature.map_or(false, |signature| {
+                    verify_hmac(algorithm, token, body, &signature)
+                })
+            }
+        }
+    }
+
+    /// Whether unauthenticated GET verification challenges are answered, see `okta_challenge`.
+    pub fn allows_challenge(&self) -> bool {
+        matches!(self, Verification::Token | Verification::Okta)
+    }
+}
+
+/// The challenge of an Okta event hook verification request, echoed back to prove ownership.
+pub fn okta_challenge(headers: &HeaderMap) -> Option<&str> {
+    header(headers, "x-okta-verification-challenge")
+}
+
+/// The challenge of a (verified) Slack `url_verification` request.
+pub fn slack_challenge(body: &[u8]) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_slice(body).ok()?;
+    if value["type"] != "url_verification" {
+        return None;
+    }
+    value["challenge"].as_str().map(|s| s.to_string())
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn verify_hmac(algorithm: hmac::Algorithm, key: &str, msg: &[u8], signature: &[u8]) -> bool {
+    let key = hmac::Key::new(algorithm, key.as_bytes());
+    hmac::verify(&key, msg, signature).is_ok()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    ring::constant_time::verify_slices_are_equal(a, b).is_ok()
+}